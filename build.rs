@@ -15,13 +15,11 @@ fn main() {
     let mut basic_programs = Vec::new();
     
     if let Ok(entries) = fs::read_dir(test_suite_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("bas") {
-                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
-                        basic_programs.push((name.to_string(), path.file_name().unwrap().to_str().unwrap().to_string()));
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("bas") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    basic_programs.push((name.to_string(), path.file_name().unwrap().to_str().unwrap().to_string()));
                 }
             }
         }
@@ -31,17 +29,22 @@ fn main() {
 
     // Generate the test functions
     writeln!(f, "// This file is automatically generated by build.rs").unwrap();
-    writeln!(f, "use std::process::Command;").unwrap();
+    writeln!(f, "use std::process::{{self, Command, Stdio}};").unwrap();
     writeln!(f, "use std::path::Path;").unwrap();
     writeln!(f, "use std::time::Duration;").unwrap();
     writeln!(f, "use wait_timeout::ChildExt;").unwrap();
+    writeln!(f, "use std::env;").unwrap();
     writeln!(f, "use std::fs;").unwrap();
-    writeln!(f, "").unwrap();
+    writeln!(f, "use std::io::{{Read, Write}};").unwrap();
+    writeln!(f, "use std::thread;").unwrap();
+    writeln!(f, "use basic_rs::llvm_toolchain::Toolchain;").unwrap();
+    writeln!(f).unwrap();
 
     // Helper functions
     writeln!(f, "const TEST_TIMEOUT_SECS: u64 = 30;").unwrap();
-    writeln!(f, "").unwrap();
+    writeln!(f).unwrap();
     
+    writeln!(f, "#[allow(dead_code)]").unwrap();
     writeln!(f, "fn get_expected_exit_code(program_path: &Path) -> i32 {{").unwrap();
     writeln!(f, "    if let Ok(content) = fs::read_to_string(program_path) {{").unwrap();
     writeln!(f, "        if let Some(first_line) = content.lines().next() {{").unwrap();
@@ -55,47 +58,263 @@ fn main() {
     writeln!(f, "    }}").unwrap();
     writeln!(f, "    0 // Default to 0 (success)").unwrap();
     writeln!(f, "}}").unwrap();
-    writeln!(f, "").unwrap();
+    writeln!(f).unwrap();
 
+    // Golden output: expected stdout and stdin given inline or in sibling files
+    writeln!(f, "/// Text of a `REM` line with the line number, `REM` and one following space removed").unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn rem_text(line: &str) -> Option<&str> {{").unwrap();
+    writeln!(f, "    let statement = line.trim_start().trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();").unwrap();
+    writeln!(f, "    let text = statement.strip_prefix(\"REM\")?;").unwrap();
+    writeln!(f, "    Some(text.strip_prefix(' ').unwrap_or(text))").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "/// Stdin for a test: a sibling `.in` file, or the `REM @INPUT` lines in the program").unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn read_test_input(program_path: &Path) -> Vec<u8> {{").unwrap();
+    writeln!(f, "    if let Ok(input) = fs::read(program_path.with_extension(\"in\")) {{").unwrap();
+    writeln!(f, "        return input;").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    let content = fs::read_to_string(program_path).unwrap_or_default();").unwrap();
+    writeln!(f, "    let mut input = String::new();").unwrap();
+    writeln!(f, "    for text in content.lines().filter_map(rem_text) {{").unwrap();
+    writeln!(f, "        if let Some(value) = text.strip_prefix(\"@INPUT\") {{").unwrap();
+    writeln!(f, "            input.push_str(value.strip_prefix(' ').unwrap_or(value));").unwrap();
+    writeln!(f, "            input.push('\\n');").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    input.into_bytes()").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "/// Expected stdout for a test: a sibling `.out` file, or the `REM` lines between").unwrap();
+    writeln!(f, "/// `REM @EXPECT_OUTPUT` and `REM @END_EXPECT_OUTPUT` (or the first line that is not a REM)").unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn get_expected_output(program_path: &Path) -> Option<String> {{").unwrap();
+    writeln!(f, "    if let Ok(output) = fs::read_to_string(program_path.with_extension(\"out\")) {{").unwrap();
+    writeln!(f, "        return Some(output);").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    let content = fs::read_to_string(program_path).ok()?;").unwrap();
+    writeln!(f, "    let mut lines = content.lines();").unwrap();
+    writeln!(f, "    lines.by_ref().find(|line| rem_text(line).map(str::trim) == Some(\"@EXPECT_OUTPUT\"))?;").unwrap();
+    writeln!(f, "    let mut expected = String::new();").unwrap();
+    writeln!(f, "    for line in lines {{").unwrap();
+    writeln!(f, "        match rem_text(line) {{").unwrap();
+    writeln!(f, "            Some(text) if text.trim() == \"@END_EXPECT_OUTPUT\" => break,").unwrap();
+    writeln!(f, "            Some(text) => {{").unwrap();
+    writeln!(f, "                expected.push_str(text);").unwrap();
+    writeln!(f, "                expected.push('\\n');").unwrap();
+    writeln!(f, "            }}").unwrap();
+    writeln!(f, "            None => break,").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    Some(expected)").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "/// Unified diff of expected and actual output, with three lines of context").unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn unified_diff(expected: &str, actual: &str) -> String {{").unwrap();
+    writeln!(f, "    const CONTEXT: usize = 3;").unwrap();
+    writeln!(f, "    // diff::lines splits on '\\n'; drop the final newline so it does not show up as an empty line").unwrap();
+    writeln!(f, "    let expected_lines = expected.strip_suffix('\\n').unwrap_or(expected);").unwrap();
+    writeln!(f, "    let actual_lines = actual.strip_suffix('\\n').unwrap_or(actual);").unwrap();
+    writeln!(f, "    let lines: Vec<(char, &str)> = diff::lines(expected_lines, actual_lines)").unwrap();
+    writeln!(f, "        .into_iter()").unwrap();
+    writeln!(f, "        .map(|change| match change {{").unwrap();
+    writeln!(f, "            diff::Result::Left(line) => ('-', line),").unwrap();
+    writeln!(f, "            diff::Result::Both(line, _) => (' ', line),").unwrap();
+    writeln!(f, "            diff::Result::Right(line) => ('+', line),").unwrap();
+    writeln!(f, "        }})").unwrap();
+    writeln!(f, "        .collect();").unwrap();
+    writeln!(f, "    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    let mut text = String::from(\"--- expected\\n+++ actual\\n\");").unwrap();
+    writeln!(f, "    if changed.is_empty() {{").unwrap();
+    writeln!(f, "        text.push_str(\"(outputs differ only in the final newline)\\n\");").unwrap();
+    writeln!(f, "        return text;").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    let mut hunk_start = 0;").unwrap();
+    writeln!(f, "    while hunk_start < changed.len() {{").unwrap();
+    writeln!(f, "        // Changes closer together than twice the context share a hunk").unwrap();
+    writeln!(f, "        let mut hunk_end = hunk_start;").unwrap();
+    writeln!(f, "        while hunk_end + 1 < changed.len() && changed[hunk_end + 1] - changed[hunk_end] <= 2 * CONTEXT {{").unwrap();
+    writeln!(f, "            hunk_end += 1;").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "        let from = changed[hunk_start].saturating_sub(CONTEXT);").unwrap();
+    writeln!(f, "        let to = (changed[hunk_end] + CONTEXT + 1).min(lines.len());").unwrap();
+    writeln!(f, "        let count = |range: &[(char, &str)], skip: char| range.iter().filter(|(tag, _)| *tag != skip).count();").unwrap();
+    writeln!(f, "        let old_start = count(&lines[..from], '+') + 1;").unwrap();
+    writeln!(f, "        let new_start = count(&lines[..from], '-') + 1;").unwrap();
+    writeln!(f, "        text.push_str(&format!(\"@@ -{{}},{{}} +{{}},{{}} @@\\n\", old_start, count(&lines[from..to], '+'), new_start, count(&lines[from..to], '-')));").unwrap();
+    writeln!(f, "        for (tag, line) in &lines[from..to] {{").unwrap();
+    writeln!(f, "            text.push_str(&format!(\"{{}}{{}}\\n\", tag, line));").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "        hunk_start = hunk_end + 1;").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    text").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[test]").unwrap();
+    writeln!(f, "fn test_unified_diff() {{").unwrap();
+    writeln!(f, "    let expected = \"1\\n2\\n3\\n4\\n5\\n6\\n7\\n8\\n9\\n10\\n\";").unwrap();
+    writeln!(f, "    let actual = \"1\\n2\\nthree\\n4\\n5\\n6\\n7\\n8\\n9\\n10\\n11\\n\";").unwrap();
+    writeln!(f, "    assert_eq!(unified_diff(expected, actual),").unwrap();
+    writeln!(f, "               \"--- expected\\n+++ actual\\n\\").unwrap();
+    writeln!(f, "                @@ -1,6 +1,6 @@\\n 1\\n 2\\n-3\\n+three\\n 4\\n 5\\n 6\\n\\").unwrap();
+    writeln!(f, "                @@ -8,3 +8,4 @@\\n 8\\n 9\\n 10\\n+11\\n\");").unwrap();
+    writeln!(f, "    assert_eq!(unified_diff(\"1\\n\", \"1\"), \"--- expected\\n+++ actual\\n(outputs differ only in the final newline)\\n\");").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
     writeln!(f, "fn run_basic_test(file_name: &str) -> Result<(), String> {{").unwrap();
     writeln!(f, "    let test_suite_dir = Path::new(env!(\"CARGO_MANIFEST_DIR\")).join(\"test_suite\");").unwrap();
     writeln!(f, "    let program_path = test_suite_dir.join(file_name);").unwrap();
-    writeln!(f, "    ").unwrap();
+    writeln!(f).unwrap();
     writeln!(f, "    if !program_path.exists() {{").unwrap();
     writeln!(f, "        return Err(format!(\"Test file {{}} not found\", file_name));").unwrap();
     writeln!(f, "    }}").unwrap();
-    writeln!(f, "    ").unwrap();
+    writeln!(f).unwrap();
     writeln!(f, "    let expected_exit_code = get_expected_exit_code(&program_path);").unwrap();
     writeln!(f, "    let mut command = Command::new(env!(\"CARGO_BIN_EXE_basic_rs\"));").unwrap();
     writeln!(f, "    command.arg(&program_path);").unwrap();
-    writeln!(f, "    ").unwrap();
-    writeln!(f, "    match command.spawn() {{").unwrap();
-    writeln!(f, "        Ok(mut child) => {{").unwrap();
-    writeln!(f, "            match child.wait_timeout(Duration::from_secs(TEST_TIMEOUT_SECS)) {{").unwrap();
-    writeln!(f, "                Ok(Some(status)) => {{").unwrap();
-    writeln!(f, "                    let actual_exit_code = status.code().unwrap_or(-1);").unwrap();
-    writeln!(f, "                    if actual_exit_code == expected_exit_code {{").unwrap();
-    writeln!(f, "                        Ok(())").unwrap();
-    writeln!(f, "                    }} else {{").unwrap();
-    writeln!(f, "                        Err(format!(\"Expected exit code: {{}}, got: {{}}\", expected_exit_code, actual_exit_code))").unwrap();
-    writeln!(f, "                    }}").unwrap();
-    writeln!(f, "                }}").unwrap();
-    writeln!(f, "                Ok(None) => {{").unwrap();
-    writeln!(f, "                    let _ = child.kill();").unwrap();
-    writeln!(f, "                    Err(format!(\"Test timed out after {{}} seconds\", TEST_TIMEOUT_SECS))").unwrap();
-    writeln!(f, "                }}").unwrap();
-    writeln!(f, "                Err(e) => Err(format!(\"Error waiting for process: {{}}\", e)),").unwrap();
-    writeln!(f, "            }}").unwrap();
+    writeln!(f, "    let run = run_with_input(command, &read_test_input(&program_path))?;").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    if run.exit_code != expected_exit_code {{").unwrap();
+    writeln!(f, "        return Err(format!(\"Expected exit code: {{}}, got: {{}}\\n{{}}\", expected_exit_code, run.exit_code, run.stdout));").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    if let Some(expected) = get_expected_output(&program_path) {{").unwrap();
+    writeln!(f, "        let actual = strip_driver_status(&run.stdout);").unwrap();
+    writeln!(f, "        if actual != expected {{").unwrap();
+    writeln!(f, "            return Err(format!(\"Output does not match\\n{{}}\", unified_diff(&expected, actual)));").unwrap();
     writeln!(f, "        }}").unwrap();
-    writeln!(f, "        Err(e) => Err(format!(\"Failed to spawn process: {{}}\", e)),").unwrap();
     writeln!(f, "    }}").unwrap();
+    writeln!(f, "    Ok(())").unwrap();
     writeln!(f, "}}").unwrap();
-    writeln!(f, "").unwrap();
+    writeln!(f).unwrap();
+
+    // Differential testing: the same program, interpreted and compiled, must behave the same
+    writeln!(f, "// Lines the interpreter driver prints after the program ends; compiled programs do not").unwrap();
+    writeln!(f, "const DRIVER_STATUS_LINES: [&str; 3] = [\"Program completed successfully\", \"Program reached end\", \"Program stopped\"];").unwrap();
+    writeln!(f, "// basic_rs exits with 2 when the program does not parse").unwrap();
+    writeln!(f, "const INTERPRETER_PARSE_ERROR: i32 = 2;").unwrap();
+    writeln!(f, "// basic-compiler exit codes for lexing and parse errors").unwrap();
+    writeln!(f, "const COMPILER_PARSE_ERRORS: std::ops::RangeInclusive<i32> = 10..=14;").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "struct ProgramRun {{").unwrap();
+    writeln!(f, "    exit_code: i32,").unwrap();
+    writeln!(f, "    stdout: String,").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn run_with_input(mut command: Command, input: &[u8]) -> Result<ProgramRun, String> {{").unwrap();
+    writeln!(f, "    command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null());").unwrap();
+    writeln!(f, "    let mut child = command.spawn().map_err(|e| format!(\"Failed to spawn process: {{}}\", e))?;").unwrap();
+    writeln!(f, "    let mut stdin = child.stdin.take().unwrap();").unwrap();
+    writeln!(f, "    let input = input.to_vec();").unwrap();
+    writeln!(f, "    let writer = thread::spawn(move || {{").unwrap();
+    writeln!(f, "        // The program may exit without reading all of its input").unwrap();
+    writeln!(f, "        let _ = stdin.write_all(&input);").unwrap();
+    writeln!(f, "    }});").unwrap();
+    writeln!(f, "    let mut stdout = child.stdout.take().unwrap();").unwrap();
+    writeln!(f, "    let reader = thread::spawn(move || {{").unwrap();
+    writeln!(f, "        let mut buffer = Vec::new();").unwrap();
+    writeln!(f, "        let _ = stdout.read_to_end(&mut buffer);").unwrap();
+    writeln!(f, "        buffer").unwrap();
+    writeln!(f, "    }});").unwrap();
+    writeln!(f, "    match child.wait_timeout(Duration::from_secs(TEST_TIMEOUT_SECS)) {{").unwrap();
+    writeln!(f, "        Ok(Some(status)) => {{").unwrap();
+    writeln!(f, "            let _ = writer.join();").unwrap();
+    writeln!(f, "            let stdout = reader.join().unwrap_or_default();").unwrap();
+    writeln!(f, "            Ok(ProgramRun {{").unwrap();
+    writeln!(f, "                exit_code: status.code().unwrap_or(-1),").unwrap();
+    writeln!(f, "                stdout: String::from_utf8_lossy(&stdout).into_owned(),").unwrap();
+    writeln!(f, "            }})").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "        Ok(None) => {{").unwrap();
+    writeln!(f, "            let _ = child.kill();").unwrap();
+    writeln!(f, "            let _ = child.wait();").unwrap();
+    writeln!(f, "            Err(format!(\"Test timed out after {{}} seconds\", TEST_TIMEOUT_SECS))").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "        Err(e) => Err(format!(\"Error waiting for process: {{}}\", e)),").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn strip_driver_status(stdout: &str) -> &str {{").unwrap();
+    writeln!(f, "    for status in DRIVER_STATUS_LINES {{").unwrap();
+    writeln!(f, "        if let Some(program_output) = stdout.strip_suffix(&format!(\"{{}}\\n\", status)) {{").unwrap();
+    writeln!(f, "            return program_output;").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    stdout").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn compare_compiled(program_path: &Path, work_dir: &Path, executable: &Path) -> Result<(), String> {{").unwrap();
+    writeln!(f, "    let input = read_test_input(program_path);").unwrap();
+    writeln!(f, "    let mut interpreter = Command::new(env!(\"CARGO_BIN_EXE_basic_rs\"));").unwrap();
+    writeln!(f, "    interpreter.arg(program_path).current_dir(work_dir);").unwrap();
+    writeln!(f, "    let interpreted = run_with_input(interpreter, &input)?;").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    let compile = Command::new(env!(\"CARGO_BIN_EXE_basic-compiler\"))").unwrap();
+    writeln!(f, "        .arg(program_path)").unwrap();
+    writeln!(f, "        .arg(\"-o\")").unwrap();
+    writeln!(f, "        .arg(executable)").unwrap();
+    writeln!(f, "        .output()").unwrap();
+    writeln!(f, "        .map_err(|e| format!(\"Failed to run basic-compiler: {{}}\", e))?;").unwrap();
+    writeln!(f, "    if !compile.status.success() {{").unwrap();
+    writeln!(f, "        let code = compile.status.code().unwrap_or(-1);").unwrap();
+    writeln!(f, "        // A program the interpreter rejects is not expected to compile either").unwrap();
+    writeln!(f, "        if interpreted.exit_code == INTERPRETER_PARSE_ERROR && COMPILER_PARSE_ERRORS.contains(&code) {{").unwrap();
+    writeln!(f, "            return Ok(());").unwrap();
+    writeln!(f, "        }}").unwrap();
+    writeln!(f, "        return Err(format!(\"basic-compiler failed with exit code {{}}:\\n{{}}\", code, String::from_utf8_lossy(&compile.stderr)));").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    let mut compiled_program = Command::new(executable);").unwrap();
+    writeln!(f, "    compiled_program.current_dir(work_dir);").unwrap();
+    writeln!(f, "    let compiled = run_with_input(compiled_program, &input)?;").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    if compiled.exit_code != interpreted.exit_code {{").unwrap();
+    writeln!(f, "        return Err(format!(\"Exit codes differ: interpreted {{}}, compiled {{}}\", interpreted.exit_code, compiled.exit_code));").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    let expected = strip_driver_status(&interpreted.stdout);").unwrap();
+    writeln!(f, "    if compiled.stdout != expected {{").unwrap();
+    writeln!(f, "        return Err(format!(\"Output differs\\n--- interpreted\\n{{}}\\n--- compiled\\n{{}}\", expected, compiled.stdout));").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "    Ok(())").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "#[allow(dead_code)]").unwrap();
+    writeln!(f, "fn run_compiled_test(file_name: &str) -> Result<(), String> {{").unwrap();
+    writeln!(f, "    let test_suite_dir = Path::new(env!(\"CARGO_MANIFEST_DIR\")).join(\"test_suite\");").unwrap();
+    writeln!(f, "    let program_path = test_suite_dir.join(file_name);").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    if !program_path.exists() {{").unwrap();
+    writeln!(f, "        return Err(format!(\"Test file {{}} not found\", file_name));").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    let toolchain = Toolchain::discover();").unwrap();
+    writeln!(f, "    if !toolchain.can_build_executables() {{").unwrap();
+    writeln!(f, "        // Written to stderr directly, which the test harness does not capture, so the skip shows").unwrap();
+    writeln!(f, "        let _ = writeln!(std::io::stderr(), \"SKIPPED compiled test {{}}: no native toolchain or runtime library found\", file_name);").unwrap();
+    writeln!(f, "        return Ok(());").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f).unwrap();
+    writeln!(f, "    // Each test gets its own directory, so files the programs write cannot collide").unwrap();
+    writeln!(f, "    let stem = program_path.file_stem().unwrap().to_string_lossy().into_owned();").unwrap();
+    writeln!(f, "    let work_dir = env::temp_dir().join(format!(\"basic_rs_compiled_{{}}_{{}}\", stem, process::id()));").unwrap();
+    writeln!(f, "    fs::create_dir_all(&work_dir).map_err(|e| format!(\"Failed to create {{}}: {{}}\", work_dir.display(), e))?;").unwrap();
+    writeln!(f, "    let result = compare_compiled(&program_path, &work_dir, &work_dir.join(&stem));").unwrap();
+    writeln!(f, "    let _ = fs::remove_dir_all(&work_dir);").unwrap();
+    writeln!(f, "    result").unwrap();
+    writeln!(f, "}}").unwrap();
+    writeln!(f).unwrap();
 
     // Generate individual test functions
     for (test_name, file_name) in basic_programs {
         // Convert file name to valid Rust identifier
-        let rust_test_name = test_name.replace("-", "_").replace(".", "_");
+        let rust_test_name = test_name.replace(['-', '.'], "_");
         
         writeln!(f, "#[test]").unwrap();
         writeln!(f, "fn test_basic_{}() {{", rust_test_name).unwrap();
@@ -104,6 +323,15 @@ fn main() {
         writeln!(f, "        Err(error) => panic!(\"BASIC test failed: {{}}\", error),").unwrap();
         writeln!(f, "    }}").unwrap();
         writeln!(f, "}}").unwrap();
-        writeln!(f, "").unwrap();
+        writeln!(f).unwrap();
+
+        writeln!(f, "#[test]").unwrap();
+        writeln!(f, "fn test_compiled_{}() {{", rust_test_name).unwrap();
+        writeln!(f, "    match run_compiled_test(\"{}\") {{", file_name).unwrap();
+        writeln!(f, "        Ok(()) => {{}}, // Interpreted and compiled runs match").unwrap();
+        writeln!(f, "        Err(error) => panic!(\"Compiled test failed: {{}}\", error),").unwrap();
+        writeln!(f, "    }}").unwrap();
+        writeln!(f, "}}").unwrap();
+        writeln!(f).unwrap();
     }
 } 
\ No newline at end of file
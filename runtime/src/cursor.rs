@@ -0,0 +1,93 @@
+//! Output column tracking for PRINT zones (comma) and TAB
+
+/// Width of a PRINT zone: a comma moves to the next multiple of this column
+pub const PRINT_ZONE_WIDTH: usize = 8;
+
+/// Tracks the output column so commas and TAB know how far to pad.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrintCursor {
+    column: usize,
+}
+
+impl PrintCursor {
+    pub const fn new() -> Self {
+        PrintCursor { column: 0 }
+    }
+
+    /// A cursor at `column`
+    pub const fn at(column: usize) -> Self {
+        PrintCursor { column }
+    }
+
+    /// Current output column, 0 at the start of a line
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Records that `text` was written; a newline in it starts a new line
+    pub fn advance(&mut self, text: &str) {
+        match text.rfind('\n') {
+            Some(end) => self.column = text.len() - end - 1,
+            None => self.column += text.len(),
+        }
+    }
+
+    /// Spaces needed to reach the next print zone; moves the cursor there
+    pub fn comma(&mut self) -> usize {
+        let next_tab = ((self.column / PRINT_ZONE_WIDTH) + 1) * PRINT_ZONE_WIDTH;
+        let spaces_needed = next_tab - self.column;
+        self.column = next_tab;
+        spaces_needed
+    }
+
+    /// Spaces needed for TAB(n); zero if the cursor is already at or past `n`
+    pub fn tab(&mut self, n: usize) -> usize {
+        if n > self.column {
+            let spaces_needed = n - self.column;
+            self.column = n;
+            spaces_needed
+        } else {
+            0
+        }
+    }
+
+    /// Records that a newline was written
+    pub fn newline(&mut self) {
+        self.column = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comma_moves_to_next_zone() {
+        let mut cursor = PrintCursor::new();
+        assert_eq!(cursor.comma(), 8);
+        cursor.advance("ABC");
+        assert_eq!(cursor.column(), 11);
+        assert_eq!(cursor.comma(), 5);
+        assert_eq!(cursor.column(), 16);
+    }
+
+    #[test]
+    fn test_tab() {
+        let mut cursor = PrintCursor::new();
+        cursor.advance("HELLO");
+        assert_eq!(cursor.tab(10), 5);
+        assert_eq!(cursor.tab(3), 0);
+        assert_eq!(cursor.column(), 10);
+        cursor.newline();
+        assert_eq!(cursor.column(), 0);
+    }
+
+    #[test]
+    fn test_advance_past_newline() {
+        let mut cursor = PrintCursor::at(4);
+        cursor.advance("AB\nCDE");
+        assert_eq!(cursor.column(), 3);
+        cursor.advance("\n");
+        assert_eq!(cursor.column(), 0);
+    }
+}
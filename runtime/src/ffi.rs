@@ -0,0 +1,835 @@
+//! `extern "C"` entry points called from the LLVM-IR that `basic-compiler` generates.
+//!
+//! Every string argument must be null (read as "") or a NUL-terminated string, which is
+//! all the generated code ever passes. Strings handed back are allocated here and never
+//! freed: BASIC programs are short-lived and never release string storage themselves.
+#![allow(clippy::missing_safety_doc)]
+
+use std::borrow::Cow;
+use std::ffi::{CStr, CString};
+use std::io::{self, BufRead, Write};
+use std::os::raw::c_char;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::cursor::PrintCursor;
+use crate::format::NumberFormat;
+use crate::files::{self, FileError, FileMode, FileTable};
+use crate::matrix::{self, Matrix, MatrixError, MatrixErrorKind, Operation};
+use crate::operators::{self, IntegerOp};
+use crate::precision::{self, Precision};
+use crate::rng::{self, Rng, RndAlgorithm};
+use crate::using::{self, UsingError, UsingValue};
+use crate::{format, input, limits, mbf, strings};
+
+/// Exit code and label for each error kind, matching what `basic_rs` reports
+pub const ERROR_SYNTAX: i32 = 5;
+pub const ERROR_RUNTIME: i32 = 6;
+pub const ERROR_INTERNAL: i32 = 7;
+pub const ERROR_TYPE: i32 = 8;
+
+struct Runtime {
+    cursor: PrintCursor,
+    rng: Rng,
+    input_retries: usize,
+    files: FileTable,
+    file_error: Option<FileError>,  // Left by the last file operation, for basic_file_status
+    fields: Vec<(f64, String)>,     // The FIELD statement being built by basic_file_field_add
+    using_values: Vec<UsingValue>,  // The values of the PRINT USING being built
+    using_error: Option<UsingError>,
+    number_format: NumberFormat,
+    matrices: Vec<Matrix>,            // Operands of the MAT statement being run
+    matrix_result: Option<Matrix>,    // Its result, waiting for basic_mat_store
+    matrix_error: Option<MatrixError>,
+    determinant: f64,                 // DET, from the last INV
+}
+
+static RUNTIME: Mutex<Runtime> = Mutex::new(Runtime {
+    cursor: PrintCursor::new(),
+    rng: Rng::new(0),
+    input_retries: 0,
+    files: FileTable::new(),
+    file_error: None,
+    fields: Vec::new(),
+    using_values: Vec::new(),
+    using_error: None,
+    number_format: NumberFormat::DOUBLE,
+    matrices: Vec::new(),
+    matrix_result: None,
+    matrix_error: None,
+    determinant: 0.0,
+});
+
+fn runtime() -> MutexGuard<'static, Runtime> {
+    RUNTIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Stands in for CHR$(0) in the strings compiled code holds, as a C string ends at a NUL.
+/// It is the first character that is not a BASIC byte, so no BASIC string has one.
+const NUL_STAND_IN: char = '\u{100}';
+
+unsafe fn c_str<'a>(ptr: *const c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        return Cow::Borrowed("");
+    }
+    let s = CStr::from_ptr(ptr).to_string_lossy();
+    if s.contains(NUL_STAND_IN) {
+        Cow::Owned(s.replace(NUL_STAND_IN, "\0"))
+    } else {
+        s
+    }
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    let s = if s.contains('\0') { s.replace('\0', &NUL_STAND_IN.to_string()) } else { s };
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+fn write_out(text: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+}
+
+fn fail(kind: i32, message: &str, line: i32) -> ! {
+    let _ = io::stdout().flush();
+    let _ = runtime().files.close_all();
+    let label = match kind {
+        ERROR_SYNTAX => "Syntax",
+        ERROR_INTERNAL => "Internal",
+        ERROR_TYPE => "Type",
+        _ => "Runtime",
+    };
+    eprintln!("{} error:, BASIC line {} {}", label, line, message);
+    std::process::exit(kind);
+}
+
+/// Called once at the start of main
+#[no_mangle]
+pub extern "C" fn basic_runtime_init() {
+    runtime().rng = Rng::start(RndAlgorithm::BasicRs);
+}
+
+/// Sets how PRINT, STR$ and WRITE # write numbers, from the dialect's NumberFormat
+#[no_mangle]
+pub extern "C" fn basic_number_format(digits: i32, min_fixed_exponent: i32, str_sign_space: i32) {
+    runtime().number_format = NumberFormat {
+        digits: digits.max(1) as usize,
+        min_fixed_exponent,
+        str_sign_space: str_sign_space != 0,
+    };
+}
+
+/// A number rounded to the 40-bit Microsoft Binary Format, for dialects computing in it
+#[no_mangle]
+pub extern "C" fn basic_round_mbf40(n: f64) -> f64 {
+    Precision::Mbf40.round(n)
+}
+
+/// `a op b` for the IntegerOp with this code, on integers of `bits`, or NaN on Overflow.
+/// Compiled code checks for division by zero first, with basic_divides_by_zero.
+#[no_mangle]
+pub extern "C" fn basic_integer_op(op: i32, a: f64, b: f64, bits: i32) -> f64 {
+    IntegerOp::from_code(op).apply(a, b, bits as u32).unwrap_or(f64::NAN)
+}
+
+/// 1 if `b` as the right operand of the IntegerOp with this code divides by zero
+#[no_mangle]
+pub extern "C" fn basic_divides_by_zero(op: i32, b: f64, bits: i32) -> i32 {
+    IntegerOp::from_code(op).divides_by_zero(b, bits as u32) as i32
+}
+
+/// NOT n on integers of `bits`, or NaN on Overflow
+#[no_mangle]
+pub extern "C" fn basic_not(n: f64, bits: i32) -> f64 {
+    operators::not(n, bits as u32).unwrap_or(f64::NAN)
+}
+
+/// Called before main returns, so buffered output is not lost
+#[no_mangle]
+pub extern "C" fn basic_runtime_shutdown() {
+    let _ = io::stdout().flush();
+    let _ = runtime().files.close_all();
+}
+
+/// Reports an error of the given kind (one of the ERROR_* codes) at a BASIC line and exits
+#[no_mangle]
+pub unsafe extern "C" fn basic_runtime_error(kind: i32, message: *const c_char, line: i32) -> ! {
+    fail(kind, &c_str(message), line)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_print_string(s: *const c_char) {
+    let s = c_str(s);
+    write_out(&s);
+    runtime().cursor.advance(&s);
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_number(n: f64) {
+    let mut rt = runtime();
+    let text = format::print_number(n, &rt.number_format);
+    write_out(&text);
+    rt.cursor.advance(&text);
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_double(n: f64) {
+    let mut rt = runtime();
+    let text = format::print_number(n, &rt.number_format.double());
+    write_out(&text);
+    rt.cursor.advance(&text);
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_single(n: f64) {
+    let mut rt = runtime();
+    let text = format::print_number(n, &rt.number_format.single());
+    write_out(&text);
+    rt.cursor.advance(&text);
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_newline() {
+    write_out("\n");
+    runtime().cursor.newline();
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_comma() {
+    let spaces = runtime().cursor.comma();
+    write_out(&" ".repeat(spaces));
+}
+
+#[no_mangle]
+pub extern "C" fn basic_print_tab(column: f64) {
+    let spaces = runtime().cursor.tab(column as usize);
+    write_out(&" ".repeat(spaces));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_str_concat(a: *const c_char, b: *const c_char) -> *mut c_char {
+    into_c_string(format!("{}{}", c_str(a), c_str(b)))
+}
+
+/// The STRING TOO LONG message if `s` is longer than `max`, or null if it fits;
+/// the generated code raises the message so ON ERROR can trap it
+#[no_mangle]
+pub unsafe extern "C" fn basic_check_string(s: *const c_char, max: i32) -> *mut c_char {
+    match limits::check_string(&c_str(s), max as usize) {
+        Ok(()) => std::ptr::null_mut(),
+        Err(message) => into_c_string(message),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_str_compare(a: *const c_char, b: *const c_char) -> f64 {
+    strings::compare(&c_str(a), &c_str(b))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_left(s: *const c_char, n: f64) -> *mut c_char {
+    into_c_string(strings::left(&c_str(s), n))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_right(s: *const c_char, n: f64) -> *mut c_char {
+    into_c_string(strings::right(&c_str(s), n))
+}
+
+/// MID$(s, start, n), or null for a start below 1
+#[no_mangle]
+pub unsafe extern "C" fn basic_mid(s: *const c_char, start: f64, n: f64) -> *mut c_char {
+    strings::mid(&c_str(s), start, n).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+#[no_mangle]
+pub extern "C" fn basic_chr(n: f64) -> *mut c_char {
+    into_c_string(strings::chr(n))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_space(n: f64) -> *mut c_char {
+    into_c_string(strings::space(n))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_str(n: f64) -> *mut c_char {
+    let text = format::str_number(n, &runtime().number_format);
+    into_c_string(text)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_len(s: *const c_char) -> f64 {
+    strings::len(&c_str(s))
+}
+
+/// MID$(s, start, n) = text: the new value of s, or null when start is outside it
+#[no_mangle]
+pub unsafe extern "C" fn basic_mid_assign(s: *const c_char, start: f64, n: f64, has_length: i32, text: *const c_char) -> *mut c_char {
+    let n = (has_length != 0).then_some(n);
+    strings::mid_assign(&c_str(s), start, n, &c_str(text)).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+/// INSTR(start, s, t), or NaN for a start below 1
+#[no_mangle]
+pub unsafe extern "C" fn basic_instr(start: f64, s: *const c_char, t: *const c_char) -> f64 {
+    strings::instr(start, &c_str(s), &c_str(t)).unwrap_or(f64::NAN)
+}
+
+/// STRING$(n, code), or null for a bad count or code
+#[no_mangle]
+pub extern "C" fn basic_string(n: f64, code: f64) -> *mut c_char {
+    strings::string(n, code).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+/// STRING$(n, s), or null for a bad count or an empty s
+#[no_mangle]
+pub unsafe extern "C" fn basic_string_of(n: f64, s: *const c_char) -> *mut c_char {
+    strings::string_of(n, &c_str(s)).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_ucase(s: *const c_char) -> *mut c_char {
+    into_c_string(strings::ucase(&c_str(s)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_lcase(s: *const c_char) -> *mut c_char {
+    into_c_string(strings::lcase(&c_str(s)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_ltrim(s: *const c_char) -> *mut c_char {
+    into_c_string(strings::ltrim(&c_str(s)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_rtrim(s: *const c_char) -> *mut c_char {
+    into_c_string(strings::rtrim(&c_str(s)))
+}
+
+/// HEX$(n) for the dialect's integer size, or null on Overflow
+#[no_mangle]
+pub extern "C" fn basic_hex(n: f64, bits: i32) -> *mut c_char {
+    strings::hex(n, bits as u32).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+/// OCT$(n) for the dialect's integer size, or null on Overflow
+#[no_mangle]
+pub extern "C" fn basic_oct(n: f64, bits: i32) -> *mut c_char {
+    strings::oct(n, bits as u32).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+/// VAL(s) read the way the ValSyntax with this code reads it, or NaN for its error
+#[no_mangle]
+pub unsafe extern "C" fn basic_val(s: *const c_char, syntax: i32) -> f64 {
+    strings::val(&c_str(s), strings::ValSyntax::from_code(syntax)).unwrap_or(f64::NAN)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_asc(s: *const c_char, line: i32) -> f64 {
+    match strings::asc(&c_str(s)) {
+        Some(code) => code,
+        None => fail(ERROR_RUNTIME, "ASC requires a non-empty string", line),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn basic_rnd(x: f64) -> f64 {
+    runtime().rng.rnd(x)
+}
+
+/// Switches RND to the dialect's generator, by RndAlgorithm code, as the program starts
+#[no_mangle]
+pub extern "C" fn basic_rnd_algorithm(code: i32) {
+    runtime().rng = Rng::start(RndAlgorithm::from_code(code));
+}
+
+#[no_mangle]
+pub extern "C" fn basic_randomize(seed: f64) {
+    runtime().rng.randomize(seed);
+}
+
+#[no_mangle]
+pub extern "C" fn basic_timer() -> f64 {
+    rng::timer()
+}
+
+/// Starts an INPUT statement: resets the retry count
+#[no_mangle]
+pub extern "C" fn basic_input_begin() {
+    runtime().input_retries = 0;
+}
+
+/// Prints the prompt ("? " when `prompt` is null) and reads one line from stdin, after
+/// which output starts a new line
+#[no_mangle]
+pub unsafe extern "C" fn basic_input_line(prompt: *const c_char) -> *mut c_char {
+    let prompt = if prompt.is_null() { None } else { Some(c_str(prompt)) };
+    write_out(&input::prompt_text(prompt.as_deref()));
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    runtime().cursor.newline();
+    into_c_string(line)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_input_field_count(line: *const c_char) -> i32 {
+    input::split_fields(&c_str(line)).len() as i32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_input_field(line: *const c_char, index: i32) -> *mut c_char {
+    let line = c_str(line);
+    let fields = input::split_fields(&line);
+    into_c_string(fields.get(index as usize).copied().unwrap_or("").to_string())
+}
+
+/// Parses a numeric field into `out`; returns 0 if it is not a number
+#[no_mangle]
+pub unsafe extern "C" fn basic_parse_number(s: *const c_char, out: *mut f64) -> i32 {
+    match input::parse_number(&c_str(s)) {
+        Some(n) => {
+            *out = n;
+            1
+        }
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_to_upper(s: *const c_char) -> *mut c_char {
+    into_c_string(c_str(s).to_uppercase())
+}
+
+fn input_retry(message: String) -> *mut c_char {
+    let retries = {
+        let mut rt = runtime();
+        rt.input_retries += 1;
+        rt.input_retries
+    };
+    if retries >= input::MAX_RETRIES {
+        return into_c_string(message);
+    }
+    write_out(input::REDO_MESSAGE);
+    write_out("\n");
+    std::ptr::null_mut()
+}
+
+/// The line had the wrong number of values: prints "?Redo from start" and returns null so
+/// the caller asks again, or returns the error message once retries run out
+#[no_mangle]
+pub extern "C" fn basic_input_bad_count(expected: i32, got: i32) -> *mut c_char {
+    input_retry(input::wrong_count_message(expected as usize, got as usize))
+}
+
+/// A value for a numeric variable was not a number: null to ask again, or the error message
+#[no_mangle]
+pub extern "C" fn basic_input_bad_number() -> *mut c_char {
+    input_retry(input::BAD_NUMBER_MESSAGE.to_string())
+}
+
+/// Runs a file operation, keeping its error for basic_file_status; a failed operation
+/// hands back the default value
+fn file_op<T: Default>(op: impl FnOnce(&mut FileTable) -> Result<T, FileError>) -> T {
+    let mut rt = runtime();
+    let result = op(&mut rt.files);
+    match result {
+        Ok(value) => {
+            rt.file_error = None;
+            value
+        }
+        Err(error) => {
+            rt.file_error = Some(error);
+            T::default()
+        }
+    }
+}
+
+/// Status of the last file operation: 0 if it succeeded, else its `FileErrorKind` code
+#[no_mangle]
+pub extern "C" fn basic_file_status() -> i32 {
+    runtime().file_error.as_ref().map_or(0, |error| error.kind.code())
+}
+
+/// Message of the last file operation's error
+#[no_mangle]
+pub extern "C" fn basic_file_error() -> *mut c_char {
+    let message = runtime().file_error.as_ref().map(|error| error.message.clone()).unwrap_or_default();
+    into_c_string(message)
+}
+
+/// Restricts OPEN to files inside `dir`
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_sandbox(dir: *const c_char) {
+    runtime().files.set_sandbox(Some(c_str(dir).into_owned().into()));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_open(name: *const c_char, mode: i32, number: f64, record_len: f64) {
+    let name = c_str(name);
+    let mode = FileMode::from_code(mode).unwrap_or(FileMode::Input);
+    file_op(|files| files.open(number, &name, mode, record_len))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_close(number: f64) {
+    file_op(|files| files.close(number))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_close_all() {
+    file_op(|files| files.close_all())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_print_string(number: f64, s: *const c_char) {
+    let s = c_str(s);
+    file_op(|files| files.print(number, &s))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_number(number: f64, n: f64) {
+    let text = format::print_number(n, &runtime().number_format);
+    file_op(|files| files.print(number, &text))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_double(number: f64, n: f64) {
+    let text = format::print_number(n, &runtime().number_format.double());
+    file_op(|files| files.print(number, &text))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_single(number: f64, n: f64) {
+    let text = format::print_number(n, &runtime().number_format.single());
+    file_op(|files| files.print(number, &text))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_comma(number: f64) {
+    file_op(|files| files.comma(number))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_tab(number: f64, column: f64) {
+    file_op(|files| files.tab(number, column as usize))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_print_newline(number: f64) {
+    file_op(|files| files.newline(number))
+}
+
+/// Writes a WRITE # value: a string in quotes
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_write_string(number: f64, s: *const c_char) {
+    let s = c_str(s);
+    file_op(|files| files.print(number, &files::quote(&s)))
+}
+
+/// Writes a WRITE # value: a number without the spaces PRINT puts around it
+#[no_mangle]
+pub extern "C" fn basic_file_write_number(number: f64, n: f64) {
+    let text = format::number_text(n, &runtime().number_format);
+    file_op(|files| files.print(number, &text))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_input_string(number: f64) -> *mut c_char {
+    into_c_string(file_op(|files| files.input_field(number)))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_input_number(number: f64) -> f64 {
+    file_op(|files| files.input_number(number))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_line_input(number: f64) -> *mut c_char {
+    into_c_string(file_op(|files| files.line_input(number)))
+}
+
+/// EOF(n): 1 when a file open for INPUT has nothing left to read, else 0
+#[no_mangle]
+pub extern "C" fn basic_file_eof(number: f64) -> f64 {
+    if file_op(|files| files.eof(number)) { 1.0 } else { 0.0 }
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_lof(number: f64) -> f64 {
+    file_op(|files| files.lof(number))
+}
+
+/// Adds a `width AS name` of a FIELD statement; basic_file_field binds them all
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_field_add(width: f64, name: *const c_char) {
+    let name = c_str(name).into_owned();
+    runtime().fields.push((width, name));
+}
+
+/// FIELD #number with the fields added since the last FIELD. The generated code then
+/// reads each variable back with basic_file_field_value.
+#[no_mangle]
+pub extern "C" fn basic_file_field(number: f64) {
+    let fields = std::mem::take(&mut runtime().fields);
+    file_op(|files| files.field(number, &fields))
+}
+
+/// The value of a variable FIELD bound to file `number`, or `current` when it is not
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_field_value(number: f64, name: *const c_char, current: *const c_char) -> *const c_char {
+    let name = c_str(name);
+    let value = runtime().files.fields(number).ok()
+        .and_then(|fields| fields.into_iter().find(|(field, _)| *field == name))
+        .map(|(_, value)| value);
+    match value {
+        Some(value) => into_c_string(value),
+        None => current,
+    }
+}
+
+/// LSET (`right` 0) or RSET (`right` 1): the variable's new value
+#[no_mangle]
+pub unsafe extern "C" fn basic_file_set_field(name: *const c_char, current: *const c_char, value: *const c_char, right: i32) -> *mut c_char {
+    let (name, current, value) = (c_str(name), c_str(current), c_str(value));
+    into_c_string(runtime().files.set_field(&name, &current, &value, right != 0))
+}
+
+/// GET #number, with a record number when `has_record` is 1
+#[no_mangle]
+pub extern "C" fn basic_file_get(number: f64, record: f64, has_record: i32) {
+    file_op(|files| files.get(number, (has_record != 0).then_some(record)))
+}
+
+#[no_mangle]
+pub extern "C" fn basic_file_put(number: f64, record: f64, has_record: i32) {
+    file_op(|files| files.put(number, (has_record != 0).then_some(record)))
+}
+
+/// MKI$(n), or null when n overflows; the generated code raises the error
+#[no_mangle]
+pub extern "C" fn basic_mki(n: f64) -> *mut c_char {
+    mbf::mki(n).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+#[no_mangle]
+pub extern "C" fn basic_mks(n: f64) -> *mut c_char {
+    mbf::mks(n).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+#[no_mangle]
+pub extern "C" fn basic_mkd(n: f64) -> *mut c_char {
+    mbf::mkd(n).map_or(std::ptr::null_mut(), into_c_string)
+}
+
+/// CVI(s), or NaN when s is too short; the generated code raises the error
+#[no_mangle]
+pub unsafe extern "C" fn basic_cvi(s: *const c_char) -> f64 {
+    mbf::cvi(&c_str(s)).unwrap_or(f64::NAN)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_cvs(s: *const c_char) -> f64 {
+    mbf::cvs(&c_str(s)).unwrap_or(f64::NAN)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_cvd(s: *const c_char) -> f64 {
+    mbf::cvd(&c_str(s)).unwrap_or(f64::NAN)
+}
+
+/// Adds a number to the values of the next basic_print_using
+#[no_mangle]
+pub extern "C" fn basic_using_number(n: f64) {
+    runtime().using_values.push(UsingValue::Number(n));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn basic_using_string(s: *const c_char) {
+    let s = c_str(s).into_owned();
+    runtime().using_values.push(UsingValue::String(s));
+}
+
+/// The values added since the last call laid out with `format`; "" on an error, which
+/// basic_using_status and basic_using_error then report
+#[no_mangle]
+pub unsafe extern "C" fn basic_print_using(format: *const c_char) -> *mut c_char {
+    let format = c_str(format);
+    let mut rt = runtime();
+    let values = std::mem::take(&mut rt.using_values);
+    let result = using::format_using(&format, &values);
+    rt.using_error = result.as_ref().err().cloned();
+    into_c_string(result.unwrap_or_default())
+}
+
+/// 0 if the last basic_print_using succeeded, else its error's code
+#[no_mangle]
+pub extern "C" fn basic_using_status() -> i32 {
+    runtime().using_error.as_ref().map_or(0, UsingError::code)
+}
+
+#[no_mangle]
+pub extern "C" fn basic_using_error() -> *mut c_char {
+    let message = runtime().using_error.as_ref().map(|error| error.to_string()).unwrap_or_default();
+    into_c_string(message)
+}
+
+/// Trace output for `basic-compiler --trace`; does not move the PRINT cursor
+#[no_mangle]
+pub extern "C" fn basic_trace_line(line: i32) {
+    write_out(&format!("Executing line {}\n", line));
+}
+
+unsafe fn array_dimensions(dims: *const i64, rank: i32) -> Vec<usize> {
+    std::slice::from_raw_parts(dims, rank.max(0) as usize).iter().map(|&size| size as usize).collect()
+}
+
+/// Adds array `name` to the operands of the MAT statement being run. `dims` holds the
+/// elements in each of its `rank` dimensions, with subscripts from `offset`.
+#[no_mangle]
+pub unsafe extern "C" fn basic_mat_push(data: *const f64, dims: *const i64, rank: i32, offset: i32, name: *const c_char) {
+    let dimensions = array_dimensions(dims, rank);
+    let data = std::slice::from_raw_parts(data, dimensions.iter().product());
+    let mut rt = runtime();
+    if rt.matrix_error.is_some() {
+        return;
+    }
+    match Matrix::from_array(&c_str(name), data, &dimensions, offset as usize) {
+        Ok(matrix) => rt.matrices.push(matrix),
+        Err(error) => rt.matrix_error = Some(error),
+    }
+}
+
+/// Works out a MAT assignment, a `matrix::Operation`, from the operands pushed, with the
+/// first `bound_count` of the bounds
+#[no_mangle]
+pub extern "C" fn basic_mat_compute(op: i32, scalar: f64, bound1: f64, bound2: f64, bound_count: i32) {
+    let mut rt = runtime();
+    let operands = std::mem::take(&mut rt.matrices);
+    if rt.matrix_error.is_some() {
+        return;
+    }
+    let result = (|| {
+        let op = Operation::from_code(op).ok_or_else(|| MatrixError::new(MatrixErrorKind::IllegalFunctionCall, "unknown MAT operation"))?;
+        // Only two bounds are passed, as more is an error whatever they are
+        let bounds = match bound_count {
+            0 => None,
+            n => {
+                let values: Vec<f64> = [bound1, bound2].into_iter().chain(std::iter::repeat(0.0)).take(n as usize).collect();
+                Some(matrix::bounds(&values)?)
+            }
+        };
+        if op == Operation::Inverse {
+            rt.determinant = 0.0;
+        }
+        matrix::apply(op, &operands, scalar, bounds)
+    })();
+    match result {
+        Ok((result, determinant)) => {
+            if let Some(determinant) = determinant {
+                rt.determinant = determinant;
+            }
+            rt.matrix_result = Some(result);
+        }
+        Err(error) => rt.matrix_error = Some(error),
+    }
+}
+
+/// Fills the MAT result with numbers from the DATA tables, for MAT READ. `position` is
+/// the index of the next DATA value, which this moves on.
+#[no_mangle]
+pub unsafe extern "C" fn basic_mat_read(numbers: *const f64, kinds: *const i8, count: i32, position: *mut i32) {
+    let mut rt = runtime();
+    if rt.matrix_error.is_some() {
+        return;
+    }
+    let Some(mut result) = rt.matrix_result.take() else {
+        return;
+    };
+    for value in result.values.iter_mut() {
+        let next = *position;
+        if next >= count {
+            rt.matrix_error = Some(MatrixError { kind: MatrixErrorKind::OutOfData, message: "Out of DATA values".to_string() });
+            return;
+        }
+        *position = next + 1;
+        if *kinds.add(next as usize) != 0 {
+            rt.matrix_error = Some(MatrixError { kind: MatrixErrorKind::TypeMismatch, message: "Type mismatch in READ".to_string() });
+            return;
+        }
+        *value = *numbers.add(next as usize);
+    }
+    rt.matrix_result = Some(result);
+}
+
+/// Stores the MAT result in array `name`, which has room for `capacity` elements, rounded
+/// to a `Precision` code or, for an integer array, to whole numbers of `integer_bits`
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn basic_mat_store(data: *mut f64, dims: *mut i64, rank: i32, offset: i32, capacity: i64,
+                                         name: *const c_char, precision: i32, integer_bits: i32) {
+    let mut rt = runtime();
+    if rt.matrix_error.is_some() {
+        return;
+    }
+    let Some(mut result) = rt.matrix_result.take() else {
+        return;
+    };
+    let name = c_str(name);
+    let stored = (|| {
+        for value in result.values.iter_mut() {
+            *value = match integer_bits {
+                0 => Precision::from_code(precision).round(*value),
+                bits => precision::round_integer(*value, bits as u32).ok_or_else(|| MatrixError {
+                    kind: MatrixErrorKind::Overflow,
+                    message: precision::integer_overflow_message(&name, bits as u32),
+                })?,
+            };
+        }
+        let mut dimensions = array_dimensions(dims, rank);
+        let data = std::slice::from_raw_parts_mut(data, capacity.max(0) as usize);
+        result.store(&name, data, &mut dimensions, offset as usize)?;
+        for (i, size) in dimensions.into_iter().enumerate() {
+            *dims.add(i) = size as i64;
+        }
+        Ok(())
+    })();
+    if let Err(error) = stored {
+        rt.matrix_error = Some(error);
+    }
+}
+
+/// Prints the array last pushed, for MAT PRINT; `packed` for one followed by a semicolon
+#[no_mangle]
+pub extern "C" fn basic_mat_print(packed: i32, double: i32) {
+    let mut rt = runtime();
+    let Some(matrix) = rt.matrices.pop() else {
+        return;
+    };
+    let format = if double != 0 { rt.number_format.double() } else { rt.number_format };
+    let text = matrix::print_text(&matrix, packed != 0, &format, &mut rt.cursor);
+    write_out(&text);
+}
+
+/// Status of the MAT statement just run: 0 if it succeeded, else its `MatrixErrorKind` code
+#[no_mangle]
+pub extern "C" fn basic_mat_status() -> i32 {
+    runtime().matrix_error.as_ref().map_or(0, |error| error.kind.code())
+}
+
+/// Message of the MAT statement's error, which this clears for the next one
+#[no_mangle]
+pub extern "C" fn basic_mat_error() -> *mut c_char {
+    let mut rt = runtime();
+    rt.matrices.clear();
+    rt.matrix_result = None;
+    let message = rt.matrix_error.take().map(|error| error.message).unwrap_or_default();
+    into_c_string(message)
+}
+
+/// DET: the determinant of the array last inverted, or 0 if that failed
+#[no_mangle]
+pub extern "C" fn basic_mat_det() -> f64 {
+    runtime().determinant
+}
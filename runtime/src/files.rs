@@ -0,0 +1,609 @@
+//! Files for OPEN, CLOSE, PRINT #, WRITE #, INPUT #, LINE INPUT #, EOF and LOF, and random
+//! files of fixed-length records for FIELD, GET, PUT, LSET and RSET
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::cursor::PrintCursor;
+use crate::input;
+use crate::strings::{from_bytes, to_bytes};
+
+/// Highest file number OPEN accepts; file numbers start at 1
+pub const MAX_FILE_NUMBER: usize = 255;
+
+/// Record length of a random file OPEN gives no LEN=
+pub const DEFAULT_RECORD_LEN: usize = 128;
+
+/// Longest record LEN= accepts
+pub const MAX_RECORD_LEN: usize = 32767;
+
+/// Highest record number GET and PUT accept; records start at 1
+pub const MAX_RECORD_NUMBER: usize = 16_777_215;
+
+/// How OPEN opens a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMode {
+    Input,   // Read from the start; the file must exist
+    Output,  // Created, or emptied if it exists
+    Append,  // Created if needed; writes go to the end
+    Random,  // Created if needed; GET and PUT read and write whole records
+}
+
+impl FileMode {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            FileMode::Input => "INPUT",
+            FileMode::Output => "OUTPUT",
+            FileMode::Append => "APPEND",
+            FileMode::Random => "RANDOM",
+        }
+    }
+
+    /// The mode's number in the compiled code's calls into the runtime
+    pub fn code(&self) -> i32 {
+        match self {
+            FileMode::Input => 0,
+            FileMode::Output => 1,
+            FileMode::Append => 2,
+            FileMode::Random => 3,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<FileMode> {
+        match code {
+            0 => Some(FileMode::Input),
+            1 => Some(FileMode::Output),
+            2 => Some(FileMode::Append),
+            3 => Some(FileMode::Random),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
+
+/// What went wrong with a file operation. Each kind's summary starts its message, and the
+/// interpreter and the code generator both map the kind to the condition ERR reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileErrorKind {
+    BadFileNumber,
+    FileNotFound,
+    BadFileMode,
+    FileAlreadyOpen,
+    InputPastEnd,
+    TypeMismatch,
+    PathAccess,
+    FieldOverflow,
+    BadRecordNumber,
+}
+
+impl FileErrorKind {
+    pub const ALL: [FileErrorKind; 9] = [
+        FileErrorKind::BadFileNumber,
+        FileErrorKind::FileNotFound,
+        FileErrorKind::BadFileMode,
+        FileErrorKind::FileAlreadyOpen,
+        FileErrorKind::InputPastEnd,
+        FileErrorKind::TypeMismatch,
+        FileErrorKind::PathAccess,
+        FileErrorKind::FieldOverflow,
+        FileErrorKind::BadRecordNumber,
+    ];
+
+    pub fn summary(&self) -> &'static str {
+        match self {
+            FileErrorKind::BadFileNumber => "Bad file number",
+            FileErrorKind::FileNotFound => "File not found",
+            FileErrorKind::BadFileMode => "Bad file mode",
+            FileErrorKind::FileAlreadyOpen => "File already open",
+            FileErrorKind::InputPastEnd => "Input past end",
+            FileErrorKind::TypeMismatch => "Type mismatch",
+            FileErrorKind::PathAccess => "Path/File access error",
+            FileErrorKind::FieldOverflow => "Field overflow",
+            FileErrorKind::BadRecordNumber => "Bad record number",
+        }
+    }
+
+    /// The kind's status code in the compiled code's calls into the runtime; 0 is success
+    pub fn code(&self) -> i32 {
+        Self::ALL.iter().position(|kind| kind == self).map_or(0, |i| i as i32 + 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    pub kind: FileErrorKind,
+    pub message: String,
+}
+
+impl FileError {
+    fn new(kind: FileErrorKind, detail: impl fmt::Display) -> Self {
+        FileError { kind, message: format!("{}: {}", kind.summary(), detail) }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+enum Handle {
+    Reader {
+        reader: BufReader<File>,
+        rest: Option<String>,  // The part of the current line INPUT # has not read yet
+    },
+    Writer {
+        writer: BufWriter<File>,
+        cursor: PrintCursor,
+    },
+    Random(RandomFile),
+}
+
+struct RandomFile {
+    file: File,
+    buffer: Vec<u8>,     // The record GET read or PUT will write; its length is the LEN=
+    next: usize,         // Index of the record a GET or PUT without a number uses
+    fields: Vec<Field>,  // The string variables FIELD bound to slices of the buffer
+    short_read: bool,    // The last GET could not read a whole record
+}
+
+struct Field {
+    name: String,
+    offset: usize,
+    width: usize,
+}
+
+struct OpenFile {
+    mode: FileMode,
+    handle: Handle,
+}
+
+/// The program's open files, by file number
+#[derive(Default)]
+pub struct FileTable {
+    files: BTreeMap<usize, OpenFile>,
+    sandbox: Option<PathBuf>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable { files: BTreeMap::new(), sandbox: None }
+    }
+
+    /// Restricts OPEN to relative paths inside `dir`, or lifts the restriction
+    pub fn set_sandbox(&mut self, dir: Option<PathBuf>) {
+        self.sandbox = dir;
+    }
+
+    /// The path OPEN uses for a file name
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, FileError> {
+        let path = Path::new(name);
+        let Some(dir) = &self.sandbox else {
+            return Ok(path.to_path_buf());
+        };
+        let inside = path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(FileError::new(FileErrorKind::PathAccess, format!("{} is outside {}", name, dir.display())));
+        }
+        Ok(dir.join(path))
+    }
+
+    /// Opens a file. `record_len` is the LEN= of a random file; other modes ignore it.
+    pub fn open(&mut self, number: f64, name: &str, mode: FileMode, record_len: f64) -> Result<(), FileError> {
+        let number = file_number(number)?;
+        if self.files.contains_key(&number) {
+            return Err(FileError::new(FileErrorKind::FileAlreadyOpen, format!("#{}", number)));
+        }
+        let record_len = record_len.trunc();
+        if mode == FileMode::Random && !(1.0..=MAX_RECORD_LEN as f64).contains(&record_len) {
+            return Err(FileError::new(FileErrorKind::FieldOverflow, format!("record length {} for #{}", record_len, number)));
+        }
+        let path = self.resolve(name)?;
+        let opened = match mode {
+            FileMode::Input => File::open(&path),
+            FileMode::Output => File::create(&path),
+            FileMode::Append => OpenOptions::new().append(true).create(true).open(&path),
+            FileMode::Random => OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path),
+        };
+        let file = opened.map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => FileError::new(FileErrorKind::FileNotFound, name),
+            _ => FileError::new(FileErrorKind::PathAccess, format!("{}: {}", name, e)),
+        })?;
+        let handle = match mode {
+            FileMode::Input => Handle::Reader { reader: BufReader::new(file), rest: None },
+            FileMode::Output | FileMode::Append => Handle::Writer { writer: BufWriter::new(file), cursor: PrintCursor::new() },
+            FileMode::Random => Handle::Random(RandomFile { file, buffer: vec![0; record_len as usize], next: 0, fields: Vec::new(), short_read: false }),
+        };
+        self.files.insert(number, OpenFile { mode, handle });
+        Ok(())
+    }
+
+    /// Closes one file, writing out anything still buffered
+    pub fn close(&mut self, number: f64) -> Result<(), FileError> {
+        let number = file_number(number)?;
+        match self.files.remove(&number) {
+            Some(file) => flush(file),
+            None => Err(not_open(number)),
+        }
+    }
+
+    pub fn close_all(&mut self) -> Result<(), FileError> {
+        let mut result = Ok(());
+        while let Some((_, file)) = self.files.pop_first() {
+            result = result.and(flush(file));
+        }
+        result
+    }
+
+    /// Writes PRINT # text, moving the file's print cursor
+    pub fn print(&mut self, number: f64, text: &str) -> Result<(), FileError> {
+        let (writer, cursor) = self.writer(number)?;
+        cursor.advance(text);
+        writer.write_all(text.as_bytes()).map_err(write_error)
+    }
+
+    /// Pads to the next print zone, for a comma in PRINT #
+    pub fn comma(&mut self, number: f64) -> Result<(), FileError> {
+        let (writer, cursor) = self.writer(number)?;
+        let spaces = cursor.comma();
+        writer.write_all(" ".repeat(spaces).as_bytes()).map_err(write_error)
+    }
+
+    pub fn tab(&mut self, number: f64, column: usize) -> Result<(), FileError> {
+        let (writer, cursor) = self.writer(number)?;
+        let spaces = cursor.tab(column);
+        writer.write_all(" ".repeat(spaces).as_bytes()).map_err(write_error)
+    }
+
+    pub fn newline(&mut self, number: f64) -> Result<(), FileError> {
+        let (writer, cursor) = self.writer(number)?;
+        cursor.newline();
+        writer.write_all(b"\n").map_err(write_error)
+    }
+
+    /// Reads the next comma-separated value for INPUT #, going on to the next line
+    /// when the current one is used up
+    pub fn input_field(&mut self, number: f64) -> Result<String, FileError> {
+        let key = file_number(number)?;
+        let (reader, rest) = self.reader(number)?;
+        let line = match rest.take() {
+            Some(line) => line,
+            None => read_line(reader).map_err(write_error)?.ok_or_else(|| past_end(key))?,
+        };
+        let (field, remainder) = next_field(&line);
+        *rest = remainder.map(str::to_string);
+        Ok(field)
+    }
+
+    /// Reads a value for a numeric variable in INPUT #; an empty value reads as 0
+    pub fn input_number(&mut self, number: f64) -> Result<f64, FileError> {
+        let field = self.input_field(number)?;
+        if field.is_empty() {
+            return Ok(0.0);
+        }
+        input::parse_number(&field).ok_or_else(|| {
+            FileError::new(FileErrorKind::TypeMismatch, format!("file #{} has \"{}\" where a number was expected", file_number(number).unwrap_or(0), field))
+        })
+    }
+
+    /// Reads the rest of the current line, or the next whole line, for LINE INPUT #
+    pub fn line_input(&mut self, number: f64) -> Result<String, FileError> {
+        let key = file_number(number)?;
+        let (reader, rest) = self.reader(number)?;
+        match rest.take() {
+            Some(line) => Ok(line),
+            None => read_line(reader).map_err(write_error)?.ok_or_else(|| past_end(key)),
+        }
+    }
+
+    /// True once a file open for INPUT has nothing left to read, or once GET could not
+    /// read a whole record of a random file
+    pub fn eof(&mut self, number: f64) -> Result<bool, FileError> {
+        if let Ok(random) = self.random(number) {
+            return Ok(random.short_read);
+        }
+        let (reader, rest) = self.reader(number)?;
+        if rest.is_some() {
+            return Ok(false);
+        }
+        Ok(reader.fill_buf().map_err(write_error)?.is_empty())
+    }
+
+    /// Length of the file in bytes, including anything PRINT # has buffered
+    pub fn lof(&mut self, number: f64) -> Result<f64, FileError> {
+        let key = file_number(number)?;
+        let file = self.files.get_mut(&key).ok_or_else(|| not_open(key))?;
+        let metadata = match &mut file.handle {
+            Handle::Reader { reader, .. } => reader.get_ref().metadata(),
+            Handle::Writer { writer, .. } => writer.flush().and_then(|_| writer.get_ref().metadata()),
+            Handle::Random(random) => random.file.metadata(),
+        };
+        metadata.map(|m| m.len() as f64).map_err(write_error)
+    }
+
+    /// Binds string variables to slices of a random file's record buffer, for FIELD. The
+    /// slices follow each other from the start of the record. A variable bound before, to
+    /// this file or another, moves to its new slice.
+    pub fn field(&mut self, number: f64, fields: &[(f64, String)]) -> Result<(), FileError> {
+        let key = file_number(number)?;
+        let record_len = self.random(number)?.buffer.len();
+        let mut offset = 0;
+        let mut bound = Vec::new();
+        for (width, name) in fields {
+            let width = width.trunc();
+            if width < 0.0 || offset as f64 + width > record_len as f64 {
+                return Err(FileError::new(FileErrorKind::FieldOverflow, format!("{} AS {} goes past the {} byte record of #{}", width, name, record_len, key)));
+            }
+            bound.push(Field { name: name.clone(), offset, width: width as usize });
+            offset += width as usize;
+        }
+        for file in self.files.values_mut() {
+            if let Handle::Random(random) = &mut file.handle {
+                random.fields.retain(|field| !bound.iter().any(|b| b.name == field.name));
+            }
+        }
+        self.random(number)?.fields.extend(bound);
+        Ok(())
+    }
+
+    /// Each variable FIELD bound to this random file, with its slice of the record buffer
+    pub fn fields(&mut self, number: f64) -> Result<Vec<(String, String)>, FileError> {
+        let random = self.random(number)?;
+        Ok(random.fields.iter().map(|field| (field.name.clone(), from_bytes(&random.buffer[field.offset..field.offset + field.width]))).collect())
+    }
+
+    /// LSET and RSET: `value` cut or padded with spaces on the right (LSET) or left (RSET).
+    /// A variable FIELD bound to a record buffer takes its field's width, and the result
+    /// is written into the buffer; any other variable keeps the length of its `current`
+    /// value. Returns the variable's new value.
+    pub fn set_field(&mut self, name: &str, current: &str, value: &str, right: bool) -> String {
+        let slot = self.files.values_mut().find_map(|file| match &mut file.handle {
+            Handle::Random(random) => random.fields.iter()
+                .find(|field| field.name == name)
+                .map(|field| &mut random.buffer[field.offset..field.offset + field.width]),
+            _ => None,
+        });
+        let width = slot.as_ref().map_or_else(|| current.chars().count(), |slot| slot.len());
+        let mut bytes = to_bytes(value);
+        bytes.truncate(width);
+        let padding = vec![b' '; width - bytes.len()];
+        let bytes = if right { [padding, bytes].concat() } else { [bytes, padding].concat() };
+        if let Some(slot) = slot {
+            slot.copy_from_slice(&bytes);
+        }
+        from_bytes(&bytes)
+    }
+
+    /// GET: reads a record of a random file into its buffer. With no record number, reads
+    /// the one after the last GET or PUT. Past the end of the file the buffer holds zeros.
+    pub fn get(&mut self, number: f64, record: Option<f64>) -> Result<(), FileError> {
+        let random = self.seek_record(number, record)?;
+        let mut read = 0;
+        while read < random.buffer.len() {
+            match random.file.read(&mut random.buffer[read..]).map_err(write_error)? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        random.short_read = read < random.buffer.len();
+        random.buffer[read..].fill(0);
+        random.next += 1;
+        Ok(())
+    }
+
+    /// PUT: writes a random file's buffer to a record, by default the one after the last
+    /// GET or PUT
+    pub fn put(&mut self, number: f64, record: Option<f64>) -> Result<(), FileError> {
+        let random = self.seek_record(number, record)?;
+        random.file.write_all(&random.buffer).map_err(write_error)?;
+        random.next += 1;
+        Ok(())
+    }
+
+    /// Moves a random file to the start of a record for GET or PUT
+    fn seek_record(&mut self, number: f64, record: Option<f64>) -> Result<&mut RandomFile, FileError> {
+        let random = self.random(number)?;
+        if let Some(record) = record {
+            let record = record.trunc();
+            if record < 1.0 || record > MAX_RECORD_NUMBER as f64 {
+                return Err(FileError::new(FileErrorKind::BadRecordNumber, record));
+            }
+            random.next = record as usize - 1;
+        }
+        let position = (random.next * random.buffer.len()) as u64;
+        random.file.seek(SeekFrom::Start(position)).map_err(write_error)?;
+        Ok(random)
+    }
+
+    fn writer(&mut self, number: f64) -> Result<(&mut BufWriter<File>, &mut PrintCursor), FileError> {
+        let key = file_number(number)?;
+        let file = self.files.get_mut(&key).ok_or_else(|| not_open(key))?;
+        match &mut file.handle {
+            Handle::Writer { writer, cursor } => Ok((writer, cursor)),
+            _ => Err(wrong_mode(key, file.mode)),
+        }
+    }
+
+    fn reader(&mut self, number: f64) -> Result<(&mut BufReader<File>, &mut Option<String>), FileError> {
+        let key = file_number(number)?;
+        let file = self.files.get_mut(&key).ok_or_else(|| not_open(key))?;
+        match &mut file.handle {
+            Handle::Reader { reader, rest } => Ok((reader, rest)),
+            _ => Err(wrong_mode(key, file.mode)),
+        }
+    }
+
+    fn random(&mut self, number: f64) -> Result<&mut RandomFile, FileError> {
+        let key = file_number(number)?;
+        let file = self.files.get_mut(&key).ok_or_else(|| not_open(key))?;
+        match &mut file.handle {
+            Handle::Random(random) => Ok(random),
+            _ => Err(wrong_mode(key, file.mode)),
+        }
+    }
+}
+
+/// Text WRITE # produces for a string: in quotes, so INPUT # reads it back whole
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
+fn file_number(number: f64) -> Result<usize, FileError> {
+    let n = number.trunc();
+    if n < 1.0 || n > MAX_FILE_NUMBER as f64 {
+        return Err(FileError::new(FileErrorKind::BadFileNumber, number));
+    }
+    Ok(n as usize)
+}
+
+fn not_open(number: usize) -> FileError {
+    FileError::new(FileErrorKind::BadFileNumber, format!("#{} is not open", number))
+}
+
+fn wrong_mode(number: usize, mode: FileMode) -> FileError {
+    FileError::new(FileErrorKind::BadFileMode, format!("#{} is open for {}", number, mode))
+}
+
+fn past_end(number: usize) -> FileError {
+    FileError::new(FileErrorKind::InputPastEnd, format!("file #{}", number))
+}
+
+fn write_error(e: io::Error) -> FileError {
+    FileError::new(FileErrorKind::PathAccess, e)
+}
+
+fn flush(file: OpenFile) -> Result<(), FileError> {
+    match file.handle {
+        Handle::Writer { mut writer, .. } => writer.flush().map_err(write_error),
+        Handle::Reader { .. } | Handle::Random(_) => Ok(()),
+    }
+}
+
+/// The next line without its line ending, or None at the end of the file
+fn read_line(reader: &mut BufReader<File>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let trimmed = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+/// Splits the first value off a line INPUT # is reading. A value in quotes may hold
+/// commas; anything between its closing quote and the next comma is dropped.
+fn next_field(line: &str) -> (String, Option<&str>) {
+    let line = line.trim_start();
+    let (field, after) = match line.strip_prefix('"') {
+        Some(quoted) => match quoted.find('"') {
+            Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+            None => (quoted.to_string(), ""),
+        },
+        None => {
+            let end = line.find(',').unwrap_or(line.len());
+            (line[..end].trim_end().to_string(), &line[end..])
+        }
+    };
+    let rest = after.find(',').map(|comma| &after[comma + 1..]);
+    (field, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_field() {
+        assert_eq!(next_field(" 12, \"A, B\" ,x"), ("12".to_string(), Some(" \"A, B\" ,x")));
+        assert_eq!(next_field(" \"A, B\" ,x"), ("A, B".to_string(), Some("x")));
+        assert_eq!(next_field("x"), ("x".to_string(), None));
+        assert_eq!(next_field("1,"), ("1".to_string(), Some("")));
+    }
+
+    #[test]
+    fn test_sandbox_paths() {
+        let mut files = FileTable::new();
+        assert_eq!(files.resolve("/tmp/x").unwrap(), PathBuf::from("/tmp/x"));
+        files.set_sandbox(Some(PathBuf::from("data")));
+        assert_eq!(files.resolve("scores.txt").unwrap(), PathBuf::from("data/scores.txt"));
+        assert_eq!(files.resolve("levels/1.txt").unwrap(), PathBuf::from("data/levels/1.txt"));
+        for name in ["../x", "/etc/passwd", "a/../../x", ""] {
+            assert_eq!(files.resolve(name).unwrap_err().kind, FileErrorKind::PathAccess, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        let dir = std::env::temp_dir().join(format!("basic_runtime_files_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut files = FileTable::new();
+        files.set_sandbox(Some(dir.clone()));
+
+        files.open(1.0, "t.txt", FileMode::Output, 0.0).unwrap();
+        assert_eq!(files.open(1.0, "t.txt", FileMode::Output, 0.0).unwrap_err().kind, FileErrorKind::FileAlreadyOpen);
+        files.print(1.0, &quote("HI, THERE")).unwrap();
+        files.print(1.0, ",42").unwrap();
+        files.newline(1.0).unwrap();
+        files.print(1.0, "LAST").unwrap();
+        files.newline(1.0).unwrap();
+        assert_eq!(files.lof(1.0).unwrap(), 20.0);
+        assert_eq!(files.input_field(1.0).unwrap_err().kind, FileErrorKind::BadFileMode);
+        files.close(1.0).unwrap();
+
+        files.open(2.0, "t.txt", FileMode::Input, 0.0).unwrap();
+        assert_eq!(files.input_field(2.0).unwrap(), "HI, THERE");
+        assert!(!files.eof(2.0).unwrap());
+        assert_eq!(files.input_number(2.0).unwrap(), 42.0);
+        assert_eq!(files.line_input(2.0).unwrap(), "LAST");
+        assert!(files.eof(2.0).unwrap());
+        assert_eq!(files.line_input(2.0).unwrap_err().kind, FileErrorKind::InputPastEnd);
+        files.close_all().unwrap();
+
+        assert_eq!(files.close(2.0).unwrap_err().kind, FileErrorKind::BadFileNumber);
+        assert_eq!(files.open(0.0, "t.txt", FileMode::Input, 0.0).unwrap_err().kind, FileErrorKind::BadFileNumber);
+        assert_eq!(files.open(1.0, "missing.txt", FileMode::Input, 0.0).unwrap_err().kind, FileErrorKind::FileNotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_random_records() {
+        let dir = std::env::temp_dir().join(format!("basic_runtime_random_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut files = FileTable::new();
+        files.set_sandbox(Some(dir.clone()));
+
+        assert_eq!(files.open(1.0, "r.dat", FileMode::Random, 0.0).unwrap_err().kind, FileErrorKind::FieldOverflow);
+        files.open(1.0, "r.dat", FileMode::Random, 6.0).unwrap();
+        assert_eq!(files.field(1.0, &[(4.0, "A$".to_string()), (3.0, "B$".to_string())]).unwrap_err().kind, FileErrorKind::FieldOverflow);
+        files.field(1.0, &[(4.0, "A$".to_string()), (2.0, "B$".to_string())]).unwrap();
+        assert_eq!(files.set_field("A$", "", "HELLO", false), "HELL");
+        assert_eq!(files.set_field("B$", "", "X", true), " X");
+        assert_eq!(files.set_field("C$", "ABC", "X", false), "X  ");
+        files.put(1.0, Some(2.0)).unwrap();
+        assert_eq!(files.lof(1.0).unwrap(), 12.0);
+        assert_eq!(files.put(1.0, Some(0.0)).unwrap_err().kind, FileErrorKind::BadRecordNumber);
+        assert_eq!(files.print(1.0, "X").unwrap_err().kind, FileErrorKind::BadFileMode);
+
+        files.get(1.0, Some(1.0)).unwrap();
+        assert_eq!(files.fields(1.0).unwrap()[0], ("A$".to_string(), from_bytes(&[0; 4])));
+        assert!(!files.eof(1.0).unwrap());
+        files.get(1.0, None).unwrap();
+        assert_eq!(files.fields(1.0).unwrap(), [("A$".to_string(), "HELL".to_string()), ("B$".to_string(), " X".to_string())]);
+        assert!(!files.eof(1.0).unwrap());
+        files.get(1.0, None).unwrap();
+        assert!(files.eof(1.0).unwrap());
+        files.field(1.0, &[(6.0, "W$".to_string())]).unwrap();
+        files.get(1.0, Some(2.0)).unwrap();
+        assert_eq!(files.fields(1.0).unwrap()[2], ("W$".to_string(), "HELL X".to_string()));
+        files.close_all().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
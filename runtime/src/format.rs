@@ -0,0 +1,149 @@
+//! Number formatting used by PRINT and STR$
+
+/// How a dialect writes numbers: rounded to a number of significant digits, without a 0
+/// before the point, and with an exponent when the number is too big or too small to
+/// show in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    /// Significant digits shown: 6 or 9 for single precision BASICs, 16 for double
+    pub digits: usize,
+
+    /// Smallest power of ten shown without an exponent: -2 prints .01 but 1E-03.
+    /// Numbers of `digits` or more digits before the point always get an exponent.
+    pub min_fixed_exponent: i32,
+
+    /// STR$ keeps the space a positive number has in place of a sign, as Microsoft BASICs do
+    pub str_sign_space: bool,
+}
+
+impl NumberFormat {
+    /// Double precision, which BasicRS computes in
+    pub const DOUBLE: NumberFormat = NumberFormat {
+        digits: 16,
+        min_fixed_exponent: -3,
+        str_sign_space: false,
+    };
+
+    /// The format for double precision numbers, `A#`, in a dialect whose own are single:
+    /// the same, with all their digits
+    pub fn double(self) -> NumberFormat {
+        NumberFormat { digits: NumberFormat::DOUBLE.digits, ..self }
+    }
+
+    /// The format for single precision numbers, `A!`, in a dialect whose own are more
+    /// precise: the 7 digits GW-BASIC shows for them
+    pub fn single(self) -> NumberFormat {
+        NumberFormat { digits: self.digits.min(7), ..self }
+    }
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat::DOUBLE
+    }
+}
+
+/// A number's digits, with a minus sign when it is negative: 2.5, -.5, 1E+20
+pub fn number_text(n: f64, format: &NumberFormat) -> String {
+    if !n.is_finite() {
+        return n.to_string();
+    }
+    if n == 0.0 {
+        return "0".to_string();
+    }
+    let sign = if n < 0.0 { "-" } else { "" };
+    let scientific = format!("{:.*e}", format.digits.max(1) - 1, n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let digits = mantissa.replace('.', "");
+    let digits = digits.trim_end_matches('0');
+
+    if exponent < format.min_fixed_exponent || exponent >= format.digits as i32 {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}{}{}{}E{}{:02}", sign, first, point, rest, exponent_sign, exponent.abs());
+    }
+    if exponent < 0 {
+        return format!("{}.{}{}", sign, "0".repeat((-exponent - 1) as usize), digits);
+    }
+    let whole = exponent as usize + 1;
+    if digits.len() <= whole {
+        format!("{}{:0<width$}", sign, digits, width = whole)
+    } else {
+        format!("{}{}.{}", sign, &digits[..whole], &digits[whole..])
+    }
+}
+
+/// Text PRINT produces for a number.
+///
+/// In classic BASIC, positive numbers get both leading and trailing spaces.
+/// Negative numbers get only a trailing space (the minus sign serves as the leading char).
+pub fn print_number(n: f64, format: &NumberFormat) -> String {
+    if n >= 0.0 || n.is_nan() {
+        format!(" {} ", number_text(n, format))
+    } else {
+        format!("{} ", number_text(n, format))
+    }
+}
+
+/// Text STR$ produces for a number
+pub fn str_number(n: f64, format: &NumberFormat) -> String {
+    if format.str_sign_space && (n >= 0.0 || n.is_nan()) {
+        format!(" {}", number_text(n, format))
+    } else {
+        number_text(n, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: NumberFormat = NumberFormat { digits: 9, min_fixed_exponent: -2, str_sign_space: true };
+
+    #[test]
+    fn test_print_number() {
+        let double = NumberFormat::DOUBLE;
+        assert_eq!(print_number(5.0, &double), " 5 ");
+        assert_eq!(print_number(0.0, &double), " 0 ");
+        assert_eq!(print_number(-2.5, &double), "-2.5 ");
+        assert_eq!(print_number(1.0 / 3.0, &double), " .3333333333333333 ");
+        assert_eq!(print_number(1e15, &double), " 1000000000000000 ");
+        assert_eq!(print_number(1e16, &double), " 1E+16 ");
+        assert_eq!(print_number(0.1 + 0.2, &double), " .3 ");
+    }
+
+    #[test]
+    fn test_number_text() {
+        assert_eq!(number_text(0.5, &SINGLE), ".5");
+        assert_eq!(number_text(-0.5, &SINGLE), "-.5");
+        assert_eq!(number_text(1.0 / 3.0, &SINGLE), ".333333333");
+        assert_eq!(number_text(2.0 / 3.0, &SINGLE), ".666666667");
+        assert_eq!(number_text(123456789.0, &SINGLE), "123456789");
+        assert_eq!(number_text(1234567890.0, &SINGLE), "1.23456789E+09");
+        assert_eq!(number_text(1e20, &SINGLE), "1E+20");
+        assert_eq!(number_text(-1.5e-10, &SINGLE), "-1.5E-10");
+        assert_eq!(number_text(0.01, &SINGLE), ".01");
+        assert_eq!(number_text(0.001, &SINGLE), "1E-03");
+        assert_eq!(number_text(1e100, &SINGLE), "1E+100");
+        assert_eq!(number_text(100.0, &SINGLE), "100");
+        assert_eq!(number_text(99.999999999, &SINGLE), "100");
+        let six = NumberFormat { digits: 6, ..SINGLE };
+        assert_eq!(number_text(std::f64::consts::PI, &six), "3.14159");
+        assert_eq!(number_text(1e6, &six), "1E+06");
+        assert_eq!(number_text(999999.0, &six), "999999");
+        assert_eq!(number_text(1.0 / 3.0, &six.double()), ".3333333333333333");
+        assert_eq!(number_text(1.0 / 3.0, &NumberFormat::DOUBLE.single()), ".3333333");
+        assert_eq!(number_text(1.0 / 3.0, &six.single()), ".333333");
+    }
+
+    #[test]
+    fn test_str_number() {
+        let double = NumberFormat::DOUBLE;
+        assert_eq!(str_number(3.5, &double), "3.5");
+        assert_eq!(str_number(-7.0, &double), "-7");
+        assert_eq!(str_number(3.5, &SINGLE), " 3.5");
+        assert_eq!(str_number(-7.0, &SINGLE), "-7");
+    }
+}
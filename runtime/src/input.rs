@@ -0,0 +1,58 @@
+//! INPUT line parsing
+
+/// Number of times INPUT re-prompts before giving up with a runtime error
+pub const MAX_RETRIES: usize = 3;
+
+/// Printed when a line has the wrong number of values or a bad number
+pub const REDO_MESSAGE: &str = "?Redo from start";
+
+/// Prompt printed before reading a line
+pub fn prompt_text(prompt: Option<&str>) -> String {
+    match prompt {
+        Some(p) => format!("{}? ", p),
+        None => "? ".to_string(),
+    }
+}
+
+/// Splits an input line into its comma-separated values
+pub fn split_fields(line: &str) -> Vec<&str> {
+    line.trim().split(',').map(|part| part.trim()).collect()
+}
+
+/// Parses a value typed for a numeric variable
+pub fn parse_number(field: &str) -> Option<f64> {
+    field.trim().parse::<f64>().ok()
+}
+
+/// Error once the retries run out on a line with the wrong number of values
+pub fn wrong_count_message(expected: usize, got: usize) -> String {
+    format!("Expected {} input values, got {}. Maximum retries exceeded.", expected, got)
+}
+
+/// Error once the retries run out on a line with a value that is not a number
+pub const BAD_NUMBER_MESSAGE: &str = "Invalid numeric input. Maximum retries exceeded.";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(split_fields(" 1, two ,3\n"), vec!["1", "two", "3"]);
+        assert_eq!(split_fields("\n"), vec![""]);
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("12.5"), Some(12.5));
+        assert_eq!(parse_number("-3"), Some(-3.0));
+        assert_eq!(parse_number("abc"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn test_prompt_text() {
+        assert_eq!(prompt_text(Some("NAME")), "NAME? ");
+        assert_eq!(prompt_text(None), "? ");
+    }
+}
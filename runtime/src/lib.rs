@@ -0,0 +1,23 @@
+//! Runtime support for BASIC programs.
+//!
+//! Programs built by `basic-compiler` link against this crate as a static library and
+//! call the `extern "C"` entry points in [`ffi`]. The interpreter uses the same Rust
+//! functions, so PRINT formatting, PRINT USING, print zones, string functions, integer
+//! operators, INPUT parsing, files and MKI$/CVI conversions behave identically whether a
+//! program is interpreted or compiled.
+
+pub mod cursor;
+pub mod ffi;
+pub mod files;
+pub mod format;
+pub mod input;
+pub mod limits;
+pub mod matrix;
+pub mod mbf;
+pub mod operators;
+pub mod precision;
+pub mod rng;
+pub mod strings;
+pub mod using;
+
+pub use cursor::PrintCursor;
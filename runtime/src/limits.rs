@@ -0,0 +1,56 @@
+//! Error messages for exceeding a dialect's limits. The interpreter raises them itself;
+//! compiled programs get them baked into the generated code or from [`check_string`].
+
+/// A string result longer than the dialect allows
+pub fn string_too_long_message(len: usize, max: usize) -> String {
+    format!("STRING TOO LONG: {} characters, the limit is {}", len, max)
+}
+
+/// One FOR more than the dialect's FOR stack holds
+pub fn for_depth_message(max: usize) -> String {
+    format!("OUT OF MEMORY: FOR loops nested more than {} deep", max)
+}
+
+/// One GOSUB more than the dialect's GOSUB stack holds
+pub fn gosub_depth_message(max: usize) -> String {
+    format!("OUT OF MEMORY: GOSUBs nested more than {} deep", max)
+}
+
+/// A user function call nested deeper than the dialect's recursion limit
+pub fn recursion_depth_message(max: usize) -> String {
+    format!("OUT OF MEMORY: function calls nested more than {} deep", max)
+}
+
+/// A new variable or array past the dialect's variable limit
+pub fn variables_message(max: usize) -> String {
+    format!("OUT OF MEMORY: more than {} variables", max)
+}
+
+/// DIM with more dimensions than the dialect allows
+pub fn array_dims_message(name: &str, dims: usize, max: usize) -> String {
+    format!("Array '{}' has {} dimensions, the limit is {}", name, dims, max)
+}
+
+/// Checks a string result against the maximum length, counted in characters like LEN,
+/// so CHR$(200) counts once even though it takes two bytes in UTF-8
+pub fn check_string(s: &str, max: usize) -> Result<(), String> {
+    let len = s.chars().count();
+    if len > max {
+        Err(string_too_long_message(len, max))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_string() {
+        assert_eq!(check_string("HELLO", 5), Ok(()));
+        assert_eq!(check_string("HELLO!", 5), Err("STRING TOO LONG: 6 characters, the limit is 5".to_string()));
+        assert_eq!(check_string(&"\u{c8}".repeat(255), 255), Ok(()));
+        assert_eq!(check_string(&"\u{c8}".repeat(256), 255), Err("STRING TOO LONG: 256 characters, the limit is 255".to_string()));
+    }
+}
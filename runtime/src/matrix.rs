@@ -0,0 +1,468 @@
+//! MAT statements of Dartmouth BASIC: arithmetic on whole numeric arrays of one or two
+//! dimensions. MAT uses subscripts from 1 to each array's highest, so in a dialect whose
+//! arrays start at 0 it leaves row and column 0 alone. A one-dimensional array is a
+//! vector: a column on the right of `*` and a row on its left.
+//!
+//! A MAT result may change the dimensions of the array it is stored in, as long as the
+//! array keeps its number of dimensions and the result fits in the elements it was
+//! dimensioned for.
+
+use std::fmt;
+
+use crate::cursor::PrintCursor;
+use crate::format::{self, NumberFormat};
+
+/// What went wrong with a MAT statement. Each kind's summary starts its message, and the
+/// interpreter and the code generator both map the kind to the condition ERR reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixErrorKind {
+    /// Arrays whose dimensions do not suit the operation
+    IllegalFunctionCall,
+    /// A result too big for the array it is stored in
+    SubscriptOutOfRange,
+    OutOfData,
+    TypeMismatch,
+    Overflow,
+}
+
+impl MatrixErrorKind {
+    pub const ALL: [MatrixErrorKind; 5] = [
+        MatrixErrorKind::IllegalFunctionCall,
+        MatrixErrorKind::SubscriptOutOfRange,
+        MatrixErrorKind::OutOfData,
+        MatrixErrorKind::TypeMismatch,
+        MatrixErrorKind::Overflow,
+    ];
+
+    pub fn summary(&self) -> &'static str {
+        match self {
+            MatrixErrorKind::IllegalFunctionCall => "Illegal function call",
+            MatrixErrorKind::SubscriptOutOfRange => "Subscript out of range",
+            MatrixErrorKind::OutOfData => "Out of DATA",
+            MatrixErrorKind::TypeMismatch => "Type mismatch",
+            MatrixErrorKind::Overflow => "Overflow",
+        }
+    }
+
+    /// The kind's status code in the compiled code's calls into the runtime; 0 is success
+    pub fn code(&self) -> i32 {
+        Self::ALL.iter().position(|kind| kind == self).map_or(0, |i| i as i32 + 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixError {
+    pub kind: MatrixErrorKind,
+    pub message: String,
+}
+
+impl MatrixError {
+    pub fn new(kind: MatrixErrorKind, detail: impl fmt::Display) -> Self {
+        MatrixError { kind, message: format!("{}: {}", kind.summary(), detail) }
+    }
+
+    fn illegal(detail: impl fmt::Display) -> Self {
+        Self::new(MatrixErrorKind::IllegalFunctionCall, detail)
+    }
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The right-hand sides of MAT assignment, numbered for compiled code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Copy,        // MAT A = B
+    Sum,         // MAT A = B + C
+    Difference,  // MAT A = B - C
+    Product,     // MAT A = B * C
+    Scaled,      // MAT A = (K) * B
+    Transpose,   // MAT A = TRN(B)
+    Inverse,     // MAT A = INV(B), which also sets DET
+    Zero,        // MAT A = ZER or ZER(2, 3)
+    Constant,    // MAT A = CON: all ones
+    Identity,    // MAT A = IDN
+}
+
+impl Operation {
+    pub const ALL: [Operation; 10] = [
+        Operation::Copy,
+        Operation::Sum,
+        Operation::Difference,
+        Operation::Product,
+        Operation::Scaled,
+        Operation::Transpose,
+        Operation::Inverse,
+        Operation::Zero,
+        Operation::Constant,
+        Operation::Identity,
+    ];
+
+    pub fn code(self) -> i32 {
+        Self::ALL.iter().position(|&op| op == self).unwrap_or(0) as i32
+    }
+
+    pub fn from_code(code: i32) -> Option<Operation> {
+        usize::try_from(code).ok().and_then(|i| Self::ALL.get(i).copied())
+    }
+}
+
+/// A numeric array as MAT sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    /// Highest subscript in each of its one or two dimensions
+    pub bounds: Vec<usize>,
+    /// Elements from subscript 1 on, in row-major order
+    pub values: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn new(bounds: Vec<usize>) -> Self {
+        let values = vec![0.0; bounds.iter().product()];
+        Matrix { bounds, values }
+    }
+
+    /// The matrix in array `name`, which holds `data` laid out in `dimensions`, the
+    /// number of elements in each, with subscripts from `offset`
+    pub fn from_array(name: &str, data: &[f64], dimensions: &[usize], offset: usize) -> Result<Self, MatrixError> {
+        check_rank(name, dimensions.len())?;
+        let bounds: Vec<usize> = dimensions.iter().map(|&size| (size + offset).saturating_sub(1)).collect();
+        let mut matrix = Matrix::new(bounds);
+        let columns = matrix.columns();
+        let skip = 1 - offset;
+        for row in 0..matrix.rows() {
+            for column in 0..columns {
+                let stored = match dimensions {
+                    [_] => column + skip,
+                    _ => (row + skip) * dimensions[1] + column + skip,
+                };
+                matrix.values[row * columns + column] = data[stored];
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Stores the matrix in array `name`, laid out as in `from_array`, giving the array
+    /// the matrix's dimensions. `data` holds all the elements the array was dimensioned for.
+    pub fn store(&self, name: &str, data: &mut [f64], dimensions: &mut [usize], offset: usize) -> Result<(), MatrixError> {
+        if dimensions.len() != self.bounds.len() {
+            return Err(MatrixError::illegal(format!("{} has {} dimensions, and the MAT result {}",
+                name, dimensions.len(), self.bounds.len())));
+        }
+        let sizes: Vec<usize> = self.bounds.iter().map(|&bound| bound + 1 - offset).collect();
+        let needed: usize = sizes.iter().product();
+        if needed > data.len() {
+            return Err(MatrixError::new(MatrixErrorKind::SubscriptOutOfRange,
+                format!("{} was dimensioned for {} elements, and MAT needs {}", name, data.len(), needed)));
+        }
+        if dimensions != sizes.as_slice() {
+            dimensions.copy_from_slice(&sizes);
+            data[..needed].fill(0.0);
+        }
+        let columns = self.columns();
+        let skip = 1 - offset;
+        for row in 0..self.rows() {
+            for column in 0..columns {
+                let stored = match sizes.as_slice() {
+                    [_] => column + skip,
+                    _ => (row + skip) * sizes[1] + column + skip,
+                };
+                data[stored] = self.values[row * columns + column];
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows of a two-dimensional matrix; a vector is one row
+    fn rows(&self) -> usize {
+        if self.bounds.len() == 1 { 1 } else { self.bounds[0] }
+    }
+
+    fn columns(&self) -> usize {
+        *self.bounds.last().unwrap_or(&0)
+    }
+
+    fn get(&self, row: usize, column: usize) -> f64 {
+        self.values[row * self.columns() + column]
+    }
+
+    /// The dimensions as messages give them: 3, or 2 BY 3
+    fn size(&self) -> String {
+        let bounds: Vec<String> = self.bounds.iter().map(|bound| bound.to_string()).collect();
+        bounds.join(" BY ")
+    }
+
+    fn square(&self, function: &str) -> Result<usize, MatrixError> {
+        match self.bounds[..] {
+            [rows, columns] if rows == columns => Ok(rows),
+            _ => Err(MatrixError::illegal(format!("{} needs a square array, not {}", function, self.size()))),
+        }
+    }
+}
+
+fn check_rank(name: &str, rank: usize) -> Result<(), MatrixError> {
+    if !(1..=2).contains(&rank) {
+        return Err(MatrixError::illegal(format!("MAT needs an array of one or two dimensions, and {} has {}", name, rank)));
+    }
+    Ok(())
+}
+
+/// The dimensions given to ZER, CON, IDN or MAT READ: highest subscripts, as numbers
+pub fn bounds(values: &[f64]) -> Result<Vec<usize>, MatrixError> {
+    check_rank("the list", values.len())?;
+    values.iter()
+        .map(|&value| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(value as usize)
+            } else {
+                Err(MatrixError::illegal(format!("{} is not an array dimension", format::number_text(value, &NumberFormat::DOUBLE))))
+            }
+        })
+        .collect()
+}
+
+/// Works out a MAT assignment. `operands` are the arrays the right-hand side names, in
+/// order; ZER, CON and IDN take their size from `bounds` or, without them, from their one
+/// operand, the array assigned to. Also gives DET for INV.
+pub fn apply(op: Operation, operands: &[Matrix], scalar: f64, bounds: Option<Vec<usize>>) -> Result<(Matrix, Option<f64>), MatrixError> {
+    let operand = |i: usize| &operands[i];
+    let shape = || bounds.clone().unwrap_or_else(|| operand(0).bounds.clone());
+    let result = match op {
+        Operation::Copy => operand(0).clone(),
+        Operation::Sum => combine(operand(0), operand(1), "+", |a, b| a + b)?,
+        Operation::Difference => combine(operand(0), operand(1), "-", |a, b| a - b)?,
+        Operation::Product => product(operand(0), operand(1))?,
+        Operation::Scaled => Matrix {
+            bounds: operand(0).bounds.clone(),
+            values: operand(0).values.iter().map(|value| scalar * value).collect(),
+        },
+        Operation::Transpose => transpose(operand(0))?,
+        Operation::Inverse => {
+            let (inverse, determinant) = inverse(operand(0))?;
+            return Ok((inverse, Some(determinant)));
+        }
+        Operation::Zero => Matrix::new(shape()),
+        Operation::Constant => {
+            let mut matrix = Matrix::new(shape());
+            matrix.values.fill(1.0);
+            matrix
+        }
+        Operation::Identity => {
+            let mut matrix = Matrix::new(shape());
+            let size = matrix.square("IDN")?;
+            for i in 0..size {
+                matrix.values[i * size + i] = 1.0;
+            }
+            matrix
+        }
+    };
+    Ok((result, None))
+}
+
+fn combine(a: &Matrix, b: &Matrix, op: &str, f: impl Fn(f64, f64) -> f64) -> Result<Matrix, MatrixError> {
+    if a.bounds != b.bounds {
+        return Err(MatrixError::illegal(format!("MAT {} needs arrays of the same size, not {} and {}", op, a.size(), b.size())));
+    }
+    let values = a.values.iter().zip(&b.values).map(|(&x, &y)| f(x, y)).collect();
+    Ok(Matrix { bounds: a.bounds.clone(), values })
+}
+
+fn product(a: &Matrix, b: &Matrix) -> Result<Matrix, MatrixError> {
+    // A vector is a row on the left and a column on the right; the result is a vector
+    let (rows, inner, columns) = match (&a.bounds[..], &b.bounds[..]) {
+        (&[rows, inner], &[inner_b, columns]) if inner == inner_b => (rows, inner, columns),
+        (&[rows, inner], &[inner_b]) if inner == inner_b => (rows, inner, 1),
+        (&[inner], &[inner_b, columns]) if inner == inner_b => (1, inner, columns),
+        _ => return Err(MatrixError::illegal(format!(
+            "MAT * needs as many columns on the left as rows on the right, not {} and {}", a.size(), b.size()))),
+    };
+    let bounds = match (a.bounds.len(), b.bounds.len()) {
+        (2, 2) => vec![rows, columns],
+        (2, _) => vec![rows],
+        _ => vec![columns],
+    };
+    let mut result = Matrix::new(bounds);
+    for row in 0..rows {
+        for column in 0..columns {
+            let left = |k: usize| if a.bounds.len() == 1 { a.values[k] } else { a.get(row, k) };
+            let right = |k: usize| if b.bounds.len() == 1 { b.values[k] } else { b.get(k, column) };
+            result.values[row * columns + column] = (0..inner).map(|k| left(k) * right(k)).sum();
+        }
+    }
+    Ok(result)
+}
+
+fn transpose(a: &Matrix) -> Result<Matrix, MatrixError> {
+    let &[rows, columns] = &a.bounds[..] else {
+        return Err(MatrixError::illegal(format!("TRN needs an array of two dimensions, not {}", a.size())));
+    };
+    let mut result = Matrix::new(vec![columns, rows]);
+    for row in 0..rows {
+        for column in 0..columns {
+            result.values[column * rows + row] = a.get(row, column);
+        }
+    }
+    Ok(result)
+}
+
+/// The inverse and the determinant, by Gauss-Jordan elimination with partial pivoting
+fn inverse(a: &Matrix) -> Result<(Matrix, f64), MatrixError> {
+    let size = a.square("INV")?;
+    let mut work = a.values.clone();
+    let mut result = Matrix::new(vec![size, size]);
+    for i in 0..size {
+        result.values[i * size + i] = 1.0;
+    }
+    // Pivots this much smaller than the largest element are taken as zero
+    let tolerance = a.values.iter().fold(0.0f64, |max, value| max.max(value.abs())) * 1e-12;
+    let mut determinant = 1.0;
+    for column in 0..size {
+        let pivot_row = (column..size)
+            .max_by(|&x, &y| work[x * size + column].abs().total_cmp(&work[y * size + column].abs()))
+            .unwrap_or(column);
+        let pivot = work[pivot_row * size + column];
+        if pivot.abs() <= tolerance {
+            return Err(MatrixError::illegal("INV of an array whose DET is 0"));
+        }
+        if pivot_row != column {
+            for k in 0..size {
+                work.swap(pivot_row * size + k, column * size + k);
+                result.values.swap(pivot_row * size + k, column * size + k);
+            }
+            determinant = -determinant;
+        }
+        determinant *= pivot;
+        for k in 0..size {
+            work[column * size + k] /= pivot;
+            result.values[column * size + k] /= pivot;
+        }
+        for row in (0..size).filter(|&row| row != column) {
+            let factor = work[row * size + column];
+            if factor != 0.0 {
+                for k in 0..size {
+                    work[row * size + k] -= factor * work[column * size + k];
+                    result.values[row * size + k] -= factor * result.values[column * size + k];
+                }
+            }
+        }
+    }
+    Ok((result, determinant))
+}
+
+/// Text MAT PRINT produces for a matrix: each row on a line of its own followed by a
+/// blank one, its numbers in print zones, or packed together when `packed`
+pub fn print_text(matrix: &Matrix, packed: bool, format: &NumberFormat, cursor: &mut PrintCursor) -> String {
+    let mut text = String::new();
+    let columns = matrix.columns();
+    for row in 0..matrix.rows() {
+        for column in 0..columns {
+            if column > 0 && !packed {
+                text.push_str(&" ".repeat(cursor.comma()));
+            }
+            let number = format::print_number(matrix.get(row, column), format);
+            cursor.advance(&number);
+            text.push_str(&number);
+        }
+        text.push_str("\n\n");
+        cursor.newline();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(bounds: &[usize], values: &[f64]) -> Matrix {
+        Matrix { bounds: bounds.to_vec(), values: values.to_vec() }
+    }
+
+    #[test]
+    fn test_array_layout() {
+        // DIM A(2, 2) with subscripts from 0: MAT leaves row and column 0 alone
+        let mut data = vec![9.0, 9.0, 9.0, 9.0, 1.0, 2.0, 9.0, 3.0, 4.0];
+        let mut dimensions = vec![3, 3];
+        let a = Matrix::from_array("A", &data, &dimensions, 0).unwrap();
+        assert_eq!(a, matrix(&[2, 2], &[1.0, 2.0, 3.0, 4.0]));
+        assert_eq!(Matrix::from_array("A", &data, &dimensions, 1).unwrap().bounds, vec![3, 3]);
+
+        // Redimensioned to 1 by 3, which fits in the 9 elements
+        matrix(&[1, 3], &[5.0, 6.0, 7.0]).store("A", &mut data, &mut dimensions, 0).unwrap();
+        assert_eq!(dimensions, vec![2, 4]);
+        assert_eq!(&data[..8], &[0.0, 0.0, 0.0, 0.0, 0.0, 5.0, 6.0, 7.0]);
+
+        let error = Matrix::new(vec![3, 3]).store("A", &mut data, &mut dimensions, 0).unwrap_err();
+        assert_eq!(error.message, "Subscript out of range: A was dimensioned for 9 elements, and MAT needs 16");
+        let error = Matrix::new(vec![3]).store("A", &mut data, &mut dimensions, 0).unwrap_err();
+        assert_eq!(error.kind, MatrixErrorKind::IllegalFunctionCall);
+        assert!(Matrix::from_array("A", &data, &[1, 1, 9], 0).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = matrix(&[2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = matrix(&[3, 2], &[7.0, 8.0, 9.0, 10.0, 11.0, 12.0]);
+        let (sum, _) = apply(Operation::Sum, &[a.clone(), a.clone()], 0.0, None).unwrap();
+        assert_eq!(sum.values, vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+        let error = apply(Operation::Difference, &[a.clone(), b.clone()], 0.0, None).unwrap_err();
+        assert_eq!(error.message, "Illegal function call: MAT - needs arrays of the same size, not 2 BY 3 and 3 BY 2");
+
+        let (product, _) = apply(Operation::Product, &[a.clone(), b.clone()], 0.0, None).unwrap();
+        assert_eq!(product, matrix(&[2, 2], &[58.0, 64.0, 139.0, 154.0]));
+        assert!(apply(Operation::Product, &[a.clone(), a.clone()], 0.0, None).is_err());
+        // A vector is a column on the right and a row on the left
+        let (column, _) = apply(Operation::Product, &[a.clone(), matrix(&[3], &[1.0, 0.0, 1.0])], 0.0, None).unwrap();
+        assert_eq!(column, matrix(&[2], &[4.0, 10.0]));
+        let (row, _) = apply(Operation::Product, &[matrix(&[2], &[1.0, 1.0]), a.clone()], 0.0, None).unwrap();
+        assert_eq!(row, matrix(&[3], &[5.0, 7.0, 9.0]));
+
+        let (scaled, _) = apply(Operation::Scaled, std::slice::from_ref(&a), 2.0, None).unwrap();
+        assert_eq!(scaled.values, vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+        let (transposed, _) = apply(Operation::Transpose, &[a], 0.0, None).unwrap();
+        assert_eq!(transposed, matrix(&[3, 2], &[1.0, 4.0, 2.0, 5.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let a = matrix(&[2, 2], &[4.0, 7.0, 2.0, 6.0]);
+        let (inverse, determinant) = apply(Operation::Inverse, &[a], 0.0, None).unwrap();
+        assert!((determinant.unwrap() - 10.0).abs() < 1e-12);
+        let expected = [0.6, -0.7, -0.2, 0.4];
+        assert!(inverse.values.iter().zip(expected).all(|(x, y)| (x - y).abs() < 1e-12));
+
+        // Pivoting copes with a zero in the corner, and swapping rows flips the sign
+        let (_, determinant) = apply(Operation::Inverse, &[matrix(&[2, 2], &[0.0, 1.0, 1.0, 0.0])], 0.0, None).unwrap();
+        assert_eq!(determinant, Some(-1.0));
+        let singular = matrix(&[2, 2], &[1.0, 2.0, 2.0, 4.0]);
+        assert!(apply(Operation::Inverse, &[singular], 0.0, None).is_err());
+        assert!(apply(Operation::Inverse, &[matrix(&[2, 3], &[0.0; 6])], 0.0, None).is_err());
+    }
+
+    #[test]
+    fn test_constant_matrices() {
+        let (zero, _) = apply(Operation::Zero, &[], 0.0, Some(vec![2])).unwrap();
+        assert_eq!(zero, matrix(&[2], &[0.0, 0.0]));
+        // Without dimensions, the size of the array assigned to
+        let (ones, _) = apply(Operation::Constant, &[matrix(&[1, 2], &[5.0, 5.0])], 0.0, None).unwrap();
+        assert_eq!(ones, matrix(&[1, 2], &[1.0, 1.0]));
+        let (identity, _) = apply(Operation::Identity, &[], 0.0, Some(vec![2, 2])).unwrap();
+        assert_eq!(identity.values, vec![1.0, 0.0, 0.0, 1.0]);
+        assert!(apply(Operation::Identity, &[], 0.0, Some(vec![2, 3])).is_err());
+
+        assert_eq!(bounds(&[2.7, 3.0]), Ok(vec![2, 3]));
+        assert!(bounds(&[-1.0]).is_err());
+        assert!(bounds(&[1.0, 1.0, 1.0]).is_err());
+    }
+
+    #[test]
+    fn test_print_text() {
+        let a = matrix(&[2, 2], &[1.0, -2.0, 3.0, 4.0]);
+        let mut cursor = PrintCursor::new();
+        assert_eq!(print_text(&a, false, &NumberFormat::DOUBLE, &mut cursor), " 1      -2 \n\n 3       4 \n\n");
+        assert_eq!(print_text(&a, true, &NumberFormat::DOUBLE, &mut cursor), " 1 -2 \n\n 3  4 \n\n");
+        assert_eq!(cursor.column(), 0);
+    }
+}
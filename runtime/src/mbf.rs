@@ -0,0 +1,146 @@
+//! MKI$, MKS$, MKD$ and CVI, CVS, CVD: numbers packed into strings the way Microsoft BASIC
+//! stores them in random files. Integers are 16-bit little-endian; single and double
+//! precision numbers use the Microsoft Binary Format (MBF), so records are byte for byte
+//! what GW-BASIC writes.
+//!
+//! MBF keeps the exponent, biased by 128, in the last byte, and 0 there means zero. The
+//! byte before it holds the sign in its top bit, followed by the mantissa without its
+//! leading 1, most significant byte first when reading backwards.
+
+use crate::strings::{from_bytes, to_bytes};
+
+/// Error for MKI$, MKS$ or MKD$ given a number their format cannot hold
+pub fn overflow_message(function: &str) -> String {
+    format!("Overflow in {}", function)
+}
+
+/// Error for CVI, CVS or CVD given a string shorter than the number they unpack
+pub fn short_string_message(function: &str, bytes: usize) -> String {
+    format!("{} requires a string of at least {} bytes", function, bytes)
+}
+
+/// MKI$(n): n rounded to a 16-bit integer, low byte first
+pub fn mki(n: f64) -> Result<String, String> {
+    let n = n.round();
+    if !(i16::MIN as f64..=i16::MAX as f64).contains(&n) {
+        return Err(overflow_message("MKI$"));
+    }
+    Ok(from_bytes(&(n as i16).to_le_bytes()))
+}
+
+/// MKS$(n): n rounded to single precision, as 4 bytes of MBF
+pub fn mks(n: f64) -> Result<String, String> {
+    let bits = (n as f32).to_bits();
+    let exponent = (bits >> 23) & 0xFF;
+    if exponent == 0 {
+        return Ok(from_bytes(&[0; 4]));
+    }
+    let exponent = exponent + 2;
+    if exponent > 0xFF {
+        return Err(overflow_message("MKS$"));
+    }
+    let sign = (bits >> 31) as u8;
+    let mantissa = bits & 0x7F_FFFF;
+    Ok(from_bytes(&[mantissa as u8, (mantissa >> 8) as u8, (sign << 7) | (mantissa >> 16) as u8, exponent as u8]))
+}
+
+/// MKD$(n): n as 8 bytes of MBF. MBF has 3 more mantissa bits than an f64 and a far
+/// smaller exponent range; numbers too small for it become zero.
+pub fn mkd(n: f64) -> Result<String, String> {
+    let bits = n.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i64 - 1023 + 129;
+    if exponent <= 0 || n == 0.0 {
+        return Ok(from_bytes(&[0; 8]));
+    }
+    if exponent > 0xFF {
+        return Err(overflow_message("MKD$"));
+    }
+    let sign = (bits >> 63) as u8;
+    let mantissa = (bits & 0xF_FFFF_FFFF_FFFF) << 3;
+    let mut bytes = mantissa.to_le_bytes();
+    bytes[6] = (sign << 7) | (bytes[6] & 0x7F);
+    bytes[7] = exponent as u8;
+    Ok(from_bytes(&bytes))
+}
+
+/// CVI(s): the integer MKI$ packed into the first 2 bytes of s
+pub fn cvi(s: &str) -> Result<f64, String> {
+    let bytes = leading_bytes::<2>("CVI", s)?;
+    Ok(i16::from_le_bytes(bytes) as f64)
+}
+
+/// CVS(s): the single precision number MKS$ packed into the first 4 bytes of s
+pub fn cvs(s: &str) -> Result<f64, String> {
+    let bytes = leading_bytes::<4>("CVS", s)?;
+    if bytes[3] == 0 {
+        return Ok(0.0);
+    }
+    let mantissa = (((bytes[2] & 0x7F) as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[0] as u32;
+    let magnitude = (mantissa | 0x80_0000) as f64 * 2f64.powi(bytes[3] as i32 - 152);
+    Ok(if bytes[2] & 0x80 != 0 { -magnitude } else { magnitude })
+}
+
+/// CVD(s): the double precision number MKD$ packed into the first 8 bytes of s. The 3
+/// mantissa bits an f64 has no room for are dropped.
+pub fn cvd(s: &str) -> Result<f64, String> {
+    let bytes = leading_bytes::<8>("CVD", s)?;
+    if bytes[7] == 0 {
+        return Ok(0.0);
+    }
+    let sign = (bytes[6] >> 7) as u64;
+    let mut mantissa_bytes = bytes;
+    mantissa_bytes[6] &= 0x7F;
+    mantissa_bytes[7] = 0;
+    let mantissa = u64::from_le_bytes(mantissa_bytes) >> 3;
+    let exponent = bytes[7] as u64 + 1023 - 129;
+    Ok(f64::from_bits((sign << 63) | (exponent << 52) | mantissa))
+}
+
+fn leading_bytes<const N: usize>(function: &str, s: &str) -> Result<[u8; N], String> {
+    let bytes = to_bytes(s);
+    bytes.get(..N)
+        .and_then(|prefix| prefix.try_into().ok())
+        .ok_or_else(|| short_string_message(function, N))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gwbasic_bytes() {
+        assert_eq!(to_bytes(&mki(1.0).unwrap()), [0x01, 0x00]);
+        assert_eq!(to_bytes(&mki(-2.0).unwrap()), [0xFE, 0xFF]);
+        assert_eq!(to_bytes(&mks(1.0).unwrap()), [0x00, 0x00, 0x00, 0x81]);
+        assert_eq!(to_bytes(&mks(-0.5).unwrap()), [0x00, 0x00, 0x80, 0x80]);
+        assert_eq!(to_bytes(&mks(10.0).unwrap()), [0x00, 0x00, 0x20, 0x84]);
+        assert_eq!(to_bytes(&mks(0.0).unwrap()), [0; 4]);
+        assert_eq!(to_bytes(&mkd(1.0).unwrap()), [0, 0, 0, 0, 0, 0, 0x00, 0x81]);
+        assert_eq!(to_bytes(&mkd(-3.0).unwrap()), [0, 0, 0, 0, 0, 0, 0xC0, 0x82]);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for n in [0.0, 1.0, -1.0, 32767.0, -32768.0, 42.0] {
+            assert_eq!(cvi(&mki(n).unwrap()).unwrap(), n);
+        }
+        for n in [0.0, 1.0, -0.5, 10.0, 1234.5, -1e20] {
+            assert_eq!(cvs(&mks(n).unwrap()).unwrap(), n as f32 as f64);
+        }
+        for n in [0.0, 1.0, -3.0, 0.1, 1e30, -123456.789] {
+            assert_eq!(cvd(&mkd(n).unwrap()).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(mki(32768.0).is_err());
+        assert!(mks(1e39).is_err());
+        assert!(mkd(1e39).is_err());
+        assert_eq!(mkd(1e-40).unwrap(), from_bytes(&[0; 8]));
+        assert_eq!(cvi("A").unwrap_err(), "CVI requires a string of at least 2 bytes");
+        assert!(cvs("ABC").is_err());
+        assert!(cvd("ABCDEFG").is_err());
+        assert_eq!(cvi("AB...").unwrap(), (b'A' as i16 + 256 * b'B' as i16) as f64);
+    }
+}
@@ -0,0 +1,143 @@
+//! The operators that work on whole numbers: `\`, MOD, and the bitwise AND, OR, XOR, EQV,
+//! IMP and NOT. Their operands are rounded to integers of the dialect's size, 16 bits in
+//! Microsoft BASICs, and combined bit by bit in two's complement, so NOT 0 is -1 and
+//! -1 AND 255 is 255. An operand that doesn't fit is an Overflow.
+
+use crate::mbf::overflow_message;
+use crate::precision::round_integer;
+
+/// An operator taking two whole numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerOp {
+    IntDivide,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Eqv,
+    Imp,
+}
+
+impl IntegerOp {
+    pub const ALL: [IntegerOp; 7] = [IntegerOp::IntDivide, IntegerOp::Mod, IntegerOp::And, IntegerOp::Or,
+                                     IntegerOp::Xor, IntegerOp::Eqv, IntegerOp::Imp];
+
+    /// The operator as a program writes it
+    pub fn name(self) -> &'static str {
+        match self {
+            IntegerOp::IntDivide => "\\",
+            IntegerOp::Mod => "MOD",
+            IntegerOp::And => "AND",
+            IntegerOp::Or => "OR",
+            IntegerOp::Xor => "XOR",
+            IntegerOp::Eqv => "EQV",
+            IntegerOp::Imp => "IMP",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IntegerOp> {
+        IntegerOp::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    /// The operator's number in compiled code's calls into the runtime
+    pub fn code(self) -> i32 {
+        IntegerOp::ALL.iter().position(|op| *op == self).unwrap() as i32
+    }
+
+    pub fn from_code(code: i32) -> IntegerOp {
+        IntegerOp::ALL.get(code as usize).copied().unwrap_or(IntegerOp::And)
+    }
+
+    /// Whether a right operand of 0 is a division by zero
+    pub fn divides(self) -> bool {
+        matches!(self, IntegerOp::IntDivide | IntegerOp::Mod)
+    }
+
+    /// Whether `b` as the right operand divides by zero: it rounds to 0. One too big for
+    /// `bits` is an Overflow instead, which `apply` reports.
+    pub fn divides_by_zero(self, b: f64, bits: u32) -> bool {
+        self.divides() && round_integer(b, bits) == Some(0.0)
+    }
+
+    /// `a op b` on `bits`-bit integers. `\` truncates toward zero and MOD takes the sign
+    /// of `a`, as in GW-BASIC.
+    pub fn apply(self, a: f64, b: f64, bits: u32) -> Result<f64, String> {
+        let b = to_integer(b, bits, self.name())?;
+        if self.divides() && b == 0 {
+            return Err("Division by zero".to_string());
+        }
+        let a = to_integer(a, bits, self.name())?;
+        let result = match self {
+            IntegerOp::IntDivide => a / b,
+            IntegerOp::Mod => a % b,
+            IntegerOp::And => a & b,
+            IntegerOp::Or => a | b,
+            IntegerOp::Xor => a ^ b,
+            IntegerOp::Eqv => !(a ^ b),
+            IntegerOp::Imp => !a | b,
+        };
+        // Only -32768 \ -1 leaves the range
+        from_integer(result, bits, self.name())
+    }
+}
+
+/// NOT n: every bit of the `bits`-bit integer n flipped
+pub fn not(n: f64, bits: u32) -> Result<f64, String> {
+    Ok(!to_integer(n, bits, "NOT")? as f64)
+}
+
+fn to_integer(n: f64, bits: u32, op: &str) -> Result<i64, String> {
+    round_integer(n, bits).map(|n| n as i64).ok_or_else(|| overflow_message(op))
+}
+
+fn from_integer(n: i64, bits: u32, op: &str) -> Result<f64, String> {
+    round_integer(n as f64, bits).ok_or_else(|| overflow_message(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(not(0.0, 16), Ok(-1.0));
+        assert_eq!(not(-1.0, 16), Ok(0.0));
+        assert_eq!(not(5.0, 16), Ok(-6.0));
+        assert_eq!(IntegerOp::And.apply(-1.0, 255.0, 16), Ok(255.0));
+        assert_eq!(IntegerOp::Or.apply(12.0, 3.0, 16), Ok(15.0));
+        assert_eq!(IntegerOp::Xor.apply(-1.0, 1.0, 16), Ok(-2.0));
+        assert_eq!(IntegerOp::Eqv.apply(-1.0, -1.0, 16), Ok(-1.0));
+        assert_eq!(IntegerOp::Eqv.apply(0.0, -1.0, 16), Ok(0.0));
+        assert_eq!(IntegerOp::Imp.apply(-1.0, 0.0, 16), Ok(0.0));
+        assert_eq!(IntegerOp::Imp.apply(0.0, 0.0, 16), Ok(-1.0));
+        assert_eq!(IntegerOp::And.apply(2.6, 3.0, 16), Ok(3.0));
+        assert_eq!(IntegerOp::Or.apply(32768.0, 0.0, 16), Err("Overflow in OR".to_string()));
+        assert_eq!(IntegerOp::Or.apply(32768.0, 0.0, 32), Ok(32768.0));
+        assert_eq!(not(f64::NAN, 16), Err("Overflow in NOT".to_string()));
+    }
+
+    #[test]
+    fn test_divide_and_mod() {
+        assert_eq!(IntegerOp::IntDivide.apply(7.0, 2.0, 16), Ok(3.0));
+        assert_eq!(IntegerOp::IntDivide.apply(-7.0, 2.0, 16), Ok(-3.0));
+        assert_eq!(IntegerOp::IntDivide.apply(10.0, 2.6, 16), Ok(3.0));
+        assert_eq!(IntegerOp::Mod.apply(7.0, 3.0, 16), Ok(1.0));
+        assert_eq!(IntegerOp::Mod.apply(-7.0, 3.0, 16), Ok(-1.0));
+        assert_eq!(IntegerOp::Mod.apply(5.0, 0.4, 16), Err("Division by zero".to_string()));
+        assert_eq!(IntegerOp::IntDivide.apply(-32768.0, -1.0, 16), Err("Overflow in \\".to_string()));
+
+        assert!(IntegerOp::Mod.divides_by_zero(-0.4, 16));
+        assert!(!IntegerOp::Mod.divides_by_zero(0.5, 16));
+        assert!(!IntegerOp::IntDivide.divides_by_zero(40000.0, 16));
+        assert!(!IntegerOp::And.divides_by_zero(0.0, 16));
+    }
+
+    #[test]
+    fn test_names_and_codes() {
+        for op in IntegerOp::ALL {
+            assert_eq!(IntegerOp::from_name(op.name()), Some(op));
+            assert_eq!(IntegerOp::from_code(op.code()), op);
+        }
+        assert_eq!(IntegerOp::from_name("+"), None);
+    }
+}
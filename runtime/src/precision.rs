@@ -0,0 +1,113 @@
+//! The precision numbers are kept in. BasicRS computes in f64; the classic Microsoft
+//! BASICs computed in 32-bit floats (GW-BASIC's single precision) or in the 40-bit
+//! Microsoft Binary Format of the 6502 BASICs, with a 32-bit mantissa. Under those, every
+//! operation and assignment rounds its result, so programs see the same rounding.
+//! Integer variables, `A%`, hold whole numbers of 16 bits in those BASICs.
+
+/// Numeric precision of a dialect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    /// 64-bit floats, 53-bit mantissa
+    #[default]
+    Double,
+    /// IEEE 32-bit floats, 24-bit mantissa
+    Single,
+    /// 40-bit Microsoft Binary Format: 32-bit mantissa, exponents from 2^-128 to 2^127
+    Mbf40,
+}
+
+impl Precision {
+    /// `n` rounded to the nearest number this precision holds. Numbers too small for it
+    /// become zero and numbers too large become infinite.
+    pub fn round(self, n: f64) -> f64 {
+        match self {
+            Precision::Double => n,
+            Precision::Single => n as f32 as f64,
+            Precision::Mbf40 => round_mbf40(n),
+        }
+    }
+
+    /// The precision's number in compiled code's calls into the runtime
+    pub fn code(self) -> i32 {
+        match self {
+            Precision::Double => 0,
+            Precision::Single => 1,
+            Precision::Mbf40 => 2,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Precision {
+        match code {
+            1 => Precision::Single,
+            2 => Precision::Mbf40,
+            _ => Precision::Double,
+        }
+    }
+}
+
+/// `n` rounded to a whole number, as an integer variable of `bits` bits holds it, or None
+/// if it does not fit
+pub fn round_integer(n: f64, bits: u32) -> Option<f64> {
+    let limit = 2f64.powi(bits as i32 - 1);
+    let rounded = n.round();
+    (rounded >= -limit && rounded < limit).then_some(rounded)
+}
+
+/// The Overflow error for a number that does not fit in the integer variable `name`
+pub fn integer_overflow_message(name: &str, bits: u32) -> String {
+    let limit = 1i64 << (bits - 1);
+    format!("Overflow: {} holds whole numbers from {} to {}", name, -limit, limit - 1)
+}
+
+fn round_mbf40(n: f64) -> f64 {
+    if n == 0.0 || !n.is_finite() {
+        return n;
+    }
+    // MBF holds 0.1mmm... x 2^e with e from -127 to 127, so magnitudes in [2^-128, 2^127)
+    if n.abs() < 2f64.powi(-128) {
+        return 0.0;
+    }
+    let exponent = ((n.to_bits() >> 52) & 0x7FF) as i32 - 1023;
+    let scale = 2f64.powi(31 - exponent);
+    let rounded = (n * scale).round() / scale;
+    if rounded.abs() >= 2f64.powi(127) {
+        return f64::INFINITY.copysign(n);
+    }
+    rounded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round() {
+        assert_eq!(Precision::Double.round(0.1), 0.1);
+        assert_eq!(Precision::Single.round(0.1), 0.1f32 as f64);
+        assert_eq!(Precision::Single.round(16777217.0), 16777216.0);
+        assert_eq!(Precision::Single.round(1e39), f64::INFINITY);
+
+        // 2^32 + 1 needs 33 bits of mantissa, and halves round away from zero as MBF does;
+        // 2^31 + 1 fits in 32
+        assert_eq!(Precision::Mbf40.round(4294967297.0), 4294967298.0);
+        assert_eq!(Precision::Mbf40.round(2147483649.0), 2147483649.0);
+        assert_eq!(Precision::Mbf40.round(-2147483649.5), -2147483650.0);
+        let third = Precision::Mbf40.round(1.0 / 3.0);
+        assert!(third != 1.0 / 3.0 && (third - 1.0 / 3.0).abs() < 1e-10);
+        assert_eq!(Precision::Mbf40.round(1e-40), 0.0);
+        assert_eq!(Precision::Mbf40.round(-2e38), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_round_integer() {
+        assert_eq!(round_integer(2.4, 16), Some(2.0));
+        assert_eq!(round_integer(2.5, 16), Some(3.0));
+        assert_eq!(round_integer(-2.5, 16), Some(-3.0));
+        assert_eq!(round_integer(32767.0, 16), Some(32767.0));
+        assert_eq!(round_integer(-32768.0, 16), Some(-32768.0));
+        assert_eq!(round_integer(32767.5, 16), None);
+        assert_eq!(round_integer(40000.0, 32), Some(40000.0));
+        assert_eq!(round_integer(f64::NAN, 32), None);
+        assert_eq!(integer_overflow_message("I%", 16), "Overflow: I% holds whole numbers from -32768 to 32767");
+    }
+}
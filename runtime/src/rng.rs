@@ -0,0 +1,369 @@
+//! Random numbers for RND, shared by the interpreter and compiled programs.
+//!
+//! BasicRS draws from its own small generator. The others reproduce other BASICs bit for
+//! bit: Microsoft's is the 24-bit generator of QuickBASIC and QBasic, and TrekBasic's is
+//! the Mersenne Twister of Python's `random` module, which TrekBasic's RND calls.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The generator RND draws from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RndAlgorithm {
+    /// xorshift64*, seeded from the clock
+    #[default]
+    BasicRs,
+    /// QBasic's: the same sequence every run until RANDOMIZE
+    Microsoft,
+    /// Python's Mersenne Twister, seeded from the clock
+    TrekBasic,
+}
+
+impl RndAlgorithm {
+    pub const ALL: [RndAlgorithm; 3] = [RndAlgorithm::BasicRs, RndAlgorithm::Microsoft, RndAlgorithm::TrekBasic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RndAlgorithm::BasicRs => "basicrs",
+            RndAlgorithm::Microsoft => "microsoft",
+            RndAlgorithm::TrekBasic => "trekbasic",
+        }
+    }
+
+    /// The algorithm's number in compiled code's calls into the runtime
+    pub fn code(self) -> i32 {
+        Self::ALL.iter().position(|&algorithm| algorithm == self).unwrap_or(0) as i32
+    }
+
+    pub fn from_code(code: i32) -> RndAlgorithm {
+        Self::ALL.get(code.max(0) as usize).copied().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RndAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RndAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|algorithm| algorithm.name()).collect();
+                format!("unknown RND algorithm '{}', expected one of: {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Seconds since midnight, UTC, for TIMER and RANDOMIZE TIMER
+pub fn timer() -> f64 {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    seconds % 86400.0
+}
+
+fn clock_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// QBasic's generator: seed = seed * 0xFD43FD + 0xC39EC3, modulo 2^24, starting at 0x50000
+const MICROSOFT_START: u32 = 0x50000;
+const MICROSOFT_MULTIPLIER: u32 = 0xFD43FD;
+const MICROSOFT_INCREMENT: u32 = 0xC39EC3;
+const MICROSOFT_MASK: u32 = 0xFF_FFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Generator {
+    Xorshift(u64),
+    Microsoft(u32),
+    Mersenne(Box<Mersenne>),
+}
+
+/// The state RND draws from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    generator: Generator,
+    last: f64, // RND(0)
+}
+
+impl Rng {
+    /// BasicRS's generator with a given seed
+    pub const fn new(seed: u64) -> Self {
+        Rng { generator: Generator::Xorshift(Self::xorshift_state(seed)), last: 0.0 }
+    }
+
+    /// An algorithm's generator as a program starts: seeded from the clock, except for
+    /// Microsoft's, which always starts the same way
+    pub fn start(algorithm: RndAlgorithm) -> Self {
+        let generator = match algorithm {
+            RndAlgorithm::BasicRs => Generator::Xorshift(Self::xorshift_state(clock_seed())),
+            RndAlgorithm::Microsoft => Generator::Microsoft(MICROSOFT_START),
+            RndAlgorithm::TrekBasic => Generator::Mersenne(Box::new(Mersenne::from_int(clock_seed()))),
+        };
+        Rng { generator, last: 0.0 }
+    }
+
+    const fn xorshift_state(seed: u64) -> u64 {
+        // xorshift must never hold a zero state
+        if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }
+    }
+
+    /// RANDOMIZE `seed`
+    pub fn randomize(&mut self, seed: f64) {
+        match &mut self.generator {
+            Generator::Xorshift(_) => self.reseed(seed),
+            Generator::Microsoft(state) => {
+                // The two high words of the double, xored, become the middle of the seed
+                let bits = seed.to_bits();
+                let word = ((bits >> 48) ^ (bits >> 32)) as u32 & 0xFFFF;
+                *state = (*state & 0xFF) | (word << 8);
+            }
+            Generator::Mersenne(mersenne) => **mersenne = Mersenne::from_float(seed),
+        }
+    }
+
+    /// Starts the sequence a negative argument to RND names
+    fn reseed(&mut self, x: f64) {
+        match &mut self.generator {
+            Generator::Xorshift(state) => *state = Self::xorshift_state((x.abs() * 1000000.0) as u64),
+            Generator::Microsoft(state) => {
+                // The bytes of the single precision number, with its top byte added
+                let bits = (x as f32).to_bits();
+                *state = bits.wrapping_add(bits >> 24) & MICROSOFT_MASK;
+            }
+            Generator::Mersenne(mersenne) => **mersenne = Mersenne::from_float(x),
+        }
+    }
+
+    /// Uniform value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        let value = match &mut self.generator {
+            Generator::Xorshift(state) => {
+                *state ^= *state >> 12;
+                *state ^= *state << 25;
+                *state ^= *state >> 27;
+                let bits = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+                (bits >> 11) as f64 / (1u64 << 53) as f64
+            }
+            Generator::Microsoft(state) => {
+                *state = state.wrapping_mul(MICROSOFT_MULTIPLIER).wrapping_add(MICROSOFT_INCREMENT) & MICROSOFT_MASK;
+                *state as f64 / (MICROSOFT_MASK + 1) as f64
+            }
+            Generator::Mersenne(mersenne) => mersenne.random(),
+        };
+        self.last = value;
+        value
+    }
+
+    /// RND(x): the next number; a negative argument first reseeds from the argument, and
+    /// 0 gives the last number again
+    pub fn rnd(&mut self, x: f64) -> f64 {
+        if x == 0.0 {
+            return match self.generator {
+                Generator::Microsoft(state) => state as f64 / (MICROSOFT_MASK + 1) as f64,
+                _ => self.last,
+            };
+        }
+        if x < 0.0 {
+            self.reseed(x);
+        }
+        self.next_f64()
+    }
+}
+
+const MT_N: usize = 624;
+const MT_M: usize = 397;
+
+/// MT19937, seeded and scaled as Python's `random` module does it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mersenne {
+    state: [u32; MT_N],
+    index: usize,
+}
+
+impl Mersenne {
+    fn from_seed(seed: u32) -> Self {
+        let mut state = [0u32; MT_N];
+        state[0] = seed;
+        for i in 1..MT_N {
+            state[i] = 1812433253u32.wrapping_mul(state[i - 1] ^ (state[i - 1] >> 30)).wrapping_add(i as u32);
+        }
+        Mersenne { state, index: MT_N }
+    }
+
+    /// `random.seed(n)` for a whole number: its 32-bit words, lowest first, are the key
+    fn from_int(n: u64) -> Self {
+        let key: &[u32] = &[n as u32, (n >> 32) as u32];
+        Self::from_key(if n >> 32 == 0 { &key[..1] } else { key })
+    }
+
+    /// `random.seed(x)` for a float, which Python seeds from the float's hash
+    fn from_float(x: f64) -> Self {
+        Self::from_int(python_hash(x) as u64)
+    }
+
+    fn from_key(key: &[u32]) -> Self {
+        let mut mersenne = Self::from_seed(19650218);
+        let state = &mut mersenne.state;
+        let (mut i, mut j) = (1, 0);
+        for _ in 0..MT_N.max(key.len()) {
+            state[i] = (state[i] ^ (state[i - 1] ^ (state[i - 1] >> 30)).wrapping_mul(1664525))
+                .wrapping_add(key[j]).wrapping_add(j as u32);
+            i += 1;
+            j += 1;
+            if i >= MT_N {
+                state[0] = state[MT_N - 1];
+                i = 1;
+            }
+            if j >= key.len() {
+                j = 0;
+            }
+        }
+        for _ in 0..MT_N - 1 {
+            state[i] = (state[i] ^ (state[i - 1] ^ (state[i - 1] >> 30)).wrapping_mul(1566083941)).wrapping_sub(i as u32);
+            i += 1;
+            if i >= MT_N {
+                state[0] = state[MT_N - 1];
+                i = 1;
+            }
+        }
+        state[0] = 0x8000_0000;
+        mersenne
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.index >= MT_N {
+            for i in 0..MT_N {
+                let y = (self.state[i] & 0x8000_0000) | (self.state[(i + 1) % MT_N] & 0x7FFF_FFFF);
+                let mag = if y & 1 == 0 { 0 } else { 0x9908_B0DF };
+                self.state[i] = self.state[(i + MT_M) % MT_N] ^ (y >> 1) ^ mag;
+            }
+            self.index = 0;
+        }
+        let mut y = self.state[self.index];
+        self.index += 1;
+        y ^= y >> 11;
+        y ^= (y << 7) & 0x9D2C_5680;
+        y ^= (y << 15) & 0xEFC6_0000;
+        y ^ (y >> 18)
+    }
+
+    /// `random.random()`: 53 random bits
+    fn random(&mut self) -> f64 {
+        let a = (self.next_u32() >> 5) as f64;
+        let b = (self.next_u32() >> 6) as f64;
+        (a * 67108864.0 + b) / 9007199254740992.0
+    }
+}
+
+/// Python's `hash` of a float: the number modulo 2^61 - 1, keeping its sign, with -1
+/// made -2
+fn python_hash(x: f64) -> i64 {
+    const BITS: i32 = 61;
+    const MODULUS: u64 = (1 << BITS) - 1;
+    if x.is_infinite() {
+        return if x > 0.0 { 314159 } else { -314159 };
+    }
+    if x.is_nan() || x == 0.0 {
+        return 0;
+    }
+    let (mut mantissa, mut exponent) = frexp(x.abs());
+    let mut hash: u64 = 0;
+    while mantissa != 0.0 {
+        hash = ((hash << 28) & MODULUS) | (hash >> (BITS - 28));
+        mantissa *= 268435456.0;
+        exponent -= 28;
+        let digit = mantissa as u64;
+        mantissa -= digit as f64;
+        hash += digit;
+        if hash >= MODULUS {
+            hash -= MODULUS;
+        }
+    }
+    let exponent = if exponent >= 0 { exponent % BITS } else { BITS - 1 - ((-1 - exponent) % BITS) };
+    hash = ((hash << exponent) & MODULUS) | (hash >> (BITS - exponent));
+    let hash = if x < 0.0 { -(hash as i64) } else { hash as i64 };
+    if hash == -1 { -2 } else { hash }
+}
+
+/// `x` as a mantissa in [0.5, 1) times 2 to the exponent, for a positive finite `x`
+fn frexp(x: f64) -> (f64, i32) {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i32;
+    if exponent == 0 {
+        // Subnormal: scale into the normal range first
+        let (mantissa, exponent) = frexp(x * 2f64.powi(64));
+        return (mantissa, exponent - 64);
+    }
+    let mantissa = f64::from_bits((bits & !(0x7FF << 52)) | (1022 << 52));
+    (mantissa, exponent - 1022)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        let mut rng = Rng::new(42);
+        for _ in 0..1000 {
+            let v = rng.next_f64();
+            assert!((0.0..1.0).contains(&v));
+        }
+    }
+
+    #[test]
+    fn test_negative_argument_reseeds() {
+        let mut a = Rng::start(RndAlgorithm::BasicRs);
+        let mut b = Rng::new(7);
+        assert_eq!(a.rnd(-3.0), b.rnd(-3.0));
+        assert_eq!(a.rnd(1.0), b.rnd(1.0));
+        let last = a.rnd(1.0);
+        assert_eq!(a.rnd(0.0), last);
+    }
+
+    #[test]
+    fn test_microsoft() {
+        // QBasic prints .7055475, .533424 and .5795186 without RANDOMIZE, and .224007 for RND(-1)
+        let mut rng = Rng::start(RndAlgorithm::Microsoft);
+        assert_eq!(rng.rnd(0.0), 0.01953125);
+        let values: Vec<f32> = (0..3).map(|_| rng.rnd(1.0) as f32).collect();
+        assert_eq!(values, [0.7055475, 0.533424, 0.5795186]);
+        assert_eq!(rng.rnd(0.0) as f32, 0.5795186);
+        assert_eq!((rng.rnd(-1.0) * 1e7).round(), 2240070.0);
+    }
+
+    #[test]
+    fn test_trekbasic() {
+        // random.seed(0); random.random() and the like, from CPython
+        assert_eq!(Mersenne::from_int(0).random(), 0.8444218515250481);
+        assert_eq!(Mersenne::from_int(1).random(), 0.13436424411240122);
+        assert_eq!(Mersenne::from_float(-1.0).random(), 0.13119521545503066);
+        assert_eq!(Mersenne::from_float(0.5).random(), 0.2794930785867423);
+        assert_eq!(Mersenne::from_float(-0.5).random(), 0.6833350889696508);
+        let mut rng = Rng::start(RndAlgorithm::TrekBasic);
+        rng.randomize(1.5);
+        assert_eq!(rng.rnd(1.0), 0.551763726942059);
+        assert_eq!(python_hash(1.5), 1152921504606846977);
+        assert_eq!(python_hash(-1.0), -2);
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for algorithm in RndAlgorithm::ALL {
+            assert_eq!(algorithm.name().parse::<RndAlgorithm>(), Ok(algorithm));
+            assert_eq!(RndAlgorithm::from_code(algorithm.code()), algorithm);
+        }
+        assert!("python".parse::<RndAlgorithm>().unwrap_err().contains("basicrs, microsoft, trekbasic"));
+    }
+}
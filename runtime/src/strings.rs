@@ -0,0 +1,392 @@
+//! String built-in functions. Numeric arguments arrive as f64 and are truncated the way
+//! the interpreter always has (`as usize`, so negative counts become zero).
+//!
+//! A BASIC string is a string of bytes. Each byte is kept as the character with the same
+//! code, so CHR$(200), MKI$ and random file records count one character per byte.
+
+use crate::mbf::overflow_message;
+
+/// LEFT$(s, n)
+pub fn left(s: &str, n: f64) -> String {
+    s.chars().take(n as usize).collect()
+}
+
+/// RIGHT$(s, n)
+pub fn right(s: &str, n: f64) -> String {
+    let start = s.chars().count().saturating_sub(n as usize);
+    s.chars().skip(start).collect()
+}
+
+/// MID$(s, start, n) with a 1-based start, which must be at least 1
+pub fn mid(s: &str, start: f64, n: f64) -> Result<String, String> {
+    if start < 1.0 {
+        return Err(illegal_call_message("MID$"));
+    }
+    Ok(s.chars().skip(start as usize - 1).take(n as usize).collect())
+}
+
+/// CHR$(n)
+pub fn chr(n: f64) -> String {
+    ((n as u8) as char).to_string()
+}
+
+/// SPACE$(n)
+pub fn space(n: f64) -> String {
+    " ".repeat(n as usize)
+}
+
+/// LEN(s), in bytes
+pub fn len(s: &str) -> f64 {
+    s.chars().count() as f64
+}
+
+/// ASC(s): the code of the first character, or None for an empty string
+pub fn asc(s: &str) -> Option<f64> {
+    s.chars().next().map(|c| c as u8 as f64)
+}
+
+/// Error for a string function given an argument outside what it accepts
+pub fn illegal_call_message(function: &str) -> String {
+    format!("Illegal function call in {}", function)
+}
+
+/// MID$(s, start[, n]) = text: s with its characters from `start` replaced by those of
+/// `text`, at most `n` of them, and never past the end of s. `start` must fall inside s.
+pub fn mid_assign(s: &str, start: f64, n: Option<f64>, text: &str) -> Result<String, String> {
+    let length = s.chars().count();
+    if start < 1.0 || start as usize > length {
+        return Err(illegal_call_message("MID$"));
+    }
+    let start = start as usize - 1;
+    let count = n.map_or(usize::MAX, |n| n as usize).min(text.chars().count()).min(length - start);
+    Ok(s.chars().take(start)
+        .chain(text.chars().take(count))
+        .chain(s.chars().skip(start + count))
+        .collect())
+}
+
+/// INSTR(start, s, t): where t is first found in s, searching from the 1-based `start`,
+/// or 0. An empty t is found at `start` if that is inside s.
+pub fn instr(start: f64, s: &str, t: &str) -> Result<f64, String> {
+    if start < 1.0 {
+        return Err(illegal_call_message("INSTR"));
+    }
+    let chars: Vec<char> = s.chars().collect();
+    let needle: Vec<char> = t.chars().collect();
+    let start = start as usize - 1;
+    if start >= chars.len() {
+        return Ok(0.0);
+    }
+    if needle.is_empty() {
+        return Ok(start as f64 + 1.0);
+    }
+    let found = chars[start..].windows(needle.len()).position(|window| window == needle.as_slice());
+    Ok(found.map_or(0.0, |i| (start + i + 1) as f64))
+}
+
+/// STRING$(n, code): n copies of the character with this code
+pub fn string(n: f64, code: f64) -> Result<String, String> {
+    if n < 0.0 || !(0.0..256.0).contains(&code) {
+        return Err(illegal_call_message("STRING$"));
+    }
+    Ok(chr(code).repeat(n as usize))
+}
+
+/// STRING$(n, s): n copies of the first character of s
+pub fn string_of(n: f64, s: &str) -> Result<String, String> {
+    let code = asc(s).ok_or_else(|| illegal_call_message("STRING$"))?;
+    string(n, code)
+}
+
+/// UCASE$(s): only the letters A to Z change, so other bytes keep their codes
+pub fn ucase(s: &str) -> String {
+    s.to_ascii_uppercase()
+}
+
+/// LCASE$(s)
+pub fn lcase(s: &str) -> String {
+    s.to_ascii_lowercase()
+}
+
+/// LTRIM$(s): without its leading spaces, though not other blanks such as tabs
+pub fn ltrim(s: &str) -> String {
+    s.trim_start_matches(' ').to_string()
+}
+
+/// RTRIM$(s)
+pub fn rtrim(s: &str) -> String {
+    s.trim_end_matches(' ').to_string()
+}
+
+/// HEX$(n): n rounded to a whole number and written in hexadecimal. A negative number is
+/// written as its `bits`-bit two's complement, so in 16 bits HEX$(-1) is FFFF; one
+/// outside -2^(bits-1) to 2^bits - 1 is an Overflow.
+pub fn hex(n: f64, bits: u32) -> Result<String, String> {
+    radix_digits(n, bits, "HEX$").map(|n| format!("{:X}", n))
+}
+
+/// OCT$(n), as HEX$ but in octal
+pub fn oct(n: f64, bits: u32) -> Result<String, String> {
+    radix_digits(n, bits, "OCT$").map(|n| format!("{:o}", n))
+}
+
+fn radix_digits(n: f64, bits: u32, function: &str) -> Result<u64, String> {
+    let n = n.round();
+    let limit = 2f64.powi(bits as i32);
+    if n < -limit / 2.0 || n >= limit {
+        return Err(overflow_message(function));
+    }
+    Ok(if n < 0.0 { (n + limit) as u64 } else { n as u64 })
+}
+
+/// How VAL reads a number from a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValSyntax {
+    /// The number at the start, with spaces ignored, as Microsoft BASICs read it:
+    /// VAL("12AB") is 12, VAL(" 1 2") is 12 and VAL("AB") is 0
+    Prefix,
+    /// As Prefix, and `&H` and `&O` (or just `&`) start 16-bit hexadecimal and octal
+    /// numbers, as in GW-BASIC: VAL("&HFFFF") is -1
+    PrefixRadix,
+    /// The whole string, but for surrounding spaces, must be a number, as ANSI BASIC
+    /// requires: VAL("12AB") and VAL("") are illegal function calls
+    Whole,
+}
+
+impl ValSyntax {
+    pub const ALL: [ValSyntax; 3] = [ValSyntax::Prefix, ValSyntax::PrefixRadix, ValSyntax::Whole];
+
+    /// The syntax's number in compiled code's calls into the runtime
+    pub fn code(self) -> i32 {
+        Self::ALL.iter().position(|&syntax| syntax == self).unwrap_or(0) as i32
+    }
+
+    pub fn from_code(code: i32) -> ValSyntax {
+        Self::ALL.get(code.max(0) as usize).copied().unwrap_or(ValSyntax::Prefix)
+    }
+
+    /// The error VAL can raise in this syntax, if any
+    pub fn error_message(self) -> Option<String> {
+        match self {
+            ValSyntax::Prefix => None,
+            ValSyntax::PrefixRadix => Some(overflow_message("VAL")),
+            ValSyntax::Whole => Some(illegal_call_message("VAL")),
+        }
+    }
+}
+
+/// VAL(s)
+pub fn val(s: &str, syntax: ValSyntax) -> Result<f64, String> {
+    match syntax {
+        ValSyntax::Whole => {
+            let (n, used) = scan_number(s.trim_matches(' ').chars());
+            match n {
+                Some(n) if used == s.trim_matches(' ').chars().count() => Ok(n),
+                _ => Err(illegal_call_message("VAL")),
+            }
+        }
+        ValSyntax::Prefix | ValSyntax::PrefixRadix => {
+            let chars: Vec<char> = s.chars().filter(|&c| c != ' ').collect();
+            if syntax == ValSyntax::PrefixRadix && chars.first() == Some(&'&') {
+                return radix_val(&chars[1..]);
+            }
+            Ok(scan_number(chars.into_iter()).0.unwrap_or(0.0))
+        }
+    }
+}
+
+/// The digits after `&` in VAL: H for hexadecimal, O or nothing for octal
+fn radix_val(chars: &[char]) -> Result<f64, String> {
+    let (radix, digits) = match chars.first().map(|c| c.to_ascii_uppercase()) {
+        Some('H') => (16, &chars[1..]),
+        Some('O') => (8, &chars[1..]),
+        _ => (8, chars),
+    };
+    let mut n: u32 = 0;
+    for digit in digits.iter().map_while(|c| c.to_digit(radix)) {
+        n = n * radix + digit;
+        if n > 0xFFFF {
+            return Err(overflow_message("VAL"));
+        }
+    }
+    Ok(if n >= 0x8000 { n as f64 - 65536.0 } else { n as f64 })
+}
+
+/// The number at the start of `chars`: a sign, digits with perhaps a point, and perhaps an
+/// exponent after E or D. Gives the number, or None without a digit, and how many
+/// characters it took up; an exponent letter with no digits after it is not part of it.
+fn scan_number(chars: impl Iterator<Item = char>) -> (Option<f64>, usize) {
+    let mut chars = chars.peekable();
+    let mut text = String::new();
+    let mut used = 0;
+    if let Some(&sign) = chars.peek().filter(|&&c| c == '+' || c == '-') {
+        text.push(sign);
+        chars.next();
+        used += 1;
+    }
+    let mut digits = 0;
+    let mut point = false;
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            digits += 1;
+        } else if c == '.' && !point {
+            point = true;
+        } else {
+            break;
+        }
+        text.push(c);
+        chars.next();
+        used += 1;
+    }
+    if digits == 0 {
+        return (None, used);
+    }
+    if chars.next_if(|c| matches!(c.to_ascii_uppercase(), 'E' | 'D')).is_some() {
+        let mut exponent = String::from("E");
+        if let Some(sign) = chars.next_if(|&c| c == '+' || c == '-') {
+            exponent.push(sign);
+        }
+        let before = exponent.len();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            exponent.push(c);
+        }
+        if exponent.len() > before {
+            used += exponent.len();
+            text.push_str(&exponent);
+        }
+    }
+    (text.parse().ok(), used)
+}
+
+/// The bytes of a BASIC string; a character no byte stands for becomes `?`
+pub fn to_bytes(s: &str) -> Vec<u8> {
+    s.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+/// The BASIC string holding these bytes
+pub fn from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Orders two strings the way the `<`/`=`/`>` operators compare them: -1, 0 or 1
+pub fn compare(a: &str, b: &str) -> f64 {
+    match a.cmp(b) {
+        std::cmp::Ordering::Less => -1.0,
+        std::cmp::Ordering::Equal => 0.0,
+        std::cmp::Ordering::Greater => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substrings() {
+        assert_eq!(left("HELLO", 2.0), "HE");
+        assert_eq!(left("HELLO", -1.0), "");
+        assert_eq!(right("HELLO", 2.0), "LO");
+        assert_eq!(right("HELLO", 9.0), "HELLO");
+        assert_eq!(mid("HELLO", 2.0, 3.0), Ok("ELL".to_string()));
+        assert_eq!(mid("HELLO", 9.0, 2.0), Ok(String::new()));
+        assert_eq!(mid("HELLO", 0.0, 2.0), Err("Illegal function call in MID$".to_string()));
+    }
+
+    #[test]
+    fn test_character_functions() {
+        assert_eq!(chr(65.0), "A");
+        assert_eq!(space(3.0), "   ");
+        assert_eq!(len("ABC"), 3.0);
+        assert_eq!(asc("A"), Some(65.0));
+        assert_eq!(asc(""), None);
+        assert_eq!(len(&chr(200.0)), 1.0);
+        assert_eq!(right(&format!("{}AB", chr(200.0)), 2.0), "AB");
+        assert_eq!(to_bytes(&from_bytes(&[0, 65, 200, 255])), [0, 65, 200, 255]);
+    }
+
+    #[test]
+    fn test_mid_assign_and_instr() {
+        assert_eq!(mid_assign("HELLO", 2.0, None, "IPPY"), Ok("HIPPY".to_string()));
+        assert_eq!(mid_assign("HELLO", 4.0, None, "PFUL"), Ok("HELPF".to_string()));
+        assert_eq!(mid_assign("HELLO", 1.0, Some(1.0), "JX"), Ok("JELLO".to_string()));
+        assert!(mid_assign("HELLO", 6.0, None, "X").unwrap_err().contains("Illegal function call"));
+        assert!(mid_assign("HELLO", 0.0, None, "X").is_err());
+
+        assert_eq!(instr(1.0, "ABCABC", "BC"), Ok(2.0));
+        assert_eq!(instr(3.0, "ABCABC", "BC"), Ok(5.0));
+        assert_eq!(instr(1.0, "ABC", "X"), Ok(0.0));
+        assert_eq!(instr(2.0, "ABC", ""), Ok(2.0));
+        assert_eq!(instr(4.0, "ABC", ""), Ok(0.0));
+        assert_eq!(instr(1.0, "", ""), Ok(0.0));
+        assert!(instr(0.0, "ABC", "A").is_err());
+    }
+
+    #[test]
+    fn test_string_case_and_trim() {
+        assert_eq!(string(3.0, 42.0), Ok("***".to_string()));
+        assert_eq!(string_of(2.0, "AB"), Ok("AA".to_string()));
+        assert!(string_of(2.0, "").is_err());
+        assert!(string(2.0, 256.0).is_err());
+        assert!(string(-1.0, 65.0).is_err());
+        assert_eq!(ucase(&format!("ab{}", chr(233.0))), format!("AB{}", chr(233.0)));
+        assert_eq!(lcase("AbC"), "abc");
+        assert_eq!(ltrim("  A \t"), "A \t");
+        assert_eq!(rtrim("\t A  "), "\t A");
+    }
+
+    #[test]
+    fn test_hex_and_oct() {
+        assert_eq!(hex(255.0, 16), Ok("FF".to_string()));
+        assert_eq!(hex(-1.0, 16), Ok("FFFF".to_string()));
+        assert_eq!(hex(-1.0, 32), Ok("FFFFFFFF".to_string()));
+        assert_eq!(hex(65535.0, 16), Ok("FFFF".to_string()));
+        assert_eq!(hex(10.6, 16), Ok("B".to_string()));
+        assert_eq!(hex(65536.0, 16), Err("Overflow in HEX$".to_string()));
+        assert!(hex(-32769.0, 16).is_err());
+        assert_eq!(oct(8.0, 16), Ok("10".to_string()));
+        assert_eq!(oct(-1.0, 16), Ok("177777".to_string()));
+    }
+
+    #[test]
+    fn test_val() {
+        let prefix = |s| val(s, ValSyntax::Prefix).unwrap();
+        assert_eq!(prefix("12AB"), 12.0);
+        assert_eq!(prefix(" -1 2.5"), -12.5);
+        assert_eq!(prefix("1.5E3X"), 1500.0);
+        assert_eq!(prefix("2D2"), 200.0);
+        assert_eq!(prefix("3E"), 3.0);
+        assert_eq!(prefix(".5"), 0.5);
+        assert_eq!(prefix("AB"), 0.0);
+        assert_eq!(prefix(""), 0.0);
+        assert_eq!(prefix("&H10"), 0.0);
+
+        let radix = |s| val(s, ValSyntax::PrefixRadix);
+        assert_eq!(radix("&H1F"), Ok(31.0));
+        assert_eq!(radix("&hFFFF"), Ok(-1.0));
+        assert_eq!(radix("&O17"), Ok(15.0));
+        assert_eq!(radix("&17"), Ok(15.0));
+        assert_eq!(radix("&H"), Ok(0.0));
+        assert_eq!(radix("&H10000"), Err("Overflow in VAL".to_string()));
+        assert_eq!(radix("12AB"), Ok(12.0));
+
+        let whole = |s| val(s, ValSyntax::Whole);
+        assert_eq!(whole(" 12.5 "), Ok(12.5));
+        assert_eq!(whole("-1E-2"), Ok(-0.01));
+        assert_eq!(whole("12AB"), Err("Illegal function call in VAL".to_string()));
+        assert!(whole("").is_err());
+        assert!(whole("1 2").is_err());
+        assert!(whole("+").is_err());
+        assert!(whole("3E").is_err());
+
+        for syntax in ValSyntax::ALL {
+            assert_eq!(ValSyntax::from_code(syntax.code()), syntax);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("A", "B"), -1.0);
+        assert_eq!(compare("B", "B"), 0.0);
+        assert_eq!(compare("C", "B"), 1.0);
+    }
+}
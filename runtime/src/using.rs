@@ -0,0 +1,399 @@
+//! PRINT USING: values laid out by a format string, the way GW-BASIC does it.
+//!
+//! A string field is `!` (the first character), `&` (the whole string) or `\  \` (as many
+//! characters as the backslashes and the spaces between them). A number field is `#` digit
+//! positions with an optional `.`. It may start with `+` (always show the sign), `**`
+//! (fill with asterisks), `$$` (a dollar sign just before the number) or `**$`, hold a `,`
+//! before the point (commas between thousands), and end with `^^^^` (an exponent) or a
+//! `+` or `-` for a sign after the number. `_` prints the next character as it is, and
+//! so does anything that starts no field. A number too wide for its field is printed in
+//! full after a `%`.
+
+use std::fmt;
+
+/// A value PRINT USING lays out
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsingValue {
+    Number(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsingError {
+    /// The format has no field to lay out a value with
+    NoFields(String),
+    /// A string for a number field, or a number for a string field
+    TypeMismatch(String),
+}
+
+impl UsingError {
+    pub const NO_FIELDS: i32 = 1;
+    pub const TYPE_MISMATCH: i32 = 2;
+
+    /// The error's status in the compiled code's calls into the runtime; 0 is success
+    pub fn code(&self) -> i32 {
+        match self {
+            UsingError::NoFields(_) => Self::NO_FIELDS,
+            UsingError::TypeMismatch(_) => Self::TYPE_MISMATCH,
+        }
+    }
+}
+
+impl fmt::Display for UsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsingError::NoFields(format) => write!(f, "Illegal function call: PRINT USING format \"{}\" has no fields", format),
+            UsingError::TypeMismatch(field) => write!(f, "Type mismatch: PRINT USING field {}", field),
+        }
+    }
+}
+
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+enum Field {
+    Fixed(usize),  // `!` is one character wide, `\  \` is two or more
+    Whole,
+    Number(NumberField),
+}
+
+#[derive(Default)]
+struct NumberField {
+    left: usize,            // Positions before the point, counting a leading +, * and $ and commas
+    right: Option<usize>,   // Digits after the point; None when the field has no point
+    sign: Sign,
+    commas: bool,
+    stars: bool,
+    dollar: bool,
+    exponent: usize,        // Digits of the exponent: 2 for `^^^^`, 3 for `^^^^^`, 0 for none
+}
+
+#[derive(Default, PartialEq)]
+enum Sign {
+    #[default]
+    Minus,          // A minus takes one of the positions before the point
+    Leading,        // `+##`
+    TrailingPlus,   // `##+`
+    TrailingMinus,  // `##-`: a minus or a space after the number
+}
+
+/// Lays out `values` with `format`. When the values outnumber the fields the format is
+/// used again from the start; after the last value, text up to the next field is printed.
+pub fn format_using(format: &str, values: &[UsingValue]) -> Result<String, UsingError> {
+    let pieces = parse(format);
+    if !pieces.iter().any(|piece| matches!(piece, Piece::Field(_))) {
+        return Err(UsingError::NoFields(format.to_string()));
+    }
+    let mut out = String::new();
+    let mut pos = 0;
+    for value in values {
+        loop {
+            if pos == pieces.len() {
+                pos = 0;
+            }
+            pos += 1;
+            match &pieces[pos - 1] {
+                Piece::Literal(text) => out.push_str(text),
+                Piece::Field(field) => {
+                    out.push_str(&format_field(field, value)?);
+                    break;
+                }
+            }
+        }
+    }
+    while let Some(Piece::Literal(text)) = pieces.get(pos) {
+        out.push_str(text);
+        pos += 1;
+    }
+    Ok(out)
+}
+
+fn format_field(field: &Field, value: &UsingValue) -> Result<String, UsingError> {
+    match (field, value) {
+        (Field::Fixed(width), UsingValue::String(s)) => Ok(format!("{:<width$}", s.chars().take(*width).collect::<String>(), width = width)),
+        (Field::Whole, UsingValue::String(s)) => Ok(s.clone()),
+        (Field::Number(field), UsingValue::Number(n)) => Ok(field.format(*n)),
+        (Field::Number(_), UsingValue::String(s)) => Err(UsingError::TypeMismatch(format!("needs a number, got \"{}\"", s))),
+        (_, UsingValue::Number(n)) => Err(UsingError::TypeMismatch(format!("needs a string, got {}", n))),
+    }
+}
+
+fn parse(format: &str) -> Vec<Piece> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (field, len) = match chars[i] {
+            '_' => {
+                literal.push(chars.get(i + 1).copied().unwrap_or('_'));
+                i += 2;
+                continue;
+            }
+            '!' => (Some(Field::Fixed(1)), 1),
+            '&' => (Some(Field::Whole), 1),
+            '\\' => match chars[i + 1..].iter().position(|&c| c != ' ') {
+                Some(spaces) if chars[i + 1 + spaces] == '\\' => (Some(Field::Fixed(spaces + 2)), spaces + 2),
+                _ => (None, 1),
+            },
+            _ => match number_field(&chars[i..]) {
+                Some((field, len)) => (Some(Field::Number(field)), len),
+                None => (None, 1),
+            },
+        };
+        match field {
+            Some(field) => {
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(Piece::Field(field));
+            }
+            None => literal.push(chars[i]),
+        }
+        i += len;
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    pieces
+}
+
+/// The number field at the start of `chars` and its length, if one starts there
+fn number_field(chars: &[char]) -> Option<(NumberField, usize)> {
+    let starts = |i: usize, text: &str| text.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c));
+    let mut field = NumberField::default();
+    let mut i = 0;
+    if starts(0, "+") {
+        field.sign = Sign::Leading;
+        field.left += 1;
+        i += 1;
+    }
+    if starts(i, "**$") {
+        (field.stars, field.dollar) = (true, true);
+        field.left += 3;
+        i += 3;
+    } else if starts(i, "**") {
+        field.stars = true;
+        field.left += 2;
+        i += 2;
+    } else if starts(i, "$$") {
+        field.dollar = true;
+        field.left += 2;
+        i += 2;
+    }
+    let mut digits = 0;
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '#' => digits += 1,
+            ',' if digits > 0 && matches!(chars.get(i + 1), Some('#' | ',' | '.')) => field.commas = true,
+            _ => break,
+        }
+        field.left += 1;
+        i += 1;
+    }
+    if starts(i, ".") && (digits > 0 || field.stars || field.dollar || starts(i + 1, "#")) {
+        i += 1;
+        let right = chars[i..].iter().take_while(|&&c| c == '#').count();
+        field.right = Some(right);
+        i += right;
+    }
+    if digits == 0 && field.right.unwrap_or(0) == 0 && !field.stars && !field.dollar {
+        return None;
+    }
+    if starts(i, "^^^^^") {
+        field.exponent = 3;
+        i += 5;
+    } else if starts(i, "^^^^") {
+        field.exponent = 2;
+        i += 4;
+    }
+    if field.sign != Sign::Leading {
+        if starts(i, "+") {
+            field.sign = Sign::TrailingPlus;
+            i += 1;
+        } else if starts(i, "-") {
+            field.sign = Sign::TrailingMinus;
+            i += 1;
+        }
+    }
+    Some((field, i))
+}
+
+impl NumberField {
+    fn format(&self, n: f64) -> String {
+        let right = self.right.unwrap_or(0);
+        let (digits, exponent) = if self.exponent > 0 {
+            let (digits, exponent) = self.scientific(n.abs());
+            (digits, Some(exponent))
+        } else {
+            (format!("{:.0}", (n.abs() * 10f64.powi(right as i32)).round()), None)
+        };
+        let digits = format!("{:0>width$}", digits, width = right + 1);
+        let negative = n < 0.0 && digits.chars().any(|c| c != '0');
+        let (whole, fraction) = digits.split_at(digits.len() - right);
+        let mut whole = whole.to_string();
+        if self.commas {
+            whole = group_thousands(&whole);
+        }
+        if exponent.is_some() && self.mantissa_digits() == 0 {
+            whole.clear();
+        }
+
+        let mut prefix = String::new();
+        match self.sign {
+            Sign::Leading => prefix.push(if negative { '-' } else { '+' }),
+            Sign::Minus if negative => prefix.push('-'),
+            Sign::Minus if exponent.is_some() && self.left > 0 => prefix.push(' '),
+            _ => {}
+        }
+        if self.dollar {
+            prefix.push('$');
+        }
+        let mut suffix = String::new();
+        if self.right.is_some() {
+            suffix.push('.');
+            suffix.push_str(fraction);
+        }
+        if let Some(exponent) = exponent {
+            suffix.push_str(&format!("E{}{:0width$}", if exponent < 0 { '-' } else { '+' }, exponent.abs(), width = self.exponent));
+        }
+        match self.sign {
+            Sign::TrailingPlus => suffix.push(if negative { '-' } else { '+' }),
+            Sign::TrailingMinus => suffix.push(if negative { '-' } else { ' ' }),
+            _ => {}
+        }
+
+        // A lone 0 before the point goes when there is no room for it
+        if prefix.len() + whole.len() > self.left && whole == "0" && self.right.is_some() {
+            whole.clear();
+        }
+        let used = prefix.chars().count() + whole.len();
+        if used > self.left {
+            if whole.is_empty() && exponent.is_none() {
+                whole.push('0');
+            }
+            return format!("%{}{}{}", prefix, whole, suffix);
+        }
+        let fill = if self.stars { "*" } else { " " };
+        format!("{}{}{}{}", fill.repeat(self.left - used), prefix, whole, suffix)
+    }
+
+    /// Digits before the point in a number with an exponent; one position is kept for the sign
+    fn mantissa_digits(&self) -> usize {
+        match self.sign {
+            Sign::Minus | Sign::Leading => self.left.saturating_sub(1),
+            Sign::TrailingPlus | Sign::TrailingMinus => self.left,
+        }
+    }
+
+    /// The digits of `n`'s mantissa, rounded to fit the field, and its exponent
+    fn scientific(&self, n: f64) -> (String, i32) {
+        let right = self.right.unwrap_or(0) as i32;
+        let before = self.mantissa_digits() as i32;
+        if n == 0.0 {
+            return ("0".to_string(), 0);
+        }
+        let mut exponent = n.log10().floor() as i32 + 1 - before;
+        let mut scaled = (n / 10f64.powi(exponent - right)).round();
+        if scaled >= 10f64.powi(before + right) {
+            exponent += 1;
+            scaled = (n / 10f64.powi(exponent - right)).round();
+        }
+        (format!("{:.0}", scaled), exponent)
+    }
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn using(format: &str, numbers: &[f64]) -> String {
+        let values: Vec<UsingValue> = numbers.iter().map(|&n| UsingValue::Number(n)).collect();
+        format_using(format, &values).unwrap()
+    }
+
+    fn using_strings(format: &str, strings: &[&str]) -> String {
+        let values: Vec<UsingValue> = strings.iter().map(|s| UsingValue::String(s.to_string())).collect();
+        format_using(format, &values).unwrap()
+    }
+
+    // Expected output from the examples in the GW-BASIC manual
+
+    #[test]
+    fn test_digits_and_point() {
+        assert_eq!(using("##.##", &[0.78]), " 0.78");
+        assert_eq!(using("###.##", &[987.654]), "987.65");
+        assert_eq!(using("##.##  ", &[10.2, 5.3, 66.789, 0.234]), "10.20   5.30  66.79   0.23  ");
+        assert_eq!(using(".##", &[0.78]), ".78");
+        assert_eq!(using("#.##", &[-0.5]), "-.50");
+        assert_eq!(using("###", &[-12.0]), "-12");
+        assert_eq!(using("##.", &[3.0]), " 3.");
+    }
+
+    #[test]
+    fn test_signs() {
+        assert_eq!(using("+##.##  ", &[-68.95, 2.4, 55.6, -0.9]), "-68.95   +2.40  +55.60   -0.90  ");
+        assert_eq!(using("##.##-  ", &[-68.95, 22.449, -7.01]), "68.95-  22.45    7.01-  ");
+        assert_eq!(using("##.##+", &[5.0, -5.0]), " 5.00+ 5.00-");
+    }
+
+    #[test]
+    fn test_stars_dollars_and_commas() {
+        assert_eq!(using("**#.#  ", &[12.39, -0.9, 765.1]), "*12.4  *-0.9  765.1  ");
+        assert_eq!(using("$$###.##", &[456.78]), " $456.78");
+        assert_eq!(using("**$##.##", &[2.34]), "***$2.34");
+        assert_eq!(using("####,.##", &[1234.5]), "1,234.50");
+        assert_eq!(using("##,###,###", &[1234567.0]), " 1,234,567");
+    }
+
+    #[test]
+    fn test_exponents() {
+        assert_eq!(using("##.##^^^^", &[234.56]), " 2.35E+02");
+        assert_eq!(using(".####^^^^-", &[-888888.0]), ".8889E+06-");
+        assert_eq!(using("+.##^^^^", &[123.0]), "+.12E+03");
+        assert_eq!(using("##.##^^^^^", &[0.000123]), " 1.23E-004");
+        assert_eq!(using("##.##^^^^", &[9.999]), " 1.00E+01");
+        assert_eq!(using("##.##^^^^", &[0.0]), " 0.00E+00");
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(using("##.##", &[111.22]), "%111.22");
+        assert_eq!(using(".##", &[0.999]), "%1.00");
+        assert_eq!(using("#", &[-5.0]), "%-5");
+    }
+
+    #[test]
+    fn test_strings_and_literals() {
+        assert_eq!(using_strings("!", &["ABC"]), "A");
+        assert_eq!(using_strings("!!", &["ABC", "XYZ"]), "AX");
+        assert_eq!(using_strings("\\  \\", &["LOOK", "OUT"]), "LOOKOUT ");
+        assert_eq!(using_strings("\\  \\", &["LOOKING"]), "LOOK");
+        assert_eq!(using_strings("& IS &!", &["TOM", "HERE"]), "TOM IS HERE");
+        assert_eq!(using("_!##.##_!", &[12.34]), "!12.34!");
+        assert_eq!(using("TOTAL: $###.## DUE", &[12.5]), "TOTAL: $ 12.50 DUE");
+        assert_eq!(using("A=# ", &[1.0, 2.0]), "A=1 A=2 ");
+    }
+
+    #[test]
+    fn test_errors() {
+        let values = [UsingValue::Number(1.0)];
+        assert_eq!(format_using("ABC", &values).unwrap_err().code(), 1);
+        assert_eq!(format_using("&", &values).unwrap_err().code(), 2);
+        let err = format_using("##", &[UsingValue::String("X".to_string())]).unwrap_err();
+        assert_eq!(err.to_string(), "Type mismatch: PRINT USING field needs a number, got \"X\"");
+    }
+}
@@ -0,0 +1,332 @@
+//! Jump targets of multi-line blocks.
+//!
+//! The clauses of a block IF or SELECT CASE can be many lines apart. Rather than
+//! searching for the next ELSEIF, CASE or END each time a condition is tested, the targets
+//! are found once, when the program is loaded, and kept by statement location:
+//! (line index, offset). The same pass pairs every WHILE and DO with its WEND or LOOP,
+//! and checks that each one is closed.
+
+use std::collections::HashMap;
+use crate::basic_types::{BasicError, ErrorCondition, LoopKind, Program, Statement};
+
+/// Why a loop statement has nowhere to go: a WHILE or DO without its WEND or LOOP, a
+/// closer of the wrong kind before it, or a WEND, LOOP or EXIT DO outside any loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopMismatch {
+    pub condition: ErrorCondition,
+    pub message: String,
+    pub line_number: usize, // Where the error is reported
+}
+
+impl LoopMismatch {
+    /// The runtime error a program gets when it reaches the statement
+    pub fn to_error(&self) -> BasicError {
+        BasicError::Runtime {
+            message: self.message.clone(),
+            condition: self.condition,
+            basic_line_number: Some(self.line_number),
+            file_line_number: None,
+        }
+    }
+}
+
+/// The other end of a loop, or why there is none
+pub type LoopTarget = Result<(usize, usize), LoopMismatch>;
+
+#[derive(Debug, Clone, Default)]
+pub struct BlockTargets {
+    next_clause: HashMap<(usize, usize), (usize, usize)>, // IF, ELSEIF, SELECT or CASE -> the clause tried next when it does not match
+    block_end: HashMap<(usize, usize), (usize, usize)>,   // ELSEIF, ELSE, CASE or CASE ELSE -> its END IF or END SELECT
+    loop_end: HashMap<(usize, usize), LoopTarget>,        // WHILE or DO -> its WEND or LOOP
+    loop_start: HashMap<(usize, usize), LoopTarget>,      // WEND, LOOP or EXIT DO -> its WHILE or DO
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    If,
+    Select,
+}
+
+impl BlockKind {
+    fn opener(&self) -> &'static str {
+        match self {
+            BlockKind::If => "IF",
+            BlockKind::Select => "SELECT CASE",
+        }
+    }
+
+    fn else_clause(&self) -> &'static str {
+        match self {
+            BlockKind::If => "ELSE",
+            BlockKind::Select => "CASE ELSE",
+        }
+    }
+
+    fn closer(&self) -> &'static str {
+        match self {
+            BlockKind::If => "END IF",
+            BlockKind::Select => "END SELECT",
+        }
+    }
+}
+
+// A block that has not reached its END yet
+struct OpenBlock {
+    kind: BlockKind,
+    start: (usize, usize),            // The IF or SELECT CASE
+    last_clause: (usize, usize),      // The opening statement, or its latest clause
+    clauses: Vec<(usize, usize)>,     // Clause statements, which jump to the END
+    has_else: bool,
+}
+
+impl BlockTargets {
+    /// Pairs every block IF and SELECT CASE with its clauses, and every WHILE and DO with
+    /// its WEND or LOOP, in one pass over the program. A clause or END outside its kind of
+    /// block, a clause after the ELSE, or a block without its END, is a syntax error, as
+    /// is a WHILE or DO that no WEND or LOOP closes. A loop inside a SUB or FUNCTION must
+    /// close there, and one outside passes over them. Other mismatched loops are left for
+    /// the program to report when it meets them.
+    pub fn resolve(program: &Program) -> Result<Self, BasicError> {
+        let mut targets = BlockTargets::default();
+        let mut open: Vec<OpenBlock> = Vec::new();
+        let mut open_loops: Vec<(LoopKind, (usize, usize))> = Vec::new(); // WHILE or DO not yet closed
+        let mut outer_loops = Vec::new(); // Those outside the procedure being read
+
+        for (i, line) in program.lines.iter().enumerate() {
+            for (offset, stmt) in line.statements.iter().enumerate() {
+                let here = (i, offset);
+                match stmt {
+                    Statement::Sub { .. } | Statement::Function { .. } => outer_loops = std::mem::take(&mut open_loops),
+                    Statement::EndSub | Statement::EndFunction => {
+                        if let Some(error) = Self::unclosed_loop(program, &open_loops) {
+                            return Err(error);
+                        }
+                        open_loops = std::mem::take(&mut outer_loops);
+                    }
+                    Statement::ExitDo => {
+                        let start = open_loops.iter().rev().find(|(kind, _)| *kind == LoopKind::Do)
+                            .map(|(_, start)| *start)
+                            .ok_or_else(|| LoopMismatch {
+                                condition: ErrorCondition::WendWithoutWhile,
+                                message: "EXIT DO without DO".to_string(),
+                                line_number: line.line_number,
+                            });
+                        targets.loop_start.insert(here, start);
+                    }
+                    _ => {}
+                }
+                match LoopKind::of(stmt) {
+                    Some((kind, true)) => open_loops.push((kind, here)),
+                    Some((kind, false)) => targets.close_loop(program, &mut open_loops, kind, here),
+                    None => {}
+                }
+                let error = |message: String| BasicError::Syntax {
+                    message,
+                    basic_line_number: Some(line.line_number),
+                    file_line_number: None,
+                };
+                let (kind, clause_name, is_else) = match stmt {
+                    Statement::IfBlock { .. } | Statement::SelectCase { .. } => {
+                        let kind = if matches!(stmt, Statement::IfBlock { .. }) { BlockKind::If } else { BlockKind::Select };
+                        open.push(OpenBlock { kind, start: here, last_clause: here, clauses: Vec::new(), has_else: false });
+                        continue;
+                    }
+                    Statement::EndIf | Statement::EndSelect => {
+                        let kind = if matches!(stmt, Statement::EndIf) { BlockKind::If } else { BlockKind::Select };
+                        let block = match open.pop() {
+                            Some(block) if block.kind == kind => block,
+                            Some(block) => return Err(error(format!("Mismatched {}: expected {} for the {} at line {}",
+                                                                    kind.closer(), block.kind.closer(), block.kind.opener(),
+                                                                    program.lines[block.start.0].line_number))),
+                            None => return Err(error(format!("{} without {}", kind.closer(), kind.opener()))),
+                        };
+                        targets.next_clause.insert(block.last_clause, here);
+                        for clause in block.clauses {
+                            targets.block_end.insert(clause, here);
+                        }
+                        continue;
+                    }
+                    Statement::ElseIf { .. } => (BlockKind::If, "ELSEIF", false),
+                    Statement::ElseBlock => (BlockKind::If, "ELSE", true),
+                    Statement::Case { .. } => (BlockKind::Select, "CASE", false),
+                    Statement::CaseElse => (BlockKind::Select, "CASE ELSE", true),
+                    _ => continue,
+                };
+                let Some(block) = open.last_mut().filter(|block| block.kind == kind) else {
+                    return Err(error(format!("{} without {}", clause_name, kind.opener())));
+                };
+                if block.has_else {
+                    return Err(error(format!("{} after {}", clause_name, kind.else_clause())));
+                }
+                targets.next_clause.insert(block.last_clause, here);
+                block.last_clause = here;
+                block.clauses.push(here);
+                block.has_else = is_else;
+            }
+        }
+
+        // Report the outermost block left open, which starts first
+        if let Some(block) = open.first() {
+            return Err(BasicError::Syntax {
+                message: format!("{} without {}", block.kind.opener(), block.kind.closer()),
+                basic_line_number: Some(program.lines[block.start.0].line_number),
+                file_line_number: None,
+            });
+        }
+        if let Some(error) = Self::unclosed_loop(program, &open_loops) {
+            return Err(error);
+        }
+        Ok(targets)
+    }
+
+    /// Pairs a WEND or LOOP with the innermost open loop, which must be of its kind. A
+    /// closer of the wrong kind stays unpaired, and is where every loop still open stops
+    /// with an error, since each of them would meet it before its own closer.
+    fn close_loop(&mut self, program: &Program, open_loops: &mut Vec<(LoopKind, (usize, usize))>, kind: LoopKind, here: (usize, usize)) {
+        let line_number = program.lines[here.0].line_number;
+        match open_loops.last().copied() {
+            Some((open_kind, start)) if open_kind == kind => {
+                open_loops.pop();
+                self.loop_end.entry(start).or_insert(Ok(here));
+                self.loop_start.insert(here, Ok(start));
+            }
+            Some((open_kind, start)) => {
+                let mismatch = LoopMismatch {
+                    condition: ErrorCondition::WendWithoutWhile,
+                    message: format!("Mismatched {}: expected {} for the {} at line {}",
+                                     kind.closer(), open_kind.closer(), open_kind.opener(), program.lines[start.0].line_number),
+                    line_number,
+                };
+                for (_, start) in open_loops.iter() {
+                    self.loop_end.entry(*start).or_insert_with(|| Err(mismatch.clone()));
+                }
+                self.loop_start.insert(here, Err(mismatch));
+            }
+            None => {
+                self.loop_start.insert(here, Err(LoopMismatch {
+                    condition: ErrorCondition::WendWithoutWhile,
+                    message: format!("{} without {}", kind.closer(), kind.opener()),
+                    line_number,
+                }));
+            }
+        }
+    }
+
+    /// The syntax error for the outermost of the loops left open, if any
+    fn unclosed_loop(program: &Program, open_loops: &[(LoopKind, (usize, usize))]) -> Option<BasicError> {
+        open_loops.first().map(|(kind, start)| BasicError::Syntax {
+            message: format!("{} without {}", kind.opener(), kind.closer()),
+            basic_line_number: Some(program.lines[start.0].line_number),
+            file_line_number: None,
+        })
+    }
+
+    /// The clause to try when the IF, ELSEIF or CASE at `location` does not match; for a
+    /// SELECT CASE, its first clause
+    pub fn next_clause(&self, location: (usize, usize)) -> Option<(usize, usize)> {
+        self.next_clause.get(&location).copied()
+    }
+
+    /// The END IF or END SELECT where the branch ending at the clause at `location` continues
+    pub fn block_end(&self, location: (usize, usize)) -> Option<(usize, usize)> {
+        self.block_end.get(&location).copied()
+    }
+
+    /// The WEND or LOOP that closes the WHILE or DO at `location`
+    pub fn loop_end(&self, location: (usize, usize)) -> Option<&LoopTarget> {
+        self.loop_end.get(&location)
+    }
+
+    /// The WHILE or DO that the WEND, LOOP or EXIT DO at `location` belongs to
+    pub fn loop_start(&self, location: (usize, usize)) -> Option<&LoopTarget> {
+        self.loop_start.get(&location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_lexer::Lexer;
+    use crate::basic_parser::Parser;
+
+    fn parse_lines(source: &str) -> Program {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse_lines().unwrap()
+    }
+
+    #[test]
+    fn test_block_if_targets() {
+        let program = parse_lines("10 IF A THEN\n20 PRINT 1\n30 ELSEIF B THEN\n40 IF C THEN\n50 END IF\n60 ELSE\n70 PRINT 3\n80 END IF");
+        let targets = BlockTargets::resolve(&program).unwrap();
+
+        assert_eq!(targets.next_clause((0, 0)), Some((2, 0)));
+        assert_eq!(targets.next_clause((2, 0)), Some((5, 0)));
+        assert_eq!(targets.next_clause((5, 0)), Some((7, 0)));
+        assert_eq!(targets.next_clause((3, 0)), Some((4, 0)));
+        assert_eq!(targets.block_end((2, 0)), Some((7, 0)));
+        assert_eq!(targets.block_end((5, 0)), Some((7, 0)));
+        assert_eq!(targets.block_end((0, 0)), None);
+
+        let program = parse_lines("10 SELECT CASE A\n20 CASE 1, 2\n30 CASE IS > 5\n40 CASE ELSE\n50 END SELECT");
+        let targets = BlockTargets::resolve(&program).unwrap();
+        assert_eq!(targets.next_clause((0, 0)), Some((1, 0)));
+        assert_eq!(targets.next_clause((1, 0)), Some((2, 0)));
+        assert_eq!(targets.next_clause((2, 0)), Some((3, 0)));
+        assert_eq!(targets.block_end((1, 0)), Some((4, 0)));
+        assert_eq!(targets.block_end((3, 0)), Some((4, 0)));
+
+        // A closer of the wrong kind is left for the interpreter, as long as every loop closes
+        let program = parse_lines("10 WHILE A\n20 DO\n30 WEND\n40 LOOP\n50 WEND");
+        assert!(BlockTargets::resolve(&program).is_ok());
+    }
+
+    #[test]
+    fn test_loop_targets() {
+        let program = parse_lines("10 WHILE A\n20 DO\n30 EXIT DO\n40 LOOP\n50 WEND");
+        let targets = BlockTargets::resolve(&program).unwrap();
+        assert_eq!(targets.loop_end((0, 0)), Some(&Ok((4, 0))));
+        assert_eq!(targets.loop_end((1, 0)), Some(&Ok((3, 0))));
+        assert_eq!(targets.loop_start((2, 0)), Some(&Ok((1, 0))));
+        assert_eq!(targets.loop_start((4, 0)), Some(&Ok((0, 0))));
+
+        // Both loops would meet the WEND at line 30 before their own closers
+        let program = parse_lines("10 WHILE A\n20 DO\n30 WEND\n40 LOOP\n50 WEND");
+        let targets = BlockTargets::resolve(&program).unwrap();
+        for start in [(0, 0), (1, 0)] {
+            let mismatch = targets.loop_end(start).unwrap().clone().unwrap_err();
+            assert_eq!(mismatch.message, "Mismatched WEND: expected LOOP for the DO at line 20");
+            assert_eq!(mismatch.line_number, 30);
+        }
+        assert_eq!(targets.loop_start((3, 0)), Some(&Ok((1, 0))));
+
+        // A procedure's loops are its own: the LOOP inside the SUB does not close the DO
+        let program = parse_lines("10 DO\n20 SUB S\n30 LOOP\n40 EXIT DO\n50 END SUB\n60 LOOP");
+        let targets = BlockTargets::resolve(&program).unwrap();
+        assert_eq!(targets.loop_end((0, 0)), Some(&Ok((5, 0))));
+        assert_eq!(targets.loop_start((2, 0)).unwrap().clone().unwrap_err().message, "LOOP without DO");
+        assert_eq!(targets.loop_start((3, 0)).unwrap().clone().unwrap_err().message, "EXIT DO without DO");
+    }
+
+    #[test]
+    fn test_unbalanced_blocks() {
+        let cases = [
+            ("10 IF A THEN\n20 PRINT 1", "IF without END IF", 10),
+            ("10 IF A THEN\n20 IF B THEN\n30 END IF", "IF without END IF", 10),
+            ("10 END IF", "END IF without IF", 10),
+            ("10 PRINT 1\n20 ELSE", "ELSE without IF", 20),
+            ("10 IF A THEN\n20 ELSE\n30 ELSEIF B THEN\n40 END IF", "ELSEIF after ELSE", 30),
+            ("10 SELECT CASE A\n20 CASE 1", "SELECT CASE without END SELECT", 10),
+            ("10 SELECT CASE A\n20 CASE ELSE\n30 CASE 1\n40 END SELECT", "CASE after CASE ELSE", 30),
+            ("10 IF A THEN\n20 CASE 1\n30 END IF", "CASE without SELECT CASE", 20),
+            ("10 SELECT CASE A\n20 END IF", "Mismatched END IF: expected END SELECT for the SELECT CASE at line 10", 20),
+            ("10 WHILE 1\n20 PRINT 1\n30 END", "WHILE without WEND", 10),
+            ("10 DO\n20 WHILE A\n30 WEND\n40 WHILE B\n50 LOOP", "DO without LOOP", 10),
+            ("10 SUB S\n20 WHILE A\n30 END SUB\n40 WEND", "WHILE without WEND", 20),
+        ];
+        for (source, message, line) in cases {
+            let err = BlockTargets::resolve(&parse_lines(source)).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", source, err);
+            assert!(matches!(err, BasicError::Syntax { basic_line_number: Some(n), .. } if n == line));
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! The terminal a program runs on.
+//!
+//! The interpreter writes PRINT output and reads INPUT through a `Console`, so it can run
+//! inside another program: a test, a GUI, or a bot playing a game in-process. A console
+//! keeps track of the output column, which PRINT's commas and TAB are measured from.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use basic_runtime::PrintCursor;
+
+pub trait Console {
+    /// Writes `text` at the cursor
+    fn write(&mut self, text: &str) -> io::Result<()>;
+
+    /// Reads a line typed for INPUT, with its line ending; "" at the end of input.
+    /// Output then starts a new line.
+    fn read_line(&mut self) -> io::Result<String>;
+
+    /// Makes sure everything written has been shown
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Column the next character goes in, 0 at the start of a line
+    fn column(&self) -> usize;
+}
+
+/// The process's stdout and stdin
+#[derive(Debug, Default)]
+pub struct StdioConsole {
+    cursor: PrintCursor,
+}
+
+impl StdioConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Console for StdioConsole {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        io::stdout().write_all(text.as_bytes())?;
+        self.cursor.advance(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        self.cursor.newline();
+        Ok(line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn column(&self) -> usize {
+        self.cursor.column()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buffers {
+    output: String,
+    input: VecDeque<String>,
+    cursor: PrintCursor,
+}
+
+/// Output kept in memory, and input from lines queued beforehand. Clones share the same
+/// buffers, so a caller can keep one and hand the other to the interpreter.
+#[derive(Debug, Clone, Default)]
+pub struct BufferConsole {
+    buffers: Rc<RefCell<Buffers>>,
+}
+
+impl BufferConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a line for INPUT to read
+    pub fn push_input(&self, line: &str) {
+        self.buffers.borrow_mut().input.push_back(line.to_string());
+    }
+
+    /// Everything written so far
+    pub fn output(&self) -> String {
+        self.buffers.borrow().output.clone()
+    }
+
+    /// Everything written since the last call, which this clears
+    pub fn take_output(&self) -> String {
+        std::mem::take(&mut self.buffers.borrow_mut().output)
+    }
+}
+
+impl Console for BufferConsole {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let mut buffers = self.buffers.borrow_mut();
+        buffers.output.push_str(text);
+        buffers.cursor.advance(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut buffers = self.buffers.borrow_mut();
+        buffers.cursor.newline();
+        Ok(buffers.input.pop_front().map(|line| line + "\n").unwrap_or_default())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn column(&self) -> usize {
+        self.buffers.borrow().cursor.column()
+    }
+}
+
+/// Answers INPUT from a script of lines, writing each to the console it wraps as though
+/// it had been typed. Asking for more input than the script has is an error.
+pub struct ScriptedConsole<C: Console> {
+    inner: C,
+    script: VecDeque<String>,
+}
+
+impl<C: Console> ScriptedConsole<C> {
+    pub fn new<I, S>(inner: C, script: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ScriptedConsole { inner, script: script.into_iter().map(Into::into).collect() }
+    }
+
+    /// Lines of the script not read yet
+    pub fn remaining(&self) -> usize {
+        self.script.len()
+    }
+}
+
+impl<C: Console> Console for ScriptedConsole<C> {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.inner.write(text)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let line = self.script.pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the input script has no more lines"))?;
+        self.inner.write(&format!("{}\n", line))?;
+        Ok(line + "\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn column(&self) -> usize {
+        self.inner.column()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_console() {
+        let buffer = BufferConsole::new();
+        let mut console = buffer.clone();
+        console.write("AB").unwrap();
+        assert_eq!(console.column(), 2);
+        buffer.push_input("YES");
+        assert_eq!(console.read_line().unwrap(), "YES\n");
+        assert_eq!(console.column(), 0);
+        assert_eq!(console.read_line().unwrap(), "");
+        console.write("C\nDE").unwrap();
+        assert_eq!(console.column(), 2);
+        assert_eq!(buffer.take_output(), "ABC\nDE");
+        assert_eq!(buffer.output(), "");
+    }
+
+    #[test]
+    fn test_scripted_console() {
+        let buffer = BufferConsole::new();
+        let mut console = ScriptedConsole::new(buffer.clone(), ["1, 2"]);
+        console.write("? ").unwrap();
+        assert_eq!(console.read_line().unwrap(), "1, 2\n");
+        assert_eq!(console.remaining(), 0);
+        assert_eq!(buffer.output(), "? 1, 2\n");
+        assert_eq!(console.read_line().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
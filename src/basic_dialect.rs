@@ -1,58 +1,434 @@
-/// Configuration constants for controlling BASIC dialect features.
-///
-/// This module defines switches that control various language features that vary
-/// between different dialects of BASIC. These settings allow the interpreter to
-/// emulate different BASIC variants by changing behavior for arrays, operators,
-/// input handling, etc. We expect to add more, as we support more programs.
+//! Configuration for controlling BASIC dialect features.
+//!
+//! A `Dialect` holds the switches for language features that vary between different
+//! dialects of BASIC. The lexer, parser, symbol table and interpreter each take one, so a
+//! single binary can emulate different BASIC variants by changing behavior for arrays,
+//! operators, input handling, etc. We expect to add more, as we support more programs.
 
-// =============================================================================
-// OPERATOR CONFIGURATION
-// =============================================================================
+use std::fmt;
+use std::str::FromStr;
+use crate::basic_types::{ErrorCondition, VarType};
+use basic_runtime::format::NumberFormat;
+use basic_runtime::precision::Precision;
+use basic_runtime::rng::RndAlgorithm;
+use basic_runtime::strings::ValSyntax;
 
-/// Operator used for exponentiation in mathematical expressions
-/// Standard options: '^' (most common) or '**' (requires lexer changes)
-pub const EXPONENTIATION_OPERATOR: &str = "^";
+/// Error codes of Microsoft BASIC-80 and GW-BASIC
+pub const MICROSOFT_ERROR_CODES: &[(ErrorCondition, u16)] = &[
+    (ErrorCondition::NextWithoutFor, 1),
+    (ErrorCondition::Syntax, 2),
+    (ErrorCondition::ReturnWithoutGosub, 3),
+    (ErrorCondition::OutOfData, 4),
+    (ErrorCondition::IllegalFunctionCall, 5),
+    (ErrorCondition::Overflow, 6),
+    (ErrorCondition::OutOfMemory, 7),
+    (ErrorCondition::UndefinedLine, 8),
+    (ErrorCondition::SubscriptOutOfRange, 9),
+    (ErrorCondition::DuplicateDefinition, 10),
+    (ErrorCondition::DivisionByZero, 11),
+    (ErrorCondition::TypeMismatch, 13),
+    (ErrorCondition::StringTooLong, 15),
+    (ErrorCondition::UndefinedUserFunction, 18),
+    (ErrorCondition::ResumeWithoutError, 20),
+    (ErrorCondition::WhileWithoutWend, 29),
+    (ErrorCondition::WendWithoutWhile, 30),
+    (ErrorCondition::BadFileNumber, 52),
+    (ErrorCondition::FileNotFound, 53),
+    (ErrorCondition::BadFileMode, 54),
+    (ErrorCondition::FileAlreadyOpen, 55),
+    (ErrorCondition::InputPastEnd, 62),
+    (ErrorCondition::PathAccess, 75),
+    (ErrorCondition::FieldOverflow, 50),
+    (ErrorCondition::BadRecordNumber, 63),
+    (ErrorCondition::ForWithoutNext, 26),
+    (ErrorCondition::BadInput, 13),
+    (ErrorCondition::Unprintable, 21),
+];
 
-// =============================================================================
-// ARRAY CONFIGURATION
-// =============================================================================
+/// Error codes of Microsoft's 6502 BASIC, as Applesoft's ONERR reports them
+pub const MICROSOFT_6502_ERROR_CODES: &[(ErrorCondition, u16)] = &[
+    (ErrorCondition::NextWithoutFor, 0),
+    (ErrorCondition::Syntax, 16),
+    (ErrorCondition::ReturnWithoutGosub, 22),
+    (ErrorCondition::OutOfData, 42),
+    (ErrorCondition::IllegalFunctionCall, 53),
+    (ErrorCondition::Overflow, 69),
+    (ErrorCondition::OutOfMemory, 77),
+    (ErrorCondition::UndefinedLine, 90),
+    (ErrorCondition::SubscriptOutOfRange, 107),
+    (ErrorCondition::DuplicateDefinition, 120),
+    (ErrorCondition::DivisionByZero, 133),
+    (ErrorCondition::TypeMismatch, 163),
+    (ErrorCondition::StringTooLong, 176),
+    (ErrorCondition::UndefinedUserFunction, 224),
+    (ErrorCondition::BadInput, 254),
+    (ErrorCondition::Unprintable, 255),
+];
 
-/// Base index for array subscripts
-/// 0 = Zero-based arrays (like C, Rust): A(0) is first element
-/// 1 = One-based arrays (traditional BASIC): A(1) is first element
-pub const ARRAY_OFFSET: usize = 1;
+/// How long variable names may be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableNames {
+    /// A letter and an optional digit, then the type suffix: A, A1, A1$
+    Short,
+    /// Letters and digits, of which only the first two count, as in Microsoft BASIC 2.0:
+    /// PLAYER and PLANET are both PL. A keyword inside a name is still read as one, so
+    /// SCORE is SC OR E.
+    TwoSignificant,
+    /// Letters and digits, all of which count, as in GW-BASIC and QBasic. A name may
+    /// hold a keyword, but not start with one.
+    Full,
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    /// Profile name, as given to `--dialect`
+    pub name: &'static str,
 
-/// Controls whether user input is automatically converted to uppercase
-/// true = Convert input to uppercase (traditional BASIC behavior)
-/// false = Preserve original case of user input
-pub const UPPERCASE_INPUT: bool = true;
+    // =========================================================================
+    // OPERATOR CONFIGURATION
+    // =========================================================================
 
+    /// Operator used for exponentiation in mathematical expressions
+    /// '^' is always accepted; "**" additionally lexes as exponentiation
+    pub exponentiation_operator: &'static str,
 
-// =============================================================================
-// Not yet implemented features:
-// =============================================================================
+    // =========================================================================
+    // ARRAY CONFIGURATION
+    // =========================================================================
 
-/// Maximum line number
-pub const MAX_LINE_NUMBER: usize = 99999;
+    /// Base index for array subscripts. DIM gives the highest subscript.
+    /// 0 = Zero-based arrays: DIM A(10) holds A(0) to A(10)
+    /// 1 = One-based arrays: DIM A(10) holds A(1) to A(10)
+    pub array_offset: usize,
 
-/// Maximum string length
-pub const MAX_STRING_LENGTH: usize = 255;
+    // =========================================================================
+    // INPUT CONFIGURATION
+    // =========================================================================
 
-// Maximum array dimensions
-pub const MAX_ARRAY_DIMS: usize = 2;
+    /// Controls whether user input is automatically converted to uppercase
+    /// true = Convert input to uppercase (traditional BASIC behavior)
+    /// false = Preserve original case of user input
+    pub uppercase_input: bool,
 
-// Maximum number of variables
-pub const MAX_VARIABLES: usize = 1000;
+    // =========================================================================
+    // NAME CONFIGURATION
+    // =========================================================================
 
-// Maximum recursion depth
-pub const MAX_RECURSION_DEPTH: usize = 100;
+    /// Whether variable names may be longer than a letter and a digit, and how much of
+    /// them counts
+    pub variable_names: VariableNames,
 
-// Maximum number of nested FOR loops
-pub const MAX_FOR_DEPTH: usize = 200;
+    // =========================================================================
+    // NUMERIC CONFIGURATION
+    // =========================================================================
 
-// Maximum number of nested GOSUB calls
-pub const MAX_GOSUB_DEPTH: usize = 200;
+    /// Precision numbers are computed and stored in; every operation and assignment
+    /// rounds to it
+    pub precision: Precision,
 
+    /// Bits in an integer variable, `A%`: 16 in Microsoft BASICs. Storing a number
+    /// outside their range is an Overflow.
+    pub integer_bits: u32,
 
- 
\ No newline at end of file
+    /// Value of a true comparison: -1, every bit set, in Microsoft BASICs, so that AND,
+    /// OR and NOT work on conditions bit by bit; or 1
+    pub true_value: i32,
+
+    /// Whether NOT gives true for 0 and 0 for anything else, rather than flipping the bits
+    /// of an integer as Microsoft BASICs do
+    pub logical_not: bool,
+
+    /// Generator RND draws from. Microsoft and TrekBasic reproduce those BASICs' sequences
+    /// exactly, given the same seed.
+    pub rnd: RndAlgorithm,
+
+    /// How VAL reads its string: whether VAL("12AB") is 12 or an error, and whether
+    /// `&H` starts a hexadecimal number
+    pub val: ValSyntax,
+
+    // =========================================================================
+    // OUTPUT CONFIGURATION
+    // =========================================================================
+
+    /// How PRINT, STR$ and WRITE # write numbers
+    pub number_format: NumberFormat,
+
+    // =========================================================================
+    // LIMITS
+    // =========================================================================
+
+    /// Maximum line number
+    pub max_line_number: usize,
+
+    /// Maximum string length
+    pub max_string_length: usize,
+
+    /// Maximum array dimensions
+    pub max_array_dims: usize,
+
+    /// Maximum number of variables
+    pub max_variables: usize,
+
+    /// Maximum recursion depth
+    pub max_recursion_depth: usize,
+
+    /// Maximum number of nested FOR loops
+    pub max_for_depth: usize,
+
+    /// Maximum number of nested GOSUB calls
+    pub max_gosub_depth: usize,
+
+    // =========================================================================
+    // ERROR TRAPPING
+    // =========================================================================
+
+    /// The value ERR returns for each error condition. Conditions missing from the
+    /// table use the code for `ErrorCondition::Unprintable`.
+    pub error_codes: &'static [(ErrorCondition, u16)],
+}
+
+impl Dialect {
+    /// BasicRS's own dialect, used when no other is selected
+    pub const BASICRS: Dialect = Dialect {
+        name: "basicrs",
+        exponentiation_operator: "^",
+        array_offset: 1,
+        uppercase_input: true,
+        variable_names: VariableNames::Short,
+        precision: Precision::Double,
+        integer_bits: 32,
+        true_value: 1,
+        logical_not: true,
+        rnd: RndAlgorithm::BasicRs,
+        val: ValSyntax::PrefixRadix,
+        number_format: NumberFormat::DOUBLE,
+        max_line_number: 99999,
+        max_string_length: 255,
+        max_array_dims: 2,
+        max_variables: 1000,
+        max_recursion_depth: 100,
+        max_for_depth: 200,
+        max_gosub_depth: 200,
+        error_codes: MICROSOFT_ERROR_CODES,
+    };
+
+    /// Dartmouth BASIC: upper-case teletype input, `**` for powers
+    pub const DARTMOUTH: Dialect = Dialect {
+        name: "dartmouth",
+        exponentiation_operator: "**",
+        array_offset: 0,
+        uppercase_input: true,
+        variable_names: VariableNames::Short,
+        precision: Precision::Double,
+        integer_bits: 32,
+        true_value: 1,
+        logical_not: true,
+        rnd: RndAlgorithm::BasicRs,
+        val: ValSyntax::Whole,
+        number_format: NumberFormat { digits: 6, min_fixed_exponent: -3, str_sign_space: false },
+        max_line_number: 99999,
+        max_string_length: 255,
+        max_array_dims: 2,
+        max_variables: 286,
+        max_recursion_depth: 100,
+        max_for_depth: 26,
+        max_gosub_depth: 64,
+        error_codes: MICROSOFT_ERROR_CODES,
+    };
+
+    /// Microsoft BASIC 2 (Altair, Commodore, Apple Applesoft era)
+    pub const MSBASIC2: Dialect = Dialect {
+        name: "msbasic2",
+        exponentiation_operator: "^",
+        array_offset: 0,
+        uppercase_input: false,
+        variable_names: VariableNames::TwoSignificant,
+        precision: Precision::Mbf40,
+        integer_bits: 16,
+        true_value: -1,
+        logical_not: false,
+        rnd: RndAlgorithm::BasicRs,
+        val: ValSyntax::Prefix,
+        number_format: NumberFormat { digits: 9, min_fixed_exponent: -2, str_sign_space: true },
+        max_line_number: 63999,
+        max_string_length: 255,
+        max_array_dims: 255,
+        max_variables: 1000,
+        max_recursion_depth: 100,
+        max_for_depth: 100,
+        max_gosub_depth: 100,
+        error_codes: MICROSOFT_6502_ERROR_CODES,
+    };
+
+    /// GW-BASIC
+    pub const GWBASIC: Dialect = Dialect {
+        name: "gwbasic",
+        exponentiation_operator: "^",
+        array_offset: 0,
+        uppercase_input: false,
+        variable_names: VariableNames::Full,
+        precision: Precision::Single,
+        integer_bits: 16,
+        true_value: -1,
+        logical_not: false,
+        rnd: RndAlgorithm::BasicRs,
+        val: ValSyntax::PrefixRadix,
+        number_format: NumberFormat { digits: 7, min_fixed_exponent: -3, str_sign_space: true },
+        max_line_number: 65529,
+        max_string_length: 255,
+        max_array_dims: 255,
+        max_variables: 1000,
+        max_recursion_depth: 100,
+        max_for_depth: 200,
+        max_gosub_depth: 200,
+        error_codes: MICROSOFT_ERROR_CODES,
+    };
+
+    /// ANSI X3.60 Minimal BASIC, at the minimums the standard requires
+    pub const ANSI_MINIMAL: Dialect = Dialect {
+        name: "ansi-minimal",
+        exponentiation_operator: "^",
+        array_offset: 0,
+        uppercase_input: true,
+        variable_names: VariableNames::Short,
+        precision: Precision::Double,
+        integer_bits: 32,
+        true_value: 1,
+        logical_not: true,
+        rnd: RndAlgorithm::BasicRs,
+        val: ValSyntax::Whole,
+        number_format: NumberFormat { digits: 6, min_fixed_exponent: -3, str_sign_space: false },
+        max_line_number: 9999,
+        max_string_length: 18,
+        max_array_dims: 2,
+        max_variables: 286,
+        max_recursion_depth: 100,
+        max_for_depth: 26,
+        max_gosub_depth: 26,
+        error_codes: MICROSOFT_ERROR_CODES,
+    };
+
+    /// Every named profile, in the order `--help` lists them
+    pub const PROFILES: [Dialect; 5] = [
+        Dialect::BASICRS,
+        Dialect::DARTMOUTH,
+        Dialect::MSBASIC2,
+        Dialect::GWBASIC,
+        Dialect::ANSI_MINIMAL,
+    ];
+
+    /// Looks up a profile by name, ignoring case
+    pub fn by_name(name: &str) -> Option<Dialect> {
+        Self::PROFILES.iter().find(|d| d.name.eq_ignore_ascii_case(name)).copied()
+    }
+
+    pub fn profile_names() -> Vec<&'static str> {
+        Self::PROFILES.iter().map(|d| d.name).collect()
+    }
+
+    /// The code ERR returns for an error condition
+    pub fn error_code(&self, condition: ErrorCondition) -> u16 {
+        let lookup = |wanted: ErrorCondition| self.error_codes.iter().find(|(c, _)| *c == wanted).map(|(_, code)| *code);
+        lookup(condition).or_else(|| lookup(ErrorCondition::Unprintable)).unwrap_or(0)
+    }
+
+    /// The condition VAL raises for a string it rejects: an `&H` number too big for 16
+    /// bits, or a string that is not wholly a number
+    pub fn val_error(&self) -> ErrorCondition {
+        match self.val {
+            ValSyntax::Whole => ErrorCondition::IllegalFunctionCall,
+            ValSyntax::Prefix | ValSyntax::PrefixRadix => ErrorCondition::Overflow,
+        }
+    }
+
+    /// The type of a numeric variable without a suffix: `A` is `A!` in GW-BASIC and `A#`
+    /// in BasicRS. The 40-bit numbers of the 6502 BASICs are neither.
+    pub fn default_type(&self) -> Option<VarType> {
+        match self.precision {
+            Precision::Double => Some(VarType::Double),
+            Precision::Single => Some(VarType::Single),
+            Precision::Mbf40 => None,
+        }
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::BASICRS
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Dialect::by_name(name).ok_or_else(|| {
+            format!("unknown dialect '{}' (expected one of: {})", name, Dialect::profile_names().join(", "))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_types::BasicError;
+    use basic_runtime::files::FileErrorKind;
+
+    #[test]
+    fn test_profiles_by_name() {
+        assert_eq!(Dialect::by_name("gwbasic"), Some(Dialect::GWBASIC));
+        assert_eq!("ANSI-Minimal".parse::<Dialect>(), Ok(Dialect::ANSI_MINIMAL));
+        assert_eq!(Dialect::default(), Dialect::BASICRS);
+        for dialect in Dialect::PROFILES {
+            assert_eq!(dialect.name.parse::<Dialect>(), Ok(dialect));
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let division = BasicError::Runtime {
+            message: "Division by zero".to_string(),
+            condition: ErrorCondition::DivisionByZero,
+            basic_line_number: Some(10),
+            file_line_number: None,
+        };
+        let condition = division.condition().unwrap();
+        assert_eq!(Dialect::GWBASIC.error_code(condition), 11);
+        assert_eq!(Dialect::MSBASIC2.error_code(condition), 133);
+        // Applesoft has RESUME but no code for one without an error, so the catch-all is used
+        assert_eq!(Dialect::MSBASIC2.error_code(ErrorCondition::ResumeWithoutError), 255);
+        assert_eq!(Dialect::GWBASIC.error_code(ErrorCondition::ForWithoutNext), 26);
+        assert_eq!(Dialect::MSBASIC2.error_code(ErrorCondition::BadInput), 254);
+
+        // The condition is the one the error was raised with, whatever its message says
+        let missing = BasicError::Runtime {
+            message: "File not found: Division by zero.txt".to_string(),
+            condition: FileErrorKind::FileNotFound.into(),
+            basic_line_number: None,
+            file_line_number: None,
+        };
+        assert_eq!(Dialect::GWBASIC.error_code(missing.condition().unwrap()), 53);
+        let syntax = BasicError::Syntax { message: "Expected THEN".to_string(), basic_line_number: None, file_line_number: None };
+        assert_eq!(syntax.condition(), Some(ErrorCondition::Syntax));
+        let mismatch = BasicError::Type { message: "IF condition must evaluate to a number".to_string(), basic_line_number: None, file_line_number: None };
+        assert_eq!(Dialect::GWBASIC.error_code(mismatch.condition().unwrap()), 13);
+        let internal = BasicError::Internal { message: "I/O error".to_string(), basic_line_number: None, file_line_number: None };
+        assert_eq!(internal.condition(), None);
+
+        assert_eq!(Dialect::GWBASIC.val_error(), ErrorCondition::Overflow);
+        assert_eq!(Dialect::ANSI_MINIMAL.val_error(), ErrorCondition::IllegalFunctionCall);
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let err = "fortran".parse::<Dialect>().unwrap_err();
+        assert!(err.contains("fortran"));
+        assert!(err.contains("dartmouth"));
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
-use crate::basic_types::BasicError;
-use rand::Rng;
-use rand::SeedableRng;
-use rand::rngs::StdRng;
+use std::sync::Mutex;
+use crate::basic_types::{BasicError, ErrorCondition};
+use basic_runtime::format::{self, NumberFormat};
+use basic_runtime::rng::{Rng, RndAlgorithm};
+use basic_runtime::{mbf, strings};
+use basic_runtime::strings::ValSyntax;
 
 /// Argument types for BASIC functions
 #[derive(Clone, Debug, PartialEq)]
@@ -36,17 +38,27 @@ pub struct FunctionDef {
 
 pub struct FunctionRegistry {
     functions: HashMap<&'static str, FunctionDef>,
+    other_forms: HashMap<&'static str, Vec<Vec<ArgType>>>, // Argument lists accepted besides `arg_types`
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FunctionRegistry {
     pub fn new() -> Self {
         let mut registry = FunctionRegistry {
             functions: HashMap::new(),
+            other_forms: HashMap::new(),
         };
         
         // Register all built-in functions
         registry.register_math_functions();
         registry.register_string_functions();
+        registry.register_error_functions();
+        registry.register_file_functions();
         
         registry
     }
@@ -118,30 +130,25 @@ impl FunctionRegistry {
             },
         });
         
-        // RND function
+        // RND function. A running program draws from its own generator, which RANDOMIZE
+        // and --seed set; calls made here share BasicRS's.
         self.functions.insert("RND", FunctionDef {
             name: "RND",
             function_type: FunctionType::Number,
             arg_types: vec![ArgType::Number],
             implementation: |args| {
                 let value: f64 = args[0].parse().unwrap();
-                if value < 0.0 {
-                    // Negative values seed the generator and return a random number
-                    let seed = (value.abs() * 1000000.0) as u64;
-                    let mut rng = StdRng::seed_from_u64(seed);
-                    let result: f64 = rng.gen();
-                    Ok(result.to_string())
-                } else if value == 0.0 {
-                    // Return random number between 0 and 1
-                    let result: f64 = rand::thread_rng().gen();
-                    Ok(result.to_string())
-                } else {
-                    // Return random number between 0 and 1
-                    let result: f64 = rand::thread_rng().gen();
-                    Ok(result.to_string())
-                }
+                Ok(RND_GENERATOR.lock().unwrap().rnd(value).to_string())
             },
         });
+
+        // TIMER function: seconds since midnight
+        self.functions.insert("TIMER", FunctionDef {
+            name: "TIMER",
+            function_type: FunctionType::Number,
+            arg_types: vec![],
+            implementation: |_| Ok(basic_runtime::rng::timer().to_string()),
+        });
         
         // SGN function
         self.functions.insert("SGN", FunctionDef {
@@ -192,6 +199,19 @@ impl FunctionRegistry {
                 Ok(value.tan().to_string())
             },
         });
+
+        // DET is the determinant of the array MAT INV last inverted, which only the
+        // running program knows. Like ERR it is written without parentheses.
+        self.functions.insert("DET", FunctionDef {
+            name: "DET",
+            function_type: FunctionType::Number,
+            arg_types: vec![],
+            implementation: |_| Err(BasicError::Internal {
+                message: "DET is evaluated by the interpreter".to_string(),
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        });
     }
     
     fn register_string_functions(&mut self) {
@@ -202,15 +222,15 @@ impl FunctionRegistry {
             arg_types: vec![ArgType::String],
             implementation: |args| {
                 let s = args[0].trim_matches('"');
-                if s.is_empty() {
-                    return Err(BasicError::Syntax {
+                match strings::asc(s) {
+                    Some(ascii_value) => Ok(ascii_value.to_string()),
+                    None => Err(BasicError::Runtime {
                         message: "ASC requires a non-empty string".to_string(),
+                        condition: ErrorCondition::IllegalFunctionCall,
                         basic_line_number: None,
                         file_line_number: None,
-                    });
+                    }),
                 }
-                let ascii_value = s.chars().next().unwrap() as u8;
-                Ok(ascii_value.to_string())
             },
         });
         
@@ -221,8 +241,7 @@ impl FunctionRegistry {
             arg_types: vec![ArgType::Number],
             implementation: |args| {
                 let ascii_value: f64 = args[0].parse().unwrap();
-                let ascii_value = ascii_value as u8;
-                Ok((ascii_value as char).to_string())
+                Ok(strings::chr(ascii_value))
             },
         });
         
@@ -234,9 +253,7 @@ impl FunctionRegistry {
             implementation: |args| {
                 let s = args[0].trim_matches('"');
                 let len: f64 = args[1].parse().unwrap();
-                let len = len as usize;
-                let result = s.chars().take(len).collect::<String>();
-                Ok(result)
+                Ok(strings::left(s, len))
             },
         });
         
@@ -247,11 +264,11 @@ impl FunctionRegistry {
             arg_types: vec![ArgType::String],
             implementation: |args| {
                 let s = args[0].trim_matches('"');
-                Ok(s.len().to_string())
+                Ok(strings::len(s).to_string())
             },
         });
         
-        // MID$ function
+        // MID$ function; without a length, it goes to the end of the string
         self.functions.insert("MID$", FunctionDef {
             name: "MID$",
             function_type: FunctionType::String,
@@ -259,13 +276,11 @@ impl FunctionRegistry {
             implementation: |args| {
                 let s = args[0].trim_matches('"');
                 let start: f64 = args[1].parse().unwrap();
-                let len: f64 = args[2].parse().unwrap();
-                let start = (start as usize).saturating_sub(1);
-                let len = len as usize;
-                let result = s.chars().skip(start).take(len).collect::<String>();
-                Ok(result)
+                let len: f64 = args.get(2).map_or(strings::len(s), |len| len.parse().unwrap());
+                strings::mid(s, start, len).map_err(function_error(ErrorCondition::IllegalFunctionCall))
             },
         });
+        self.other_forms.insert("MID$", vec![vec![ArgType::String, ArgType::Number]]);
         
         // RIGHT$ function
         self.functions.insert("RIGHT$", FunctionDef {
@@ -275,10 +290,7 @@ impl FunctionRegistry {
             implementation: |args| {
                 let s = args[0].trim_matches('"');
                 let len: f64 = args[1].parse().unwrap();
-                let len = len as usize;
-                let start = s.len().saturating_sub(len);
-                let result = s.chars().skip(start).collect::<String>();
-                Ok(result)
+                Ok(strings::right(s, len))
             },
         });
         
@@ -289,20 +301,18 @@ impl FunctionRegistry {
             arg_types: vec![ArgType::Number],
             implementation: |args| {
                 let count: f64 = args[0].parse().unwrap();
-                let count = count as usize;
-                let result = " ".repeat(count);
-                Ok(result)
+                Ok(strings::space(count))
             },
         });
         
-        // STR$ function
+        // STR$ function; the interpreter calls it with the dialect's number format instead
         self.functions.insert("STR$", FunctionDef {
             name: "STR$",
             function_type: FunctionType::String,
             arg_types: vec![ArgType::Number],
             implementation: |args| {
                 let value: f64 = args[0].parse().unwrap();
-                Ok(value.to_string())
+                Ok(format::str_number(value, &NumberFormat::default()))
             },
         });
         
@@ -318,6 +328,178 @@ impl FunctionRegistry {
                 Ok(format!("TAB({})", column))
             },
         });
+
+        // INSTR function; the start is optional and comes first
+        self.functions.insert("INSTR", FunctionDef {
+            name: "INSTR",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::Number, ArgType::String, ArgType::String],
+            implementation: |args| {
+                let (start, strings) = match args {
+                    [start, rest @ ..] if args.len() == 3 => (start.parse().unwrap(), rest),
+                    _ => (1.0, args),
+                };
+                strings::instr(start, &strings[0], &strings[1]).map(|n| n.to_string()).map_err(function_error(ErrorCondition::IllegalFunctionCall))
+            },
+        });
+        self.other_forms.insert("INSTR", vec![vec![ArgType::String, ArgType::String]]);
+
+        // STRING$ function: copies of a character given by code or, in the interpreter,
+        // as the first character of a string
+        self.functions.insert("STRING$", FunctionDef {
+            name: "STRING$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number, ArgType::Number],
+            implementation: |args| {
+                strings::string(args[0].parse().unwrap(), args[1].parse().unwrap()).map_err(function_error(ErrorCondition::IllegalFunctionCall))
+            },
+        });
+        self.other_forms.insert("STRING$", vec![vec![ArgType::Number, ArgType::String]]);
+
+        self.functions.insert("UCASE$", FunctionDef {
+            name: "UCASE$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::String],
+            implementation: |args| Ok(strings::ucase(&args[0])),
+        });
+
+        self.functions.insert("LCASE$", FunctionDef {
+            name: "LCASE$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::String],
+            implementation: |args| Ok(strings::lcase(&args[0])),
+        });
+
+        self.functions.insert("LTRIM$", FunctionDef {
+            name: "LTRIM$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::String],
+            implementation: |args| Ok(strings::ltrim(&args[0])),
+        });
+
+        self.functions.insert("RTRIM$", FunctionDef {
+            name: "RTRIM$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::String],
+            implementation: |args| Ok(strings::rtrim(&args[0])),
+        });
+
+        // VAL, HEX$ and OCT$ depend on the dialect; the interpreter calls them with its own
+        self.functions.insert("VAL", FunctionDef {
+            name: "VAL",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::String],
+            implementation: |args| strings::val(&args[0], ValSyntax::PrefixRadix).map(|n| n.to_string()).map_err(function_error(ErrorCondition::Overflow)),
+        });
+
+        self.functions.insert("HEX$", FunctionDef {
+            name: "HEX$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number],
+            implementation: |args| strings::hex(args[0].parse().unwrap(), 32).map_err(function_error(ErrorCondition::Overflow)),
+        });
+
+        self.functions.insert("OCT$", FunctionDef {
+            name: "OCT$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number],
+            implementation: |args| strings::oct(args[0].parse().unwrap(), 32).map_err(function_error(ErrorCondition::Overflow)),
+        });
+    }
+    
+    fn register_error_functions(&mut self) {
+        // ERR and ERL report the last trapped error, which only the running program knows.
+        // They take no arguments and are written without parentheses.
+        self.functions.insert("ERR", FunctionDef {
+            name: "ERR",
+            function_type: FunctionType::Number,
+            arg_types: vec![],
+            implementation: |_| Err(BasicError::Internal {
+                message: "ERR is evaluated by the interpreter".to_string(),
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        });
+
+        self.functions.insert("ERL", FunctionDef {
+            name: "ERL",
+            function_type: FunctionType::Number,
+            arg_types: vec![],
+            implementation: |_| Err(BasicError::Internal {
+                message: "ERL is evaluated by the interpreter".to_string(),
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        });
+    }
+    
+    fn register_file_functions(&mut self) {
+        // EOF and LOF ask about a file the running program has open
+        self.functions.insert("EOF", FunctionDef {
+            name: "EOF",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::Number],
+            implementation: |_| Err(BasicError::Internal {
+                message: "EOF is evaluated by the interpreter".to_string(),
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        });
+
+        self.functions.insert("LOF", FunctionDef {
+            name: "LOF",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::Number],
+            implementation: |_| Err(BasicError::Internal {
+                message: "LOF is evaluated by the interpreter".to_string(),
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        });
+
+        // MKI$, MKS$ and MKD$ pack numbers into strings for random file records; CVI,
+        // CVS and CVD unpack them
+        self.functions.insert("MKI$", FunctionDef {
+            name: "MKI$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number],
+            implementation: |args| mbf::mki(args[0].parse().unwrap()).map_err(function_error(ErrorCondition::Overflow)),
+        });
+
+        self.functions.insert("MKS$", FunctionDef {
+            name: "MKS$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number],
+            implementation: |args| mbf::mks(args[0].parse().unwrap()).map_err(function_error(ErrorCondition::Overflow)),
+        });
+
+        self.functions.insert("MKD$", FunctionDef {
+            name: "MKD$",
+            function_type: FunctionType::String,
+            arg_types: vec![ArgType::Number],
+            implementation: |args| mbf::mkd(args[0].parse().unwrap()).map_err(function_error(ErrorCondition::Overflow)),
+        });
+
+        self.functions.insert("CVI", FunctionDef {
+            name: "CVI",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::String],
+            implementation: |args| mbf::cvi(&args[0]).map(|n| n.to_string()).map_err(function_error(ErrorCondition::IllegalFunctionCall)),
+        });
+
+        self.functions.insert("CVS", FunctionDef {
+            name: "CVS",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::String],
+            implementation: |args| mbf::cvs(&args[0]).map(|n| n.to_string()).map_err(function_error(ErrorCondition::IllegalFunctionCall)),
+        });
+
+        self.functions.insert("CVD", FunctionDef {
+            name: "CVD",
+            function_type: FunctionType::Number,
+            arg_types: vec![ArgType::String],
+            implementation: |args| mbf::cvd(&args[0]).map(|n| n.to_string()).map_err(function_error(ErrorCondition::IllegalFunctionCall)),
+        });
     }
     
     // Public API methods
@@ -365,6 +547,7 @@ impl FunctionRegistry {
         } else {
             Err(BasicError::Runtime {
                 message: format!("Unknown function: {}", name),
+                condition: ErrorCondition::Unprintable,
                 basic_line_number: None,
                 file_line_number: None,
             })
@@ -379,6 +562,32 @@ impl FunctionRegistry {
         self.functions.get(name).map(|def| def.arg_types.len())
     }
 
+    /// Every argument list a function accepts: its `arg_types`, then any others, such as
+    /// MID$ without a length
+    pub fn get_arg_forms(&self, name: &str) -> Vec<&[ArgType]> {
+        let Some(def) = self.functions.get(name) else { return Vec::new() };
+        std::iter::once(def.arg_types.as_slice())
+            .chain(self.other_forms.get(name).into_iter().flatten().map(Vec::as_slice))
+            .collect()
+    }
+
+    /// The argument list a call uses, given whether each argument is a string: the first
+    /// form with that many arguments of those types, or else the first with that many,
+    /// for the caller to report the mismatched types of. No form that long is an error.
+    pub fn select_arg_form(&self, name: &str, is_string: &[bool]) -> Result<&[ArgType], String> {
+        let forms = self.get_arg_forms(name);
+        let matches = |form: &[ArgType]| form.iter().zip(is_string).all(|(arg_type, &string)| (*arg_type == ArgType::String) == string);
+        let same_length: Vec<&[ArgType]> = forms.iter().copied().filter(|form| form.len() == is_string.len()).collect();
+        if let Some(form) = same_length.iter().find(|form| matches(form)).or(same_length.first()) {
+            return Ok(form);
+        }
+        let mut counts: Vec<usize> = forms.iter().map(|form| form.len()).collect();
+        counts.sort();
+        counts.dedup();
+        let counts: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+        Err(format!("Function '{}' expects {} arguments, got {}", name, counts.join(" or "), is_string.len()))
+    }
+
     /// Call a numeric function with f64 arguments (for interpreter use)
     pub fn call_numeric_function(&self, name: &str, args: &[f64]) -> Option<f64> {
         if self.is_numeric_function(name) {
@@ -407,6 +616,7 @@ impl FunctionRegistry {
                     Token::Identifier(name, IdentifierType::Variable) => Ok(name),
                     _ => Err(BasicError::Runtime {
                         message: format!("Invalid token: {:?}", t),
+                        condition: ErrorCondition::Unprintable,
                         basic_line_number: None,
                         file_line_number: None,
                     }),
@@ -424,6 +634,7 @@ impl FunctionRegistry {
         } else {
             Err(BasicError::Runtime {
                 message: format!("Unknown function '{}'", name),
+                condition: ErrorCondition::Unprintable,
                 basic_line_number: None,
                 file_line_number: None,
             })
@@ -431,9 +642,16 @@ impl FunctionRegistry {
     }
 }
 
+/// Makes the runtime error, with its condition, for a function given an argument it
+/// cannot use, such as "Overflow in MKS$" or "Illegal function call in INSTR"
+fn function_error(condition: ErrorCondition) -> impl Fn(String) -> BasicError {
+    move |message| BasicError::Runtime { message, condition, basic_line_number: None, file_line_number: None }
+}
+
 // Global singleton instance
 lazy_static::lazy_static! {
     pub static ref FUNCTION_REGISTRY: FunctionRegistry = FunctionRegistry::new();
+    static ref RND_GENERATOR: Mutex<Rng> = Mutex::new(Rng::start(RndAlgorithm::BasicRs));
 }
 
 #[cfg(test)]
@@ -467,6 +685,29 @@ mod tests {
         assert!(registry.is_function("SPACE$"));
         assert!(registry.is_function("STR$"));
         assert!(registry.is_function("TAB"));
+        assert!(registry.is_numeric_function("VAL"));
+        assert!(registry.is_numeric_function("INSTR"));
+        assert!(registry.is_string_function("STRING$"));
+        assert!(registry.is_string_function("UCASE$"));
+        assert!(registry.is_string_function("LCASE$"));
+        assert!(registry.is_string_function("LTRIM$"));
+        assert!(registry.is_string_function("RTRIM$"));
+        assert!(registry.is_string_function("HEX$"));
+        assert!(registry.is_string_function("OCT$"));
+
+        assert_eq!(registry.get_arg_count("DET"), Some(0));
+        assert_eq!(registry.get_arg_count("TIMER"), Some(0));
+
+        // Error trapping functions
+        assert!(registry.is_numeric_function("ERR"));
+        assert_eq!(registry.get_arg_count("ERL"), Some(0));
+
+        // File functions
+        assert!(registry.is_numeric_function("EOF"));
+        assert_eq!(registry.get_arg_count("LOF"), Some(1));
+        assert!(registry.is_string_function("MKI$"));
+        assert!(registry.is_string_function("MKD$"));
+        assert!(registry.is_numeric_function("CVS"));
     }
     
     #[test]
@@ -496,6 +737,16 @@ mod tests {
         assert_eq!(result, "5");
     }
     
+    #[test]
+    fn test_rnd_function() {
+        // A negative argument starts the sequence it names, as in a program
+        let registry = FunctionRegistry::new();
+        let first = registry.call_numeric_function("RND", &[-3.0]).unwrap();
+        assert!((0.0..1.0).contains(&first));
+        assert_eq!(registry.call_numeric_function("RND", &[0.0]), Some(first));
+        assert_eq!(registry.call_numeric_function("RND", &[-3.0]), Some(first));
+    }
+
     #[test]
     fn test_chr_function() {
         let registry = FunctionRegistry::new();
@@ -546,4 +797,15 @@ mod tests {
         assert_eq!(registry.get_arg_count("MID$"), Some(3));
         assert_eq!(registry.get_arg_count("NONEXISTENT"), None);
     }
+
+    #[test]
+    fn test_select_arg_form() {
+        let registry = FunctionRegistry::new();
+        assert_eq!(registry.select_arg_form("MID$", &[true, false]), Ok(&[ArgType::String, ArgType::Number][..]));
+        assert_eq!(registry.select_arg_form("INSTR", &[true, true]), Ok(&[ArgType::String, ArgType::String][..]));
+        assert_eq!(registry.select_arg_form("INSTR", &[false, true, true]).map(|form| form.len()), Ok(3));
+        assert_eq!(registry.select_arg_form("STRING$", &[false, true]), Ok(&[ArgType::Number, ArgType::String][..]));
+        assert_eq!(registry.select_arg_form("MID$", &[true]),
+                   Err("Function 'MID$' expects 2 or 3 arguments, got 1".to_string()));
+    }
 } 
\ No newline at end of file
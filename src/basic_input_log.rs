@@ -0,0 +1,97 @@
+//! Recordings of the lines typed for INPUT, so a session can be played back exactly.
+//!
+//! A recording has one line per response: the BASIC line of the INPUT statement, when it
+//! was typed (UTC, to the millisecond) and what was typed, separated by tabs, as in
+//! `120<tab>2026-10-16T09:30:12.345Z<tab>Y`.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use chrono::{SecondsFormat, Utc};
+
+/// One response in a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRecord {
+    pub line: usize,
+    pub timestamp: String,
+    pub text: String,
+}
+
+impl InputRecord {
+    fn parse(entry: &str) -> Option<InputRecord> {
+        let mut fields = entry.splitn(3, '\t');
+        let line = fields.next()?.parse().ok()?;
+        let timestamp = fields.next()?.to_string();
+        let text = fields.next()?.to_string();
+        Some(InputRecord { line, timestamp, text })
+    }
+}
+
+/// Writes each response to a recording as it is typed
+pub struct InputRecorder {
+    file: File,
+}
+
+impl InputRecorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(InputRecorder { file: File::create(path)? })
+    }
+
+    /// Records `text`, without its line ending, as typed for the INPUT on BASIC line `line`
+    pub fn record(&mut self, line: usize, text: &str) -> io::Result<()> {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        writeln!(self.file, "{}\t{}\t{}", line, timestamp, text)
+    }
+}
+
+/// Responses read back from a recording, handed out in order
+#[derive(Debug)]
+pub struct InputReplay {
+    records: VecDeque<InputRecord>,
+}
+
+impl InputReplay {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(recording: &str) -> io::Result<Self> {
+        let records = recording.lines()
+            .enumerate()
+            .filter(|(_, entry)| !entry.is_empty())
+            .map(|(i, entry)| InputRecord::parse(entry).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {} of the recording is not LINE<tab>TIME<tab>TEXT", i + 1))
+            }))
+            .collect::<io::Result<_>>()?;
+        Ok(InputReplay { records })
+    }
+
+    /// The next response, which must have been typed for the INPUT on BASIC line `line`,
+    /// or None when every response has been used. A response for another line is left in
+    /// place.
+    pub fn next(&mut self, line: usize) -> Result<Option<String>, String> {
+        match self.records.front() {
+            Some(record) if record.line == line => Ok(self.records.pop_front().map(|record| record.text)),
+            Some(record) => Err(format!("Replay expected INPUT at line {}, but the program asked at line {}", record.line, line)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let mut replay = InputReplay::parse("120\t2026-10-16T09:30:12.345Z\tY\n130\t2026-10-16T09:30:15.002Z\t3,\t4\n").unwrap();
+        assert_eq!(replay.next(120), Ok(Some("Y".to_string())));
+        assert!(replay.next(140).unwrap_err().contains("Replay expected INPUT at line 130, but the program asked at line 140"));
+        assert_eq!(replay.next(130), Ok(Some("3,\t4".to_string())));
+        assert_eq!(replay.next(130), Ok(None));
+
+        let mut replay = InputReplay::parse("130\tT\t3,\t4").unwrap();
+        assert_eq!(replay.next(130), Ok(Some("3,\t4".to_string())));
+        assert!(InputReplay::parse("120 Y").unwrap_err().to_string().contains("line 1 of the recording"));
+    }
+}
@@ -1,18 +1,31 @@
 use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
 use std::fmt;
 use std::fs::File;
+use std::path::PathBuf;
 use std::io::{self, Write};
 use crate::basic_symbols::SymbolTable;
+use crate::basic_console::Console;
+use crate::basic_input_log::{InputRecorder, InputReplay};
 use crate::basic_reports::CoverageData;
 
 use crate::basic_types::{
     Program, ProgramLine, Statement, Expression, BasicError,
-    ExpressionType, RunStatus, SymbolValue, Token, PrintItem,
+    ExpressionType, RunStatus, SymbolValue, Token, PrintItem, ResumeTarget, LoopCondition, CaseTest,
+    MatValue, VarType, ErrorCondition, LoopKind,
 };
 
 use crate::basic_function_registry::FUNCTION_REGISTRY;
-use crate::basic_operators::{BASIC_FALSE_F, BASIC_TRUE_F};
-use crate::basic_dialect::UPPERCASE_INPUT;
+use crate::basic_blocks::BlockTargets;
+use crate::basic_procedures::{Procedure, ProcedureKind, Procedures};
+use crate::basic_operators::BASIC_FALSE_F;
+use crate::basic_dialect::Dialect;
+use basic_runtime::files::{self, FileError, FileTable};
+use basic_runtime::using::{self, UsingError, UsingValue};
+use basic_runtime::matrix::{self, Matrix, Operation};
+use basic_runtime::operators::{self, IntegerOp};
+use basic_runtime::rng::{self, Rng};
+use basic_runtime::{format, input, limits, precision, strings, PrintCursor};
 
 const TRACE_FILE_NAME: &str = "basic_trace.txt";
 
@@ -38,13 +51,50 @@ pub struct ForRecord {
     pub stmt: Option<ControlLocation>, // Statement location
 }
 
+// WHILE or DO loop record
+#[derive(Debug, Clone)]
+pub struct LoopRecord {
+    pub kind: LoopKind,
+    pub stmt: ControlLocation,  // The WHILE or DO statement
+}
+
+// An error sent to the ON ERROR GOTO handler, kept until RESUME
+#[derive(Debug)]
+struct ErrorTrap {
+    location: ControlLocation,  // The statement that failed
+    error: BasicError,
+}
+
+// A SUB or FUNCTION call in progress
+struct ProcedureFrame {
+    name: String,
+    return_to: ControlLocation,         // The CALL, or the statement that called the FUNCTION
+    caller_scope: Option<SymbolTable>,  // The caller's own variables, if it is a procedure
+    for_base: usize,                    // Stack depths at the call: the records below
+    loop_base: usize,                   // them belong to the caller
+    gosub_base: usize,
+}
+
 pub struct Interpreter {
     program: Program,
     location: ControlLocation,
     internal_symbols: SymbolTable,  // Internal symbol table for function definitions
     symbols: SymbolTable,           // Current scope symbol table
     for_stack: Vec<ForRecord>,
+    loop_stack: Vec<LoopRecord>,    // Active WHILE and DO loops
+    blocks: BlockTargets,           // Block IF, SELECT CASE and loop jump targets, found when the program is loaded
+    procedures: Procedures,         // SUB and FUNCTION extents and variables, found the same way
+    frames: Vec<ProcedureFrame>,    // SUB and FUNCTION calls in progress
+    in_scope: bool,                 // `symbols` is a procedure or DEF FN scope, its parent the program's variables
+    function_result: Option<SymbolValue>, // Set by END FUNCTION, for the expression that called it
     gosub_stack: Vec<ControlLocation>,
+    fn_depth: usize,                // Nesting depth of user function calls
+    error_handler: Option<usize>,   // ON ERROR GOTO line, while trapping is on
+    error_trap: Option<ErrorTrap>,  // The error being handled, until RESUME
+    err_code: u16,                  // ERR: code of the last trapped error
+    err_line: usize,                // ERL: line of the last trapped error
+    determinant: f64,               // DET: of the array MAT INV last inverted
+    rng: Rng,                       // What RND draws from, in the dialect's algorithm
     data_pointer: usize,
     data_values: Vec<SymbolValue>,
     data_line_map: HashMap<usize, usize>, // Maps line numbers to data positions
@@ -59,7 +109,11 @@ pub struct Interpreter {
     // the next statement in the main loop, we are already where we want to be. So set this
     // on control transfers. (GOTO, GOSUB, FOR/NEXT, IF. Anything else?)
     advance_stmt: bool,
-    cursor_position: usize,     // Current cursor position for PRINT formatting
+    console: Box<dyn Console>,  // Where PRINT writes and INPUT reads
+    input_recorder: Option<InputRecorder>, // Logs every INPUT response, for --record-input
+    input_replay: Option<InputReplay>,     // Answers INPUT instead of the console, for --replay-input
+    files: FileTable,           // Files opened by OPEN, by file number
+    dialect: Dialect,
 }
 
 impl Interpreter {
@@ -73,9 +127,10 @@ impl Interpreter {
                     file_line_number,
                 }
             }
-            BasicError::Runtime { message, basic_line_number: None, file_line_number } => {
+            BasicError::Runtime { message, condition, basic_line_number: None, file_line_number } => {
                 BasicError::Runtime {
                     message,
+                    condition,
                     basic_line_number: Some(self.get_current_line().line_number),
                     file_line_number,
                 }
@@ -107,11 +162,13 @@ impl Interpreter {
                 SymbolValue::Number(n) if n >= 0.0 => Ok(n.trunc() as usize),
                 SymbolValue::Number(n) => Err(BasicError::Runtime {
                     message: format!("Array index must be non-negative, got: {}", n),
+                    condition: ErrorCondition::SubscriptOutOfRange,
                     basic_line_number: Some(self.get_current_line().line_number),
                     file_line_number: None,
                 }),
                 _ => Err(BasicError::Runtime {
                     message: "Array index must be a number".to_string(),
+                    condition: ErrorCondition::TypeMismatch,
                     basic_line_number: Some(self.get_current_line().line_number),
                     file_line_number: None,
                 })
@@ -119,7 +176,11 @@ impl Interpreter {
             .collect()
     }
 
-    pub fn new(program: Program) -> Self {
+    pub fn new(program: Program, console: Box<dyn Console>) -> Self {
+        Self::with_dialect(program, Dialect::default(), console)
+    }
+
+    pub fn with_dialect(program: Program, dialect: Dialect, console: Box<dyn Console>) -> Self {
         let mut line_number_map = HashMap::new();
         for (i, line) in program.lines.iter().enumerate() {
             line_number_map.insert(line.line_number, i);
@@ -127,16 +188,33 @@ impl Interpreter {
 
 
 
-        let internal_symbols = SymbolTable::new();
+        let internal_symbols = SymbolTable::with_dialect(dialect);
         let symbols = internal_symbols.get_nested_scope();
-        
+        // The parser rejects unbalanced blocks, but the shell edits programs a line at a
+        // time; such a program fails when it reaches a block statement
+        let blocks = BlockTargets::resolve(&program).unwrap_or_default();
+        let procedures = Procedures::resolve(&program).unwrap_or_default();
+
         Interpreter {
             program,
             location: ControlLocation { index: 0, offset: 0 },
             internal_symbols,
             symbols,
             for_stack: Vec::new(),
+            loop_stack: Vec::new(),
+            blocks,
+            procedures,
+            frames: Vec::new(),
+            in_scope: false,
+            function_result: None,
             gosub_stack: Vec::new(),
+            fn_depth: 0,
+            error_handler: None,
+            error_trap: None,
+            err_code: 0,
+            err_line: 0,
+            determinant: 0.0,
+            rng: Rng::start(dialect.rnd),
             data_pointer: 0,
             data_values: Vec::new(), // Initialize to empty, data values are collected later
             data_line_map: HashMap::new(),
@@ -147,7 +225,11 @@ impl Interpreter {
             data_breakpoints: HashSet::new(),
             line_number_map,
             advance_stmt: true,
-            cursor_position: 0,
+            console,
+            input_recorder: None,
+            input_replay: None,
+            files: FileTable::new(),
+            dialect,
         }
     }
 
@@ -170,7 +252,8 @@ impl Interpreter {
             if self.location.index + 1 < self.program.lines.len() {
                 self.location.index += 1;
                 self.location.offset =  0;
-            } else {
+            } else if self.run_status == RunStatus::Run {
+                // END or STOP on the last line keeps its own status
                 self.run_status = RunStatus::EndOfProgram;
             }
         }
@@ -221,6 +304,10 @@ impl Interpreter {
             };
 
             for (j, stmt) in line.statements.iter().enumerate().skip(start_offset) {
+                // A loop in a procedure closes there, and one outside passes over procedures
+                if !self.procedures.same_owner((self.location.index, self.location.offset), (i, j)) {
+                    continue;
+                }
                 match stmt {
                     Statement::For { .. } => {
                         depth += 1;
@@ -232,6 +319,7 @@ impl Interpreter {
                             } else {
                                 return Err(BasicError::Runtime {
                                     message: format!("Unexpected NEXT for '{}' while looking for NEXT for '{}'", next_var, var),
+                                    condition: ErrorCondition::NextWithoutFor,
                                     basic_line_number: Some(self.program.lines[i].line_number),
                                     file_line_number: None,
                                 });
@@ -247,11 +335,143 @@ impl Interpreter {
 
         Err(BasicError::Runtime {
             message: format!("No matching NEXT found for FOR {}", var),
+            condition: ErrorCondition::ForWithoutNext,
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })
+    }
+
+    /// Continues after the WEND or LOOP that closes the loop at `start`
+    fn leave_loop(&mut self, start: ControlLocation) -> Result<(), BasicError> {
+        let end = match self.blocks.loop_end((start.index, start.offset)) {
+            Some(Err(mismatch)) => return Err(mismatch.to_error()),
+            Some(Ok(end)) => Some(*end),
+            None => None,
+        };
+        let end = self.block_target(end)?;
+        self.control_transfer(end);
+        self.advance_stmt = true;
+        Ok(())
+    }
+
+    /// Starting a WHILE or DO discards the loop's old record, and every loop inside it, as
+    /// when a program jumps out of a loop and enters it again
+    fn enter_loop(&mut self, kind: LoopKind) {
+        let (_, loop_base, _) = self.stack_bases();
+        if let Some(pos) = self.loop_stack[loop_base..].iter().rposition(|record| record.stmt == self.location) {
+            self.loop_stack.truncate(loop_base + pos);
+        }
+        self.loop_stack.push(LoopRecord { kind, stmt: self.location });
+    }
+
+    /// Pops the innermost loop for a WEND or LOOP, which must be of the matching kind
+    fn pop_loop(&mut self, kind: LoopKind) -> Result<LoopRecord, BasicError> {
+        let (_, loop_base, _) = self.stack_bases();
+        let message = match self.loop_stack.last().filter(|_| self.loop_stack.len() > loop_base) {
+            Some(record) if record.kind == kind => return Ok(self.loop_stack.pop().unwrap()),
+            Some(record) => format!("Mismatched {}: expected {} for the {} at line {}",
+                                    kind.closer(), record.kind.closer(), record.kind.opener(),
+                                    self.program.lines[record.stmt.index].line_number),
+            None => format!("{} without {}", kind.closer(), kind.opener()),
+        };
+        Err(BasicError::Runtime {
+            message,
+            condition: ErrorCondition::WendWithoutWhile,
             basic_line_number: Some(self.get_current_line().line_number),
             file_line_number: None,
         })
     }
 
+    /// Evaluates a loop test; `keyword` names the statement if the value is not a number
+    fn condition_holds(&mut self, condition: &Expression, keyword: &str) -> Result<bool, BasicError> {
+        match self.evaluate_expression(condition)? {
+            SymbolValue::Number(n) => Ok(n != BASIC_FALSE_F),
+            _ => Err(BasicError::Type {
+                message: format!("{} condition must evaluate to a number", keyword),
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            }),
+        }
+    }
+
+    /// Evaluates the WHILE or UNTIL test of a DO or LOOP; true means keep looping
+    fn loop_continues(&mut self, condition: &LoopCondition, keyword: &str) -> Result<bool, BasicError> {
+        let holds = self.condition_holds(condition.expression(), keyword)?;
+        Ok(match condition {
+            LoopCondition::While(_) => holds,
+            LoopCondition::Until(_) => !holds,
+        })
+    }
+
+    /// Converts a jump target from `self.blocks`. A missing one means the program's blocks
+    /// do not balance, so the error is whatever resolving them reports.
+    fn block_target(&self, target: Option<(usize, usize)>) -> Result<ControlLocation, BasicError> {
+        match target {
+            Some((index, offset)) => Ok(ControlLocation { index, offset }),
+            None => Err(BlockTargets::resolve(&self.program).err().unwrap_or_else(|| BasicError::Internal {
+                message: "Block statement has no jump target".to_string(),
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            })),
+        }
+    }
+
+    /// After a false IF or ELSEIF, tests the block's following ELSEIFs and continues after
+    /// the first one that holds, or after the ELSE or END IF if none does
+    fn take_next_clause(&mut self) -> Result<(), BasicError> {
+        loop {
+            let clause = self.block_target(self.blocks.next_clause((self.location.index, self.location.offset)))?;
+            self.control_transfer(clause);
+            if let Statement::ElseIf { condition } = self.get_current_stmt().clone() {
+                if !self.condition_holds(&condition, "ELSEIF")? {
+                    continue;
+                }
+            }
+            self.advance_stmt = true;
+            return Ok(());
+        }
+    }
+
+    /// True if the SELECT CASE value matches any of a CASE statement's tests, tried in order
+    fn case_matches(&mut self, value: &SymbolValue, tests: &[CaseTest]) -> Result<bool, BasicError> {
+        for test in tests {
+            let matched = match test {
+                CaseTest::Value(expr) => self.compare_case(value, expr)? == Ordering::Equal,
+                CaseTest::Range(low, high) => {
+                    self.compare_case(value, low)? != Ordering::Less && self.compare_case(value, high)? != Ordering::Greater
+                }
+                CaseTest::Is(op, expr) => {
+                    let ordering = self.compare_case(value, expr)?;
+                    match op.as_str() {
+                        "=" => ordering == Ordering::Equal,
+                        "<>" => ordering != Ordering::Equal,
+                        "<" => ordering == Ordering::Less,
+                        "<=" => ordering != Ordering::Greater,
+                        ">" => ordering == Ordering::Greater,
+                        _ => ordering != Ordering::Less,
+                    }
+                }
+            };
+            if matched {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Orders the SELECT CASE value against a CASE expression, which must be of the same type
+    fn compare_case(&mut self, value: &SymbolValue, expr: &Expression) -> Result<Ordering, BasicError> {
+        match (value, self.evaluate_expression(expr)?) {
+            (SymbolValue::Number(a), SymbolValue::Number(b)) => Ok(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+            (SymbolValue::String(a), SymbolValue::String(b)) => Ok(a.as_str().cmp(b.as_str())),
+            _ => Err(BasicError::Type {
+                message: "CASE value must have the same type as SELECT CASE".to_string(),
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            }),
+        }
+    }
+
     pub fn enable_trace(&mut self) -> io::Result<()> {
         self.trace_file = Some(File::create(TRACE_FILE_NAME)?);
         Ok(())
@@ -289,6 +509,10 @@ impl Interpreter {
     pub fn get_program(&self) -> &Program {
         &self.program
     }
+
+    pub fn get_dialect(&self) -> &Dialect {
+        &self.dialect
+    }
     
     pub fn get_current_location(&self) -> &ControlLocation {
         &self.location
@@ -298,6 +522,10 @@ impl Interpreter {
         &self.for_stack
     }
     
+    pub fn get_loop_stack(&self) -> &Vec<LoopRecord> {
+        &self.loop_stack
+    }
+
     pub fn get_gosub_stack(&self) -> &Vec<ControlLocation> {
         &self.gosub_stack
     }
@@ -306,14 +534,28 @@ impl Interpreter {
         self.location = ControlLocation { index: 0, offset: 0 };
         self.run_status = RunStatus::Run;
         self.for_stack.clear();
+        self.loop_stack.clear();
         self.gosub_stack.clear();
-        self.cursor_position = 0;
+        self.frames.clear();
+        self.in_scope = false;
+        self.function_result = None;
+        self.fn_depth = 0;
+        self.error_handler = None;
+        self.error_trap = None;
+        self.err_code = 0;
+        self.err_line = 0;
+        self.determinant = 0.0;
+        // The shell writes to the terminal itself, so the next run starts on a line of its own
+        if self.console.column() != 0 {
+            let _ = self.console.write("\n");
+        }
+        let _ = self.files.close_all();
         // Reset symbols to initial state but keep the program
         self.symbols = self.internal_symbols.get_nested_scope();
     }
 
-    pub fn set_symbol_value(&mut self, name: String, value: SymbolValue) {
-        self.symbols.put_symbol(name, value);
+    pub fn set_symbol_value(&mut self, name: String, value: SymbolValue) -> Result<(), BasicError> {
+        self.symbols.put_symbol(name, value)
     }
 
     pub fn get_current_line_number(&self) -> usize {
@@ -328,7 +570,33 @@ impl Interpreter {
         self.run_status = status;
     }
 
+    /// Restricts OPEN to files inside `dir`: absolute paths and `..` are refused
+    pub fn set_sandbox(&mut self, dir: PathBuf) {
+        self.files.set_sandbox(Some(dir));
+    }
+
+    /// Records every line typed for INPUT, with its BASIC line number and the time
+    pub fn record_input(&mut self, recorder: InputRecorder) {
+        self.input_recorder = Some(recorder);
+    }
+
+    /// Answers INPUT from a recording instead of the console
+    pub fn replay_input(&mut self, replay: InputReplay) {
+        self.input_replay = Some(replay);
+    }
+
+    /// Seeds RND as `RANDOMIZE seed` would, for runs that repeat
+    pub fn randomize(&mut self, seed: f64) {
+        self.rng.randomize(seed);
+    }
+
     pub fn run(&mut self) -> Result<(), BasicError> {
+        let result = self.run_statements();
+        self.close_files_if_ended();
+        result
+    }
+
+    fn run_statements(&mut self) -> Result<(), BasicError> {
         // Collect all data values and build line mapping
         for pl in &self.program.lines {
             for stmt in &pl.statements {
@@ -372,10 +640,12 @@ impl Interpreter {
                 println!("Symbol Table END");
             }
             // Execute statement
-            match self.execute_statement(&current_stmt) {
+            let location = self.location;
+            match self.execute_statement(&current_stmt).or_else(|err| self.trap_error(err, location)) {
                 Ok(()) => {
                     self.advance_location();
                 }
+                Err(_) if self.is_halted() => return Ok(()),
                 Err(err) => {
                     self.run_status = match err {
                         BasicError::Syntax { .. } => RunStatus::EndErrorSyntax,
@@ -405,44 +675,28 @@ impl Interpreter {
         match stmt {
             Statement::Let { var, value } => {
                 let result = self.evaluate_expression(value)?;
-                match &var.expr_type {
-                    ExpressionType::Variable(name) => {
-                        self.put_symbol(name.clone(), result);
-                        Ok(())
-                    }
-                    ExpressionType::Array { name, indices } => {
-                        let indices = self.evaluate_array_indices(indices)?;
-                        self.symbols
-                            .set_array_element(name, &indices, result)
-                            .map_err(|mut err| {
-                                if let BasicError::Runtime {
-                                    ref mut basic_line_number,
-                                    ref mut file_line_number,
-                                    ..
-                                } = err
-                                {
-                                    *basic_line_number = Some(self.get_current_line().line_number);
-                                    *file_line_number = None;
-                                }
-                                err
-                            })?;
-                        Ok(())
-                    }
-                    _ => {
-                        // Try to evaluate the left-hand side as an expression
-                        // This handles cases like LET A = B where A might be a variable
-                        if let ExpressionType::Variable(name) = &var.expr_type {
-                            self.put_symbol(name.clone(), result);
-                            Ok(())
-                        } else {
-                            Err(BasicError::Runtime {
-                                message: "Invalid left-hand side in assignment".to_string(),
-                                basic_line_number: Some(self.get_current_line().line_number),
-                                file_line_number: None,
-                            })
-                        }
-                    }
-                }
+                self.store_to(var, result)
+            }
+            Statement::MidAssign { var, start, length, value } => {
+                let line_number = self.get_current_line().line_number;
+                let mismatch = move || BasicError::Type {
+                    message: "Type mismatch in MID$ assignment".to_string(),
+                    basic_line_number: Some(line_number),
+                    file_line_number: None,
+                };
+                let current = self.evaluate_expression(var)?;
+                let start = self.evaluate_expression(start)?;
+                let length = match length.as_ref().map(|length| self.evaluate_expression(length)).transpose()? {
+                    None => None,
+                    Some(SymbolValue::Number(n)) => Some(n),
+                    Some(_) => return Err(mismatch()),
+                };
+                let text = self.evaluate_expression(value)?;
+                let (SymbolValue::String(current), SymbolValue::Number(start), SymbolValue::String(text)) = (current, start, text) else {
+                    return Err(mismatch());
+                };
+                let result = strings::mid_assign(&current, start, length, &text).map_err(|message| self.function_error(ErrorCondition::IllegalFunctionCall, message))?;
+                self.store_to(var, SymbolValue::String(result))
             }
             Statement::Print { items } => {
                 let mut needs_newline = true;
@@ -450,27 +704,18 @@ impl Interpreter {
                 for item in items {
                     match item {
                         PrintItem::Expression(expr) => {
-                            let value = self.evaluate_expression(expr)?;
-                            let value_str = value.to_string();
-                            print!("{}", value_str);
-                            self.cursor_position += value_str.len();
+                            let value_str = self.print_expression(expr)?;
+                            self.console.write(&value_str)?;
                         }
                         PrintItem::Tab(n) => {
                             // Move cursor to specific column (1-based)
-                            if *n > self.cursor_position {
-                                let spaces_needed = *n - self.cursor_position;
-                                print!("{}", " ".repeat(spaces_needed));
-                                self.cursor_position = *n;
-                            }
+                            let spaces_needed = PrintCursor::at(self.console.column()).tab(*n);
+                            self.console.write(&" ".repeat(spaces_needed))?;
                         }
                         PrintItem::Comma => {
-                            // Tab to next column (every 8 characters, standard tab stops)
-                            let next_tab = ((self.cursor_position / 8) + 1) * 8;
-                            if next_tab > self.cursor_position {
-                                let spaces_needed = next_tab - self.cursor_position;
-                                print!("{}", " ".repeat(spaces_needed));
-                                self.cursor_position = next_tab;
-                            }
+                            // Tab to next print zone (every 8 characters, standard tab stops)
+                            let spaces_needed = PrintCursor::at(self.console.column()).comma();
+                            self.console.write(&" ".repeat(spaces_needed))?;
                         }
                         PrintItem::Semicolon => {
                             // Semicolon suppresses spacing and newlines
@@ -485,41 +730,35 @@ impl Interpreter {
                 
                 // Add newline unless last item was a semicolon
                 if needs_newline {
-                    println!();
-                    self.cursor_position = 0;
+                    self.console.write("\n")?;
                 }
                 
-                io::stdout().flush()?;
+                self.console.flush()?;
                 Ok(())
             }
             Statement::Input { vars, prompt } => {
-                const MAX_RETRIES: usize = 3;
                 let mut retry_count = 0;
                 
                 loop {
-                    let mut input = String::new();
-                    if let Some(p) = prompt {
-                        print!("{}? ", p);
-                    } else {
-                        print!("? ");
-                    }
-                    io::stdout().flush()?;
-                    io::stdin().read_line(&mut input)?;
+                    self.console.write(&input::prompt_text(prompt.as_deref()))?;
+                    self.console.flush()?;
+                    let input = self.read_input()?;
                     
                     // Split input by commas and process each part
-                    let input_parts: Vec<&str> = input.trim().split(',').collect();
+                    let input_parts = input::split_fields(&input);
                     
                     // Check if we have the right number of inputs
                     if input_parts.len() != vars.len() {
                         retry_count += 1;
-                        if retry_count >= MAX_RETRIES {
+                        if retry_count >= input::MAX_RETRIES {
                             return Err(BasicError::Runtime {
-                                message: format!("Expected {} input values, got {}. Maximum retries exceeded.", vars.len(), input_parts.len()),
+                                message: input::wrong_count_message(vars.len(), input_parts.len()),
+                                condition: ErrorCondition::BadInput,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             });
                         }
-                        println!("?Redo from start");
+                        self.console.write(&format!("{}\n", input::REDO_MESSAGE))?;
                         continue;
                     }
                     
@@ -528,12 +767,12 @@ impl Interpreter {
                     let mut parse_error = false;
                     
                     for (i, var) in vars.iter().enumerate() {
-                        let input_part = input_parts[i].trim();
+                        let input_part = input_parts[i];
                         let is_string_variable = var.ends_with('$');
                         
                         let value = if is_string_variable {
                             // For string variables (A$), always treat input as string
-                            let processed_str = if UPPERCASE_INPUT {
+                            let processed_str = if self.dialect.uppercase_input {
                                 input_part.to_uppercase()
                             } else {
                                 input_part.to_string()
@@ -541,7 +780,7 @@ impl Interpreter {
                             SymbolValue::String(processed_str)
                         } else {
                             // For numeric variables (A), try to parse as number
-                            if let Ok(n) = input_part.parse::<f64>() {
+                            if let Some(n) = input::parse_number(input_part) {
                                 SymbolValue::Number(n)
                             } else {
                                 parse_error = true;
@@ -553,25 +792,192 @@ impl Interpreter {
                     
                     if parse_error {
                         retry_count += 1;
-                        if retry_count >= MAX_RETRIES {
+                        if retry_count >= input::MAX_RETRIES {
                             return Err(BasicError::Runtime {
-                                message: "Invalid numeric input. Maximum retries exceeded.".to_string(),
+                                message: input::BAD_NUMBER_MESSAGE.to_string(),
+                                condition: ErrorCondition::BadInput,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             });
                         }
-                        println!("?Redo from start");
+                        self.console.write(&format!("{}\n", input::REDO_MESSAGE))?;
                         continue;
                     }
                     
                     // All inputs were valid, store the values
                     for (var, value) in values {
-                        self.put_symbol(var, value);
+                        self.put_symbol(var, value)?;
                     }
                     break;
                 }
                 Ok(())
             }
+            Statement::Open { path, mode, number, record_len } => {
+                let name = match self.evaluate_expression(path)? {
+                    SymbolValue::String(name) => name,
+                    _ => return Err(BasicError::Type {
+                        message: "OPEN file name must be a string".to_string(),
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    }),
+                };
+                let number = self.file_number(number)?;
+                let record_len = match record_len {
+                    Some(expr) => self.file_argument(expr, "Record length")?,
+                    None => files::DEFAULT_RECORD_LEN as f64,
+                };
+                self.files.open(number, &name, *mode, record_len).map_err(|e| self.file_error(e))
+            }
+            Statement::Close { numbers } => {
+                if numbers.is_empty() {
+                    return self.files.close_all().map_err(|e| self.file_error(e));
+                }
+                for number in numbers {
+                    let number = self.file_number(number)?;
+                    self.files.close(number).map_err(|e| self.file_error(e))?;
+                }
+                Ok(())
+            }
+            Statement::PrintFile { number, items } => {
+                let number = self.file_number(number)?;
+                for item in items {
+                    let written = match item {
+                        PrintItem::Expression(expr) => {
+                            let text = self.print_expression(expr)?;
+                            self.files.print(number, &text)
+                        }
+                        PrintItem::Tab(n) => self.files.tab(number, *n),
+                        PrintItem::Comma => self.files.comma(number),
+                        PrintItem::Semicolon => Ok(()),
+                    };
+                    written.map_err(|e| self.file_error(e))?;
+                }
+                if !matches!(items.last(), Some(PrintItem::Semicolon)) {
+                    self.files.newline(number).map_err(|e| self.file_error(e))?;
+                }
+                Ok(())
+            }
+            Statement::PrintUsing { number, format, exprs, newline } => {
+                let number = match number {
+                    Some(number) => Some(self.file_number(number)?),
+                    None => None,
+                };
+                let format = match self.evaluate_expression(format)? {
+                    SymbolValue::String(s) => s,
+                    _ => return Err(BasicError::Type {
+                        message: "PRINT USING format must be a string".to_string(),
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    }),
+                };
+                let mut values = Vec::new();
+                for expr in exprs {
+                    values.push(match self.evaluate_expression(expr)? {
+                        SymbolValue::String(s) => UsingValue::String(s),
+                        SymbolValue::Number(n) => UsingValue::Number(n),
+                        other => return Err(BasicError::Type {
+                            message: format!("Cannot PRINT USING {}", other),
+                            basic_line_number: Some(self.get_current_line().line_number),
+                            file_line_number: None,
+                        }),
+                    });
+                }
+                let text = using::format_using(&format, &values).map_err(|e| {
+                    let (message, basic_line_number) = (e.to_string(), Some(self.get_current_line().line_number));
+                    match e {
+                        UsingError::TypeMismatch(_) => BasicError::Type { message, basic_line_number, file_line_number: None },
+                        UsingError::NoFields(_) => BasicError::Runtime { message, condition: ErrorCondition::IllegalFunctionCall, basic_line_number, file_line_number: None },
+                    }
+                })?;
+                match number {
+                    Some(number) => {
+                        self.files.print(number, &text).map_err(|e| self.file_error(e))?;
+                        if *newline {
+                            self.files.newline(number).map_err(|e| self.file_error(e))?;
+                        }
+                    }
+                    None => {
+                        self.console.write(&text)?;
+                        if *newline {
+                            self.console.write("\n")?;
+                        }
+                        self.console.flush()?;
+                    }
+                }
+                Ok(())
+            }
+            Statement::WriteFile { number, exprs } => {
+                let number = self.file_number(number)?;
+                let mut fields = Vec::new();
+                for expr in exprs {
+                    fields.push(match self.evaluate_expression(expr)? {
+                        SymbolValue::String(s) => files::quote(&s),
+                        SymbolValue::Number(n) => format::number_text(n, &self.dialect.number_format),
+                        other => return Err(BasicError::Type {
+                            message: format!("Cannot WRITE {}", other),
+                            basic_line_number: Some(self.get_current_line().line_number),
+                            file_line_number: None,
+                        }),
+                    });
+                }
+                self.files.print(number, &fields.join(","))
+                    .and_then(|_| self.files.newline(number))
+                    .map_err(|e| self.file_error(e))
+            }
+            Statement::InputFile { number, vars } => {
+                let number = self.file_number(number)?;
+                for var in vars {
+                    let value = if var.ends_with('$') {
+                        self.files.input_field(number).map(SymbolValue::String)
+                    } else {
+                        self.files.input_number(number).map(SymbolValue::Number)
+                    };
+                    let value = value.map_err(|e| self.file_error(e))?;
+                    self.put_symbol(var.clone(), value)?;
+                }
+                Ok(())
+            }
+            Statement::LineInputFile { number, var } => {
+                let number = self.file_number(number)?;
+                let line = self.files.line_input(number).map_err(|e| self.file_error(e))?;
+                self.put_symbol(var.clone(), SymbolValue::String(line))
+            }
+            Statement::Field { number, fields } => {
+                let number = self.file_number(number)?;
+                let mut widths = Vec::new();
+                for (width, var) in fields {
+                    widths.push((self.file_argument(width, "Field width")?, var.clone()));
+                }
+                self.files.field(number, &widths).map_err(|e| self.file_error(e))?;
+                self.load_fields(number)
+            }
+            Statement::Get { number, record } => {
+                let number = self.file_number(number)?;
+                let record = record.as_ref().map(|expr| self.file_argument(expr, "Record number")).transpose()?;
+                self.files.get(number, record).map_err(|e| self.file_error(e))?;
+                self.load_fields(number)
+            }
+            Statement::Put { number, record } => {
+                let number = self.file_number(number)?;
+                let record = record.as_ref().map(|expr| self.file_argument(expr, "Record number")).transpose()?;
+                self.files.put(number, record).map_err(|e| self.file_error(e))
+            }
+            Statement::Lset { var, value } | Statement::Rset { var, value } => {
+                let value = match self.evaluate_expression(value)? {
+                    SymbolValue::String(s) => s,
+                    _ => return Err(BasicError::Type {
+                        message: format!("{} needs a string value", if matches!(stmt, Statement::Lset { .. }) { "LSET" } else { "RSET" }),
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    }),
+                };
+                let current = match self.get_symbol(var) {
+                    Ok(SymbolValue::String(s)) => s,
+                    _ => String::new(),
+                };
+                let result = self.files.set_field(var, &current, &value, matches!(stmt, Statement::Rset { .. }));
+                self.put_symbol(var.clone(), SymbolValue::String(result))
+            }
             Statement::If { condition } => {
                 let result = self.evaluate_expression(condition)?;
                 match result {
@@ -610,6 +1016,7 @@ impl Interpreter {
                     SymbolValue::Number(n) => n,
                     _ => return Err(BasicError::Runtime {
                         message: "FOR loop start value must be a number".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     }),
@@ -620,6 +1027,7 @@ impl Interpreter {
                     SymbolValue::Number(n) => n,
                     _ => return Err(BasicError::Runtime {
                         message: "FOR loop stop value must be a number".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     }),
@@ -630,12 +1038,24 @@ impl Interpreter {
                     SymbolValue::Number(n) => n,
                     _ => return Err(BasicError::Runtime {
                         message: "FOR loop step must be a number".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     }),
                 };
 
-                self.put_symbol(var.clone(), SymbolValue::Number(current));
+                self.put_symbol(var.clone(), SymbolValue::Number(current))?;
+                let current = match self.get_symbol(var)? {
+                    SymbolValue::Number(n) => n,
+                    _ => current,
+                };
+
+                // Starting a loop discards any active loop on the same variable, and every
+                // loop inside it, as when a program jumps out of a loop and enters it again
+                let (for_base, _, _) = self.stack_bases();
+                if let Some(pos) = self.for_stack[for_base..].iter().rposition(|record| &record.var == var) {
+                    self.for_stack.truncate(for_base + pos);
+                }
 
                 // Check if loop should run
                 if (step >= 0.0 && current > stop) || (step < 0.0 && current < stop) {
@@ -648,6 +1068,14 @@ impl Interpreter {
                     self.advance_stmt = true;
                 } else {
                     // Loop will run: push loop frame
+                    if self.for_stack.len() >= self.dialect.max_for_depth {
+                        return Err(BasicError::Runtime {
+                            message: limits::for_depth_message(self.dialect.max_for_depth),
+                            condition: ErrorCondition::OutOfMemory,
+                            basic_line_number: Some(self.get_current_line().line_number),
+                            file_line_number: None,
+                        });
+                    }
                     self.for_stack.push(ForRecord {
                         var: var.clone(),
                         stop: stop_expr,
@@ -659,10 +1087,12 @@ impl Interpreter {
                 Ok(())
             }
             Statement::Next { var } => {
-                if let Some(for_record) = self.for_stack.last().cloned() {
+                let (for_base, _, _) = self.stack_bases();
+                if let Some(for_record) = self.for_stack.last().filter(|_| self.for_stack.len() > for_base).cloned() {
                     if &for_record.var != var {
                         return Err(BasicError::Runtime {
                             message: format!("Mismatched NEXT: expected '{}', found '{}'", for_record.var, var),
+                            condition: ErrorCondition::NextWithoutFor,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         });
@@ -673,6 +1103,7 @@ impl Interpreter {
                         SymbolValue::Number(n) => n,
                         _ => return Err(BasicError::Runtime {
                             message: "FOR loop variable must be numeric".to_string(),
+                            condition: ErrorCondition::TypeMismatch,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         }),
@@ -682,6 +1113,7 @@ impl Interpreter {
                         SymbolValue::Number(n) => n,
                         _ => return Err(BasicError::Runtime {
                             message: "FOR loop step must be numeric".to_string(),
+                            condition: ErrorCondition::TypeMismatch,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         }),
@@ -691,14 +1123,17 @@ impl Interpreter {
                         SymbolValue::Number(n) => n,
                         _ => return Err(BasicError::Runtime {
                             message: "FOR loop stop value must be numeric".to_string(),
+                            condition: ErrorCondition::TypeMismatch,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         }),
                     };
-                    let next_value = current + step;
-                    self.put_symbol(var.clone(), SymbolValue::Number(next_value));
-                    // Make sure that put worked.
-                    let _ = self.get_symbol(var)?;
+                    self.put_symbol(var.clone(), SymbolValue::Number(current + step))?;
+                    // The variable holds the sum rounded to its type
+                    let next_value = match self.get_symbol(var)? {
+                        SymbolValue::Number(n) => n,
+                        _ => current + step,
+                    };
                     if (step >= 0.0 && next_value <= stop) || (step < 0.0 && next_value >= stop) {
                         if let Some(stmt_loc) = for_record.stmt {
                             self.control_transfer(stmt_loc);
@@ -714,6 +1149,7 @@ impl Interpreter {
                 } else {
                     Err(BasicError::Runtime {
                         message: "NEXT without matching FOR".to_string(),
+                        condition: ErrorCondition::NextWithoutFor,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     })
@@ -724,18 +1160,21 @@ impl Interpreter {
                 Ok(())
             }
             Statement::Gosub { line } => {
-                self.gosub_stack.push(self.location);
+                self.push_gosub(self.location)?;
                 self.goto_line(*line)?;
                 Ok(())
             }
             Statement::Return => {
-                if let Some(return_loc) = self.gosub_stack.pop() {
+                let (_, _, gosub_base) = self.stack_bases();
+                let return_loc = if self.gosub_stack.len() > gosub_base { self.gosub_stack.pop() } else { None };
+                if let Some(return_loc) = return_loc {
                     self.control_transfer(return_loc);
                     self.advance_stmt = true;
                     Ok(())
                 } else {
                     Err(BasicError::Runtime {
                         message: "RETURN without GOSUB".to_string(),
+                        condition: ErrorCondition::ReturnWithoutGosub,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     })
@@ -751,11 +1190,71 @@ impl Interpreter {
             }
             Statement::Rem { .. } => Ok(()),
             Statement::Data { .. } => Ok(()),
+            Statement::DefType { .. } => Ok(()),   // The parser gave the variables their types
+            Statement::MatRead { arrays } => {
+                for (name, bounds) in arrays {
+                    let mut matrix = if bounds.is_empty() {
+                        self.program_symbols().get_matrix(name).map_err(|e| self.add_line_info_to_error(e))?
+                    } else {
+                        Matrix::new(self.matrix_bounds(bounds)?)
+                    };
+                    for value in matrix.values.iter_mut() {
+                        let (message, condition) = match self.data_values.get(self.data_pointer) {
+                            Some(&SymbolValue::Number(n)) => {
+                                *value = n;
+                                self.data_pointer += 1;
+                                continue;
+                            }
+                            Some(_) => ("Type mismatch in READ", ErrorCondition::TypeMismatch),
+                            None => ("Out of DATA values", ErrorCondition::OutOfData),
+                        };
+                        return Err(BasicError::Runtime {
+                            message: message.to_string(),
+                            condition,
+                            basic_line_number: Some(self.get_current_line().line_number),
+                            file_line_number: None,
+                        });
+                    }
+                    self.program_symbols().set_matrix(name, matrix).map_err(|e| self.add_line_info_to_error(e))?;
+                }
+                Ok(())
+            }
+            Statement::MatPrint { arrays } => {
+                for (name, packed) in arrays {
+                    let matrix = self.program_symbols().get_matrix(name).map_err(|e| self.add_line_info_to_error(e))?;
+                    let format = if VarType::of(name) == Some(VarType::Double) {
+                        self.dialect.number_format.double()
+                    } else {
+                        self.dialect.number_format
+                    };
+                    let text = matrix::print_text(&matrix, *packed, &format, &mut PrintCursor::at(self.console.column()));
+                    self.console.write(&text)?;
+                }
+                self.console.flush()?;
+                Ok(())
+            }
+            Statement::MatAssign { var, value } => self.execute_mat_assign(var, value),
+            Statement::Randomize { seed } => {
+                let seed = match seed {
+                    Some(expr) => match self.evaluate_expression(expr)? {
+                        SymbolValue::Number(n) => n,
+                        _ => return Err(BasicError::Type {
+                            message: "RANDOMIZE seed must be a number".to_string(),
+                            basic_line_number: Some(self.get_current_line().line_number),
+                            file_line_number: None,
+                        }),
+                    },
+                    None => rng::timer(),
+                };
+                self.rng.randomize(seed);
+                Ok(())
+            }
             Statement::Read { vars } => {
                 for var_expr in vars {
                     if self.data_pointer >= self.data_values.len() {
                         return Err(BasicError::Runtime {
                             message: "Out of DATA values".to_string(),
+                            condition: ErrorCondition::OutOfData,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         });
@@ -766,15 +1265,16 @@ impl Interpreter {
                     
                     match &var_expr.expr_type {
                         ExpressionType::Variable(name) => {
-                            self.put_symbol(name.clone(), value);
+                            self.put_symbol(name.clone(), value)?;
                         }
                         ExpressionType::Array { name, indices } => {
                             let indices = self.evaluate_array_indices(indices)?;
-                            self.symbols.set_array_element(name, &indices, value)?;
+                            self.program_symbols().set_array_element(name, &indices, value)?;
                         }
                         _ => {
                             return Err(BasicError::Runtime {
                                 message: "Invalid variable in READ statement".to_string(),
+                                condition: ErrorCondition::Unprintable,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             });
@@ -791,6 +1291,7 @@ impl Interpreter {
                     } else {
                         return Err(BasicError::Runtime {
                             message: format!("Line {} has no DATA statements", line_num),
+                            condition: ErrorCondition::OutOfData,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         });
@@ -803,7 +1304,7 @@ impl Interpreter {
             }
             Statement::Dim { arrays } => {
                 for array in arrays {
-                    self.symbols.create_array(array.name.clone(), array.dimensions.clone()).map_err(|e| self.add_line_info_to_error(e))?;
+                    self.program_symbols().create_array(array.name.clone(), array.dimensions.clone()).map_err(|e| self.add_line_info_to_error(e))?;
                 }
                 Ok(())
             }
@@ -812,6 +1313,7 @@ impl Interpreter {
                     SymbolValue::Number(n) if n >= 1.0 && n.fract() == 0.0 => n as usize,
                     _ => return Err(BasicError::Runtime {
                         message: "ON index must be a positive integer".to_string(),
+                        condition: ErrorCondition::IllegalFunctionCall,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     })
@@ -827,6 +1329,7 @@ impl Interpreter {
                     SymbolValue::Number(n) if n >= 1.0 && n.fract() == 0.0 => n as usize,
                     _ => return Err(BasicError::Runtime {
                         message: "ON index must be a positive integer".to_string(),
+                        condition: ErrorCondition::IllegalFunctionCall,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     })
@@ -835,7 +1338,7 @@ impl Interpreter {
                 if value <= line_numbers.len() {
                     let return_loc = self.location;
                     self.goto_line(line_numbers[value - 1])?;
-                    self.gosub_stack.push(return_loc);
+                    self.push_gosub(return_loc)?;
                 }
                 Ok(())
             }
@@ -843,10 +1346,188 @@ impl Interpreter {
                 self.internal_symbols.define_function(name.clone(), params.clone(), expr.clone())?;
                 Ok(())
             }
+            Statement::OnErrorGoto { line } => {
+                if *line == 0 {
+                    self.error_handler = None;
+                    // Turning trapping off inside a handler stops with the error being handled
+                    if let Some(trap) = self.error_trap.take() {
+                        return Err(trap.error);
+                    }
+                } else if self.line_number_map.contains_key(line) {
+                    self.error_handler = Some(*line);
+                } else {
+                    return Err(BasicError::Runtime {
+                        message: format!("Line number {} not found", line),
+                        condition: ErrorCondition::UndefinedLine,
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    });
+                }
+                Ok(())
+            }
+            Statement::While { condition } => {
+                // WEND jumps back here, so the test runs before every pass
+                if self.condition_holds(condition, "WHILE")? {
+                    self.enter_loop(LoopKind::While);
+                } else {
+                    self.leave_loop(self.location)?;
+                }
+                Ok(())
+            }
+            Statement::Wend => {
+                let record = self.pop_loop(LoopKind::While)?;
+                self.control_transfer(record.stmt);
+                Ok(())
+            }
+            Statement::Do { condition } => {
+                let runs = match condition {
+                    Some(condition) => self.loop_continues(condition, "DO")?,
+                    None => true,
+                };
+                if runs {
+                    self.enter_loop(LoopKind::Do);
+                } else {
+                    self.leave_loop(self.location)?;
+                }
+                Ok(())
+            }
+            Statement::Loop { condition } => {
+                let record = self.pop_loop(LoopKind::Do)?;
+                let again = match condition {
+                    Some(condition) => self.loop_continues(condition, "LOOP")?,
+                    None => true,
+                };
+                if again {
+                    // Back to the DO, which tests its own condition and pushes the loop again
+                    self.control_transfer(record.stmt);
+                }
+                Ok(())
+            }
+            Statement::ExitDo => {
+                let (_, loop_base, _) = self.stack_bases();
+                let Some(pos) = self.loop_stack[loop_base..].iter().rposition(|record| record.kind == LoopKind::Do).map(|pos| loop_base + pos) else {
+                    return Err(BasicError::Runtime {
+                        message: "EXIT DO without DO".to_string(),
+                        condition: ErrorCondition::WendWithoutWhile,
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    });
+                };
+                let start = self.loop_stack[pos].stmt;
+                self.loop_stack.truncate(pos);
+                self.leave_loop(start)
+            }
+            Statement::IfBlock { condition } => {
+                if !self.condition_holds(condition, "IF")? {
+                    self.take_next_clause()?;
+                }
+                Ok(())
+            }
+            Statement::ElseIf { .. } | Statement::ElseBlock | Statement::Case { .. } | Statement::CaseElse => {
+                // Reached from the end of the branch before it, which is done
+                let end = self.block_target(self.blocks.block_end((self.location.index, self.location.offset)))?;
+                self.control_transfer(end);
+                self.advance_stmt = true;
+                Ok(())
+            }
+            Statement::EndIf | Statement::EndSelect => Ok(()),
+            Statement::SelectCase { selector } => {
+                let value = self.evaluate_expression(selector)?;
+                // Continue after the first CASE that matches, or the CASE ELSE or END SELECT
+                loop {
+                    let clause = self.block_target(self.blocks.next_clause((self.location.index, self.location.offset)))?;
+                    self.control_transfer(clause);
+                    if let Statement::Case { tests } = self.get_current_stmt().clone() {
+                        if !self.case_matches(&value, &tests)? {
+                            continue;
+                        }
+                    }
+                    self.advance_stmt = true;
+                    return Ok(());
+                }
+            }
+            Statement::Sub { .. } | Statement::Function { .. } => {
+                // Reached in the flow of the program, which continues after the procedure
+                let location = (self.location.index, self.location.offset);
+                let Some(procedure) = self.procedures.containing(location) else {
+                    return Err(self.procedure_error());
+                };
+                let (index, offset) = procedure.end;
+                self.control_transfer(ControlLocation { index, offset });
+                self.advance_stmt = true;
+                Ok(())
+            }
+            Statement::EndSub | Statement::ExitSub | Statement::EndFunction | Statement::ExitFunction => {
+                self.leave_procedure()
+            }
+            Statement::Call { name, args } => {
+                let procedure = self.procedures.get(name).cloned().ok_or_else(|| self.procedure_error())?;
+                self.enter_procedure(&procedure, args)
+            }
+            Statement::Local { vars } => {
+                for var in vars {
+                    self.symbols.put_symbol(var.clone(), Self::initial_value(var))?;
+                }
+                Ok(())
+            }
+            Statement::Shared { .. } => Ok(()),
+            Statement::Resume { target } => {
+                let Some(trap) = self.error_trap.take() else {
+                    return Err(BasicError::Runtime {
+                        message: "RESUME without error".to_string(),
+                        condition: ErrorCondition::ResumeWithoutError,
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    });
+                };
+                match target {
+                    ResumeTarget::Retry => self.control_transfer(trap.location),
+                    ResumeTarget::Next => {
+                        self.control_transfer(trap.location);
+                        self.advance_stmt = true;
+                    }
+                    ResumeTarget::Line(line) => self.goto_line(*line)?,
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends an error to the ON ERROR GOTO handler, recording ERR and ERL. The error is
+    /// returned instead when trapping is off, when it cannot be trapped, or when it
+    /// happened inside the handler.
+    fn trap_error(&mut self, err: BasicError, location: ControlLocation) -> Result<(), BasicError> {
+        let Some(handler) = self.error_handler else {
+            return Err(err);
+        };
+        if self.error_trap.is_some() {
+            return Err(err);
+        }
+        // Errors inside a SUB or FUNCTION end the program
+        if !self.frames.is_empty() {
+            return Err(err);
         }
+        let Some(condition) = err.condition() else {
+            return Err(err);
+        };
+        self.err_code = self.dialect.error_code(condition);
+        self.err_line = self.program.lines[location.index].line_number;
+        self.error_trap = Some(ErrorTrap { location, error: err });
+        self.goto_line(handler)
     }
 
+    /// The value of an expression; a number is rounded to the dialect's precision, or to
+    /// single precision for one computed from `A!`, as is every number computed on the way
     fn evaluate_expression(&mut self, expr: &Expression) -> Result<SymbolValue, BasicError> {
+        match self.evaluate_exact(expr)? {
+            SymbolValue::Number(n) if expr.is_double_precision() => Ok(SymbolValue::Number(n)),
+            SymbolValue::Number(n) if expr.is_single_precision() => Ok(SymbolValue::Number(precision::Precision::Single.round(n))),
+            SymbolValue::Number(n) => Ok(SymbolValue::Number(self.dialect.precision.round(n))),
+            value => Ok(value),
+        }
+    }
+
+    fn evaluate_exact(&mut self, expr: &Expression) -> Result<SymbolValue, BasicError> {
         match &expr.expr_type {
             ExpressionType::Number(n) => Ok(SymbolValue::Number(*n)),
             ExpressionType::String(s) => Ok(SymbolValue::String(s.clone())),
@@ -860,17 +1541,11 @@ impl Interpreter {
             ExpressionType::FunctionCall { name, args } => {
                 // Check if this is a built-in function
                 if FUNCTION_REGISTRY.is_function(name) {
-                    let expected_types = FUNCTION_REGISTRY.get_arg_types(name).unwrap();
-                    if expected_types.len() != args.len() {
-                        return Err(BasicError::Runtime {
-                            message: format!("Function '{}' expects {} arguments, got {}", name, expected_types.len(), args.len()),
-                            basic_line_number: Some(self.get_current_line().line_number),
-                            file_line_number: None,
-                        });
-                    }
+                    let values = args.iter().map(|arg| self.evaluate_expression(arg)).collect::<Result<Vec<_>, _>>()?;
+                    let is_string: Vec<bool> = values.iter().map(|value| matches!(value, SymbolValue::String(_))).collect();
+                    let expected_types = FUNCTION_REGISTRY.select_arg_form(name, &is_string).map_err(|message| self.function_error(ErrorCondition::Unprintable, message))?;
                     let mut evaluated_args = Vec::new();
-                    for (arg, expected_type) in args.iter().zip(expected_types.iter()) {
-                        let value = self.evaluate_expression(arg)?;
+                    for (value, expected_type) in values.into_iter().zip(expected_types.iter()) {
                         match (expected_type, value) {
                             (crate::basic_function_registry::ArgType::Number, SymbolValue::Number(n)) => {
                                 evaluated_args.push(Token::new_number(&n.to_string()));
@@ -881,6 +1556,7 @@ impl Interpreter {
                             (crate::basic_function_registry::ArgType::Number, other) => {
                                 return Err(BasicError::Runtime {
                                     message: format!("Function '{}' expects a number argument, got {:?}", name, other),
+                                    condition: ErrorCondition::TypeMismatch,
                                     basic_line_number: Some(self.get_current_line().line_number),
                                     file_line_number: None,
                                 });
@@ -888,22 +1564,71 @@ impl Interpreter {
                             (crate::basic_function_registry::ArgType::String, other) => {
                                 return Err(BasicError::Runtime {
                                     message: format!("Function '{}' expects a string argument, got {:?}", name, other),
+                                    condition: ErrorCondition::TypeMismatch,
                                     basic_line_number: Some(self.get_current_line().line_number),
                                     file_line_number: None,
                                 });
                             }
                         }
                     }
-                    let result = FUNCTION_REGISTRY.call_function_with_tokens(name, evaluated_args).map_err(|e| self.add_line_info_to_error(e))?;
-                    match result {
+                    // ERR and ERL report the last trapped error
+                    match name.as_str() {
+                        "ERR" => return Ok(SymbolValue::Number(self.err_code as f64)),
+                        "ERL" => return Ok(SymbolValue::Number(self.err_line as f64)),
+                        "DET" => return Ok(SymbolValue::Number(self.determinant)),
+                        "RND" => {
+                            let x = evaluated_args[0].token()?.parse().unwrap_or(0.0);
+                            return Ok(SymbolValue::Number(self.rng.rnd(x)));
+                        }
+                        "STR$" => {
+                            let n = evaluated_args[0].token()?.parse().unwrap_or(0.0);
+                            return Ok(SymbolValue::String(format::str_number(n, &self.dialect.number_format)));
+                        }
+                        "VAL" => {
+                            let result = strings::val(evaluated_args[0].token()?, self.dialect.val);
+                            return result.map(SymbolValue::Number).map_err(|message| self.function_error(self.dialect.val_error(), message));
+                        }
+                        "HEX$" | "OCT$" => {
+                            let n = evaluated_args[0].token()?.parse().unwrap_or(0.0);
+                            let bits = self.dialect.integer_bits;
+                            let result = if name == "HEX$" { strings::hex(n, bits) } else { strings::oct(n, bits) };
+                            return result.map(SymbolValue::String).map_err(|message| self.function_error(ErrorCondition::Overflow, message));
+                        }
+                        "STRING$" => {
+                            if let Token::String(s) = &evaluated_args[1] {
+                                let n = evaluated_args[0].token()?.parse().unwrap_or(0.0);
+                                let s = strings::string_of(n, s).map_err(|message| self.function_error(ErrorCondition::IllegalFunctionCall, message))?;
+                                self.check_string_length(&s)?;
+                                return Ok(SymbolValue::String(s));
+                            }
+                        }
+                        "EOF" | "LOF" => {
+                            let number = evaluated_args[0].token()?.parse().unwrap_or(0.0);
+                            let result = if name == "EOF" {
+                                self.files.eof(number).map(|at_end| self.truth(at_end))
+                            } else {
+                                self.files.lof(number)
+                            };
+                            return result.map(SymbolValue::Number).map_err(|e| self.file_error(e));
+                        }
+                        _ => {}
+                    }
+                    let result = FUNCTION_REGISTRY.call_function_with_tokens(name, evaluated_args).map_err(|e| self.add_line_info_to_error(e))?;
+                    match result {
                         Token::Number(n) => Ok(SymbolValue::Number(n.parse().unwrap_or(0.0))),
-                        Token::String(s) => Ok(SymbolValue::String(s)),
+                        Token::String(s) => {
+                            self.check_string_length(&s)?;
+                            Ok(SymbolValue::String(s))
+                        }
                         _ => Err(BasicError::Runtime {
                             message: format!("Unexpected result type from function '{}'", name),
+                            condition: ErrorCondition::Unprintable,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         }),
                     }
+                } else if let Some(procedure) = self.procedures.get(name).filter(|p| p.kind == ProcedureKind::Function).cloned() {
+                    self.call_function(&procedure, args)
                 } else {
                     // Check for user-defined functions (FNA, FNB, etc.)
                     if name.len() == 3 && name.starts_with("FN") && name.chars().nth(2).unwrap().is_ascii_uppercase() {
@@ -923,31 +1648,51 @@ impl Interpreter {
                                 } else {
                                     return Err(BasicError::Runtime {
                                         message: format!("User-defined function '{}' expects number arguments", name),
+                                        condition: ErrorCondition::TypeMismatch,
                                         basic_line_number: Some(self.get_current_line().line_number),
                                         file_line_number: None,
                                     });
                                 }
                             }
                             
+                            if self.fn_depth >= self.dialect.max_recursion_depth {
+                                return Err(BasicError::Runtime {
+                                    message: limits::recursion_depth_message(self.dialect.max_recursion_depth),
+                                    condition: ErrorCondition::OutOfMemory,
+                                    basic_line_number: Some(self.get_current_line().line_number),
+                                    file_line_number: None,
+                                });
+                            }
+
+                            // Parameters take the arguments as their types hold them
+                            let mut bindings = Vec::new();
+                            for (param_name, arg_value) in param.iter().zip(evaluated_args.iter()) {
+                                let value = self.symbols.typed_value(param_name, SymbolValue::Number(*arg_value))
+                                    .map_err(|e| self.add_line_info_to_error(e))?;
+                                bindings.push((param_name.clone(), value));
+                            }
+
                             // Create a temporary scope with the function parameters
-                            let nested_scope = self.symbols.get_nested_scope();
-                            let original_symbols = std::mem::replace(&mut self.symbols, nested_scope);
-                            
+                            let caller_scope = self.enter_scope();
+
                             // Bind parameters to arguments
-                            for (param_name, arg_value) in param.iter().zip(evaluated_args.iter()) {
-                                self.symbols.put_symbol(param_name.clone(), SymbolValue::Number(*arg_value));
+                            for (param_name, value) in bindings {
+                                self.symbols.put_symbol(param_name, value)?;
                             }
                             
                             // Evaluate the function body
-                            let result = self.evaluate_expression(&expr)?;
+                            self.fn_depth += 1;
+                            let result = self.evaluate_expression(&expr);
+                            self.fn_depth -= 1;
                             
                             // Restore original symbol table
-                            self.symbols = original_symbols;
+                            self.leave_scope(caller_scope);
                             
-                            Ok(result)
+                            result
                         } else {
                             Err(BasicError::Runtime {
                                 message: format!("Undefined user function '{}'", name),
+                                condition: ErrorCondition::UndefinedUserFunction,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             })
@@ -955,6 +1700,7 @@ impl Interpreter {
                     } else {
                         Err(BasicError::Runtime {
                             message: format!("Unknown function '{}'", name),
+                            condition: ErrorCondition::Unprintable,
                             basic_line_number: Some(self.get_current_line().line_number),
                             file_line_number: None,
                         })
@@ -968,6 +1714,15 @@ impl Interpreter {
                 
                 match (left_val, right_val) {
                     (SymbolValue::Number(a), SymbolValue::Number(b)) => {
+                        if let Some(integer_op) = IntegerOp::from_name(op) {
+                            let bits = self.dialect.integer_bits;
+                            // A divisor that rounds to 0 divides by zero; anything else that fails overflows
+                            if integer_op.divides_by_zero(b, bits) {
+                                return Err(self.function_error(ErrorCondition::DivisionByZero, "Division by zero".to_string()));
+                            }
+                            let result = integer_op.apply(a, b, bits).map_err(|message| self.function_error(ErrorCondition::Overflow, message))?;
+                            return Ok(SymbolValue::Number(result));
+                        }
                         let result = match op.as_str() {
                             "+" => a + b,
                             "-" => a - b,
@@ -976,6 +1731,7 @@ impl Interpreter {
                                 if b == 0.0 {
                                     return Err(BasicError::Runtime {
                                         message: "Division by zero".to_string(),
+                                        condition: ErrorCondition::DivisionByZero,
                                         basic_line_number: Some(self.get_current_line().line_number),
                                         file_line_number: None,
                                     });
@@ -983,16 +1739,15 @@ impl Interpreter {
                                 a / b
                             }
                             "^" => a.powf(b),
-                            "=" => if a == b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            "<>" => if a != b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            "<" => if a < b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            "<=" => if a <= b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            ">" => if a > b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            ">=" => if a >= b { BASIC_TRUE_F } else { BASIC_FALSE_F },
-                            "AND" => (a as i64 & b as i64) as f64,
-                            "OR" => (a as i64 | b as i64) as f64,
+                            "=" => self.truth(a == b),
+                            "<>" => self.truth(a != b),
+                            "<" => self.truth(a < b),
+                            "<=" => self.truth(a <= b),
+                            ">" => self.truth(a > b),
+                            ">=" => self.truth(a >= b),
                             _ => return Err(BasicError::Runtime {
                                 message: format!("Unknown binary operator: {}", op),
+                                condition: ErrorCondition::Unprintable,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             }),
@@ -1001,11 +1756,16 @@ impl Interpreter {
                     }
                     (SymbolValue::String(a), SymbolValue::String(b)) => {
                         let result = match op.as_str() {
-                            "+" => Ok(SymbolValue::String(format!("{}{}", a, b))),
-                            "<>" => Ok(SymbolValue::Number(if a != b { BASIC_TRUE_F } else { BASIC_FALSE_F })),
-                            "=" => Ok(SymbolValue::Number(if a == b { BASIC_TRUE_F } else { BASIC_FALSE_F })),
+                            "+" => {
+                                let joined = format!("{}{}", a, b);
+                                self.check_string_length(&joined)?;
+                                Ok(SymbolValue::String(joined))
+                            }
+                            "<>" => Ok(SymbolValue::Number(self.truth(a != b))),
+                            "=" => Ok(SymbolValue::Number(self.truth(a == b))),
                             _ => Err(BasicError::Runtime {
                                 message: format!("Invalid operator '{}' for strings", op),
+                                condition: ErrorCondition::Unprintable,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             }),
@@ -1014,6 +1774,7 @@ impl Interpreter {
                     }
                     _ => Err(BasicError::Runtime {
                         message: format!("Type mismatch for operator '{}'", op),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     }),
@@ -1027,9 +1788,11 @@ impl Interpreter {
                     SymbolValue::Number(n) => {
                         let result = match op.as_str() {
                             "-" => -n,
-                            "NOT" => if n == BASIC_FALSE_F { BASIC_TRUE_F } else { BASIC_FALSE_F },
+                            "NOT" if self.dialect.logical_not => self.truth(n == BASIC_FALSE_F),
+                            "NOT" => operators::not(n, self.dialect.integer_bits).map_err(|message| self.function_error(ErrorCondition::Overflow, message))?,
                             _ => return Err(BasicError::Runtime {
                                 message: format!("Unknown unary operator: {}", op),
+                                condition: ErrorCondition::Unprintable,
                                 basic_line_number: Some(self.get_current_line().line_number),
                                 file_line_number: None,
                             }),
@@ -1038,6 +1801,7 @@ impl Interpreter {
                     }
                     _ => Err(BasicError::Runtime {
                         message: format!("Invalid operand type for unary operator '{}'", op),
+                        condition: ErrorCondition::Unprintable,
                         basic_line_number: Some(self.get_current_line().line_number),
                         file_line_number: None,
                     }),
@@ -1047,6 +1811,8 @@ impl Interpreter {
 
         }
     }
+    /// A variable's value. One never assigned is 0, or an empty string, as in classic
+    /// BASIC and in compiled programs.
     fn get_symbol(&self, name: &str) -> Result<SymbolValue, BasicError> {
         // Try current scope first, then parent scopes
         if let Some(value) = self.symbols.get_symbol(name) {
@@ -1059,11 +1825,7 @@ impl Interpreter {
             } else if let Some(value) = self.internal_symbols.get_symbol(name) {
                 Ok(value.clone())
             } else {
-                Err(BasicError::Runtime {
-                    message: format!("Undefined variable: {}", name),
-                    basic_line_number: Some(self.get_current_line().line_number),
-                    file_line_number: None,
-                })
+                Ok(Self::initial_value(name))
             }
         }
     }
@@ -1071,14 +1833,427 @@ impl Interpreter {
         &self.symbols
     }
 
-    fn put_symbol(&mut self, name: String, value: SymbolValue) {
+    fn put_symbol(&mut self, name: String, value: SymbolValue) -> Result<(), BasicError> {
         // In BASIC, scalar variables and arrays with the same name are separate entities
         // N and N() are different - this is legitimate BASIC behavior
+        // A procedure's SHARED variables are the program's
+        let shared = self.frames.last()
+            .and_then(|frame| self.procedures.get(&frame.name))
+            .is_some_and(|procedure| procedure.shared.contains(&name));
+        let table = if shared { self.program_symbols() } else { &mut self.symbols };
+        if let Err(e) = table.check_variable_limit(&name) {
+            return Err(self.add_line_info_to_error(e));
+        }
         let name_copy=name.clone();
-        self.symbols.put_symbol(name, value);
+        if let Err(e) = table.put_symbol(name, value) {
+            return Err(self.add_line_info_to_error(e));
+        }
         if self.data_breakpoints.contains(&name_copy) {
             self.run_status = RunStatus::BreakData;
         }
+        Ok(())
+    }
+
+    /// Raises STRING TOO LONG if a string result is longer than the dialect allows
+    fn check_string_length(&self, s: &str) -> Result<(), BasicError> {
+        limits::check_string(s, self.dialect.max_string_length).map_err(|message| BasicError::Runtime {
+            message,
+            condition: ErrorCondition::StringTooLong,
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })
+    }
+
+    /// Pushes a GOSUB return location, raising OUT OF MEMORY when the dialect's stack is full
+    fn push_gosub(&mut self, return_loc: ControlLocation) -> Result<(), BasicError> {
+        if self.gosub_stack.len() >= self.dialect.max_gosub_depth {
+            return Err(BasicError::Runtime {
+                message: limits::gosub_depth_message(self.dialect.max_gosub_depth),
+                condition: ErrorCondition::OutOfMemory,
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            });
+        }
+        self.gosub_stack.push(return_loc);
+        Ok(())
+    }
+
+    /// Depths of the FOR, loop and GOSUB stacks when the running procedure was called. The
+    /// records below them belong to its callers, out of its reach.
+    fn stack_bases(&self) -> (usize, usize, usize) {
+        self.frames.last().map_or((0, 0, 0), |frame| (frame.for_base, frame.loop_base, frame.gosub_base))
+    }
+
+    /// The program's own variables, whatever scope is active
+    fn program_symbols(&mut self) -> &mut SymbolTable {
+        if self.in_scope {
+            self.symbols.parent_mut().expect("a scope has the program's variables as its parent")
+        } else {
+            &mut self.symbols
+        }
+    }
+
+    /// Starts a scope for a procedure or DEF FN call, on top of the program's variables. The
+    /// caller's scope, if it is in one, is detached and returned, to be put back by
+    /// `leave_scope`; scopes are moved rather than copied, so the call sees and changes the
+    /// program's variables themselves.
+    fn enter_scope(&mut self) -> Option<SymbolTable> {
+        let mut current = std::mem::take(&mut self.symbols);
+        let (program, caller_scope) = if self.in_scope {
+            let program = current.take_parent().expect("a scope has the program's variables as its parent");
+            (program, Some(current))
+        } else {
+            (current, None)
+        };
+        self.symbols = program.into_nested_scope();
+        self.in_scope = true;
+        caller_scope
+    }
+
+    fn leave_scope(&mut self, caller_scope: Option<SymbolTable>) {
+        let program = self.symbols.take_parent().expect("a scope has the program's variables as its parent");
+        self.in_scope = caller_scope.is_some();
+        self.symbols = match caller_scope {
+            Some(mut scope) => {
+                scope.set_parent(program);
+                scope
+            }
+            None => program,
+        };
+    }
+
+    /// The value a local variable starts with
+    fn initial_value(name: &str) -> SymbolValue {
+        if name.ends_with('$') {
+            SymbolValue::String(String::new())
+        } else {
+            SymbolValue::Number(0.0)
+        }
+    }
+
+    /// The parser rejects misplaced procedure statements, but the shell edits programs a
+    /// line at a time; such a program fails when it reaches one, with what resolving the
+    /// procedures reports
+    fn procedure_error(&self) -> BasicError {
+        Procedures::resolve(&self.program).err().unwrap_or_else(|| BasicError::Internal {
+            message: "Procedure statement outside its procedure".to_string(),
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })
+    }
+
+    /// Calls a SUB or FUNCTION: passes the arguments by value, starts the procedure's scope
+    /// with its locals set to 0 or "", and continues at its first statement
+    fn enter_procedure(&mut self, procedure: &Procedure, args: &[Expression]) -> Result<(), BasicError> {
+        let runtime_error = |message: String, condition: ErrorCondition, interpreter: &Self| BasicError::Runtime {
+            message,
+            condition,
+            basic_line_number: Some(interpreter.get_current_line().line_number),
+            file_line_number: None,
+        };
+        let keyword = procedure.kind.keyword();
+        if args.len() != procedure.params.len() {
+            return Err(runtime_error(format!("{} {} expects {} arguments, got {}", keyword, procedure.name, procedure.params.len(), args.len()), ErrorCondition::Unprintable, self));
+        }
+        let mut values = Vec::new();
+        for (i, (arg, param)) in args.iter().zip(&procedure.params).enumerate() {
+            let value = self.evaluate_expression(arg)?;
+            if matches!(value, SymbolValue::String(_)) != param.ends_with('$') {
+                return Err(runtime_error(format!("Type mismatch in argument {} of {} {}", i + 1, keyword, procedure.name), ErrorCondition::TypeMismatch, self));
+            }
+            let value = self.symbols.typed_value(param, value).map_err(|e| self.add_line_info_to_error(e))?;
+            values.push(value);
+        }
+        if self.fn_depth >= self.dialect.max_recursion_depth {
+            return Err(runtime_error(limits::recursion_depth_message(self.dialect.max_recursion_depth), ErrorCondition::OutOfMemory, self));
+        }
+        self.fn_depth += 1;
+
+        let caller_scope = self.enter_scope();
+        for name in &procedure.locals {
+            self.symbols.put_symbol(name.clone(), Self::initial_value(name))?;
+        }
+        for (param, value) in procedure.params.iter().zip(values) {
+            self.symbols.put_symbol(param.clone(), value)?;
+        }
+        self.frames.push(ProcedureFrame {
+            name: procedure.name.clone(),
+            return_to: self.location,
+            caller_scope,
+            for_base: self.for_stack.len(),
+            loop_base: self.loop_stack.len(),
+            gosub_base: self.gosub_stack.len(),
+        });
+        let (index, offset) = procedure.start;
+        self.control_transfer(ControlLocation { index, offset });
+        self.advance_stmt = true;
+        Ok(())
+    }
+
+    /// Returns from the running procedure for END SUB, EXIT SUB, END FUNCTION or EXIT
+    /// FUNCTION, dropping the loops and GOSUBs it left open. A FUNCTION's result is the
+    /// value of the variable named after it.
+    fn leave_procedure(&mut self) -> Result<(), BasicError> {
+        let Some(frame) = self.frames.pop() else {
+            return Err(BasicError::Runtime {
+                message: format!("{} without CALL", self.get_current_stmt()),
+                condition: ErrorCondition::Unprintable,
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            });
+        };
+        if self.procedures.get(&frame.name).is_some_and(|p| p.kind == ProcedureKind::Function) {
+            self.function_result = self.symbols.get_symbol(&frame.name).cloned();
+        }
+        self.for_stack.truncate(frame.for_base);
+        self.loop_stack.truncate(frame.loop_base);
+        self.gosub_stack.truncate(frame.gosub_base);
+        self.leave_scope(frame.caller_scope);
+        self.fn_depth -= 1;
+        self.control_transfer(frame.return_to);
+        self.advance_stmt = true;
+        Ok(())
+    }
+
+    /// Runs a FUNCTION called from an expression to its END FUNCTION or EXIT FUNCTION, then
+    /// continues with the expression
+    fn call_function(&mut self, procedure: &Procedure, args: &[Expression]) -> Result<SymbolValue, BasicError> {
+        let location = self.location;
+        let advance_stmt = self.advance_stmt;
+        let depth = self.frames.len();
+        self.enter_procedure(procedure, args)?;
+        loop {
+            self.advance_location();
+            let current_stmt = self.get_current_stmt().clone();
+            self.do_trace(&current_stmt);
+            if let Some(ref mut cov) = self.coverage {
+                cov.entry(self.program.lines[self.location.index].line_number)
+                    .or_insert_with(HashSet::new)
+                    .insert(self.location.offset);
+            }
+            self.execute_statement(&current_stmt)?;
+            if self.is_halted() {
+                // The statement that called the function does not finish
+                return Err(BasicError::Runtime {
+                    message: format!("Program ended in FUNCTION {}", procedure.name),
+                    condition: ErrorCondition::Unprintable,
+                    basic_line_number: Some(self.get_current_line().line_number),
+                    file_line_number: None,
+                });
+            }
+            if self.frames.len() == depth {
+                break;
+            }
+        }
+        self.location = location;
+        self.advance_stmt = advance_stmt;
+        self.function_result.take().ok_or_else(|| BasicError::Internal {
+            message: format!("FUNCTION {} returned no value", procedure.name),
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })
+    }
+
+    /// The file number an OPEN, CLOSE or file statement names
+    fn file_number(&mut self, expr: &Expression) -> Result<f64, BasicError> {
+        self.file_argument(expr, "File number")
+    }
+
+    /// Text PRINT shows for a value, with numbers written the dialect's way
+    fn print_text(&self, value: &SymbolValue) -> String {
+        match value {
+            SymbolValue::Number(n) => format::print_number(*n, &self.dialect.number_format),
+            other => other.to_string(),
+        }
+    }
+
+    /// Text PRINT shows for an expression. A double precision one shows all its digits,
+    /// even in a dialect whose numbers are single, and a single precision one only 7.
+    fn print_expression(&mut self, expr: &Expression) -> Result<String, BasicError> {
+        match self.evaluate_expression(expr)? {
+            SymbolValue::Number(n) if expr.is_double_precision() => Ok(format::print_number(n, &self.dialect.number_format.double())),
+            SymbolValue::Number(n) if expr.is_single_precision() => Ok(format::print_number(n, &self.dialect.number_format.single())),
+            value => Ok(self.print_text(&value)),
+        }
+    }
+
+    /// MAT A = ..., worked out by `basic_runtime::matrix` as in compiled code
+    fn execute_mat_assign(&mut self, var: &str, value: &MatValue) -> Result<(), BasicError> {
+        let bounds = match value.bounds() {
+            [] => None,
+            bounds => Some(self.matrix_bounds(bounds)?),
+        };
+        let scalar = match value {
+            MatValue::Scaled(k, _) => self.matrix_number(k, "MAT scalar")?,
+            _ => 0.0,
+        };
+        let mut names: Vec<&str> = value.arrays().into_iter().map(String::as_str).collect();
+        if names.is_empty() && bounds.is_none() {
+            // ZER, CON and IDN keep the size of the array assigned to
+            names.push(var);
+        }
+        let operands = names.into_iter()
+            .map(|name| self.program_symbols().get_matrix(name))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| self.add_line_info_to_error(e))?;
+        let op = value.operation();
+        if op == Operation::Inverse {
+            self.determinant = 0.0;
+        }
+        let (result, determinant) = matrix::apply(op, &operands, scalar, bounds).map_err(|e| BasicError::Runtime {
+            message: e.message,
+            condition: e.kind.into(),
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })?;
+        if let Some(determinant) = determinant {
+            self.determinant = determinant;
+        }
+        self.program_symbols().set_matrix(var, result).map_err(|e| self.add_line_info_to_error(e))
+    }
+
+    /// A line typed for INPUT, from the replay when there is one. A replayed line is shown
+    /// as though it had been typed; one for a different INPUT statement ends the program,
+    /// and asking for more than the replay holds is an Input past end error.
+    fn read_input(&mut self) -> Result<String, BasicError> {
+        let line_number = self.get_current_line().line_number;
+        let input = match self.input_replay.as_mut() {
+            Some(replay) => {
+                let text = replay.next(line_number).map_err(|message| BasicError::Internal {
+                    message,
+                    basic_line_number: Some(line_number),
+                    file_line_number: None,
+                })?;
+                let text = text.ok_or_else(|| BasicError::Runtime {
+                    message: "Input past end of the replay".to_string(),
+                    condition: ErrorCondition::InputPastEnd,
+                    basic_line_number: Some(line_number),
+                    file_line_number: None,
+                })?;
+                self.console.write(&format!("{}\n", text))?;
+                text + "\n"
+            }
+            None => self.console.read_line()?,
+        };
+        if let Some(recorder) = self.input_recorder.as_mut() {
+            recorder.record(line_number, input.trim_end_matches(['\r', '\n']))?;
+        }
+        Ok(input)
+    }
+
+    /// The dimensions given to MAT READ's arrays and to ZER, CON and IDN
+    fn matrix_bounds(&mut self, bounds: &[Expression]) -> Result<Vec<usize>, BasicError> {
+        let values = bounds.iter()
+            .map(|bound| self.matrix_number(bound, "MAT dimension"))
+            .collect::<Result<Vec<_>, _>>()?;
+        matrix::bounds(&values).map_err(|e| BasicError::Runtime {
+            message: e.message,
+            condition: e.kind.into(),
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        })
+    }
+
+    fn matrix_number(&mut self, expr: &Expression, what: &str) -> Result<f64, BasicError> {
+        match self.evaluate_expression(expr)? {
+            SymbolValue::Number(n) => Ok(n),
+            _ => Err(BasicError::Type {
+                message: format!("{} must be a number", what),
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            }),
+        }
+    }
+
+    /// A numeric argument of a file statement, such as a record length or number
+    fn file_argument(&mut self, expr: &Expression, what: &str) -> Result<f64, BasicError> {
+        match self.evaluate_expression(expr)? {
+            SymbolValue::Number(n) => Ok(n),
+            _ => Err(BasicError::Type {
+                message: format!("{} must be a number", what),
+                basic_line_number: Some(self.get_current_line().line_number),
+                file_line_number: None,
+            }),
+        }
+    }
+
+    /// Sets the variables FIELD bound to a random file from its record buffer
+    fn load_fields(&mut self, number: f64) -> Result<(), BasicError> {
+        let fields = self.files.fields(number).map_err(|e| self.file_error(e))?;
+        for (var, value) in fields {
+            self.put_symbol(var, SymbolValue::String(value))?;
+        }
+        Ok(())
+    }
+
+    /// Stores a value into a variable or array element, as LET does
+    fn store_to(&mut self, var: &Expression, value: SymbolValue) -> Result<(), BasicError> {
+        match &var.expr_type {
+            ExpressionType::Variable(name) => {
+                self.put_symbol(name.clone(), value)
+            }
+            ExpressionType::Array { name, indices } => {
+                let indices = self.evaluate_array_indices(indices)?;
+                self.program_symbols()
+                    .set_array_element(name, &indices, value)
+                    .map_err(|mut err| {
+                        if let BasicError::Runtime {
+                            ref mut basic_line_number,
+                            ref mut file_line_number,
+                            ..
+                        } = err
+                        {
+                            *basic_line_number = Some(self.get_current_line().line_number);
+                            *file_line_number = None;
+                        }
+                        err
+                    })?;
+                Ok(())
+            }
+            _ => {
+                // Try to evaluate the left-hand side as an expression
+                // This handles cases like LET A = B where A might be a variable
+                if let ExpressionType::Variable(name) = &var.expr_type {
+                    self.put_symbol(name.clone(), value)
+                } else {
+                    Err(BasicError::Runtime {
+                        message: "Invalid left-hand side in assignment".to_string(),
+                        condition: ErrorCondition::Unprintable,
+                        basic_line_number: Some(self.get_current_line().line_number),
+                        file_line_number: None,
+                    })
+                }
+            }
+        }
+    }
+
+    /// The number a true or false comparison gives in this dialect
+    fn truth(&self, holds: bool) -> f64 {
+        if holds { self.dialect.true_value as f64 } else { BASIC_FALSE_F }
+    }
+
+    /// A runtime error from a built-in function or operator, such as "Overflow in HEX$"
+    fn function_error(&self, condition: ErrorCondition, message: String) -> BasicError {
+        BasicError::Runtime {
+            message,
+            condition,
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        }
+    }
+
+    fn file_error(&self, error: FileError) -> BasicError {
+        BasicError::Runtime {
+            message: error.message,
+            condition: error.kind.into(),
+            basic_line_number: Some(self.get_current_line().line_number),
+            file_line_number: None,
+        }
+    }
+
+    /// True once END or STOP has run
+    fn is_halted(&self) -> bool {
+        matches!(self.run_status, RunStatus::EndNormal | RunStatus::EndStop)
     }
 
     fn goto_line(&mut self, line_number: usize) -> Result<(), BasicError> {
@@ -1091,6 +2266,7 @@ impl Interpreter {
         } else {
             Err(BasicError::Runtime {
                 message: format!("Line number {} not found", line_number),
+                condition: ErrorCondition::UndefinedLine,
                 basic_line_number: Some(line_number),
                 file_line_number: None,
             })
@@ -1105,8 +2281,21 @@ impl Interpreter {
         self.coverage.as_ref()
     }
     
+    /// Once the program has stopped for good, writes out and closes the files it left open
+    fn close_files_if_ended(&mut self) {
+        if !matches!(self.run_status, RunStatus::Run | RunStatus::BreakCode | RunStatus::BreakData) {
+            let _ = self.files.close_all();
+        }
+    }
+
     /// Execute a single statement (for single-step debugging)
     pub fn step(&mut self) -> Result<(), BasicError> {
+        let result = self.step_statement();
+        self.close_files_if_ended();
+        result
+    }
+
+    fn step_statement(&mut self) -> Result<(), BasicError> {
         // Allow stepping when at a breakpoint or normally running
         if self.run_status != RunStatus::Run && self.run_status != RunStatus::BreakCode && self.run_status != RunStatus::BreakData {
             return Ok(());
@@ -1134,11 +2323,13 @@ impl Interpreter {
         }
         
         // Execute statement
-        match self.execute_statement(&current_stmt) {
+        let location = self.location;
+        match self.execute_statement(&current_stmt).or_else(|err| self.trap_error(err, location)) {
             Ok(()) => {
                 self.advance_location();
                 Ok(())
             }
+            Err(_) if self.is_halted() => Ok(()),
             Err(err) => {
                 self.run_status = match err {
                     BasicError::Syntax { .. } => RunStatus::EndErrorSyntax,
@@ -1167,7 +2358,7 @@ impl Interpreter {
                     // Found ELSE, advance to it
                     self.control_transfer(ControlLocation{
                         index: self.location.index,
-                        offset: offset,
+                        offset,
                     });
                     self.advance_stmt = true;   // The above points to the else. We don't want
                                                 // to execute the else, we want to start with
@@ -1191,6 +2382,9 @@ impl Interpreter {
 mod tests {
     use crate::basic_lexer::Lexer;
     use crate::basic_parser::Parser;
+    use crate::basic_console::{BufferConsole, ScriptedConsole};
+    use crate::basic_input_log::InputReplay;
+    use basic_runtime::rng::RndAlgorithm;
     use super::*;
     use crate::basic_types::{Statement, Expression, ArrayDecl};
     use crate::basic_types::{
@@ -1214,7 +2408,7 @@ mod tests {
             (30, vec![Statement::new_let(Expression::new_variable("Z".to_string()), Expression::new_number(3.0))]),
         ]);
         
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         interpreter.run()?;
         
         assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(1.0));
@@ -1235,7 +2429,7 @@ mod tests {
 
         println!("Program has {} lines.", program.lines.len());
         println!("{}", program);
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         interpreter.run()?;
         println!("SYMBOLLLLLLS");
         let symbols = interpreter.get_symbol_table();
@@ -1243,26 +2437,532 @@ mod tests {
             println!("{} = {}", name, value);
         }
         assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(1.0));
-        assert!(interpreter.get_symbol("Y").is_err()); // Line 30 should be skipped
+        assert!(interpreter.get_symbol_table().get_symbol("Y").is_none()); // Line 30 should be skipped
         assert_eq!(interpreter.get_symbol("Z")?, SymbolValue::Number(3.0));
         
         Ok(())
     }
 
+    #[test]
+    fn test_stop_on_last_line() -> Result<(), BasicError> {
+        let program = create_test_program(vec![
+            (10, vec![Statement::new_let(Expression::new_variable("X".to_string()), Expression::new_number(1.0))]),
+            (20, vec![Statement::Stop]),
+        ]);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
+        interpreter.run()?;
+        assert_eq!(interpreter.get_run_status(), RunStatus::EndStop);
+        Ok(())
+    }
+
+    fn run_source(source: &str, dialect: Dialect) -> (Interpreter, Result<(), BasicError>) {
+        let tokens = Lexer::with_dialect(source, dialect).tokenize().expect("Lexing failed");
+        let program = Parser::with_dialect(tokens, dialect).parse().expect("Parsing failed");
+        let mut interpreter = Interpreter::with_dialect(program, dialect, Box::new(BufferConsole::new()));
+        let result = interpreter.run();
+        (interpreter, result)
+    }
+
+    #[test]
+    fn test_runaway_gosub_limit() {
+        let (interpreter, result) = run_source("10 GOSUB 10", Dialect::ANSI_MINIMAL);
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("OUT OF MEMORY: GOSUBs nested more than 26 deep"));
+        assert_eq!(interpreter.get_gosub_stack().len(), 26);
+        assert_eq!(interpreter.get_run_status(), RunStatus::EndErrorRuntime);
+    }
+
+    #[test]
+    fn test_for_reentry_reuses_frame() -> Result<(), BasicError> {
+        // Jumping out of a loop and starting it again must not use up the FOR stack
+        let source = "5 K = 0\n10 K = K + 1\n20 FOR I = 1 TO 5\n30 IF K < 100 THEN 10\n40 NEXT I";
+        let (interpreter, result) = run_source(source, Dialect::ANSI_MINIMAL);
+        result?;
+        assert_eq!(interpreter.get_symbol("K")?, SymbolValue::Number(100.0));
+        assert!(interpreter.get_for_stack().is_empty());
+
+        let (_, result) = run_source("10 FOR I = 1 TO 2\n20 FOR J = 1 TO 2\n30 FOR K = 1 TO 2", Dialect { max_for_depth: 2, ..Dialect::default() });
+        assert!(result.unwrap_err().to_string().contains("OUT OF MEMORY: FOR loops nested more than 2 deep"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dialect_number_format() -> Result<(), BasicError> {
+        let source = "10 A$ = STR$(1/3): B$ = STR$(-2): C$ = STR$(1234567890)";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        result?;
+        assert_eq!(interpreter.get_symbol("A$")?, SymbolValue::String(" .3333333".to_string()));
+        assert_eq!(interpreter.get_symbol("B$")?, SymbolValue::String("-2".to_string()));
+        assert_eq!(interpreter.get_symbol("C$")?, SymbolValue::String(" 1.234568E+09".to_string()));
+
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("A$")?, SymbolValue::String(".3333333333333333".to_string()));
+        assert_eq!(interpreter.get_symbol("C$")?, SymbolValue::String("1234567890".to_string()));
+        assert_eq!(interpreter.print_text(&SymbolValue::Number(0.5)), " .5 ");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dialect_precision() -> Result<(), BasicError> {
+        let source = "10 A = 16777216: A = A + 1\n20 X = 0: FOR I = 1 TO 10: X = X + .1: NEXT I\n30 DIM B(1): B(1) = 1 / 3";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        result?;
+        assert_eq!(interpreter.get_symbol("A")?, SymbolValue::Number(16777216.0));
+        let mut x = 0f32;
+        for _ in 0..10 {
+            x += 0.1;
+        }
+        assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(x as f64));
+        assert_eq!(interpreter.symbols.get_array_element("B", &[1])?, SymbolValue::Number((1.0f32 / 3.0) as f64));
+
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("A")?, SymbolValue::Number(16777217.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_variables() -> Result<(), BasicError> {
+        let source = "10 DEFINT I-N: DEFSTR S\n20 I = 2.5: J% = -7.6: K! = 1 / 3: D# = 1 / 3: E# = D# / 3\n30 S = \"HI\": DIM N(2): N(1) = 9.7\n40 L = 32767: L = L + 1";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(40), .. }));
+        assert!(err.to_string().contains("Overflow: L% holds whole numbers from -32768 to 32767"));
+        assert_eq!(interpreter.get_symbol("I%")?, SymbolValue::Number(3.0));
+        assert_eq!(interpreter.get_symbol("J%")?, SymbolValue::Number(-8.0));
+        assert_eq!(interpreter.get_symbol("L%")?, SymbolValue::Number(32767.0));
+        assert_eq!(interpreter.get_symbol("S$")?, SymbolValue::String("HI".to_string()));
+        assert_eq!(interpreter.symbols.get_array_element("N%", &[1])?, SymbolValue::Number(10.0));
+        // K and K! are one variable in GW-BASIC. 1 / 3 is computed in single precision,
+        // but D# / 3 in double.
+        let third = (1.0f32 / 3.0) as f64;
+        assert_eq!(interpreter.get_symbol("K")?, SymbolValue::Number(third));
+        assert_eq!(interpreter.get_symbol("D#")?, SymbolValue::Number(third));
+        assert_eq!(interpreter.get_symbol("E#")?, SymbolValue::Number(third / 3.0));
+
+        // BasicRS integers have 32 bits, and its own numbers are double precision
+        let (mut interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("L%")?, SymbolValue::Number(32768.0));
+        assert_eq!(interpreter.get_symbol("K!")?, SymbolValue::Number(third));
+        assert_eq!(interpreter.get_symbol("D")?, SymbolValue::Number(1.0 / 3.0));
+        // K! prints, and is computed, in single precision; adding D makes it double
+        let k = Expression::new_variable("K!".to_string());
+        let twice = Expression::new_binary_op("*".to_string(), k.clone(), Expression::new_number(2.0));
+        let sum = Expression::new_binary_op("+".to_string(), k.clone(), Expression::new_variable("D".to_string()));
+        assert_eq!(interpreter.print_expression(&k)?, " .3333333 ");
+        assert_eq!(interpreter.print_expression(&twice)?, " .6666667 ");
+        assert_eq!(interpreter.print_expression(&sum)?, " .6666666766007741 ");
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_too_long() {
+        let source = "10 A$ = \"ABCDEFGHIJ\"\n20 B$ = A$ + A$";
+        let (_, result) = run_source(source, Dialect::default());
+        assert!(result.is_ok());
+        let (_, result) = run_source(source, Dialect::ANSI_MINIMAL);
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(20), .. }));
+        assert!(err.to_string().contains("STRING TOO LONG"));
+
+        let (_, result) = run_source("10 A$ = SPACE$(19)", Dialect::ANSI_MINIMAL);
+        assert!(result.unwrap_err().to_string().contains("STRING TOO LONG"));
+    }
+
+    #[test]
+    fn test_recursion_and_variable_limits() {
+        let (interpreter, result) = run_source("10 DEF FNA(X) = FNA(X + 1)\n20 Y = FNA(1)", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("OUT OF MEMORY: function calls nested more than 100 deep"));
+        // The function's parameter scope is gone after the error
+        assert!(interpreter.get_symbol_value("X").is_none());
+
+        let (_, result) = run_source("10 A = 1\n20 B = 2\n30 A = 3\n40 C = 4", Dialect { max_variables: 2, ..Dialect::default() });
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(40), .. }));
+        assert!(err.to_string().contains("OUT OF MEMORY: more than 2 variables"));
+    }
+
+    #[test]
+    fn test_on_error_resume() {
+        let source = "5 C = 0\n10 ON ERROR GOTO 100\n20 D = 0: X = 1 / D: Y = 2\n30 Z = 10 / D\n40 DIM A(2): A(5) = 1\n50 END\n\
+            100 C = C + 1: E = ERR: L = ERL\n110 IF L = 20 THEN RESUME NEXT\n120 IF L = 30 THEN D = 5: RESUME\n130 RESUME 50";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        assert!(result.is_ok());
+        assert_eq!(interpreter.get_symbol_value("Y"), Some(&SymbolValue::Number(2.0)));
+        assert_eq!(interpreter.get_symbol_value("Z"), Some(&SymbolValue::Number(2.0)));
+        assert_eq!(interpreter.get_symbol_value("C"), Some(&SymbolValue::Number(3.0)));
+        // The last error was the subscript at line 40
+        assert_eq!(interpreter.get_symbol_value("E"), Some(&SymbolValue::Number(9.0)));
+        assert_eq!(interpreter.get_symbol_value("L"), Some(&SymbolValue::Number(40.0)));
+
+        // The same program in 6502 BASIC reports its own codes
+        let (interpreter, _) = run_source("10 ON ERROR GOTO 100\n20 X = 1 / 0\n30 END\n100 E = ERR: RESUME NEXT", Dialect::MSBASIC2);
+        assert_eq!(interpreter.get_symbol_value("E"), Some(&SymbolValue::Number(133.0)));
+
+        // \ by a divisor that rounds to 0 divides by zero; an operand too big for 16 bits overflows
+        let source = "10 ON ERROR GOTO 100\n20 X = 5 \\ 0.4\n30 Y = 40000 AND 1\n40 END\n\
+            100 IF ERL = 20 THEN A = ERR\n110 IF ERL = 30 THEN B = ERR\n120 RESUME NEXT";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        assert!(result.is_ok());
+        assert_eq!(interpreter.get_symbol_value("A"), Some(&SymbolValue::Number(11.0)));
+        assert_eq!(interpreter.get_symbol_value("B"), Some(&SymbolValue::Number(6.0)));
+    }
+
+    #[test]
+    fn test_untrapped_errors() {
+        // An error inside the handler ends the program
+        let (_, result) = run_source("10 ON ERROR GOTO 100\n20 X = 1 / 0\n30 END\n100 Y = 1 / 0", Dialect::default());
+        assert!(matches!(result.unwrap_err(), BasicError::Runtime { basic_line_number: Some(100), .. }));
+
+        // ON ERROR GOTO 0 in the handler stops with the error being handled
+        let (interpreter, result) = run_source("10 ON ERROR GOTO 100\n20 X = 1 / 0\n30 END\n100 ON ERROR GOTO 0", Dialect::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(20), .. }));
+        assert!(err.to_string().contains("Division by zero"));
+        assert_eq!(interpreter.get_run_status(), RunStatus::EndErrorRuntime);
+
+        let (_, result) = run_source("10 RESUME", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("RESUME without error"));
+
+        let (_, result) = run_source("10 ON ERROR GOTO 500", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("Line number 500 not found"));
+    }
+
+    #[test]
+    fn test_console() {
+        let source = "10 PRINT \"A\";\n20 INPUT \"N\"; N\n30 PRINT ,N * 2\n40 INPUT X";
+        let program = || Parser::new(Lexer::new(source).tokenize().unwrap()).parse().unwrap();
+
+        // INPUT ends the line PRINT left open; the wrong number of values asks again
+        let buffer = BufferConsole::new();
+        let console = ScriptedConsole::new(buffer.clone(), ["1, 2", "21", "5"]);
+        let mut interpreter = Interpreter::new(program(), Box::new(console));
+        interpreter.run().unwrap();
+        assert_eq!(buffer.output(), "AN? 1, 2\n?Redo from start\nN? 21\n         42 \n? 5\n");
+
+        // Running out of script is an error rather than a wait for input
+        let console = ScriptedConsole::new(buffer.clone(), ["3"]);
+        let mut interpreter = Interpreter::new(program(), Box::new(console));
+        assert!(interpreter.run().unwrap_err().to_string().contains("the input script has no more lines"));
+    }
+
+    #[test]
+    fn test_replay_input() {
+        let program = Parser::new(Lexer::new("10 INPUT A\n20 IF A > 1 THEN INPUT B\n30 PRINT A").tokenize().unwrap()).parse().unwrap();
+        let buffer = BufferConsole::new();
+        let mut interpreter = Interpreter::new(program.clone(), Box::new(buffer.clone()));
+        interpreter.replay_input(InputReplay::parse("10\tT\t1\n").unwrap());
+        interpreter.run().unwrap();
+        assert_eq!(buffer.output(), "? 1\n 1 \n");
+
+        // A response typed at another line means the program has gone a different way
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
+        interpreter.replay_input(InputReplay::parse("10\tT\t5\n30\tT\t6\n").unwrap());
+        let err = interpreter.run().unwrap_err();
+        assert!(matches!(err, BasicError::Internal { basic_line_number: Some(20), .. }));
+        assert!(err.to_string().contains("Replay expected INPUT at line 30, but the program asked at line 20"));
+
+        // Running out of responses is an error a program can trap
+        let program = Parser::new(Lexer::new("10 ON ERROR GOTO 100\n20 INPUT A\n30 INPUT B\n40 END\n100 PRINT ERR; ERL").tokenize().unwrap()).parse().unwrap();
+        let buffer = BufferConsole::new();
+        let mut interpreter = Interpreter::with_dialect(program, Dialect::GWBASIC, Box::new(buffer.clone()));
+        interpreter.replay_input(InputReplay::parse("20\tT\t5\n").unwrap());
+        interpreter.run().unwrap();
+        assert!(buffer.output().ends_with(" 62  30 \n"));
+    }
+
+    #[test]
+    fn test_randomize() -> Result<(), BasicError> {
+        // The same seed gives the same numbers, and RND(0) repeats the last one
+        let source = "10 RANDOMIZE 5: A = RND(1): B = RND(0)\n20 RANDOMIZE 5: C = RND(1)\n30 D = RND(-2): E = RND(-2)";
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("A")?, interpreter.get_symbol("B")?);
+        assert_eq!(interpreter.get_symbol("A")?, interpreter.get_symbol("C")?);
+        assert_eq!(interpreter.get_symbol("D")?, interpreter.get_symbol("E")?);
+
+        // QBasic's first numbers, with no RANDOMIZE
+        let dialect = Dialect { rnd: RndAlgorithm::Microsoft, ..Dialect::GWBASIC };
+        let (interpreter, result) = run_source("10 A = RND(1): B = RND(1)", dialect);
+        result?;
+        assert_eq!(interpreter.get_symbol("A")?, SymbolValue::Number(0.7055475115776062));
+        assert_eq!(interpreter.get_symbol("B")?, SymbolValue::Number(0.5334240198135376));
+        Ok(())
+    }
+
+    #[test]
+    fn test_integer_operators() -> Result<(), BasicError> {
+        let source = "10 A = 7 \\ 2: B = -7 MOD 3: C = NOT 0: D = 5 XOR 3: E = 5 EQV 3: F = -1 IMP 0: G = (1 < 2)";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        result?;
+        for (name, value) in [("A", 3.0), ("B", -1.0), ("C", -1.0), ("D", 6.0), ("E", -7.0), ("F", 0.0), ("G", -1.0)] {
+            assert_eq!(interpreter.get_symbol(name)?, SymbolValue::Number(value), "{}", name);
+        }
+
+        // Elsewhere NOT is logical and true is 1
+        for dialect in [Dialect::default(), Dialect::DARTMOUTH] {
+            let (interpreter, result) = run_source("10 C = NOT 0: D = NOT 5: G = (1 < 2)", dialect);
+            result?;
+            assert_eq!(interpreter.get_symbol("C")?, SymbolValue::Number(1.0));
+            assert_eq!(interpreter.get_symbol("D")?, SymbolValue::Number(0.0));
+            assert_eq!(interpreter.get_symbol("G")?, SymbolValue::Number(1.0));
+        }
+
+        // Operands are rounded, not truncated
+        let (interpreter, result) = run_source("10 A = 2.7 AND 3: B = 2.4 AND 3: C = -2.5 OR 0", Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("A")?, SymbolValue::Number(3.0));
+        assert_eq!(interpreter.get_symbol("B")?, SymbolValue::Number(2.0));
+        assert_eq!(interpreter.get_symbol("C")?, SymbolValue::Number(-3.0));
+
+        let (_, result) = run_source("10 A = 40000 AND 1", Dialect::GWBASIC);
+        assert!(result.unwrap_err().to_string().contains("Overflow in AND"));
+        let (_, result) = run_source("10 A = 5 MOD .4", Dialect::GWBASIC);
+        assert!(result.unwrap_err().to_string().contains("Division by zero"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_functions() -> Result<(), BasicError> {
+        let source = "10 A$ = \"HELLO WORLD\": MID$(A$, 7) = \"there\": MID$(A$, 1, 1) = \"JX\"\n\
+            20 B = INSTR(A$, \"t\"): C = INSTR(3, \"ABCABC\", \"BC\"): D = VAL(\" 1 2AB\"): E = VAL(\"&HFFFF\")\n\
+            30 F$ = UCASE$(LTRIM$(\"  ab\")) + STRING$(2, \"xy\") + HEX$(255) + OCT$(-1)";
+        let (interpreter, result) = run_source(source, Dialect::GWBASIC);
+        result?;
+        assert_eq!(interpreter.get_symbol("A$")?, SymbolValue::String("JELLO there".to_string()));
+        assert_eq!(interpreter.get_symbol("B")?, SymbolValue::Number(7.0));
+        assert_eq!(interpreter.get_symbol("C")?, SymbolValue::Number(5.0));
+        assert_eq!(interpreter.get_symbol("D")?, SymbolValue::Number(12.0));
+        assert_eq!(interpreter.get_symbol("E")?, SymbolValue::Number(-1.0));
+        assert_eq!(interpreter.get_symbol("F$")?, SymbolValue::String("ABxxFF177777".to_string()));
+
+        // Dartmouth takes only whole numbers
+        let (_, result) = run_source("10 D = VAL(\"12AB\")", Dialect::DARTMOUTH);
+        assert!(result.unwrap_err().to_string().contains("Illegal function call in VAL"));
+        let (_, result) = run_source("10 A$ = \"AB\": MID$(A$, 3) = \"X\"", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("Illegal function call in MID$"));
+        // Characters count from 1, in MID$ and in an assignment to it
+        for source in ["10 A$ = \"AB\": B$ = MID$(A$, 0)", "10 A$ = \"AB\": MID$(A$, 0, 1) = \"X\""] {
+            let (_, result) = run_source(source, Dialect::GWBASIC);
+            let err = result.unwrap_err();
+            assert_eq!(err.condition(), Some(ErrorCondition::IllegalFunctionCall));
+            assert!(err.to_string().contains("Illegal function call in MID$"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_while_and_do_loops() -> Result<(), BasicError> {
+        let source = "10 I = 0: S = 0\n20 WHILE I < 5\n30 I = I + 1: S = S + I\n40 WEND\n\
+            50 J = 0: DO\n60 J = J + 1: IF J = 3 THEN EXIT DO\n70 LOOP\n\
+            80 K = 10: DO WHILE K < 5: K = K + 1: LOOP\n90 N = 0: DO: N = N + 1: LOOP UNTIL N >= 4";
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("S")?, SymbolValue::Number(15.0));
+        assert_eq!(interpreter.get_symbol("J")?, SymbolValue::Number(3.0));
+        // DO WHILE tests first, so the body never runs
+        assert_eq!(interpreter.get_symbol("K")?, SymbolValue::Number(10.0));
+        assert_eq!(interpreter.get_symbol("N")?, SymbolValue::Number(4.0));
+        assert!(interpreter.get_loop_stack().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_mismatches() {
+        let (_, result) = run_source("10 WEND", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("WEND without WHILE"));
+
+        // A WHILE that nothing closes is an error when the program is loaded, even if it
+        // would never need its WEND
+        let tokens = Lexer::new("10 WHILE 1\n20 PRINT 1\n30 END").tokenize().unwrap();
+        let err = Parser::new(tokens).parse().unwrap_err();
+        assert!(matches!(err, BasicError::Syntax { basic_line_number: Some(10), .. }));
+        assert!(err.to_string().contains("WHILE without WEND"));
+
+        let (_, result) = run_source("10 DO\n20 WEND\n30 LOOP", Dialect::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(20), .. }));
+        assert!(err.to_string().contains("Mismatched WEND: expected LOOP for the DO at line 10"));
+
+        let (_, result) = run_source("10 EXIT DO", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("EXIT DO without DO"));
+
+        // Loop errors can be trapped, with GW-BASIC's codes
+        let (interpreter, _) = run_source("10 ON ERROR GOTO 100\n20 WEND\n30 END\n100 E = ERR: RESUME NEXT", Dialect::GWBASIC);
+        assert_eq!(interpreter.get_symbol_value("E"), Some(&SymbolValue::Number(30.0)));
+    }
+
+    #[test]
+    fn test_block_if() -> Result<(), BasicError> {
+        let source = "5 A = 0: B = 0: D = 0: E = 0\n10 FOR I = 1 TO 4\n20 IF I = 1 THEN\n30 A = A + 1\n40 ELSEIF I = 2 THEN\n50 B = B + 1\n\
+            60 IF I = 2 THEN\n70 C = 1\n80 END IF\n90 ELSEIF I = 3 THEN\n100 D = D + 1\n110 ELSE\n120 E = E + 1\n130 END IF\n140 NEXT I";
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        for (name, value) in [("A", 1.0), ("B", 1.0), ("C", 1.0), ("D", 1.0), ("E", 1.0)] {
+            assert_eq!(interpreter.get_symbol(name)?, SymbolValue::Number(value), "{}", name);
+        }
+
+        // A block left open by editing fails when it is reached
+        let mut program = Program::new();
+        program.add_line(10, "IF 1 THEN".to_string(), vec![Statement::IfBlock { condition: Expression::new_number(0.0) }]);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
+        let err = interpreter.run().unwrap_err();
+        assert!(matches!(err, BasicError::Syntax { basic_line_number: Some(10), .. }));
+        assert!(err.to_string().contains("IF without END IF"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_case() -> Result<(), BasicError> {
+        // R collects one digit per pass, naming the branch taken
+        let source = "10 R = 0\n20 FOR I = 1 TO 12\n30 SELECT CASE I\n40 CASE 1, 2: K = 1\n50 CASE 3 TO 5\n60 K = 2\n\
+            70 CASE IS > 10: K = 3\n80 CASE ELSE: K = 4\n90 END SELECT\n100 R = R * 10 + K\n110 NEXT I\n\
+            120 A$ = \"KIWI\": S = 0\n130 SELECT CASE A$\n140 CASE \"APPLE\": S = 1\n150 CASE \"B\" TO \"M\": S = 2\n160 END SELECT";
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_symbol("R")?, SymbolValue::Number(112224444433.0));
+        assert_eq!(interpreter.get_symbol("S")?, SymbolValue::Number(2.0));
+
+        // A CASE of the wrong type is an error on the CASE's line
+        let (_, result) = run_source("10 SELECT CASE 1\n20 CASE \"A\"\n30 END SELECT", Dialect::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Type { basic_line_number: Some(20), .. }));
+        assert!(err.to_string().contains("CASE value must have the same type as SELECT CASE"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_procedures() -> Result<(), BasicError> {
+        let source = "10 T = 0: I = 99: DIM A(3)\n20 CALL ADDUP(4)\n30 S = SQUARE(3) + FACT(5)\n40 W$ = TWICE$(\"AB\")\n\
+            50 CALL FILL\n60 END\n\
+            100 SUB ADDUP(N)\n110 SHARED T\n120 FOR I = 1 TO N: T = T + I: NEXT I\n130 IF T > 5 THEN EXIT SUB\n140 T = -1\n150 END SUB\n\
+            200 FUNCTION SQUARE(N): SQUARE = N * N: END FUNCTION\n\
+            300 FUNCTION FACT(N)\n310 IF N <= 1 THEN FACT = 1: EXIT FUNCTION\n320 FACT = N * FACT(N - 1)\n330 END FUNCTION\n\
+            400 FUNCTION TWICE$(S$): TWICE$ = S$ + S$: END FUNCTION\n\
+            500 SUB FILL\n510 LOCAL K\n520 GOSUB 550\n530 A(2) = K\n540 EXIT SUB\n550 K = I + 1\n560 RETURN\n570 END SUB";
+        let (interpreter, result) = run_source(source, Dialect::default());
+        result?;
+        // SHARED T is the program's; the loop variable and parameter are the procedure's own
+        assert_eq!(interpreter.get_symbol("T")?, SymbolValue::Number(10.0));
+        assert_eq!(interpreter.get_symbol("I")?, SymbolValue::Number(99.0));
+        assert!(interpreter.get_symbol_table().get_symbol("N").is_none());
+        assert_eq!(interpreter.get_symbol("S")?, SymbolValue::Number(129.0));
+        assert_eq!(interpreter.get_symbol("W$")?, SymbolValue::String("ABAB".to_string()));
+        // Arrays are the program's, and unassigned names read the program's variables
+        assert_eq!(interpreter.symbols.get_array_element("A", &[2])?, SymbolValue::Number(100.0));
+        assert!(interpreter.frames.is_empty() && interpreter.gosub_stack.is_empty());
+
+        // Arguments are checked against the parameters when the call is made
+        let (_, result) = run_source("10 CALL P(\"X\")\n20 SUB P(N)\n30 END SUB", Dialect::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, BasicError::Runtime { basic_line_number: Some(10), .. }));
+        assert!(err.to_string().contains("Type mismatch in argument 1 of SUB P"));
+
+        // An error inside a procedure is not trapped
+        let (_, result) = run_source("10 ON ERROR GOTO 50\n20 CALL P\n30 END\n40 SUB P: X = 1 / 0: END SUB\n50 RESUME NEXT", Dialect::default());
+        assert!(result.unwrap_err().to_string().contains("Division by zero"));
+
+        // END inside a FUNCTION ends the program without finishing the calling statement
+        let (interpreter, result) = run_source("10 X = 1: X = F(2)\n20 FUNCTION F(N): END: END FUNCTION", Dialect::default());
+        result?;
+        assert_eq!(interpreter.get_run_status(), RunStatus::EndNormal);
+        assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(1.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_files() -> Result<(), BasicError> {
+        let dir = std::env::temp_dir().join(format!("basic_rs_files_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = "10 OPEN \"HI.TXT\" FOR OUTPUT AS #1\n20 WRITE #1, \"ANN, B\", 120\n30 PRINT #1, \"NOTE\"; 7\n40 CLOSE\n\
+            50 OPEN \"HI.TXT\" FOR INPUT AS #2\n60 N = LOF(2)\n70 INPUT #2, A$, S\n80 LINE INPUT #2, L$\n90 E = EOF(2)\n100 CLOSE #2\n\
+            110 C = 0: DIM R(2): ON ERROR GOTO 200\n120 OPEN \"../OUT.TXT\" FOR OUTPUT AS #1\n130 INPUT #1, X\n140 END\n200 C = C + 1: R(C) = ERR: RESUME NEXT";
+        let tokens = Lexer::with_dialect(source, Dialect::GWBASIC).tokenize()?;
+        let program = Parser::with_dialect(tokens, Dialect::GWBASIC).parse()?;
+        let mut interpreter = Interpreter::with_dialect(program, Dialect::GWBASIC, Box::new(BufferConsole::new()));
+        interpreter.set_sandbox(dir.clone());
+        interpreter.run()?;
+
+        assert_eq!(std::fs::read_to_string(dir.join("HI.TXT"))?, "\"ANN, B\",120\nNOTE 7 \n");
+        assert_eq!(interpreter.get_symbol("N")?, SymbolValue::Number(21.0));
+        assert_eq!(interpreter.get_symbol("A$")?, SymbolValue::String("ANN, B".to_string()));
+        assert_eq!(interpreter.get_symbol("S")?, SymbolValue::Number(120.0));
+        assert_eq!(interpreter.get_symbol("L$")?, SymbolValue::String("NOTE 7 ".to_string()));
+        assert_eq!(interpreter.get_symbol("E")?, SymbolValue::Number(-1.0));
+        // The sandbox refuses the path outside it, and #1 is then not open
+        assert_eq!(interpreter.symbols.get_array_element("R", &[1])?, SymbolValue::Number(75.0));
+        assert_eq!(interpreter.symbols.get_array_element("R", &[2])?, SymbolValue::Number(52.0));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_files() -> Result<(), BasicError> {
+        let dir = std::env::temp_dir().join(format!("basic_rs_random_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = "10 OPEN \"R.DAT\" AS #1 LEN=10\n20 FIELD #1, 6 AS N$, 4 AS V$\n\
+            30 LSET N$ = \"ALICE SMITH\": LSET V$ = MKS$(1.5): PUT #1, 2\n40 RSET N$ = \"BOB\": LSET V$ = MKI$(-2): PUT #1\n\
+            50 GET #1, 2\n60 A$ = N$: A = CVS(V$)\n70 GET #1\n80 B$ = N$: B = CVI(V$): E = EOF(1)\n90 GET #1: F = EOF(1)\n\
+            100 C = 0: DIM R(3): ON ERROR GOTO 200\n110 GET #1, 0\n120 FIELD #1, 11 AS X$\n130 X = CVD(A$)\n140 END\n\
+            200 C = C + 1: R(C) = ERR: RESUME NEXT";
+        let tokens = Lexer::with_dialect(source, Dialect::GWBASIC).tokenize()?;
+        let program = Parser::with_dialect(tokens, Dialect::GWBASIC).parse()?;
+        let mut interpreter = Interpreter::with_dialect(program, Dialect::GWBASIC, Box::new(BufferConsole::new()));
+        interpreter.set_sandbox(dir.clone());
+        interpreter.run()?;
+
+        let bytes = std::fs::read(dir.join("R.DAT"))?;
+        assert_eq!(&bytes[10..], b"ALICE \x00\x00\x40\x81   BOB\xFE\xFF  ");
+        assert_eq!(interpreter.get_symbol("A$")?, SymbolValue::String("ALICE ".to_string()));
+        assert_eq!(interpreter.get_symbol("A")?, SymbolValue::Number(1.5));
+        assert_eq!(interpreter.get_symbol("B$")?, SymbolValue::String("   BOB".to_string()));
+        assert_eq!(interpreter.get_symbol("B")?, SymbolValue::Number(-2.0));
+        assert_eq!(interpreter.get_symbol("E")?, SymbolValue::Number(BASIC_FALSE_F));
+        assert_eq!(interpreter.get_symbol("F")?, SymbolValue::Number(-1.0));
+        assert_eq!(interpreter.symbols.get_array_element("R", &[1])?, SymbolValue::Number(63.0));
+        assert_eq!(interpreter.symbols.get_array_element("R", &[2])?, SymbolValue::Number(50.0));
+        assert_eq!(interpreter.symbols.get_array_element("R", &[3])?, SymbolValue::Number(5.0));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_using() -> Result<(), BasicError> {
+        let dir = std::env::temp_dir().join(format!("basic_rs_using_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = "10 OPEN \"U.TXT\" FOR OUTPUT AS #1\n20 F$ = \"**$##.##  \"\n\
+            30 PRINT #1, USING F$; 2.34, 1234.5\n40 PRINT #1, USING \"& IS ##.#\"; \"A\"; 3;\n50 PRINT #1, USING \"!\"; \"XYZ\"\n\
+            60 C = 0: DIM R(2): ON ERROR GOTO 200\n70 PRINT #1, USING \"##\"; \"X\"\n80 PRINT #1, USING \"ABC\"; 1\n90 CLOSE #1: END\n\
+            200 C = C + 1: R(C) = ERR: RESUME NEXT";
+        let tokens = Lexer::with_dialect(source, Dialect::GWBASIC).tokenize()?;
+        let program = Parser::with_dialect(tokens, Dialect::GWBASIC).parse()?;
+        let mut interpreter = Interpreter::with_dialect(program, Dialect::GWBASIC, Box::new(BufferConsole::new()));
+        interpreter.set_sandbox(dir.clone());
+        interpreter.run()?;
+
+        assert_eq!(std::fs::read_to_string(dir.join("U.TXT"))?, "***$2.34  $1234.50  \nA IS  3.0X\n");
+        assert_eq!(interpreter.symbols.get_array_element("R", &[1])?, SymbolValue::Number(13.0));
+        assert_eq!(interpreter.symbols.get_array_element("R", &[2])?, SymbolValue::Number(5.0));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
     #[test]
     fn test_rem_statement() -> Result<(), BasicError> {
         let source = "10 X=1\n20 REM This is a comment:Y=2\n30LET Z=3"; // TODO remove space before Z
-        let mut lexer = Lexer::new(&source);
+        let mut lexer = Lexer::new(source);
         let tokens = lexer.tokenize().expect("Lexing failed");
         // for token in &tokens {
         //     println!("T: {}", token);
         // }
         let mut parser = Parser::new(tokens);
         let program = parser.parse()?; // ← You need this line to obtain the program
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         interpreter.run()?;
         assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(1.0));
-        assert!(interpreter.get_symbol("Y").is_err()); // Should be skipped after REM
+        assert!(interpreter.get_symbol_table().get_symbol("Y").is_none()); // Should be skipped after REM
         assert_eq!(interpreter.get_symbol("Z")?, SymbolValue::Number(3.0));
         Ok(())
     }
@@ -1273,7 +2973,7 @@ mod tests {
             (10, vec![Statement::new_goto(25)]), // Line 25 doesn't exist
         ]);
         
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         let result = interpreter.run();
         assert!(matches!(result, Err(BasicError::Runtime { .. })));
     }
@@ -1288,7 +2988,7 @@ mod tests {
             ]),
         ]);
         
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         interpreter.run()?;
         
         assert_eq!(interpreter.get_symbol("X")?, SymbolValue::Number(1.0));
@@ -1313,7 +3013,7 @@ mod tests {
                 ArrayDecl { name: "C$".to_string(), dimensions: vec![3] }
             ])]),
         ]);
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::new(program, Box::new(BufferConsole::new()));
         interpreter.run()?;
 
         // Test 2D numeric array (arrays stored with [] suffix)
@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use crate::basic_types::Token;
+use crate::basic_types::{Token, VarType};
 
 /// Registry that defines all BASIC keywords and their corresponding tokens
 /// This serves as the single source of truth for all keyword definitions
@@ -7,6 +7,12 @@ pub struct KeywordRegistry {
     keywords: HashMap<&'static str, Token>,
 }
 
+impl Default for KeywordRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeywordRegistry {
     pub fn new() -> Self {
         let mut registry = KeywordRegistry {
@@ -40,8 +46,54 @@ impl KeywordRegistry {
         self.keywords.insert("DIM", Token::Dim);
         self.keywords.insert("ON", Token::On);
         self.keywords.insert("DEF", Token::Def);
+        self.keywords.insert("ERROR", Token::Error);
+        self.keywords.insert("RESUME", Token::Resume);
+        self.keywords.insert("WHILE", Token::While);
+        self.keywords.insert("WEND", Token::Wend);
+        self.keywords.insert("DO", Token::Do);
+        self.keywords.insert("LOOP", Token::Loop);
+        self.keywords.insert("UNTIL", Token::Until);
+        self.keywords.insert("EXIT", Token::Exit);
+        self.keywords.insert("ELSEIF", Token::ElseIf);
+        self.keywords.insert("SELECT", Token::Select);
+        self.keywords.insert("CASE", Token::Case);
+        self.keywords.insert("IS", Token::Is);
+        self.keywords.insert("SUB", Token::Sub);
+        self.keywords.insert("FUNCTION", Token::Function);
+        self.keywords.insert("CALL", Token::Call);
+        self.keywords.insert("LOCAL", Token::Local);
+        self.keywords.insert("SHARED", Token::Shared);
+        self.keywords.insert("OPEN", Token::Open);
+        self.keywords.insert("CLOSE", Token::Close);
+        self.keywords.insert("OUTPUT", Token::Output);
+        self.keywords.insert("APPEND", Token::Append);
+        self.keywords.insert("AS", Token::As);
+        self.keywords.insert("WRITE", Token::Write);
+        self.keywords.insert("LINE", Token::Line);
+        self.keywords.insert("RANDOM", Token::Random);
+        self.keywords.insert("FIELD", Token::Field);
+        self.keywords.insert("GET", Token::Get);
+        self.keywords.insert("PUT", Token::Put);
+        self.keywords.insert("LSET", Token::Lset);
+        self.keywords.insert("RSET", Token::Rset);
+        self.keywords.insert("USING", Token::Using);
+        self.keywords.insert("DEFINT", Token::DefType(VarType::Integer));
+        self.keywords.insert("DEFSNG", Token::DefType(VarType::Single));
+        self.keywords.insert("DEFDBL", Token::DefType(VarType::Double));
+        self.keywords.insert("DEFSTR", Token::DefType(VarType::String));
+        self.keywords.insert("MAT", Token::Mat);
+        self.keywords.insert("ZER", Token::Zer);
+        self.keywords.insert("CON", Token::Con);
+        self.keywords.insert("IDN", Token::Idn);
+        self.keywords.insert("TRN", Token::Trn);
+        self.keywords.insert("INV", Token::Inv);
+        self.keywords.insert("RANDOMIZE", Token::Randomize);
         self.keywords.insert("AND", Token::And);
         self.keywords.insert("OR", Token::Or);
+        self.keywords.insert("XOR", Token::Xor);
+        self.keywords.insert("EQV", Token::Eqv);
+        self.keywords.insert("IMP", Token::Imp);
+        self.keywords.insert("MOD", Token::Mod);
         self.keywords.insert("NOT", Token::Not);
     }
 
@@ -101,7 +153,13 @@ mod tests {
             "REM", "LET", "PRINT", "INPUT", "IF", "THEN", "ELSE",
             "FOR", "TO", "STEP", "NEXT", "GOTO", "GOSUB", "RETURN",
             "END", "STOP", "DATA", "READ", "RESTORE", "DIM", "ON",
-            "DEF", "AND", "OR", "NOT"
+            "DEF", "AND", "OR", "NOT", "XOR", "EQV", "IMP", "MOD", "ERROR", "RESUME",
+            "WHILE", "WEND", "DO", "LOOP", "UNTIL", "EXIT", "ELSEIF",
+            "SELECT", "CASE", "IS", "SUB", "FUNCTION", "CALL", "LOCAL", "SHARED",
+            "OPEN", "CLOSE", "OUTPUT", "APPEND", "AS", "WRITE", "LINE",
+            "RANDOM", "FIELD", "GET", "PUT", "LSET", "RSET", "USING",
+            "DEFINT", "DEFSNG", "DEFDBL", "DEFSTR",
+            "MAT", "ZER", "CON", "IDN", "TRN", "INV", "RANDOMIZE"
         ];
         
         for expected_keyword in expected {
@@ -115,8 +173,8 @@ mod tests {
         let registry = &*KEYWORD_REGISTRY;
         let pairs = registry.get_keyword_token_pairs();
         
-        // Should have 25 keyword-token pairs
-        assert_eq!(pairs.len(), 25);
+        // Should have 71 keyword-token pairs
+        assert_eq!(pairs.len(), 71);
         
         // Test a few specific mappings
         assert!(pairs.contains(&("LET", Token::Let)));
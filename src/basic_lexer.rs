@@ -1,27 +1,77 @@
 use crate::basic_types::{Token, BasicError, is_valid_identifier, IdentifierType};
 use crate::basic_function_registry::FUNCTION_REGISTRY;
 use crate::basic_keyword_registry::KEYWORD_REGISTRY;
+use crate::basic_dialect::{Dialect, VariableNames};
+use std::collections::{BTreeMap, BTreeSet};
 
 pub struct Lexer {
     chars: Vec<char>,
     position: usize,
     file_line_number: usize,
     basic_line_number: Option<usize>,
+    dialect: Dialect,
+    functions: Vec<String>, // FUNCTION names, read whole wherever they appear
+    long_names: BTreeMap<String, BTreeSet<String>>, // Spellings of each two-letter name
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
+        Self::with_dialect(input, Dialect::default())
+    }
+
+    pub fn with_dialect(input: &str, dialect: Dialect) -> Self {
         let chars: Vec<char> = input.chars().collect();
         Lexer {
             chars,
             position: 0,
             file_line_number: 1,
             basic_line_number: None,
+            dialect,
+            functions: Vec::new(),
+            long_names: BTreeMap::new(),
         }
     }
 
+    /// Makes calls to FUNCTIONs defined elsewhere readable, as when the shell reads a
+    /// line on its own
+    pub fn set_function_names(&mut self, names: Vec<String>) {
+        self.functions = names;
+    }
+
     // Main tokenize function that processes the entire program line by line
     pub fn tokenize(&mut self) -> Result<Vec<Token>, BasicError> {
+        let tokens = self.tokenize_lines()?;
+        // A FUNCTION can be called before the line that defines it, so once the
+        // definitions are known the program is read again
+        let defined: Vec<String> = tokens.windows(2)
+            .filter_map(|pair| match pair {
+                [Token::Function, Token::Identifier(name, _)] if !self.functions.contains(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        if defined.is_empty() {
+            return Ok(tokens);
+        }
+        self.functions.extend(defined);
+        self.position = 0;
+        self.file_line_number = 1;
+        self.basic_line_number = None;
+        self.tokenize_lines()
+    }
+
+    /// Warnings for long names that are different in the program but the same variable,
+    /// because only their first two characters count
+    pub fn name_collisions(&self) -> Vec<String> {
+        self.long_names.iter()
+            .filter(|(_, spellings)| spellings.len() > 1)
+            .map(|(name, spellings)| {
+                let spellings: Vec<&str> = spellings.iter().map(String::as_str).collect();
+                format!("{} are the same variable, {}", spellings.join(" and "), name)
+            })
+            .collect()
+    }
+
+    fn tokenize_lines(&mut self) -> Result<Vec<Token>, BasicError> {
         let mut all_tokens = Vec::new();
         
         while self.position < self.chars.len() {
@@ -183,6 +233,9 @@ impl Lexer {
                     
                     tokens.push(Token::String(string));
                 }
+                'A'..='Z' | 'a'..='z' if matches!(tokens.last(), Some(Token::Sub | Token::Function | Token::Call)) => {
+                    tokens.push(self.tokenize_procedure_name());
+                }
                 'A'..='Z' | 'a'..='z' => {
                     // New lookahead-based identifier parsing for BASIC
                     let token = self.tokenize_identifier_lookahead()?;
@@ -217,13 +270,22 @@ impl Lexer {
                     self.advance();
                 }
                 '*' => {
-                    tokens.push(Token::Star);
                     self.advance();
+                    if self.dialect.exponentiation_operator == "**" && self.current_char() == '*' {
+                        tokens.push(Token::Power);
+                        self.advance();
+                    } else {
+                        tokens.push(Token::Star);
+                    }
                 }
                 '/' => {
                     tokens.push(Token::Slash);
                     self.advance();
                 }
+                '\\' => {
+                    tokens.push(Token::Backslash);
+                    self.advance();
+                }
                 '^' => {
                     tokens.push(Token::Power);
                     self.advance();
@@ -279,10 +341,14 @@ impl Lexer {
                     tokens.push(Token::Colon);
                     self.advance();
                 }
+                '#' => {
+                    tokens.push(Token::Hash);
+                    self.advance();
+                }
                 _ => {
                     return Err(BasicError::Syntax {
                         message: format!("Unexpected character: '{}' basic line {} file line {}", c,
-                                         self.basic_line_number.unwrap_or(0).to_string(),
+                                         self.basic_line_number.unwrap_or(0),
                                          self.file_line_number),
                         basic_line_number: self.basic_line_number,
                         file_line_number: Some(self.file_line_number),
@@ -326,6 +392,10 @@ impl Lexer {
         }
         
         let input_str: String = chars.iter().collect();
+
+        if self.functions.contains(&input_str) {
+            return Ok(Token::Identifier(input_str, IdentifierType::UserDefinedFunction));
+        }
         
         // Step 1: Scan for keywords, functions, or user-defined functions
         if let Some(token) = self.try_match_keyword_or_function(&input_str) {
@@ -336,10 +406,19 @@ impl Lexer {
             return Ok(token);
         }
         
+        if self.dialect.variable_names != VariableNames::Short {
+            return Ok(self.tokenize_long_name(start_pos, &input_str));
+        }
+
         // Step 2: Scan for identifiers in length order: A1$, A1, A$, A
-        if let Some((identifier, consumed_len)) = self.try_match_identifier(&input_str) {
+        if let Some((mut identifier, consumed_len)) = self.try_match_identifier(&input_str) {
             // Reset position to where we started plus the consumed length
             self.position = start_pos + consumed_len;
+            // A numeric type suffix: A%, A1!, A#
+            if !identifier.ends_with('$') && matches!(self.current_char(), '%' | '!' | '#') {
+                identifier.push(self.current_char());
+                self.advance();
+            }
             return Ok(Token::Identifier(identifier, IdentifierType::Variable));
         }
         
@@ -351,6 +430,45 @@ impl Lexer {
         })
     }
 
+    /// A variable name of letters and digits, with its type suffix. Where only two
+    /// characters count, the name ends at a keyword inside it and is cut to two.
+    fn tokenize_long_name(&mut self, start_pos: usize, input: &str) -> Token {
+        let mut length = input.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(input.len());
+        let two_significant = self.dialect.variable_names == VariableNames::TwoSignificant;
+        if two_significant {
+            if let Some(keyword_start) = (1..length).find(|&i| self.get_keyword_length(&input[i..]).is_some()) {
+                length = keyword_start;
+            }
+        }
+        self.position = start_pos + length;
+        let mut spelling = input[..length].to_string();
+        if matches!(self.current_char(), '$' | '%' | '!' | '#') {
+            spelling.push(self.current_char());
+            self.advance();
+        }
+        if !two_significant || length <= 2 {
+            return Token::Identifier(spelling, IdentifierType::Variable);
+        }
+        let name = format!("{}{}", &spelling[..2], &spelling[length..]);
+        self.long_names.entry(name.clone()).or_default().insert(spelling);
+        Token::Identifier(name, IdentifierType::Variable)
+    }
+
+    /// The name after SUB, FUNCTION or CALL: letters and digits, and a `$` for a string
+    /// FUNCTION. It is not split into keywords or variables.
+    fn tokenize_procedure_name(&mut self) -> Token {
+        let mut name = String::new();
+        while self.position < self.chars.len() && (self.chars[self.position].is_ascii_alphanumeric() || self.chars[self.position] == '_') {
+            name.push(self.chars[self.position].to_ascii_uppercase());
+            self.advance();
+        }
+        if self.current_char() == '$' {
+            name.push('$');
+            self.advance();
+        }
+        Token::Identifier(name, IdentifierType::UserDefinedFunction)
+    }
+
     // Try to match keywords or functions
     fn try_match_keyword_or_function(&mut self, input: &str) -> Option<Token> {
         // Keywords from registry
@@ -465,7 +583,7 @@ impl Lexer {
                 chars[1] == '$'
             }
             r"^[A-Z]" => {
-                chars.len() >= 1 && 
+                !chars.is_empty() && 
                 chars[0].is_ascii_uppercase()
             }
             _ => false
@@ -504,6 +622,7 @@ impl Lexer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::basic_types::VarType;
 
     #[test]
     fn test_tokenize_let() {
@@ -541,6 +660,20 @@ mod tests {
         assert_eq!(tokens[6], Token::LineNumber(20));
     }
 
+    #[test]
+    fn test_double_star_exponentiation() {
+        let mut lexer = Lexer::with_dialect("X ** 2", Dialect::DARTMOUTH);
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[1], Token::Power);
+        assert_eq!(tokens.len(), 3);
+
+        // Other dialects read two multiplications
+        let mut lexer = Lexer::new("X ** 2");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[1], Token::Star);
+        assert_eq!(tokens[2], Token::Star);
+    }
+
     #[test]
     fn test_line_number_no_spaces() {
         // Test the specific case that's failing: "200 print abs(-12)"
@@ -591,7 +724,7 @@ mod tests {
     #[test]
     fn test_valid_identifiers() {
         let valid_inputs = vec![
-            "X", "Y", "Z", "A1", "B2", "C$"
+            "X", "Y", "Z", "A1", "B2", "C$", "I%", "S1!", "D#"
         ];
         
         for input in valid_inputs {
@@ -734,4 +867,89 @@ mod tests {
         assert_eq!(tokens[11], Token::Plus);
         assert_eq!(tokens[12], Token::Number("1".to_string()));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_procedure_names() {
+        // The call comes before the FUNCTION is defined
+        let mut lexer = Lexer::new("10 X = SQUARE(3): CALL SHOWIT(X)\n20 FUNCTION SQUARE(N)\n30 SQUARE = N * N\n40 END FUNCTION");
+        let tokens = lexer.tokenize().unwrap();
+        let function = |name: &str| Token::Identifier(name.to_string(), IdentifierType::UserDefinedFunction);
+
+        assert_eq!(tokens[3], function("SQUARE"));
+        assert_eq!(tokens[8], Token::Call);
+        assert_eq!(tokens[9], function("SHOWIT"));
+        assert_eq!(tokens[15], Token::Function);
+        assert_eq!(tokens[16], function("SQUARE"));
+        assert_eq!(tokens[22], function("SQUARE"));
+
+        // A FUNCTION from another line, as in the shell
+        let mut lexer = Lexer::new("50 PRINT NAME$(1)");
+        lexer.set_function_names(vec!["NAME$".to_string()]);
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[2], function("NAME$"));
+    }
+
+    #[test]
+    fn test_file_statements() {
+        let mut lexer = Lexer::new("10 OPEN \"S.TXT\" FOR OUTPUT AS #1:PRINT #1, A:LINE INPUT #1, L$");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(&tokens[1..8], &[
+            Token::Open, Token::String("S.TXT".to_string()), Token::For, Token::Output, Token::As, Token::Hash, Token::Number("1".to_string()),
+        ]);
+        assert_eq!(&tokens[9..11], &[Token::Print, Token::Hash]);
+        assert_eq!(&tokens[15..18], &[Token::Line, Token::Input, Token::Hash]);
+    }
+
+    #[test]
+    fn test_random_file_statements() {
+        let mut lexer = Lexer::new("10 OPEN \"R\" AS #1 LEN=8:FIELD #1, 2 AS A$:GETA:LSET A$=MKI$(3)");
+        let tokens = lexer.tokenize().unwrap();
+        let function = |name: &str| Token::Identifier(name.to_string(), IdentifierType::BuiltInFunction);
+        assert_eq!(&tokens[6..9], &[function("LEN"), Token::Equal, Token::Number("8".to_string())]);
+        assert_eq!(tokens[10], Token::Field);
+        assert_eq!(&tokens[18..20], &[Token::Get, Token::Identifier("A".to_string(), IdentifierType::Variable)]);
+        assert_eq!(&tokens[21..25], &[Token::Lset, Token::Identifier("A$".to_string(), IdentifierType::Variable), Token::Equal, function("MKI$")]);
+    }
+
+    #[test]
+    fn test_typed_variables() {
+        let mut lexer = Lexer::new("10 DEFINT I-N:I%=N1#+X!:PRINT #1, A%");
+        let tokens = lexer.tokenize().unwrap();
+        let var = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        assert_eq!(&tokens[1..5], &[Token::DefType(VarType::Integer), var("I"), Token::Minus, var("N")]);
+        assert_eq!(&tokens[6..11], &[var("I%"), Token::Equal, var("N1#"), Token::Plus, var("X!")]);
+        assert_eq!(&tokens[12..16], &[Token::Print, Token::Hash, Token::Number("1".to_string()), Token::Comma]);
+        assert_eq!(tokens[16], var("A%"));
+    }
+
+    #[test]
+    fn test_integer_operators() {
+        let mut lexer = Lexer::new("10 A=B\\C MOD D XOR E EQV F IMP G");
+        let tokens = lexer.tokenize().unwrap();
+        let var = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        assert_eq!(&tokens[3..14], &[var("B"), Token::Backslash, var("C"), Token::Mod, var("D"), Token::Xor, var("E"),
+                                     Token::Eqv, var("F"), Token::Imp, var("G")]);
+    }
+
+    #[test]
+    fn test_long_names_two_significant() {
+        let mut lexer = Lexer::with_dialect("10 PLAYER=PLANET+SCORE:NAME1$=A1%", Dialect::MSBASIC2);
+        let tokens = lexer.tokenize().unwrap();
+        let var = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        assert_eq!(&tokens[1..4], &[var("PL"), Token::Equal, var("PL")]);
+        // SCORE holds OR, so it reads as SC OR E
+        assert_eq!(&tokens[4..8], &[Token::Plus, var("SC"), Token::Or, var("E")]);
+        assert_eq!(&tokens[9..12], &[var("NA$"), Token::Equal, var("A1%")]);
+        assert_eq!(lexer.name_collisions(), vec!["PLANET and PLAYER are the same variable, PL"]);
+    }
+
+    #[test]
+    fn test_long_names_full() {
+        let mut lexer = Lexer::with_dialect("10 PLAYER=PLANET+SCORE:FORI=1TO2", Dialect::GWBASIC);
+        let tokens = lexer.tokenize().unwrap();
+        let var = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        assert_eq!(&tokens[1..6], &[var("PLAYER"), Token::Equal, var("PLANET"), Token::Plus, var("SCORE")]);
+        assert_eq!(&tokens[7..9], &[Token::For, var("I")]);
+        assert!(lexer.name_collisions().is_empty());
+    }
+}
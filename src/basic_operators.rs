@@ -1,5 +1,7 @@
 use std::collections::HashMap;
-use crate::basic_types::{Token, BasicError, SymbolType};
+use basic_runtime::operators::{self, IntegerOp};
+use crate::basic_dialect::Dialect;
+use crate::basic_types::{Token, BasicError, SymbolType, ErrorCondition};
 
 #[derive(Debug, Clone)]
 pub enum Op {
@@ -7,20 +9,23 @@ pub enum Op {
     StrMono(StrMonoOp),
     StrDollar(StrDollarMonoOp),
     Str(StrOp),
+    Dialect(DialectOp),
 }
 
+/// True in BasicRS; a dialect's `true_value` may make it -1
 pub const BASIC_TRUE: &str = "1";
-pub const BASIC_TRUE_F: f64 = 1.0;
 pub const BASIC_FALSE: &str = "0";
 pub const BASIC_FALSE_F: f64 = 0.0;
 
 impl Op {
-    pub fn eval(&self, stack: &mut Vec<Token>, _op: Option<&OpOperation>) -> Result<Token, BasicError> {
+    /// Applies the operator to the operands on top of `stack`, as `dialect` does
+    pub fn eval(&self, stack: &mut Vec<Token>, dialect: &Dialect) -> Result<Token, BasicError> {
         match self {
             Op::Mono(op) => op.eval(stack, None),
             Op::StrMono(op) => op.eval(stack, None),
             Op::StrDollar(op) => op.eval(stack, None),
             Op::Str(op) => op.eval(stack, None),
+            Op::Dialect(op) => op.eval(stack, dialect),
         }
     }
 }
@@ -38,7 +43,7 @@ impl MonoOp {
     }
 
     fn check_args(&self, stack: &[Token]) -> Result<(), BasicError> {
-        if stack.len() < 1 {
+        if stack.is_empty() {
             return Err(BasicError::Syntax {
                 message: "Not enough operands for unary operator".to_string(),
                 basic_line_number: None,
@@ -209,11 +214,9 @@ impl StrOp {
         let answer = (self.lambda)(args);
         
         // Check for special prefixes that indicate dynamic return types
-        if answer.starts_with("NUMBER:") {
-            let number_part = &answer[7..]; // Remove "NUMBER:" prefix
+        if let Some(number_part) = answer.strip_prefix("NUMBER:") {
             Ok(Token::Number(number_part.to_string()))
-        } else if answer.starts_with("STRING:") {
-            let string_part = &answer[7..]; // Remove "STRING:" prefix
+        } else if let Some(string_part) = answer.strip_prefix("STRING:") {
             Ok(Token::String(string_part.to_string()))
         } else if let Some(return_type) = &self.return_type {
             if return_type == "string" {
@@ -227,6 +230,49 @@ impl StrOp {
     }
 }
 
+/// An operator on numbers whose result depends on the dialect: the comparisons give its
+/// true value, and the integer operators work on integers of its size
+#[derive(Debug, Clone)]
+pub struct DialectOp {
+    lambda: fn(&[f64], &Dialect) -> Result<f64, BasicError>,
+    name: String,
+    arg_count: usize,
+}
+
+impl DialectOp {
+    pub fn new(lambda: fn(&[f64], &Dialect) -> Result<f64, BasicError>, name: &str, arg_count: usize) -> Self {
+        DialectOp {
+            lambda,
+            name: name.to_string(),
+            arg_count,
+        }
+    }
+
+    fn eval(&self, stack: &mut Vec<Token>, dialect: &Dialect) -> Result<Token, BasicError> {
+        if stack.len() < self.arg_count {
+            return Err(BasicError::Syntax {
+                message: format!("Not enough operands for {}", self.name),
+                basic_line_number: None,
+                file_line_number: None,
+            });
+        }
+        let args = stack.split_off(stack.len() - self.arg_count)
+            .iter()
+            .map(|token| match token {
+                Token::Number(n) => n.parse::<f64>().ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(|| BasicError::Type {
+                message: format!("Expected numbers for {}", self.name),
+                basic_line_number: None,
+                file_line_number: None,
+            })?;
+        let answer = (self.lambda)(&args, dialect)?;
+        Ok(Token::Number(answer.to_string()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct OpOperation {
     pub token: String,
@@ -250,7 +296,7 @@ lazy_static::lazy_static! {
         // Exponentiation (highest precedence)
         m.insert("^".to_string(), OpDef {
             text: "^".to_string(),
-            precedence: 7,
+            precedence: 12,
             op: Op::Str(StrOp::new(|args| {
                 let a = args[0].parse::<f64>().unwrap_or(0.0);
                 let b = args[1].parse::<f64>().unwrap_or(0.0);
@@ -261,7 +307,7 @@ lazy_static::lazy_static! {
         // Multiplication and division
         m.insert("*".to_string(), OpDef {
             text: "*".to_string(),
-            precedence: 6,
+            precedence: 11,
             op: Op::Str(StrOp::new(|args| {
                 let a = args[0].parse::<f64>().unwrap_or(0.0);
                 let b = args[1].parse::<f64>().unwrap_or(0.0);
@@ -271,7 +317,7 @@ lazy_static::lazy_static! {
 
         m.insert("/".to_string(), OpDef {
             text: "/".to_string(),
-            precedence: 6,
+            precedence: 11,
             op: Op::Str(StrOp::new(|args| {
                 let a = args[0].parse::<f64>().unwrap_or(0.0);
                 let b = args[1].parse::<f64>().unwrap_or(0.0);
@@ -286,12 +332,12 @@ lazy_static::lazy_static! {
         // Addition and subtraction
         m.insert("+".to_string(), OpDef {
             text: "+".to_string(),
-            precedence: 5,
+            precedence: 8,
             op: Op::Str(StrOp::new(|args| {
                 // Check if both arguments are numeric
                 if let (Ok(a), Ok(b)) = (args[0].parse::<f64>(), args[1].parse::<f64>()) {
                     // Return numeric result - but we need to signal this is a number
-                    format!("NUMBER:{}", (a + b).to_string())
+                    format!("NUMBER:{}", (a + b))
                 } else {
                     // String concatenation
                     let mut result = args[0].clone();
@@ -309,7 +355,7 @@ lazy_static::lazy_static! {
 
         m.insert("-".to_string(), OpDef {
             text: "-".to_string(),
-            precedence: 5,
+            precedence: 8,
             op: Op::Str(StrOp::new(|args| {
                 let a = args[0].parse::<f64>().unwrap_or(0.0);
                 let b = args[1].parse::<f64>().unwrap_or(0.0);
@@ -320,92 +366,93 @@ lazy_static::lazy_static! {
         // Comparison operators
         m.insert("=".to_string(), OpDef {
             text: "=".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a == b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "=", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] == args[1], dialect)), "=", 2)),
         });
 
         m.insert("<>".to_string(), OpDef {
             text: "<>".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a != b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "<>", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] != args[1], dialect)), "<>", 2)),
         });
 
         m.insert("<".to_string(), OpDef {
             text: "<".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a < b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "<", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] < args[1], dialect)), "<", 2)),
         });
 
         m.insert(">".to_string(), OpDef {
             text: ">".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a > b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, ">", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] > args[1], dialect)), ">", 2)),
         });
 
         m.insert("<=".to_string(), OpDef {
             text: "<=".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a <= b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "<=", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] <= args[1], dialect)), "<=", 2)),
         });
 
         m.insert(">=".to_string(), OpDef {
             text: ">=".to_string(),
-            precedence: 4,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0);
-                let b = args[1].parse::<f64>().unwrap_or(0.0);
-                if a >= b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, ">=", 2, Some("number"))),
+            precedence: 7,
+            op: Op::Dialect(DialectOp::new(|args, dialect| Ok(truth(args[0] >= args[1], dialect)), ">=", 2)),
         });
 
-        // Logical operators
+        // Integer division and MOD, between * and +
+        m.insert("\\".to_string(), OpDef {
+            text: "\\".to_string(),
+            precedence: 10,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::IntDivide, args, dialect), "\\", 2)),
+        });
+
+        m.insert("MOD".to_string(), OpDef {
+            text: "MOD".to_string(),
+            precedence: 9,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::Mod, args, dialect), "MOD", 2)),
+        });
+
+        // Logical operators, bit by bit on integers of the dialect's size
         m.insert("AND".to_string(), OpDef {
             text: "AND".to_string(),
-            precedence: 3,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0) != 0.0;
-                let b = args[1].parse::<f64>().unwrap_or(0.0) != 0.0;
-                if a && b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "AND", 2, Some("number"))),
+            precedence: 5,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::And, args, dialect), "AND", 2)),
         });
 
         m.insert("OR".to_string(), OpDef {
             text: "OR".to_string(),
+            precedence: 4,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::Or, args, dialect), "OR", 2)),
+        });
+
+        m.insert("XOR".to_string(), OpDef {
+            text: "XOR".to_string(),
+            precedence: 3,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::Xor, args, dialect), "XOR", 2)),
+        });
+
+        m.insert("EQV".to_string(), OpDef {
+            text: "EQV".to_string(),
             precedence: 2,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0) != 0.0;
-                let b = args[1].parse::<f64>().unwrap_or(0.0) != 0.0;
-                if a || b { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "OR", 2, Some("number"))),
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::Eqv, args, dialect), "EQV", 2)),
+        });
+
+        m.insert("IMP".to_string(), OpDef {
+            text: "IMP".to_string(),
+            precedence: 1,
+            op: Op::Dialect(DialectOp::new(|args, dialect| integer_op(IntegerOp::Imp, args, dialect), "IMP", 2)),
         });
 
         m.insert("NOT".to_string(), OpDef {
             text: "NOT".to_string(),
-            precedence: 1,
-            op: Op::Str(StrOp::new(|args| {
-                let a = args[0].parse::<f64>().unwrap_or(0.0) != 0.0;
-                if !a { BASIC_TRUE } else { BASIC_FALSE }.to_string()
-            }, "NOT", 1, Some("number"))),
+            precedence: 6,
+            op: Op::Dialect(DialectOp::new(|args, dialect| {
+                if dialect.logical_not {
+                    return Ok(truth(args[0] == BASIC_FALSE_F, dialect));
+                }
+                operators::not(args[0], dialect.integer_bits).map_err(operator_error(ErrorCondition::Overflow))
+            }, "NOT", 1)),
         });
 
         m
@@ -413,6 +460,25 @@ lazy_static::lazy_static! {
 }
 
 
+/// The dialect's value for a condition
+fn truth(holds: bool, dialect: &Dialect) -> f64 {
+    if holds { dialect.true_value as f64 } else { BASIC_FALSE_F }
+}
+
+/// `a op b` on integers of the dialect's size. A divisor that rounds to 0 divides by
+/// zero; anything else that fails overflows.
+fn integer_op(op: IntegerOp, args: &[f64], dialect: &Dialect) -> Result<f64, BasicError> {
+    let bits = dialect.integer_bits;
+    if op.divides_by_zero(args[1], bits) {
+        return Err(operator_error(ErrorCondition::DivisionByZero)("Division by zero".to_string()));
+    }
+    op.apply(args[0], args[1], bits).map_err(operator_error(ErrorCondition::Overflow))
+}
+
+fn operator_error(condition: ErrorCondition) -> impl Fn(String) -> BasicError {
+    move |message| BasicError::Runtime { message, condition, basic_line_number: None, file_line_number: None }
+}
+
 pub fn get_op_def(operator: &str) -> Option<&'static OpDef> {
     (*OPERATORS).get(operator)
 }
@@ -423,6 +489,8 @@ pub fn get_precedence(token: &Token) -> i32 {
         Token::Minus => "-",
         Token::Star => "*",
         Token::Slash => "/",
+        Token::Backslash => "\\",
+        Token::Mod => "MOD",
         Token::Power => "^",
         Token::Equal => "=",
         Token::NotEqual => "<>",
@@ -432,6 +500,9 @@ pub fn get_precedence(token: &Token) -> i32 {
         Token::GreaterEqual => ">=",
         Token::And => "AND",
         Token::Or => "OR",
+        Token::Xor => "XOR",
+        Token::Eqv => "EQV",
+        Token::Imp => "IMP",
         Token::Not => "NOT",
         _ => return 0,
     };
@@ -464,7 +535,7 @@ mod tests {
             create_number_token("2"),
             create_number_token("3"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "8");
         } else {
@@ -477,7 +548,7 @@ mod tests {
             create_number_token("4"),
             create_number_token("5"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "20");
         } else {
@@ -490,7 +561,7 @@ mod tests {
             create_number_token("10"),
             create_number_token("2"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "5");
         } else {
@@ -502,7 +573,7 @@ mod tests {
             create_number_token("10"),
             create_number_token(BASIC_FALSE),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "Division by zero");
         } else {
@@ -515,7 +586,7 @@ mod tests {
             create_number_token("6"),
             create_number_token("7"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "13");
         } else {
@@ -527,7 +598,7 @@ mod tests {
             create_string_token("Hello "),
             create_string_token("World"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::String(s) = result {
             assert_eq!(s, "\"Hello World\"");
         } else {
@@ -540,7 +611,7 @@ mod tests {
             create_number_token("10"),
             create_number_token("3"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, "7");
         } else {
@@ -556,7 +627,7 @@ mod tests {
             create_number_token("5"),
             create_number_token("5"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -569,7 +640,7 @@ mod tests {
             create_number_token("5"),
             create_number_token("6"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -582,7 +653,7 @@ mod tests {
             create_number_token("5"),
             create_number_token("6"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -595,7 +666,7 @@ mod tests {
             create_number_token("7"),
             create_number_token("6"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -608,7 +679,7 @@ mod tests {
             create_number_token("5"),
             create_number_token("5"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -621,7 +692,7 @@ mod tests {
             create_number_token("6"),
             create_number_token("5"),
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -637,7 +708,7 @@ mod tests {
             create_number_token(BASIC_TRUE), // True in BASIC
             create_number_token(BASIC_TRUE), // True in BASIC
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -650,7 +721,7 @@ mod tests {
             create_number_token(BASIC_TRUE), // True in BASIC
             create_number_token(BASIC_FALSE),  // False in BASIC
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -662,7 +733,7 @@ mod tests {
         let mut stack = vec![
             create_number_token(BASIC_FALSE),  // False in BASIC
         ];
-        let result = op.op.eval(&mut stack, None).unwrap();
+        let result = op.op.eval(&mut stack, &Dialect::default()).unwrap();
         if let Token::Number(n) = result {
             assert_eq!(n, BASIC_TRUE); // True in BASIC
         } else {
@@ -670,13 +741,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dialect_operators() {
+        let eval = |op: &str, operands: &[&str], dialect: &Dialect| {
+            let mut stack: Vec<Token> = operands.iter().map(|n| create_number_token(n)).collect();
+            get_op_def(op).unwrap().op.eval(&mut stack, dialect)
+        };
+
+        // GW-BASIC's true is every bit set, and its NOT flips the bits of a 16-bit integer
+        assert_eq!(eval("<", &["5", "6"], &Dialect::GWBASIC).unwrap(), create_number_token("-1"));
+        assert_eq!(eval("NOT", &["0"], &Dialect::GWBASIC).unwrap(), create_number_token("-1"));
+        assert_eq!(eval("NOT", &["5"], &Dialect::GWBASIC).unwrap(), create_number_token("-6"));
+        assert_eq!(eval("NOT", &["5"], &Dialect::default()).unwrap(), create_number_token("0"));
+        assert_eq!(eval("AND", &["40000", "1"], &Dialect::default()).unwrap(), create_number_token("0"));
+        let err = eval("AND", &["40000", "1"], &Dialect::GWBASIC).unwrap_err();
+        assert_eq!(err.condition(), Some(ErrorCondition::Overflow));
+        let err = eval("MOD", &["5", ".4"], &Dialect::GWBASIC).unwrap_err();
+        assert_eq!(err.condition(), Some(ErrorCondition::DivisionByZero));
+        assert!(matches!(eval("=", &["1"], &Dialect::default()), Err(BasicError::Syntax { .. })));
+    }
+
     #[test]
     fn test_operator_precedence() {
-        assert!(get_op_def("^").unwrap().precedence > get_op_def("*").unwrap().precedence);
-        assert!(get_op_def("*").unwrap().precedence > get_op_def("+").unwrap().precedence);
-        assert!(get_op_def("+").unwrap().precedence > get_op_def("=").unwrap().precedence);
-        assert!(get_op_def("=").unwrap().precedence > get_op_def("AND").unwrap().precedence);
-        assert!(get_op_def("AND").unwrap().precedence > get_op_def("OR").unwrap().precedence);
-        assert!(get_op_def("OR").unwrap().precedence > get_op_def("NOT").unwrap().precedence);
+        let precedence = |op: &str| get_op_def(op).unwrap().precedence;
+        let order = ["^", "*", "\\", "MOD", "+", "=", "NOT", "AND", "OR", "XOR", "EQV", "IMP"];
+        for pair in order.windows(2) {
+            assert!(precedence(pair[0]) > precedence(pair[1]), "{} should bind tighter than {}", pair[0], pair[1]);
+        }
+        assert_eq!(get_precedence(&Token::Backslash), precedence("\\"));
     }
-} 
\ No newline at end of file
+}
@@ -1,4 +1,7 @@
-use crate::basic_types::{ArrayDecl, ExpressionType, IdentifierType, SymbolValue};
+use crate::basic_types::{ArrayDecl, CaseTest, ExpressionType, FileMode, IdentifierType, LoopCondition, MatValue, ResumeTarget, SymbolValue, VarType};
+use crate::basic_dialect::Dialect;
+use crate::basic_blocks::BlockTargets;
+use crate::basic_procedures::Procedures;
 
 use crate::basic_types::{
     Token, BasicError, Statement, Expression, PrintItem,
@@ -10,19 +13,38 @@ pub struct Parser {
     current: usize,
     current_basic_line: Option<usize>,  // If there is a syntax error, there may not be a line number
     current_file_line: usize,           // There should always be a 'line number the file' (or source string)
+    dialect: Dialect,
+    def_types: [Option<VarType>; 26],   // Set by DEFINT and its relatives, per first letter
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        Self::with_dialect(tokens, Dialect::default())
+    }
+
+    pub fn with_dialect(tokens: Vec<Token>, dialect: Dialect) -> Self {
         Parser {
             tokens,
             current: 0,
             current_basic_line: None,
             current_file_line: 1,
+            dialect,
+            def_types: [None; 26],
         }
     }
 
     pub fn parse(&mut self) -> Result<Program, BasicError> {
+        let program = self.parse_lines()?;
+        // Reports IF and SELECT CASE blocks that do not balance, and misplaced
+        // procedure statements
+        BlockTargets::resolve(&program)?;
+        Procedures::resolve(&program)?;
+        Ok(program)
+    }
+
+    /// Parses without checking that multi-line blocks and procedures balance, for
+    /// programs entered a line at a time
+    pub fn parse_lines(&mut self) -> Result<Program, BasicError> {
         let mut program = Program::new();
         
         while !self.is_at_end() {
@@ -49,6 +71,13 @@ impl Parser {
         match token {
             Some(Token::LineNumber(n)) => {
                 self.advance();
+                if n > self.dialect.max_line_number {
+                    return Err(BasicError::Syntax {
+                        message: format!("Line number {} exceeds the maximum of {}", n, self.dialect.max_line_number),
+                        basic_line_number: Some(n),
+                        file_line_number: Some(self.current_file_line),
+                    });
+                }
                 Ok(n)
             }
             _ => {
@@ -66,7 +95,11 @@ impl Parser {
         let mut statements = Vec::new();
 
         while !self.is_at_end() && !self.check(&Token::Newline) {
-            let stmt = self.parse_statement()?;
+            let stmt = match self.parse_statement()? {
+                // An ELSE that starts a line belongs to a block IF
+                Statement::Else if statements.is_empty() => Statement::ElseBlock,
+                stmt => stmt,
+            };
             statements.push(stmt.clone());
 
             // After REM, the rest of the line is consumed, so we can break early.
@@ -139,11 +172,30 @@ impl Parser {
             self.advance(); // skip `LET`
         }
 
+        let is_mid = self.check(&Token::Identifier("MID$".to_string(), IdentifierType::BuiltInFunction));
         let var = self.parse_variable_or_array_access()?;
         self.consume(&Token::Equal, "Expected '=' after variable name")?;
         let value = self.parse_expression()?;
 
-        Ok(Statement::Let { var, value })
+        match var.expr_type {
+            ExpressionType::Array { indices, .. } if is_mid => self.mid_assignment(indices, value),
+            _ => Ok(Statement::Let { var, value }),
+        }
+    }
+
+    /// `MID$(var, start[, length]) = value`, which overwrites part of a string variable
+    fn mid_assignment(&self, args: Vec<Expression>, value: Expression) -> Result<Statement, BasicError> {
+        let mut args = args.into_iter();
+        match (args.next(), args.next(), args.next(), args.next()) {
+            (Some(var), Some(start), length, None) if var.is_string_variable() => {
+                Ok(Statement::MidAssign { var, start, length, value })
+            }
+            _ => Err(BasicError::Syntax {
+                message: "MID$ assignment needs a string variable, a start and perhaps a length".to_string(),
+                basic_line_number: self.current_basic_line,
+                file_line_number: Some(self.current_file_line),
+            }),
+        }
     }
 
     fn parse_statement(&mut self) -> Result<Statement, BasicError> {
@@ -152,71 +204,35 @@ impl Parser {
             Some(Token::Identifier(_, _)) => self.parse_implicit_or_explicit_let(false),
             Some(Token::Print) => {
                 self.advance();
-                let mut items = Vec::new();
-                
-                // Parse comma/semicolon-separated expressions
-                if !self.is_at_end() && !self.check(&Token::Colon) && !self.check(&Token::Newline) {
-                    loop {
-                        // Parse expression (or empty string if just spacing)
-                        if self.check(&Token::Comma) {
-                            items.push(PrintItem::Comma);
-                            self.advance();
-                        } else if self.check(&Token::Semicolon) {
-                            items.push(PrintItem::Semicolon);
-                            self.advance();
-                        } else {
-                            // Parse actual expression
-                            let expr = self.parse_expression()?;
-                            
-                            // Check if this is a TAB function call
-                            if let ExpressionType::FunctionCall { name, args } = &expr.expr_type {
-                                if name == "TAB" && args.len() == 1 {
-                                    // Extract the tab position
-                                    if let ExpressionType::Number(n) = &args[0].expr_type {
-                                        items.push(PrintItem::Tab(*n as usize));
-                                    } else {
-                                        items.push(PrintItem::Expression(expr));
-                                    }
-                                } else {
-                                    items.push(PrintItem::Expression(expr));
-                                }
-                            } else {
-                                items.push(PrintItem::Expression(expr));
-                            }
-                            
-                            // Check for separator after the expression
-                            if self.check(&Token::Comma) {
-                                items.push(PrintItem::Comma);
-                                self.advance();
-                            } else if self.check(&Token::Semicolon) {
-                                items.push(PrintItem::Semicolon);
-                                self.advance();
-                            } else {
-                                break;
-                            }
-                        }
-                        
-                        // If we're at the end or hit a colon/newline, stop
-                        if self.is_at_end() || self.check(&Token::Colon) || self.check(&Token::Newline) {
-                            break;
+                if self.match_any(&[Token::Hash]) {
+                    let number = self.parse_expression()?;
+                    let items = if self.match_any(&[Token::Comma]) {
+                        if self.match_any(&[Token::Using]) {
+                            return self.parse_print_using(Some(number));
                         }
-                    }
+                        self.parse_print_items()?
+                    } else {
+                        Vec::new()
+                    };
+                    return Ok(Statement::PrintFile { number, items });
                 }
-                
-                // Check if there are unexpected tokens after the PRINT statement
-                if !self.is_at_end() && !self.check(&Token::Colon) && !self.check(&Token::Newline) {
-                    let current_token = self.peek().map(|t| format!("{:?}", t)).unwrap_or_else(|| "end of input".to_string());
-                    return Err(BasicError::Syntax {
-                        message: format!("Unexpected token after PRINT expression: {}", current_token),
-                        basic_line_number: self.current_basic_line,
-                        file_line_number: Some(self.current_file_line),
-                    });
+                if self.match_any(&[Token::Using]) {
+                    return self.parse_print_using(None);
                 }
-                
+                let items = self.parse_print_items()?;
                 Ok(Statement::Print { items })
             }
             Some(Token::Input) => {
                 self.advance();
+                if self.match_any(&[Token::Hash]) {
+                    let number = self.parse_expression()?;
+                    self.consume(&Token::Comma, "Expected ',' after file number")?;
+                    let mut vars = vec![self.parse_identifier()?];
+                    while self.match_any(&[Token::Comma]) {
+                        vars.push(self.parse_identifier()?);
+                    }
+                    return Ok(Statement::InputFile { number, vars });
+                }
 
                 // Check if there's a prompt string
                 let prompt = if let Some(Token::String(s)) = self.peek().cloned() {
@@ -258,8 +274,18 @@ impl Parser {
                 self.advance();
                 let condition = self.parse_expression()?;
                 // self.consume(&Token::Then, "Expected THEN after condition")?;
+                if self.check(&Token::Then) && self.ends_line_after_next() {
+                    self.advance();
+                    return Ok(Statement::IfBlock { condition });
+                }
                 Ok(Statement::If { condition })
             }
+            Some(Token::ElseIf) => {
+                self.advance();
+                let condition = self.parse_expression()?;
+                self.consume(&Token::Then, "Expected THEN after ELSEIF condition")?;
+                Ok(Statement::ElseIf { condition })
+            }
             Some(Token::Then) => {
                 self.advance();
                 // Check if next token is a number, for IF x THEN 100
@@ -278,6 +304,23 @@ impl Parser {
                 }
                 Ok(Statement::Else)
             }
+            Some(Token::Select) => {
+                self.advance();
+                self.consume(&Token::Case, "Expected CASE after SELECT")?;
+                let selector = self.parse_expression()?;
+                Ok(Statement::SelectCase { selector })
+            }
+            Some(Token::Case) => {
+                self.advance();
+                if self.match_any(&[Token::Else]) {
+                    return Ok(Statement::CaseElse);
+                }
+                let mut tests = vec![self.parse_case_test()?];
+                while self.match_any(&[Token::Comma]) {
+                    tests.push(self.parse_case_test()?);
+                }
+                Ok(Statement::Case { tests })
+            }
             Some(Token::For) => {
                 self.advance();
                 let var = self.parse_identifier()?;
@@ -313,6 +356,18 @@ impl Parser {
             }
             Some(Token::End) => {
                 self.advance();
+                if self.match_any(&[Token::If]) {
+                    return Ok(Statement::EndIf);
+                }
+                if self.match_any(&[Token::Select]) {
+                    return Ok(Statement::EndSelect);
+                }
+                if self.match_any(&[Token::Sub]) {
+                    return Ok(Statement::EndSub);
+                }
+                if self.match_any(&[Token::Function]) {
+                    return Ok(Statement::EndFunction);
+                }
                 Ok(Statement::End)
             }
             Some(Token::Stop) => {
@@ -364,6 +419,15 @@ impl Parser {
                 };
                 Ok(Statement::Restore { line })
             }
+            Some(Token::Randomize) => {
+                self.advance();
+                let seed = if self.is_at_end() || self.check(&Token::Colon) || self.check(&Token::Newline) || self.check(&Token::Else) {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                Ok(Statement::Randomize { seed })
+            }
             Some(Token::Dim) => {
                 self.advance();
 
@@ -400,6 +464,12 @@ impl Parser {
             }
             Some(Token::On) => {
                 self.advance();
+                if self.check(&Token::Error) {
+                    self.advance();
+                    self.consume(&Token::Goto, "Expected GOTO after ON ERROR")?;
+                    let line = self.parse_number()? as usize;
+                    return Ok(Statement::OnErrorGoto { line });
+                }
                 let expr = self.parse_expression()?;
                 
                 if self.check(&Token::Goto) {
@@ -440,6 +510,196 @@ impl Parser {
                     })
                 }
             }
+            Some(Token::Resume) => {
+                self.advance();
+                let target = if self.check(&Token::Next) {
+                    self.advance();
+                    ResumeTarget::Next
+                } else if self.check(&Token::Colon) || self.check(&Token::Newline) || self.check(&Token::Else) || self.is_at_end() {
+                    ResumeTarget::Retry
+                } else {
+                    match self.parse_number()? as usize {
+                        0 => ResumeTarget::Retry,
+                        line => ResumeTarget::Line(line),
+                    }
+                };
+                Ok(Statement::Resume { target })
+            }
+            Some(Token::While) => {
+                self.advance();
+                let condition = self.parse_expression()?;
+                Ok(Statement::While { condition })
+            }
+            Some(Token::Wend) => {
+                self.advance();
+                Ok(Statement::Wend)
+            }
+            Some(Token::Do) => {
+                self.advance();
+                let condition = self.parse_loop_condition()?;
+                Ok(Statement::Do { condition })
+            }
+            Some(Token::Loop) => {
+                self.advance();
+                let condition = self.parse_loop_condition()?;
+                Ok(Statement::Loop { condition })
+            }
+            Some(Token::Exit) => {
+                self.advance();
+                if self.match_any(&[Token::Sub]) {
+                    return Ok(Statement::ExitSub);
+                }
+                if self.match_any(&[Token::Function]) {
+                    return Ok(Statement::ExitFunction);
+                }
+                self.consume(&Token::Do, "Expected DO, SUB or FUNCTION after EXIT")?;
+                Ok(Statement::ExitDo)
+            }
+            Some(Token::Sub) => {
+                self.advance();
+                let name = self.parse_identifier()?;
+                let params = self.parse_parameters()?;
+                Ok(Statement::Sub { name, params })
+            }
+            Some(Token::Function) => {
+                self.advance();
+                let name = self.parse_identifier()?;
+                let params = self.parse_parameters()?;
+                Ok(Statement::Function { name, params })
+            }
+            Some(Token::Call) => {
+                self.advance();
+                let name = self.parse_identifier()?;
+                let mut args = Vec::new();
+                if self.match_any(&[Token::LeftParen]) {
+                    if !self.check(&Token::RightParen) {
+                        loop {
+                            args.push(self.parse_expression()?);
+                            if !self.match_any(&[Token::Comma]) {
+                                break;
+                            }
+                        }
+                    }
+                    self.consume(&Token::RightParen, "Expected ')' after arguments")?;
+                }
+                Ok(Statement::Call { name, args })
+            }
+            Some(Token::Local) | Some(Token::Shared) => {
+                let is_local = self.check(&Token::Local);
+                self.advance();
+                let mut vars = vec![self.parse_identifier()?];
+                while self.match_any(&[Token::Comma]) {
+                    vars.push(self.parse_identifier()?);
+                }
+                Ok(if is_local { Statement::Local { vars } } else { Statement::Shared { vars } })
+            }
+            Some(Token::Line) => {
+                self.advance();
+                self.consume(&Token::Input, "Expected INPUT after LINE")?;
+                self.consume(&Token::Hash, "Expected '#' after LINE INPUT")?;
+                let number = self.parse_expression()?;
+                self.consume(&Token::Comma, "Expected ',' after file number")?;
+                let var = self.parse_string_variable("LINE INPUT #")?;
+                Ok(Statement::LineInputFile { number, var })
+            }
+            Some(Token::Write) => {
+                self.advance();
+                self.consume(&Token::Hash, "Expected '#' after WRITE")?;
+                let number = self.parse_expression()?;
+                let mut exprs = Vec::new();
+                while self.match_any(&[Token::Comma]) {
+                    exprs.push(self.parse_expression()?);
+                }
+                Ok(Statement::WriteFile { number, exprs })
+            }
+            Some(Token::Open) => {
+                self.advance();
+                let path = self.parse_expression()?;
+                // With no FOR, the file is a random file
+                let mode = if self.match_any(&[Token::For]) {
+                    match self.advance() {
+                        Token::Input => FileMode::Input,
+                        Token::Output => FileMode::Output,
+                        Token::Append => FileMode::Append,
+                        Token::Random => FileMode::Random,
+                        _ => return Err(BasicError::Syntax {
+                            message: "Expected INPUT, OUTPUT, APPEND or RANDOM after FOR".to_string(),
+                            basic_line_number: self.current_basic_line,
+                            file_line_number: Some(self.current_file_line),
+                        }),
+                    }
+                } else {
+                    FileMode::Random
+                };
+                self.consume(&Token::As, "Expected AS after OPEN mode")?;
+                let number = self.parse_file_number()?;
+                let record_len = if self.match_any(&[Token::Identifier("LEN".to_string(), IdentifierType::BuiltInFunction)]) {
+                    self.consume(&Token::Equal, "Expected '=' after LEN")?;
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                Ok(Statement::Open { path, mode, number, record_len })
+            }
+            Some(Token::Field) => {
+                self.advance();
+                let number = self.parse_file_number()?;
+                let mut fields = Vec::new();
+                while self.match_any(&[Token::Comma]) {
+                    let width = self.parse_expression()?;
+                    self.consume(&Token::As, "Expected AS after FIELD width")?;
+                    fields.push((width, self.parse_string_variable("FIELD")?));
+                }
+                if fields.is_empty() {
+                    return Err(BasicError::Syntax {
+                        message: "FIELD needs at least one 'width AS variable'".to_string(),
+                        basic_line_number: self.current_basic_line,
+                        file_line_number: Some(self.current_file_line),
+                    });
+                }
+                Ok(Statement::Field { number, fields })
+            }
+            Some(Token::Get) | Some(Token::Put) => {
+                let is_get = *self.advance() == Token::Get;
+                let number = self.parse_file_number()?;
+                let record = if self.match_any(&[Token::Comma]) { Some(self.parse_expression()?) } else { None };
+                Ok(if is_get { Statement::Get { number, record } } else { Statement::Put { number, record } })
+            }
+            Some(Token::Lset) | Some(Token::Rset) => {
+                let is_lset = *self.advance() == Token::Lset;
+                let var = self.parse_string_variable(if is_lset { "LSET" } else { "RSET" })?;
+                self.consume(&Token::Equal, "Expected '=' after variable")?;
+                let value = self.parse_expression()?;
+                Ok(if is_lset { Statement::Lset { var, value } } else { Statement::Rset { var, value } })
+            }
+            Some(Token::Close) => {
+                self.advance();
+                let mut numbers = Vec::new();
+                if !self.is_at_end() && !self.check(&Token::Colon) && !self.check(&Token::Newline) {
+                    numbers.push(self.parse_file_number()?);
+                    while self.match_any(&[Token::Comma]) {
+                        numbers.push(self.parse_file_number()?);
+                    }
+                }
+                Ok(Statement::Close { numbers })
+            }
+            Some(Token::Mat) => {
+                self.advance();
+                self.parse_mat()
+            }
+            Some(&Token::DefType(var_type)) => {
+                self.advance();
+                let mut ranges = vec![self.parse_letter_range()?];
+                while self.match_any(&[Token::Comma]) {
+                    ranges.push(self.parse_letter_range()?);
+                }
+                for &(first, last) in &ranges {
+                    for letter in first..=last {
+                        self.def_types[(letter as u8 - b'A') as usize] = Some(var_type);
+                    }
+                }
+                Ok(Statement::DefType { var_type, ranges })
+            }
             Some(Token::Def) => {
                 self.advance();
                 let name = self.parse_identifier()?;
@@ -477,34 +737,356 @@ impl Parser {
         }
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, BasicError> {
-        self.parse_or()
+    /// The rest of PRINT USING, after USING: the format, a semicolon and the values,
+    /// separated by semicolons or commas. A separator at the end keeps the line open.
+    fn parse_print_using(&mut self, number: Option<Expression>) -> Result<Statement, BasicError> {
+        let format = self.parse_expression()?;
+        self.consume(&Token::Semicolon, "Expected ';' after PRINT USING format")?;
+        let mut exprs = vec![self.parse_expression()?];
+        let mut newline = true;
+        while self.match_any(&[Token::Semicolon, Token::Comma]) {
+            if self.is_at_end() || self.check(&Token::Colon) || self.check(&Token::Newline) {
+                newline = false;
+                break;
+            }
+            exprs.push(self.parse_expression()?);
+        }
+        Ok(Statement::PrintUsing { number, format, exprs, newline })
     }
 
-    fn parse_or(&mut self) -> Result<Expression, BasicError> {
-        let mut expr = self.parse_and()?;
+    /// The rest of a MAT statement, after MAT: READ, PRINT or an assignment
+    fn parse_mat(&mut self) -> Result<Statement, BasicError> {
+        if self.match_any(&[Token::Read]) {
+            let mut arrays = Vec::new();
+            loop {
+                let name = self.parse_mat_array()?;
+                let bounds = self.parse_mat_bounds()?;
+                arrays.push((name, bounds));
+                if !self.match_any(&[Token::Comma]) {
+                    break;
+                }
+            }
+            return Ok(Statement::MatRead { arrays });
+        }
+        if self.match_any(&[Token::Print]) {
+            let mut arrays = Vec::new();
+            loop {
+                let name = self.parse_mat_array()?;
+                let packed = self.check(&Token::Semicolon);
+                let separated = self.match_any(&[Token::Semicolon, Token::Comma]);
+                arrays.push((name, packed));
+                if !separated || self.is_at_end() || self.check(&Token::Colon) || self.check(&Token::Newline) {
+                    break;
+                }
+            }
+            return Ok(Statement::MatPrint { arrays });
+        }
+
+        let var = self.parse_mat_array()?;
+        self.consume(&Token::Equal, "Expected '=' after MAT array")?;
+        let value = match self.peek() {
+            Some(Token::Zer) => {
+                self.advance();
+                MatValue::Zero(self.parse_mat_bounds()?)
+            }
+            Some(Token::Con) => {
+                self.advance();
+                MatValue::Constant(self.parse_mat_bounds()?)
+            }
+            Some(Token::Idn) => {
+                self.advance();
+                MatValue::Identity(self.parse_mat_bounds()?)
+            }
+            Some(Token::Trn) | Some(Token::Inv) => {
+                let inverse = self.check(&Token::Inv);
+                self.advance();
+                self.consume(&Token::LeftParen, "Expected '(' after TRN or INV")?;
+                let name = self.parse_mat_array()?;
+                self.consume(&Token::RightParen, "Expected ')' after array")?;
+                if inverse { MatValue::Inverse(name) } else { MatValue::Transpose(name) }
+            }
+            Some(Token::LeftParen) => {
+                self.advance();
+                let scalar = self.parse_expression()?;
+                self.consume(&Token::RightParen, "Expected ')' after MAT scalar")?;
+                self.consume(&Token::Star, "Expected '*' after MAT scalar")?;
+                MatValue::Scaled(scalar, self.parse_mat_array()?)
+            }
+            _ => {
+                let left = self.parse_mat_array()?;
+                if self.match_any(&[Token::Plus]) {
+                    MatValue::Sum(left, self.parse_mat_array()?)
+                } else if self.match_any(&[Token::Minus]) {
+                    MatValue::Difference(left, self.parse_mat_array()?)
+                } else if self.match_any(&[Token::Star]) {
+                    MatValue::Product(left, self.parse_mat_array()?)
+                } else {
+                    MatValue::Array(left)
+                }
+            }
+        };
+        Ok(Statement::MatAssign { var, value })
+    }
+
+    /// The name of an array in a MAT statement, which must be numeric
+    fn parse_mat_array(&mut self) -> Result<String, BasicError> {
+        let name = self.parse_identifier()?;
+        if name.ends_with('$') {
+            return Err(BasicError::Syntax {
+                message: format!("MAT works on numeric arrays, not {}", name),
+                basic_line_number: self.current_basic_line,
+                file_line_number: Some(self.current_file_line),
+            });
+        }
+        Ok(name)
+    }
+
+    /// The dimensions in parentheses after MAT READ's arrays and ZER, CON and IDN, if any
+    fn parse_mat_bounds(&mut self) -> Result<Vec<Expression>, BasicError> {
+        let mut bounds = Vec::new();
+        if self.match_any(&[Token::LeftParen]) {
+            bounds.push(self.parse_expression()?);
+            while self.match_any(&[Token::Comma]) {
+                bounds.push(self.parse_expression()?);
+            }
+            self.consume(&Token::RightParen, "Expected ')' after dimensions")?;
+        }
+        Ok(bounds)
+    }
+
+    /// The items of a PRINT or PRINT # statement, up to the end of the statement
+    fn parse_print_items(&mut self) -> Result<Vec<PrintItem>, BasicError> {
+        let mut items = Vec::new();
+
+        // Parse comma/semicolon-separated expressions
+        if !self.is_at_end() && !self.check(&Token::Colon) && !self.check(&Token::Newline) {
+            loop {
+                // Parse expression (or empty string if just spacing)
+                if self.check(&Token::Comma) {
+                    items.push(PrintItem::Comma);
+                    self.advance();
+                } else if self.check(&Token::Semicolon) {
+                    items.push(PrintItem::Semicolon);
+                    self.advance();
+                } else {
+                    // Parse actual expression
+                    let expr = self.parse_expression()?;
+                    
+                    // Check if this is a TAB function call
+                    if let ExpressionType::FunctionCall { name, args } = &expr.expr_type {
+                        if name == "TAB" && args.len() == 1 {
+                            // Extract the tab position
+                            if let ExpressionType::Number(n) = &args[0].expr_type {
+                                items.push(PrintItem::Tab(*n as usize));
+                            } else {
+                                items.push(PrintItem::Expression(expr));
+                            }
+                        } else {
+                            items.push(PrintItem::Expression(expr));
+                        }
+                    } else {
+                        items.push(PrintItem::Expression(expr));
+                    }
+                    
+                    // Check for separator after the expression
+                    if self.check(&Token::Comma) {
+                        items.push(PrintItem::Comma);
+                        self.advance();
+                    } else if self.check(&Token::Semicolon) {
+                        items.push(PrintItem::Semicolon);
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                
+                // If we're at the end or hit a colon/newline, stop
+                if self.is_at_end() || self.check(&Token::Colon) || self.check(&Token::Newline) {
+                    break;
+                }
+            }
+        }
         
-        while self.check(&Token::Or) {
-            self.advance();
-            let right = self.parse_and()?;
-            expr = Expression::new_binary_op("OR".to_string(), expr, right);
+        // Check if there are unexpected tokens after the PRINT statement
+        if !self.is_at_end() && !self.check(&Token::Colon) && !self.check(&Token::Newline) {
+            let current_token = self.peek().map(|t| format!("{:?}", t)).unwrap_or_else(|| "end of input".to_string());
+            return Err(BasicError::Syntax {
+                message: format!("Unexpected token after PRINT expression: {}", current_token),
+                basic_line_number: self.current_basic_line,
+                file_line_number: Some(self.current_file_line),
+            });
         }
         
-        Ok(expr)
+        Ok(items)
     }
 
-    fn parse_and(&mut self) -> Result<Expression, BasicError> {
-        let mut expr = self.parse_equality()?;
-        
-        while self.check(&Token::And) {
+    /// A file number after OPEN ... AS or CLOSE, where the `#` is optional
+    fn parse_file_number(&mut self) -> Result<Expression, BasicError> {
+        self.match_any(&[Token::Hash]);
+        self.parse_expression()
+    }
+
+    /// A letter, or a range of letters such as `A-Z`, after DEFINT and its relatives
+    fn parse_letter_range(&mut self) -> Result<(char, char), BasicError> {
+        let first = self.parse_letter()?;
+        let last = if self.match_any(&[Token::Minus]) { self.parse_letter()? } else { first };
+        if last < first {
+            return Err(BasicError::Syntax {
+                message: format!("Letter range {}-{} runs backwards", first, last),
+                basic_line_number: self.current_basic_line,
+                file_line_number: Some(self.current_file_line),
+            });
+        }
+        Ok((first, last))
+    }
+
+    fn parse_letter(&mut self) -> Result<char, BasicError> {
+        if let Some(Token::Identifier(name, IdentifierType::Variable)) = self.peek() {
+            if let [letter] = name.as_bytes() {
+                let letter = *letter as char;
+                self.advance();
+                return Ok(letter);
+            }
+        }
+        Err(BasicError::Syntax {
+            message: "Expected a letter".to_string(),
+            basic_line_number: self.current_basic_line,
+            file_line_number: Some(self.current_file_line),
+        })
+    }
+
+    /// The name a variable is stored under. A name without a suffix takes the type DEFINT
+    /// and its relatives gave its first letter, and the suffix of the dialect's own numeric
+    /// type is dropped, so that `A` and `A!` are one variable in GW-BASIC.
+    fn variable_name(&self, name: &str) -> String {
+        let (base, var_type) = match VarType::of(name) {
+            Some(var_type) => (&name[..name.len() - 1], Some(var_type)),
+            None => {
+                let letter = name.as_bytes()[0].wrapping_sub(b'A') as usize;
+                (name, self.def_types.get(letter).copied().flatten())
+            }
+        };
+        match var_type {
+            Some(var_type) if Some(var_type) != self.dialect.default_type() => format!("{}{}", base, var_type.suffix()),
+            _ => base.to_string(),
+        }
+    }
+
+    /// A string variable for a statement that only takes string variables
+    fn parse_string_variable(&mut self, statement: &str) -> Result<String, BasicError> {
+        let var = self.parse_identifier()?;
+        if !var.ends_with('$') {
+            return Err(BasicError::Syntax {
+                message: format!("{} needs a string variable, got {}", statement, var),
+                basic_line_number: self.current_basic_line,
+                file_line_number: Some(self.current_file_line),
+            });
+        }
+        Ok(var)
+    }
+
+    /// The optional parameter list of a SUB or FUNCTION: `(A, B$)`
+    fn parse_parameters(&mut self) -> Result<Vec<String>, BasicError> {
+        let mut params = Vec::new();
+        if !self.match_any(&[Token::LeftParen]) {
+            return Ok(params);
+        }
+        if !self.check(&Token::RightParen) {
+            loop {
+                params.push(self.parse_identifier()?);
+                if !self.match_any(&[Token::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&Token::RightParen, "Expected ')' after parameters")?;
+        Ok(params)
+    }
+
+    /// The optional WHILE or UNTIL test after DO or LOOP
+    fn parse_loop_condition(&mut self) -> Result<Option<LoopCondition>, BasicError> {
+        if self.match_any(&[Token::While]) {
+            Ok(Some(LoopCondition::While(self.parse_expression()?)))
+        } else if self.match_any(&[Token::Until]) {
+            Ok(Some(LoopCondition::Until(self.parse_expression()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// One test in a CASE list: a value, a `low TO high` range or `IS op value`
+    fn parse_case_test(&mut self) -> Result<CaseTest, BasicError> {
+        if self.match_any(&[Token::Is]) {
+            let op = match self.peek() {
+                Some(Token::Equal) => "=",
+                Some(Token::NotEqual) => "<>",
+                Some(Token::Less) => "<",
+                Some(Token::LessEqual) => "<=",
+                Some(Token::Greater) => ">",
+                Some(Token::GreaterEqual) => ">=",
+                _ => return Err(BasicError::Syntax {
+                    message: "Expected a comparison operator after IS".to_string(),
+                    basic_line_number: self.current_basic_line,
+                    file_line_number: Some(self.current_file_line),
+                }),
+            };
             self.advance();
-            let right = self.parse_equality()?;
-            expr = Expression::new_binary_op("AND".to_string(), expr, right);
+            return Ok(CaseTest::Is(op.to_string(), self.parse_expression()?));
+        }
+        let value = self.parse_expression()?;
+        if self.match_any(&[Token::To]) {
+            return Ok(CaseTest::Range(value, self.parse_expression()?));
+        }
+        Ok(CaseTest::Value(value))
+    }
+
+    /// An expression, its operators binding loosest to tightest as in Microsoft BASIC:
+    /// IMP, EQV, XOR, OR, AND, NOT, comparisons, + and -, MOD, \\, then * / and ^
+    fn parse_expression(&mut self) -> Result<Expression, BasicError> {
+        self.parse_imp()
+    }
+
+    /// A left-associative chain of `operand op operand ...` for one precedence level
+    fn parse_binary_level(&mut self, operators: &[(Token, &str)],
+                          operand: fn(&mut Self) -> Result<Expression, BasicError>) -> Result<Expression, BasicError> {
+        let mut expr = operand(self)?;
+        while let Some((_, op)) = operators.iter().find(|(token, _)| self.check(token)) {
+            self.advance();
+            let right = operand(self)?;
+            expr = Expression::new_binary_op(op.to_string(), expr, right);
         }
-        
         Ok(expr)
     }
 
+    fn parse_imp(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Imp, "IMP")], Self::parse_eqv)
+    }
+
+    fn parse_eqv(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Eqv, "EQV")], Self::parse_xor)
+    }
+
+    fn parse_xor(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Xor, "XOR")], Self::parse_or)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Or, "OR")], Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::And, "AND")], Self::parse_not)
+    }
+
+    /// NOT applies to the whole comparison after it: NOT A = B is NOT (A = B)
+    fn parse_not(&mut self) -> Result<Expression, BasicError> {
+        if self.match_any(&[Token::Not]) {
+            let expr = self.parse_not()?;
+            return Ok(Expression::new_unary_op("NOT".to_string(), expr));
+        }
+        self.parse_equality()
+    }
+
     fn parse_equality(&mut self) -> Result<Expression, BasicError> {
         let mut expr = self.parse_comparison()?;
         
@@ -543,7 +1125,7 @@ impl Parser {
     }
 
     fn parse_term(&mut self) -> Result<Expression, BasicError> {
-        let mut expr = self.parse_factor()?;
+        let mut expr = self.parse_mod()?;
         
         while self.match_any(&[Token::Plus, Token::Minus]) {
             let op = match self.previous() {
@@ -551,13 +1133,21 @@ impl Parser {
                 Token::Minus => "-",
                 _ => unreachable!(),
             };
-            let right = self.parse_factor()?;
+            let right = self.parse_mod()?;
             expr = Expression::new_binary_op(op.to_string(), expr, right);
         }
         
         Ok(expr)
     }
 
+    fn parse_mod(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Mod, "MOD")], Self::parse_integer_division)
+    }
+
+    fn parse_integer_division(&mut self) -> Result<Expression, BasicError> {
+        self.parse_binary_level(&[(Token::Backslash, "\\")], Self::parse_factor)
+    }
+
     fn parse_factor(&mut self) -> Result<Expression, BasicError> {
         let mut expr = self.parse_unary()?;
         
@@ -576,14 +1166,12 @@ impl Parser {
     }
 
     fn parse_unary(&mut self) -> Result<Expression, BasicError> {
-        if self.match_any(&[Token::Minus, Token::Not]) {
-            let op = match self.previous() {
-                Token::Minus => "-",
-                Token::Not => "NOT",
-                _ => unreachable!(),
-            };
+        if self.match_any(&[Token::Minus]) {
             let expr = self.parse_unary()?;
-            Ok(Expression::new_unary_op(op.to_string(), expr))
+            Ok(Expression::new_unary_op("-".to_string(), expr))
+        } else if self.check(&Token::Not) {
+            // As an operand, as in A + NOT B, NOT still takes the comparison after it
+            self.parse_not()
         } else {
             self.parse_primary()
         }
@@ -632,8 +1220,17 @@ impl Parser {
             });
         };
 
-        // If there are no parentheses, it's a simple variable.
+        let name = match id_type {
+            IdentifierType::Variable | IdentifierType::Array => self.variable_name(&name),
+            _ => name,
+        };
+
+        // If there are no parentheses, it's a simple variable, or a built-in function
+        // that takes no arguments, like ERR.
         if !self.check(&Token::LeftParen) {
+            if id_type == IdentifierType::BuiltInFunction {
+                return Ok(Expression::new_function_call(name, Vec::new()));
+            }
             return Ok(Expression::new_variable(name));
         }
 
@@ -688,7 +1285,7 @@ impl Parser {
     }
 
     fn check(&self, token: &Token) -> bool {
-        self.peek().map_or(false, |t| t == token)
+        self.peek() == Some(token)
     }
 
     fn match_any(&mut self, tokens: &[Token]) -> bool {
@@ -717,11 +1314,19 @@ impl Parser {
         self.peek().is_none()
     }
 
+    /// True if the token after the current one ends the line, as after a block IF's THEN
+    fn ends_line_after_next(&self) -> bool {
+        matches!(self.tokens.get(self.current + 1), None | Some(Token::Newline))
+    }
+
     fn parse_identifier(&mut self) -> Result<String, BasicError> {
         let token = self.peek().cloned();
         match token {
-            Some(Token::Identifier(id, _)) => {
+            Some(Token::Identifier(id, id_type)) => {
                 self.advance();
+                if id_type == IdentifierType::Variable {
+                    return Ok(self.variable_name(&id));
+                }
                 Ok(id.clone())
             }
             _ => Err(BasicError::Syntax {
@@ -775,7 +1380,7 @@ impl Parser {
         while !self.is_at_end() && !self.check(&Token::Newline) {
             let token = self.advance();
             match token {
-                Token::String(s) => comment.push_str(&s), // no extra quotes
+                Token::String(s) => comment.push_str(s), // no extra quotes
                 other => comment.push_str(&other.to_string()),
             }
             if !self.is_at_end() && !self.check(&Token::Newline) {
@@ -807,8 +1412,12 @@ impl Parser {
     fn parse_variable_or_array_access(&mut self) -> Result<Expression, BasicError> {
         let token = self.peek().cloned();
         match token {
-            Some(Token::Identifier(name, _)) => {
+            Some(Token::Identifier(name, id_type)) => {
                 self.advance();
+                let name = match id_type {
+                    IdentifierType::Variable | IdentifierType::Array => self.variable_name(&name),
+                    _ => name,
+                };
                 if self.check(&Token::LeftParen) {
                     // Array access
                     self.advance();
@@ -874,6 +1483,17 @@ mod tests {
             panic!("Expected LET statement");
         }
     }
+    #[test]
+    fn test_line_number_limit() {
+        let tokens = vec![Token::LineNumber(10000), Token::End, Token::Newline];
+        let mut parser = Parser::with_dialect(tokens.clone(), Dialect::ANSI_MINIMAL);
+        let err = parser.parse().unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum of 9999"));
+
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_ok());
+    }
+
     #[test]
     fn test_parse_let_statement_with_identifier() {
         let tokens = vec![
@@ -1148,6 +1768,311 @@ mod tests {
             panic!("Expected LET statement");
         }
     }
+
+    #[test]
+    fn test_parse_on_error_and_resume() {
+        let tokens = vec![
+            Token::LineNumber(10),
+            Token::On, Token::Error, Token::Goto, Token::Number("100".to_string()),
+            Token::Colon, Token::Resume,
+            Token::Colon, Token::Resume, Token::Next,
+            Token::Colon, Token::Resume, Token::Number("0".to_string()),
+            Token::Colon, Token::Resume, Token::Number("40".to_string()),
+            Token::Newline,
+        ];
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+        assert_eq!(program.lines[0].statements, vec![
+            Statement::OnErrorGoto { line: 100 },
+            Statement::Resume { target: ResumeTarget::Retry },
+            Statement::Resume { target: ResumeTarget::Next },
+            Statement::Resume { target: ResumeTarget::Retry },
+            Statement::Resume { target: ResumeTarget::Line(40) },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_structured_loops() {
+        let tokens = vec![
+            Token::LineNumber(10),
+            Token::While, Token::Identifier("X".to_string(), IdentifierType::Variable), Token::Less, Token::Number("3".to_string()),
+            Token::Colon, Token::Wend,
+            Token::Colon, Token::Do,
+            Token::Colon, Token::Exit, Token::Do,
+            Token::Colon, Token::Loop, Token::Until, Token::Identifier("X".to_string(), IdentifierType::Variable),
+            Token::Newline,
+        ];
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+        let x = Expression::new_variable("X".to_string());
+        assert_eq!(program.lines[0].statements, vec![
+            Statement::While { condition: Expression::new_binary_op("<".to_string(), x.clone(), Expression::new_number(3.0)) },
+            Statement::Wend,
+            Statement::Do { condition: None },
+            Statement::ExitDo,
+            Statement::Loop { condition: Some(LoopCondition::Until(x)) },
+        ]);
+
+        let mut parser = Parser::new(vec![Token::LineNumber(10), Token::Exit, Token::Newline]);
+        assert!(parser.parse().unwrap_err().to_string().contains("Expected DO, SUB or FUNCTION after EXIT"));
+    }
+
+    #[test]
+    fn test_parse_block_if() {
+        let x = || Token::Identifier("X".to_string(), IdentifierType::Variable);
+        let tokens = vec![
+            Token::LineNumber(10), Token::If, x(), Token::Then, Token::Newline,
+            Token::LineNumber(20), Token::ElseIf, x(), Token::Then, Token::Newline,
+            Token::LineNumber(30), Token::Else, Token::Colon, Token::If, x(), Token::Then, Token::Number("50".to_string()), Token::Newline,
+            Token::LineNumber(40), Token::End, Token::If, Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        let x = Expression::new_variable("X".to_string());
+        assert_eq!(program.lines[0].statements, vec![Statement::IfBlock { condition: x.clone() }]);
+        assert_eq!(program.lines[1].statements, vec![Statement::ElseIf { condition: x.clone() }]);
+        // Only an ELSE starting its line is a block ELSE; the IF after it is a one-line IF
+        assert_eq!(program.lines[2].statements, vec![
+            Statement::ElseBlock,
+            Statement::If { condition: x },
+            Statement::Then,
+            Statement::Goto { line: 50 },
+        ]);
+        assert_eq!(program.lines[3].statements, vec![Statement::EndIf]);
+
+        // Unbalanced blocks are syntax errors, unless parsing lines one at a time
+        let tokens = vec![Token::LineNumber(10), Token::If, Token::Number("1".to_string()), Token::Then, Token::Newline];
+        let err = Parser::new(tokens.clone()).parse().unwrap_err();
+        assert!(matches!(err, BasicError::Syntax { basic_line_number: Some(10), .. }));
+        assert!(err.to_string().contains("IF without END IF"));
+        assert!(Parser::new(tokens).parse_lines().is_ok());
+    }
+
+    #[test]
+    fn test_parse_select_case() {
+        let n = |v: &str| Token::Number(v.to_string());
+        let tokens = vec![
+            Token::LineNumber(10), Token::Select, Token::Case, Token::Identifier("X".to_string(), IdentifierType::Variable), Token::Newline,
+            Token::LineNumber(20), Token::Case, n("1"), Token::Comma, n("3"), Token::To, n("5"), Token::Comma, Token::Is, Token::Greater, n("10"), Token::Newline,
+            Token::LineNumber(30), Token::Case, Token::Else, Token::Newline,
+            Token::LineNumber(40), Token::End, Token::Select, Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(program.lines[0].statements, vec![Statement::SelectCase { selector: Expression::new_variable("X".to_string()) }]);
+        assert_eq!(program.lines[1].statements, vec![Statement::Case { tests: vec![
+            CaseTest::Value(Expression::new_number(1.0)),
+            CaseTest::Range(Expression::new_number(3.0), Expression::new_number(5.0)),
+            CaseTest::Is(">".to_string(), Expression::new_number(10.0)),
+        ] }]);
+        assert_eq!(program.lines[1].statements[0].to_string(), "CASE 1, 3 TO 5, IS > 10");
+        assert_eq!(program.lines[2].statements, vec![Statement::CaseElse]);
+        assert_eq!(program.lines[3].statements, vec![Statement::EndSelect]);
+
+        let tokens = vec![Token::LineNumber(10), Token::Case, Token::Is, n("1"), Token::Newline];
+        assert!(Parser::new(tokens).parse_lines().unwrap_err().to_string().contains("Expected a comparison operator after IS"));
+    }
+
+    #[test]
+    fn test_parse_procedures() {
+        let id = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        let procedure = |name: &str| Token::Identifier(name.to_string(), IdentifierType::UserDefinedFunction);
+        let tokens = vec![
+            Token::LineNumber(10), Token::Call, procedure("SHOW"), Token::LeftParen, Token::Number("1".to_string()), Token::Comma, Token::String("A".to_string()), Token::RightParen, Token::Newline,
+            Token::LineNumber(20), Token::Sub, procedure("SHOW"), Token::LeftParen, id("N"), Token::Comma, id("A$"), Token::RightParen, Token::Newline,
+            Token::LineNumber(30), Token::Local, id("K"), Token::Comma, id("K$"), Token::Colon, Token::Shared, id("T"), Token::Newline,
+            Token::LineNumber(40), Token::Exit, Token::Sub, Token::Colon, Token::End, Token::Sub, Token::Newline,
+            Token::LineNumber(50), Token::Function, procedure("ONE"), Token::Colon, Token::Exit, Token::Function, Token::Colon, Token::End, Token::Function, Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(program.lines[0].statements, vec![Statement::Call { name: "SHOW".to_string(), args: vec![
+            Expression::new_number(1.0), Expression::new_string("A".to_string()),
+        ] }]);
+        assert_eq!(program.lines[1].statements, vec![Statement::Sub { name: "SHOW".to_string(), params: vec!["N".to_string(), "A$".to_string()] }]);
+        assert_eq!(program.lines[1].statements[0].to_string(), "SUB SHOW(N, A$)");
+        assert_eq!(program.lines[2].statements, vec![
+            Statement::Local { vars: vec!["K".to_string(), "K$".to_string()] },
+            Statement::Shared { vars: vec!["T".to_string()] },
+        ]);
+        assert_eq!(program.lines[3].statements, vec![Statement::ExitSub, Statement::EndSub]);
+        assert_eq!(program.lines[4].statements, vec![
+            Statement::Function { name: "ONE".to_string(), params: vec![] },
+            Statement::ExitFunction,
+            Statement::EndFunction,
+        ]);
+        assert_eq!(program.function_names(), vec!["ONE".to_string()]);
+
+        // Procedure statements out of place are rejected with the program
+        let tokens = vec![Token::LineNumber(10), Token::End, Token::Sub, Token::Newline];
+        assert!(Parser::new(tokens).parse().unwrap_err().to_string().contains("END SUB without SUB"));
+    }
+
+    #[test]
+    fn test_parse_file_statements() {
+        let id = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        let one = || Token::Number("1".to_string());
+        let tokens = vec![
+            Token::LineNumber(10), Token::Open, Token::String("S.TXT".to_string()), Token::For, Token::Append, Token::As, Token::Hash, one(), Token::Newline,
+            Token::LineNumber(20), Token::Print, Token::Hash, one(), Token::Comma, id("A"), Token::Semicolon, Token::Colon, Token::Write, Token::Hash, one(), Token::Comma, id("A"), Token::Comma, id("B$"), Token::Newline,
+            Token::LineNumber(30), Token::Input, Token::Hash, one(), Token::Comma, id("A"), Token::Comma, id("B$"), Token::Colon, Token::Line, Token::Input, Token::Hash, one(), Token::Comma, id("L$"), Token::Newline,
+            Token::LineNumber(40), Token::Close, one(), Token::Comma, Token::Hash, Token::Number("2".to_string()), Token::Colon, Token::Close, Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        let number = || Expression::new_number(1.0);
+        assert_eq!(program.lines[0].statements, vec![Statement::Open {
+            path: Expression::new_string("S.TXT".to_string()), mode: FileMode::Append, number: number(), record_len: None,
+        }]);
+        assert_eq!(program.lines[0].statements[0].to_string(), "OPEN \"S.TXT\" FOR APPEND AS #1");
+        assert_eq!(program.lines[1].statements, vec![
+            Statement::PrintFile { number: number(), items: vec![PrintItem::Expression(Expression::new_variable("A".to_string())), PrintItem::Semicolon] },
+            Statement::WriteFile { number: number(), exprs: vec![Expression::new_variable("A".to_string()), Expression::new_variable("B$".to_string())] },
+        ]);
+        assert_eq!(program.lines[2].statements, vec![
+            Statement::InputFile { number: number(), vars: vec!["A".to_string(), "B$".to_string()] },
+            Statement::LineInputFile { number: number(), var: "L$".to_string() },
+        ]);
+        assert_eq!(program.lines[3].statements, vec![
+            Statement::Close { numbers: vec![number(), Expression::new_number(2.0)] },
+            Statement::Close { numbers: vec![] },
+        ]);
+
+        // LINE INPUT # reads a whole line, so it needs a string variable
+        let tokens = vec![Token::LineNumber(10), Token::Line, Token::Input, Token::Hash, one(), Token::Comma, id("A"), Token::Newline];
+        assert!(Parser::new(tokens).parse().unwrap_err().to_string().contains("LINE INPUT # needs a string variable"));
+    }
+
+    #[test]
+    fn test_parse_random_file_statements() {
+        let id = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        let num = |n: &str| Token::Number(n.to_string());
+        let tokens = vec![
+            Token::LineNumber(10), Token::Open, Token::String("R.DAT".to_string()), Token::As, Token::Hash, num("1"),
+            Token::Identifier("LEN".to_string(), IdentifierType::BuiltInFunction), Token::Equal, num("32"), Token::Newline,
+            Token::LineNumber(20), Token::Field, Token::Hash, num("1"), Token::Comma, num("30"), Token::As, id("N$"), Token::Comma, num("2"), Token::As, id("A$"), Token::Newline,
+            Token::LineNumber(30), Token::Lset, id("N$"), Token::Equal, id("X$"), Token::Colon, Token::Rset, id("A$"), Token::Equal, id("Y$"), Token::Newline,
+            Token::LineNumber(40), Token::Put, Token::Hash, num("1"), Token::Comma, num("3"), Token::Colon, Token::Get, Token::Hash, num("1"), Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        let statements: Vec<String> = program.lines.iter()
+            .flat_map(|line| line.statements.iter().map(|s| s.to_string()))
+            .collect();
+        assert_eq!(statements, [
+            "OPEN \"R.DAT\" FOR RANDOM AS #1 LEN=32",
+            "FIELD #1, 30 AS N$, 2 AS A$",
+            "LSET N$ = X$",
+            "RSET A$ = Y$",
+            "PUT #1, 3",
+            "GET #1",
+        ]);
+
+        let tokens = vec![Token::LineNumber(10), Token::Lset, id("A"), Token::Equal, num("1"), Token::Newline];
+        assert!(Parser::new(tokens).parse().unwrap_err().to_string().contains("LSET needs a string variable"));
+    }
+
+    #[test]
+    fn test_parse_print_using() {
+        let id = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        let num = |n: &str| Token::Number(n.to_string());
+        let format = || Token::String("##.##".to_string());
+        let tokens = vec![
+            Token::LineNumber(10), Token::Print, Token::Using, format(), Token::Semicolon, id("A"), Token::Comma, id("B"), Token::Newline,
+            Token::LineNumber(20), Token::Print, Token::Using, id("F$"), Token::Semicolon, id("A"), Token::Semicolon, Token::Newline,
+            Token::LineNumber(30), Token::Print, Token::Hash, num("1"), Token::Comma, Token::Using, format(), Token::Semicolon, id("A"), Token::Newline,
+        ];
+        let program = Parser::new(tokens).parse().unwrap();
+        let statements: Vec<String> = program.lines.iter()
+            .flat_map(|line| line.statements.iter().map(|s| s.to_string()))
+            .collect();
+        assert_eq!(statements, [
+            "PRINT USING \"##.##\"; A; B",
+            "PRINT USING F$; A;",
+            "PRINT #1, USING \"##.##\"; A",
+        ]);
+
+        let tokens = vec![Token::LineNumber(10), Token::Print, Token::Using, format(), Token::Newline];
+        assert!(Parser::new(tokens).parse().unwrap_err().to_string().contains("Expected ';' after PRINT USING format"));
+    }
+
+    #[test]
+    fn test_parse_def_types() {
+        let id = |name: &str| Token::Identifier(name.to_string(), IdentifierType::Variable);
+        let tokens = vec![
+            Token::LineNumber(10), Token::Let, id("A"), Token::Equal, id("I"), Token::Plus, id("B!"), Token::Newline,
+            Token::LineNumber(20), Token::DefType(VarType::Integer), id("I"), Token::Minus, id("N"), Token::Comma, id("X"), Token::Newline,
+            Token::LineNumber(30), Token::DefType(VarType::String), id("S"), Token::Newline,
+            Token::LineNumber(40), Token::For, id("I"), Token::Equal, id("X"), Token::To, id("N1#"), Token::Newline,
+            Token::LineNumber(50), Token::Let, id("S"), Token::Equal, id("A"), Token::Plus, id("I!"), Token::Newline,
+        ];
+        let statements = |dialect: Dialect| -> Vec<String> {
+            let program = Parser::with_dialect(tokens.clone(), dialect).parse().unwrap();
+            program.lines.iter().flat_map(|line| line.statements.iter().map(|s| s.to_string())).collect()
+        };
+        assert_eq!(statements(Dialect::default()), [
+            "LET A = (I + B!)",
+            "DEFINT I-N, X",
+            "DEFSTR S",
+            "FOR I% = X% TO N1",
+            "LET S$ = (A + I!)",
+        ]);
+        // GW-BASIC's own numbers are single precision
+        assert_eq!(statements(Dialect::GWBASIC)[0], "LET A = (I + B)");
+        assert_eq!(statements(Dialect::GWBASIC)[3], "FOR I% = X% TO N1#");
+
+        let tokens = vec![Token::LineNumber(10), Token::DefType(VarType::Integer), id("Z"), Token::Minus, id("A"), Token::Newline];
+        assert!(Parser::new(tokens).parse().unwrap_err().to_string().contains("Letter range Z-A runs backwards"));
+    }
+
+    #[test]
+    fn test_parse_mat() {
+        let statements = |source: &str| -> Result<Vec<String>, String> {
+            let tokens = crate::basic_lexer::Lexer::new(source).tokenize().map_err(|e| e.to_string())?;
+            let program = Parser::new(tokens).parse().map_err(|e| e.to_string())?;
+            Ok(program.lines.iter().flat_map(|line| line.statements.iter().map(|s| s.to_string())).collect())
+        };
+        assert_eq!(statements("10 MAT READ A, B(2, N + 1)\n20 MAT PRINT A; B,C\n30 MAT C = A * B\n\
+            40 MAT C = (K * 2) * A\n50 MAT B = INV(A): MAT C = TRN(B)\n60 MAT A = ZER: MAT V = CON(3)\n70 MAT A = IDN(2, 2)").unwrap(), [
+            "MAT READ A, B(2, (N + 1))",
+            "MAT PRINT A; B, C",
+            "MAT C = A * B",
+            "MAT C = ((K * 2)) * A",
+            "MAT B = INV(A)",
+            "MAT C = TRN(B)",
+            "MAT A = ZER",
+            "MAT V = CON(3)",
+            "MAT A = IDN(2, 2)",
+        ]);
+        assert!(statements("10 MAT PRINT A$").unwrap_err().contains("MAT works on numeric arrays, not A$"));
+        assert!(statements("10 MAT C = A / B").is_err());
+    }
+
+    #[test]
+    fn test_parse_randomize() {
+        let tokens = crate::basic_lexer::Lexer::new("10 RANDOMIZE\n20 RANDOMIZE TIMER: RANDOMIZE -3\n30 IF A THEN RANDOMIZE ELSE RANDOMIZE 1").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let statements: Vec<String> = program.lines.iter().flat_map(|line| line.statements.iter().map(|s| s.to_string())).collect();
+        assert_eq!(statements[..3], ["RANDOMIZE", "RANDOMIZE TIMER()", "RANDOMIZE -3"]);
+    }
+
+    #[test]
+    fn test_parse_operator_precedence() {
+        let tokens = crate::basic_lexer::Lexer::new("10 A = 2 + 7 MOD 3 * 2 \\ 1\n20 B = NOT A = 1 AND C OR D XOR E EQV F IMP G\n30 C = 1 + NOT 0").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let statements: Vec<String> = program.lines.iter().map(|line| line.statements[0].to_string()).collect();
+        assert_eq!(statements, [
+            "LET A = (2 + (7 MOD ((3 * 2) \\ 1)))",
+            "LET B = (((((NOT (A = 1) AND C) OR D) XOR E) EQV F) IMP G)",
+            "LET C = (1 + NOT 0)",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_mid_assign() {
+        let tokens = crate::basic_lexer::Lexer::new("10 MID$(A$, 2) = \"XY\"\n20 LET MID$(B$(I), 1, 3) = C$").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(program.lines[0].statements[0].to_string(), "MID$(A$, 2) = \"XY\"");
+        assert_eq!(program.lines[1].statements[0].to_string(), "MID$(B$(I), 1, 3) = C$");
+
+        let tokens = crate::basic_lexer::Lexer::new("10 MID$(A, 2) = \"XY\"").tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
 }
 
 #[test]
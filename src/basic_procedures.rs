@@ -0,0 +1,359 @@
+//! SUB and FUNCTION procedures.
+//!
+//! A procedure runs from its SUB or FUNCTION line to the matching END SUB or END
+//! FUNCTION. Procedures are found when the program is loaded, along with the variables
+//! each one keeps to itself, so that the interpreter and the compiler agree on which names
+//! are local: the parameters, a FUNCTION's own name (which holds its result), the names on
+//! LOCAL statements, and every variable the procedure assigns unless a SHARED statement
+//! names it. Any other variable, and every array, is the program's.
+
+use std::collections::{BTreeMap, BTreeSet};
+use crate::basic_types::{BasicError, ExpressionType, Program, ResumeTarget, Statement};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcedureKind {
+    Sub,
+    Function,
+}
+
+impl ProcedureKind {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ProcedureKind::Sub => "SUB",
+            ProcedureKind::Function => "FUNCTION",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Procedure {
+    pub kind: ProcedureKind,
+    pub name: String,
+    pub params: Vec<String>,
+    pub start: (usize, usize),      // The SUB or FUNCTION statement, first on its line
+    pub end: (usize, usize),        // The END SUB or END FUNCTION, last on its line
+    pub locals: BTreeSet<String>,   // Variables of each call, the parameters among them
+    pub shared: BTreeSet<String>,   // Program variables the procedure assigns
+}
+
+impl Procedure {
+    /// True if the statement at `location` is part of the procedure
+    pub fn contains(&self, location: (usize, usize)) -> bool {
+        self.start <= location && location <= self.end
+    }
+
+    /// A FUNCTION whose name ends in `$` returns a string
+    pub fn returns_string(&self) -> bool {
+        self.kind == ProcedureKind::Function && self.name.ends_with('$')
+    }
+}
+
+// A procedure that has not reached its END yet
+struct OpenProcedure {
+    procedure: Procedure,
+    declared: BTreeSet<String>,  // Parameters, the FUNCTION's result and LOCAL names
+    assigned: BTreeSet<String>,  // Variables the body assigns
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Procedures {
+    procedures: BTreeMap<String, Procedure>,
+}
+
+impl Procedures {
+    /// Finds every SUB and FUNCTION in one pass over the program, then checks the CALLs
+    /// and jumps against them. A procedure inside another, inside a block or loop, or
+    /// without its END; an END, EXIT, LOCAL or SHARED outside one; a RESUME inside one; a
+    /// CALL that does not match a SUB; or a jump into or out of a procedure, is a syntax
+    /// error. Control can then only enter a procedure by a call and leave it by its END or
+    /// EXIT.
+    pub fn resolve(program: &Program) -> Result<Self, BasicError> {
+        let mut procedures = BTreeMap::new();
+        let mut open: Option<OpenProcedure> = None;
+        let mut open_blocks = 0usize; // Block IFs, SELECT CASEs, WHILEs and DOs not yet closed
+
+        for (i, line) in program.lines.iter().enumerate() {
+            let error = |message: String| BasicError::Syntax {
+                message,
+                basic_line_number: Some(line.line_number),
+                file_line_number: None,
+            };
+            for (offset, stmt) in line.statements.iter().enumerate() {
+                let here = (i, offset);
+                match stmt {
+                    Statement::IfBlock { .. } | Statement::SelectCase { .. } | Statement::While { .. } | Statement::Do { .. } => open_blocks += 1,
+                    Statement::EndIf | Statement::EndSelect | Statement::Wend | Statement::Loop { .. } => open_blocks = open_blocks.saturating_sub(1),
+                    _ => {}
+                }
+                match stmt {
+                    Statement::Sub { name, params } | Statement::Function { name, params } => {
+                        let kind = if matches!(stmt, Statement::Sub { .. }) { ProcedureKind::Sub } else { ProcedureKind::Function };
+                        if let Some(outer) = &open {
+                            return Err(error(format!("{} inside {} {}", kind.keyword(), outer.procedure.kind.keyword(), outer.procedure.name)));
+                        }
+                        if offset != 0 {
+                            return Err(error(format!("{} must start its line", kind.keyword())));
+                        }
+                        if open_blocks != 0 {
+                            return Err(error(format!("{} inside a block or loop", kind.keyword())));
+                        }
+                        if procedures.contains_key(name) {
+                            return Err(error(format!("{} is already defined", name)));
+                        }
+                        let mut declared: BTreeSet<String> = params.iter().cloned().collect();
+                        if kind == ProcedureKind::Function {
+                            declared.insert(name.clone());
+                        }
+                        open = Some(OpenProcedure {
+                            procedure: Procedure {
+                                kind,
+                                name: name.clone(),
+                                params: params.clone(),
+                                start: here,
+                                end: here,
+                                locals: BTreeSet::new(),
+                                shared: BTreeSet::new(),
+                            },
+                            declared,
+                            assigned: BTreeSet::new(),
+                        });
+                    }
+                    Statement::EndSub | Statement::EndFunction => {
+                        let kind = if matches!(stmt, Statement::EndSub) { ProcedureKind::Sub } else { ProcedureKind::Function };
+                        let Some(OpenProcedure { mut procedure, declared, assigned }) = open.take().filter(|p| p.procedure.kind == kind) else {
+                            return Err(error(format!("END {} without {}", kind.keyword(), kind.keyword())));
+                        };
+                        if offset + 1 != line.statements.len() {
+                            return Err(error(format!("END {} must end its line", kind.keyword())));
+                        }
+                        // A false IF would continue on the next line, past the END
+                        if line.statements[..offset].iter().any(|s| matches!(s, Statement::If { .. })) {
+                            return Err(error(format!("END {} cannot follow IF on its line", kind.keyword())));
+                        }
+                        if open_blocks != 0 {
+                            return Err(error(format!("END {} inside a block or loop", kind.keyword())));
+                        }
+                        procedure.end = here;
+                        procedure.locals = assigned.difference(&procedure.shared).cloned().collect();
+                        procedure.locals.extend(declared);
+                        procedures.insert(procedure.name.clone(), procedure);
+                    }
+                    Statement::ExitSub | Statement::ExitFunction => {
+                        let kind = if matches!(stmt, Statement::ExitSub) { ProcedureKind::Sub } else { ProcedureKind::Function };
+                        if !open.as_ref().is_some_and(|p| p.procedure.kind == kind) {
+                            return Err(error(format!("EXIT {} outside {}", kind.keyword(), kind.keyword())));
+                        }
+                    }
+                    Statement::Local { vars } | Statement::Shared { vars } => {
+                        let is_local = matches!(stmt, Statement::Local { .. });
+                        let keyword = if is_local { "LOCAL" } else { "SHARED" };
+                        let Some(p) = open.as_mut() else {
+                            return Err(error(format!("{} outside SUB or FUNCTION", keyword)));
+                        };
+                        for var in vars {
+                            let conflict = if is_local { p.procedure.shared.contains(var) } else { p.declared.contains(var) };
+                            if conflict {
+                                return Err(error(format!("{} is both local and SHARED in {} {}", var, p.procedure.kind.keyword(), p.procedure.name)));
+                            }
+                            if is_local {
+                                p.declared.insert(var.clone());
+                            } else {
+                                p.procedure.shared.insert(var.clone());
+                            }
+                        }
+                    }
+                    Statement::Resume { .. } if open.is_some() => {
+                        let p = &open.as_ref().unwrap().procedure;
+                        return Err(error(format!("RESUME inside {} {}", p.kind.keyword(), p.name)));
+                    }
+                    _ => {
+                        if let Some(p) = open.as_mut() {
+                            Self::collect_assigned(stmt, &mut p.assigned);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(p) = open {
+            let kind = p.procedure.kind.keyword();
+            return Err(BasicError::Syntax {
+                message: format!("{} without END {}", kind, kind),
+                basic_line_number: Some(program.lines[p.procedure.start.0].line_number),
+                file_line_number: None,
+            });
+        }
+        let procedures = Procedures { procedures };
+        procedures.check_calls_and_jumps(program)?;
+        Ok(procedures)
+    }
+
+    /// The scalar variables a statement stores into
+    fn collect_assigned(stmt: &Statement, assigned: &mut BTreeSet<String>) {
+        match stmt {
+            Statement::Let { var, .. } | Statement::MidAssign { var, .. } => {
+                if let ExpressionType::Variable(name) = &var.expr_type {
+                    assigned.insert(name.clone());
+                }
+            }
+            Statement::For { var, .. } => {
+                assigned.insert(var.clone());
+            }
+            Statement::Input { vars, .. } | Statement::InputFile { vars, .. } => assigned.extend(vars.iter().cloned()),
+            Statement::LineInputFile { var, .. } | Statement::Lset { var, .. } | Statement::Rset { var, .. } => {
+                assigned.insert(var.clone());
+            }
+            Statement::Field { fields, .. } => assigned.extend(fields.iter().map(|(_, var)| var.clone())),
+            Statement::Read { vars } => {
+                for var in vars {
+                    if let ExpressionType::Variable(name) = &var.expr_type {
+                        assigned.insert(name.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_calls_and_jumps(&self, program: &Program) -> Result<(), BasicError> {
+        for (i, line) in program.lines.iter().enumerate() {
+            let error = |message: String| BasicError::Syntax {
+                message,
+                basic_line_number: Some(line.line_number),
+                file_line_number: None,
+            };
+            for (offset, stmt) in line.statements.iter().enumerate() {
+                let mut owner = self.containing((i, offset));
+                let targets: Vec<usize> = match stmt {
+                    Statement::Call { name, args } => {
+                        match self.get(name) {
+                            Some(p) if p.kind == ProcedureKind::Sub => {
+                                if p.params.len() != args.len() {
+                                    return Err(error(format!("SUB {} expects {} arguments, got {}", name, p.params.len(), args.len())));
+                                }
+                            }
+                            Some(_) => return Err(error(format!("{} is a FUNCTION, not a SUB", name))),
+                            None => return Err(error(format!("Undefined SUB {}", name))),
+                        }
+                        continue;
+                    }
+                    Statement::Goto { line } | Statement::Gosub { line } => vec![*line],
+                    Statement::OnGoto { line_numbers, .. } | Statement::OnGosub { line_numbers, .. } => line_numbers.clone(),
+                    Statement::Resume { target: ResumeTarget::Line(line) } => vec![*line],
+                    Statement::OnErrorGoto { line } if *line != 0 => {
+                        // The handler runs as part of the program, wherever the error happens
+                        owner = None;
+                        vec![*line]
+                    }
+                    _ => continue,
+                };
+                for target in targets {
+                    // A line that does not exist is reported when the jump is made
+                    let Ok(index) = program.lines.binary_search_by_key(&target, |l| l.line_number) else {
+                        continue;
+                    };
+                    let target_owner = self.containing((index, 0));
+                    let same = match (owner, target_owner) {
+                        (Some(a), Some(b)) => a.name == b.name,
+                        (None, None) => true,
+                        _ => false,
+                    };
+                    if !same {
+                        let message = match (target_owner, owner) {
+                            (Some(p), _) => format!("Cannot jump to line {} inside {} {}", target, p.kind.keyword(), p.name),
+                            (None, Some(p)) => format!("Cannot jump to line {} outside {} {}", target, p.kind.keyword(), p.name),
+                            (None, None) => unreachable!(),
+                        };
+                        return Err(error(message));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Procedure> {
+        self.procedures.get(name)
+    }
+
+    /// The procedure the statement at `location` is part of, if any
+    pub fn containing(&self, location: (usize, usize)) -> Option<&Procedure> {
+        self.procedures.values().find(|p| p.contains(location))
+    }
+
+    /// True if both statements are in the same procedure, or both outside any
+    pub fn same_owner(&self, a: (usize, usize), b: (usize, usize)) -> bool {
+        self.containing(a).map(|p| &p.name) == self.containing(b).map(|p| &p.name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Procedure> {
+        self.procedures.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.procedures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_lexer::Lexer;
+    use crate::basic_parser::Parser;
+
+    fn parse_lines(source: &str) -> Program {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse_lines().unwrap()
+    }
+
+    #[test]
+    fn test_procedure_extents_and_locals() {
+        let program = parse_lines("10 CALL SHOW(1)\n20 END\n30 SUB SHOW(N)\n40 SHARED T\n50 T = T + N: C = 1\n60 PRINT T, Q\n70 END SUB\n\
+                                   80 FUNCTION TWICE$(A$)\n90 LOCAL K\n100 TWICE$ = A$ + A$\n110 END FUNCTION");
+        let procedures = Procedures::resolve(&program).unwrap();
+
+        let show = procedures.get("SHOW").unwrap();
+        assert_eq!(show.kind, ProcedureKind::Sub);
+        assert_eq!((show.start, show.end), ((2, 0), (6, 0)));
+        assert_eq!(show.locals.iter().collect::<Vec<_>>(), vec!["C", "N"]);
+        assert_eq!(show.shared.iter().collect::<Vec<_>>(), vec!["T"]);
+        assert!(!show.returns_string());
+
+        let twice = procedures.get("TWICE$").unwrap();
+        assert_eq!(twice.locals.iter().collect::<Vec<_>>(), vec!["A$", "K", "TWICE$"]);
+        assert!(twice.returns_string());
+
+        assert_eq!(procedures.containing((4, 1)).map(|p| p.name.as_str()), Some("SHOW"));
+        assert!(procedures.containing((1, 0)).is_none());
+    }
+
+    #[test]
+    fn test_misplaced_procedure_statements() {
+        let cases = [
+            ("10 SUB A\n20 PRINT 1", "SUB without END SUB", 10),
+            ("10 SUB A\n20 FUNCTION B\n30 END FUNCTION\n40 END SUB", "FUNCTION inside SUB A", 20),
+            ("10 END SUB", "END SUB without SUB", 10),
+            ("10 FUNCTION F\n20 END SUB", "END SUB without SUB", 20),
+            ("10 PRINT 1: SUB A\n20 END SUB", "SUB must start its line", 10),
+            ("10 SUB A\n20 END SUB: PRINT 1", "END SUB must end its line", 20),
+            ("10 EXIT SUB", "EXIT SUB outside SUB", 10),
+            ("10 LOCAL X", "LOCAL outside SUB or FUNCTION", 10),
+            ("10 SUB A(X)\n20 SHARED X\n30 END SUB", "X is both local and SHARED in SUB A", 20),
+            ("10 SUB A\n20 END SUB\n30 SUB A\n40 END SUB", "A is already defined", 30),
+            ("10 CALL B", "Undefined SUB B", 10),
+            ("10 CALL A(1)\n20 SUB A\n30 END SUB", "SUB A expects 0 arguments, got 1", 10),
+            ("10 GOTO 30\n20 SUB A\n30 PRINT 1\n40 END SUB", "Cannot jump to line 30 inside SUB A", 10),
+            ("10 PRINT 1\n20 SUB A\n30 GOSUB 10\n40 END SUB", "Cannot jump to line 10 outside SUB A", 30),
+            ("10 WHILE X\n20 SUB A\n30 END SUB\n40 WEND", "SUB inside a block or loop", 20),
+            ("10 SUB A\n20 DO\n30 END SUB\n40 LOOP", "END SUB inside a block or loop", 30),
+            ("10 SUB A\n20 IF X THEN PRINT 1: END SUB", "END SUB cannot follow IF on its line", 20),
+            ("10 SUB A\n20 RESUME NEXT\n30 END SUB", "RESUME inside SUB A", 20),
+            ("10 SUB A\n20 ON ERROR GOTO 30\n30 PRINT 1\n40 END SUB", "Cannot jump to line 30 inside SUB A", 20),
+        ];
+        for (source, message, line) in cases {
+            let err = Procedures::resolve(&parse_lines(source)).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", source, err);
+            assert!(matches!(err, BasicError::Syntax { basic_line_number: Some(n), .. } if n == line), "{}: {}", source, err);
+        }
+    }
+}
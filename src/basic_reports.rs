@@ -107,9 +107,9 @@ pub fn generate_html_coverage_report(coverage: &CoverageData, program: &Program,
     // Generate program listing HTML
     let mut program_listing = String::new();
     for line in &program.lines {
-        program_listing.push_str(&format!("<div class=\"program-line\">\n"));
+        program_listing.push_str("<div class=\"program-line\">\n");
         program_listing.push_str(&format!("    <div class=\"line-number\">{}</div>\n", line.line_number));
-        program_listing.push_str(&format!("    <div class=\"line-code\">"));
+        program_listing.push_str("    <div class=\"line-code\">");
         
         for (i, stmt) in line.statements.iter().enumerate() {
             let is_covered = coverage.get(&line.line_number)
@@ -458,7 +458,7 @@ fn html_escape(s: &str) -> String {
 /// Save coverage data to a JSON file
 pub fn save_coverage_to_file(coverage: &CoverageData, filename: &str) -> std::io::Result<()> {
     let json = serde_json::to_string_pretty(coverage)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
     fs::write(filename, json)
 }
 
@@ -473,7 +473,7 @@ pub fn load_coverage_from_file(filename: &str) -> std::io::Result<CoverageData>
 pub fn merge_coverage(mut existing: CoverageData, new: CoverageData) -> CoverageData {
     for (line_num, new_stmts) in new {
         existing.entry(line_num)
-            .or_insert_with(std::collections::HashSet::new)
+            .or_default()
             .extend(new_stmts);
     }
     existing
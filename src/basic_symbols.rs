@@ -1,45 +1,55 @@
 use std::collections::HashMap;
-use crate::basic_dialect::ARRAY_OFFSET;
-use crate::basic_types::{BasicError, Expression, SymbolValue, ArrayElementType, ArrayData};
+use crate::basic_dialect::Dialect;
+use crate::basic_types::{BasicError, Expression, SymbolValue, ArrayElementType, ArrayData, VarType, ErrorCondition};
+use basic_runtime::limits;
+use basic_runtime::matrix::{Matrix, MatrixError};
+use basic_runtime::precision::{self, Precision};
 
 #[derive(Clone)]
 pub struct SymbolTable {
     symbols: HashMap<String, SymbolValue>,
     parent: Option<Box<SymbolTable>>,
+    dialect: Dialect,
 }
 
-pub fn adjust(coord: usize) -> usize {
-    return coord - ARRAY_OFFSET;
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SymbolTable {
-    /// Validates array indices against ARRAY_OFFSET and dimension bounds, returning adjusted indices
+    /// Validates array indices against the dialect's array offset and dimension bounds, returning adjusted indices
     fn validate_and_adjust_indices(&self, name: &str, indices: &[usize], dimensions: &[usize]) -> Result<Vec<usize>, BasicError> {
+        let offset = self.dialect.array_offset;
         // Check dimension count
         if indices.len() != dimensions.len() {
             return Err(BasicError::Runtime {
                 message: format!("Array '{}' expects {} indices, got {}", name, dimensions.len(), indices.len()),
+                condition: ErrorCondition::SubscriptOutOfRange,
                 basic_line_number: None,
                 file_line_number: None,
             });
         }
         
-        // Check ARRAY_OFFSET bounds and adjust
+        // Check array offset bounds and adjust
         let mut adjusted = Vec::new();
         for (i, (&index, &dim_size)) in indices.iter().zip(dimensions.iter()).enumerate() {
-            if index < ARRAY_OFFSET {
+            if index < offset {
                 return Err(BasicError::Runtime {
                     message: format!("Array index {} out of bounds for '{}' dimension {}. Valid range: {} to {}", 
-                        index, name, i, ARRAY_OFFSET, dim_size - 1 + ARRAY_OFFSET),
+                        index, name, i, offset, dim_size - 1 + offset),
+                    condition: ErrorCondition::SubscriptOutOfRange,
                     basic_line_number: None,
                     file_line_number: None,
                 });
             }
-            let adjusted_index = index - ARRAY_OFFSET;
+            let adjusted_index = index - offset;
             if adjusted_index >= dim_size {
                 return Err(BasicError::Runtime {
                     message: format!("Array index {} out of bounds for '{}' dimension {}. Valid range: {} to {}", 
-                        index, name, i, ARRAY_OFFSET, dim_size - 1 + ARRAY_OFFSET),
+                        index, name, i, offset, dim_size - 1 + offset),
+                    condition: ErrorCondition::SubscriptOutOfRange,
                     basic_line_number: None,
                     file_line_number: None,
                 });
@@ -65,10 +75,12 @@ impl SymbolTable {
     }
 
     pub fn get_array_element(&self, name: &str, indices: &[usize]) -> Result<SymbolValue, BasicError> {
+        let offset = self.dialect.array_offset;
         // Arrays are stored with [] suffix to separate from scalar variables
         let array_key = format!("{}[]", name);
         let symbol = self.get_symbol(&array_key).ok_or(BasicError::Runtime {
             message: format!("Array '{}' not found", name),
+            condition: ErrorCondition::SubscriptOutOfRange,
             basic_line_number: None,
             file_line_number: None,
         })?;
@@ -88,6 +100,7 @@ impl SymbolTable {
                     }
                     _ => Err(BasicError::Runtime {
                         message: format!("Array '{}' has mismatched element type and data", name),
+                        condition: ErrorCondition::Unprintable,
                         basic_line_number: None,
                         file_line_number: None,
                     }),
@@ -99,21 +112,24 @@ impl SymbolTable {
                 if indices.len() != 1 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 1 index", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET {
+                if indices[0] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let index = adjust(indices[0]);
+                let index = indices[0] - offset;
                 if index >= vec.len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -125,23 +141,26 @@ impl SymbolTable {
                 if indices.len() != 2 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 2 indices", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET || indices[1] < ARRAY_OFFSET {
+                if indices[0] < offset || indices[1] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let row = adjust(indices[0]);
-                let col = adjust(indices[1]);
+                let row = indices[0] - offset;
+                let col = indices[1] - offset;
 
                 if row >= vec.len() || col >= vec[row].len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -153,21 +172,24 @@ impl SymbolTable {
                 if indices.len() != 1 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 1 index", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET {
+                if indices[0] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let index = adjust(indices[0]);
+                let index = indices[0] - offset;
                 if index >= vec.len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -179,22 +201,25 @@ impl SymbolTable {
                 if indices.len() != 2 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 2 indices", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET || indices[1] < ARRAY_OFFSET {
+                if indices[0] < offset || indices[1] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let row = adjust(indices[0]);
-                let col = adjust(indices[1]);
+                let row = indices[0] - offset;
+                let col = indices[1] - offset;
                 if row >= vec.len() || col >= vec[row].len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -204,6 +229,7 @@ impl SymbolTable {
 
             _ => Err(BasicError::Runtime {
                 message: format!("'{}' is not an array", name),
+                condition: ErrorCondition::Unprintable,
                 basic_line_number: None,
                 file_line_number: None,
             }),
@@ -211,6 +237,8 @@ impl SymbolTable {
     }
 
     pub fn set_array_element(&mut self, name: &str, indices: &[usize], value: SymbolValue) -> Result<(), BasicError> {
+        let offset = self.dialect.array_offset;
+        let value = self.typed_value(name, value)?;
         // Arrays are stored with [] suffix to separate from scalar variables
         let array_key = format!("{}[]", name);
         
@@ -218,6 +246,7 @@ impl SymbolTable {
         let (_adjusted_indices, flat_index) = {
             let symbol = self.symbols.get(&array_key).ok_or(BasicError::Runtime {
                 message: format!("Array '{}' not found", name),
+                condition: ErrorCondition::SubscriptOutOfRange,
                 basic_line_number: None,
                 file_line_number: None,
             })?;
@@ -238,6 +267,7 @@ impl SymbolTable {
         // Now get mutable access to update the array
         let symbol = self.symbols.get_mut(&array_key).ok_or(BasicError::Runtime {
             message: format!("Array '{}' not found", name),
+            condition: ErrorCondition::SubscriptOutOfRange,
             basic_line_number: None,
             file_line_number: None,
         })?;
@@ -257,6 +287,7 @@ impl SymbolTable {
                     (ArrayElementType::Number, _, _) => {
                         Err(BasicError::Runtime {
                             message: "Type mismatch: expected number for numeric array".to_string(),
+                            condition: ErrorCondition::TypeMismatch,
                             basic_line_number: None,
                             file_line_number: None,
                         })
@@ -264,6 +295,7 @@ impl SymbolTable {
                     (ArrayElementType::String, _, _) => {
                         Err(BasicError::Runtime {
                             message: "Type mismatch: expected string for string array".to_string(),
+                            condition: ErrorCondition::TypeMismatch,
                             basic_line_number: None,
                             file_line_number: None,
                         })
@@ -276,21 +308,24 @@ impl SymbolTable {
                 if indices.len() != 1 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 1 index", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET {
+                if indices[0] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let index = adjust(indices[0]);
+                let index = indices[0] - offset;
                 if index >= vec.len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -301,6 +336,7 @@ impl SymbolTable {
                 } else {
                     Err(BasicError::Runtime {
                         message: "Type mismatch: expected number".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: None,
                         file_line_number: None,
                     })
@@ -311,22 +347,25 @@ impl SymbolTable {
                 if indices.len() != 2 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 2 indices", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET || indices[1] < ARRAY_OFFSET {
+                if indices[0] < offset || indices[1] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let row = adjust(indices[0]);
-                let col = adjust(indices[1]);
+                let row = indices[0] - offset;
+                let col = indices[1] - offset;
                 if row >= vec.len() || col >= vec[row].len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -337,6 +376,7 @@ impl SymbolTable {
                 } else {
                     Err(BasicError::Runtime {
                         message: "Type mismatch: expected number".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: None,
                         file_line_number: None,
                     })
@@ -347,21 +387,24 @@ impl SymbolTable {
                 if indices.len() != 1 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 1 index", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET {
+                if indices[0] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let index = adjust(indices[0]);
+                let index = indices[0] - offset;
                 if index >= vec.len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index {} out of bounds for '{}'. Valid range: {} to {}", indices[0], name, offset, vec.len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -372,6 +415,7 @@ impl SymbolTable {
                 } else {
                     Err(BasicError::Runtime {
                         message: "Type mismatch: expected string".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: None,
                         file_line_number: None,
                     })
@@ -382,22 +426,25 @@ impl SymbolTable {
                 if indices.len() != 2 {
                     return Err(BasicError::Runtime {
                         message: format!("Array '{}' expects 2 indices", name),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                if indices[0] < ARRAY_OFFSET || indices[1] < ARRAY_OFFSET {
+                if indices[0] < offset || indices[1] < offset {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
                 }
-                let row = adjust(indices[0]);
-                let col = adjust(indices[1]);
+                let row = indices[0] - offset;
+                let col = indices[1] - offset;
                 if row >= vec.len() || col >= vec[row].len() {
                     return Err(BasicError::Runtime {
-                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, ARRAY_OFFSET, vec.len() - 1 + ARRAY_OFFSET, ARRAY_OFFSET, vec[0].len() - 1 + ARRAY_OFFSET),
+                        message: format!("Array index ({}, {}) out of bounds for '{}'. Valid row range: {}-{}, col range: {}-{}", indices[0], indices[1], name, offset, vec.len() - 1 + offset, offset, vec[0].len() - 1 + offset),
+                        condition: ErrorCondition::SubscriptOutOfRange,
                         basic_line_number: None,
                         file_line_number: None,
                     });
@@ -408,6 +455,7 @@ impl SymbolTable {
                 } else {
                     Err(BasicError::Runtime {
                         message: "Type mismatch: expected string".to_string(),
+                        condition: ErrorCondition::TypeMismatch,
                         basic_line_number: None,
                         file_line_number: None,
                     })
@@ -416,22 +464,96 @@ impl SymbolTable {
 
             _ => Err(BasicError::Runtime {
                 message: format!("'{}' is not an array", name),
+                condition: ErrorCondition::Unprintable,
                 basic_line_number: None,
                 file_line_number: None,
             }),
         }
     }
 
+    /// The numeric array `name` as MAT sees it
+    pub fn get_matrix(&self, name: &str) -> Result<Matrix, BasicError> {
+        match self.symbols.get(&format!("{}[]", name)) {
+            Some(SymbolValue::Array { dimensions, data: ArrayData::Numbers(data), .. }) => {
+                Matrix::from_array(name, data, dimensions, self.dialect.array_offset).map_err(Self::matrix_error)
+            }
+            Some(_) => Err(BasicError::Runtime {
+                message: format!("'{}' is not a numeric array", name),
+                condition: ErrorCondition::Unprintable,
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+            None => Err(BasicError::Runtime {
+                message: format!("Array '{}' not found", name),
+                condition: ErrorCondition::SubscriptOutOfRange,
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        }
+    }
+
+    /// Stores a MAT result in the numeric array `name`, converted to the array's type. The
+    /// array takes the result's dimensions, if it has room for them.
+    pub fn set_matrix(&mut self, name: &str, mut matrix: Matrix) -> Result<(), BasicError> {
+        for value in matrix.values.iter_mut() {
+            if let SymbolValue::Number(n) = self.typed_value(name, SymbolValue::Number(*value))? {
+                *value = n;
+            }
+        }
+        let offset = self.dialect.array_offset;
+        match self.symbols.get_mut(&format!("{}[]", name)) {
+            Some(SymbolValue::Array { dimensions, data: ArrayData::Numbers(data), .. }) => {
+                matrix.store(name, data, dimensions, offset).map_err(Self::matrix_error)
+            }
+            Some(_) => Err(BasicError::Runtime {
+                message: format!("'{}' is not a numeric array", name),
+                condition: ErrorCondition::Unprintable,
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+            None => Err(BasicError::Runtime {
+                message: format!("Array '{}' not found", name),
+                condition: ErrorCondition::SubscriptOutOfRange,
+                basic_line_number: None,
+                file_line_number: None,
+            }),
+        }
+    }
+
+    fn matrix_error(error: MatrixError) -> BasicError {
+        BasicError::Runtime {
+            message: error.message,
+            condition: error.kind.into(),
+            basic_line_number: None,
+            file_line_number: None,
+        }
+    }
+
     pub fn create_array(&mut self, name: String, dimensions: Vec<usize>) -> Result<(), BasicError> {
         // Arrays are stored with [] suffix to separate from scalar variables
         let array_key = format!("{}[]", name);
         if self.symbols.contains_key(&array_key) {
             return Err(BasicError::Runtime {
                 message: format!("Array '{}' already declared", name),
+                condition: ErrorCondition::DuplicateDefinition,
+                basic_line_number: None,
+                file_line_number: None,
+            });
+        }
+
+        if dimensions.len() > self.dialect.max_array_dims {
+            return Err(BasicError::Runtime {
+                message: limits::array_dims_message(&name, dimensions.len(), self.dialect.max_array_dims),
+                condition: ErrorCondition::IllegalFunctionCall,
                 basic_line_number: None,
                 file_line_number: None,
             });
         }
+        self.check_variable_limit(&array_key)?;
+
+        // DIM gives the highest subscript; store the number of elements in each dimension
+        let offset = self.dialect.array_offset;
+        let dimensions: Vec<usize> = dimensions.iter().map(|&bound| (bound + 1).saturating_sub(offset)).collect();
 
         let is_string = name.ends_with('$');
         let total_elements: usize = dimensions.iter().product();
@@ -458,6 +580,7 @@ impl SymbolTable {
         if self.symbols.contains_key(&name) {
             return Err(BasicError::Runtime {
                 message: format!("Function '{}' already defined", name),
+                condition: ErrorCondition::DuplicateDefinition,
                 basic_line_number: None,
                 file_line_number: None,
             });
@@ -468,9 +591,14 @@ impl SymbolTable {
     }
 
     pub fn new() -> Self {
+        Self::with_dialect(Dialect::default())
+    }
+
+    pub fn with_dialect(dialect: Dialect) -> Self {
         SymbolTable {
             symbols: HashMap::new(),
             parent: None,
+            dialect,
         }
     }
 
@@ -478,9 +606,37 @@ impl SymbolTable {
         SymbolTable {
             symbols: HashMap::new(),
             parent: Some(Box::new(self.clone())),
+            dialect: self.dialect,
         }
     }
 
+    /// Like `get_nested_scope`, but moves this table into the new scope instead of copying it
+    pub fn into_nested_scope(self) -> Self {
+        let dialect = self.dialect;
+        SymbolTable {
+            symbols: HashMap::new(),
+            parent: Some(Box::new(self)),
+            dialect,
+        }
+    }
+
+    /// Detaches the parent scope, leaving this table on its own
+    pub fn take_parent(&mut self) -> Option<Self> {
+        self.parent.take().map(|parent| *parent)
+    }
+
+    pub fn set_parent(&mut self, parent: Self) {
+        self.parent = Some(Box::new(parent));
+    }
+
+    pub fn parent_mut(&mut self) -> Option<&mut Self> {
+        self.parent.as_deref_mut()
+    }
+
+    pub fn get_dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
     pub fn get_symbol(&self, name: &str) -> Option<&SymbolValue> {
         if let Some(value) = self.symbols.get(name) {
             Some(value)
@@ -491,8 +647,49 @@ impl SymbolTable {
         }
     }
 
-    pub fn put_symbol(&mut self, name: String, value: SymbolValue) {
+    /// Fails if storing `key` would add a variable beyond the dialect's limit.
+    /// Updating an existing variable is always allowed.
+    pub fn check_variable_limit(&self, key: &str) -> Result<(), BasicError> {
+        if self.symbols.contains_key(key) || self.symbols.len() < self.dialect.max_variables {
+            return Ok(());
+        }
+        Err(BasicError::Runtime {
+            message: limits::variables_message(self.dialect.max_variables),
+            condition: ErrorCondition::OutOfMemory,
+            basic_line_number: None,
+            file_line_number: None,
+        })
+    }
+
+    /// Stores a variable, with a number converted to the variable's type
+    pub fn put_symbol(&mut self, name: String, value: SymbolValue) -> Result<(), BasicError> {
+        let value = self.typed_value(&name, value)?;
         self.symbols.insert(name, value);
+        Ok(())
+    }
+
+    /// A number as variable `name` holds it: a whole number for `A%`, single precision for
+    /// `A!`, double for `A#`, and the dialect's precision without a suffix. An integer out
+    /// of the dialect's range is an Overflow.
+    pub fn typed_value(&self, name: &str, value: SymbolValue) -> Result<SymbolValue, BasicError> {
+        let SymbolValue::Number(n) = value else {
+            return Ok(value);
+        };
+        let n = match VarType::of(name) {
+            Some(VarType::Integer) => {
+                let bits = self.dialect.integer_bits;
+                precision::round_integer(n, bits).ok_or_else(|| BasicError::Runtime {
+                    message: precision::integer_overflow_message(name, bits),
+                    condition: ErrorCondition::Overflow,
+                    basic_line_number: None,
+                    file_line_number: None,
+                })?
+            }
+            Some(VarType::Single) => Precision::Single.round(n),
+            Some(VarType::Double) => n,
+            _ => self.dialect.precision.round(n),
+        };
+        Ok(SymbolValue::Number(n))
     }
 
     pub fn dump(&self) -> HashMap<String, SymbolValue> {
@@ -502,9 +699,7 @@ impl SymbolTable {
         }
         if let Some(parent) = &self.parent {
             for (name, value) in parent.dump() {
-                if !result.contains_key(&name) {
-                    result.insert(name, value);
-                }
+                result.entry(name).or_insert(value);
             }
         }
         result
@@ -519,10 +714,10 @@ mod tests {
     #[test]
     fn test_basic_symbols() {
         let mut table = SymbolTable::new();
-        table.put_symbol("A".to_string(), SymbolValue::Number(1.0));
+        table.put_symbol("A".to_string(), SymbolValue::Number(1.0)).unwrap();
         let val = table.get_symbol("A").unwrap();
         assert_eq!(SymbolValue::Number(1.0), *val);
-        table.put_symbol("B".to_string(), SymbolValue::Number(99.0));
+        table.put_symbol("B".to_string(), SymbolValue::Number(99.0)).unwrap();
 
         let dump = table.dump();
         assert_eq!(dump.len(), 2);
@@ -578,11 +773,45 @@ mod tests {
     fn test_get_array_element_invalid_low_index() {
         let mut table = SymbolTable::new();
         table.create_array("A".to_string(), vec![2]).unwrap();
-        // Assumes ARRAY_OFFSET is 1, index 0 is invalid
+        // The default dialect is one-based, index 0 is invalid
         let result = table.get_array_element("A", &[0]);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_zero_based_dialect() {
+        let mut table = SymbolTable::with_dialect(Dialect::GWBASIC);
+        table.create_array("A".to_string(), vec![2]).unwrap();
+        // DIM A(2) holds A(0), A(1) and A(2)
+        table.set_array_element("A", &[0], SymbolValue::Number(1.0)).unwrap();
+        table.set_array_element("A", &[2], SymbolValue::Number(3.0)).unwrap();
+        assert_eq!(table.get_array_element("A", &[0]).unwrap(), SymbolValue::Number(1.0));
+        assert_eq!(table.get_array_element("A", &[2]).unwrap(), SymbolValue::Number(3.0));
+        assert!(table.get_array_element("A", &[3]).is_err());
+        assert_eq!(table.get_nested_scope().get_dialect(), &Dialect::GWBASIC);
+    }
+
+    #[test]
+    fn test_array_dimension_limit() {
+        let mut table = SymbolTable::with_dialect(Dialect::ANSI_MINIMAL);
+        table.create_array("A".to_string(), vec![2, 2]).unwrap();
+        let err = table.create_array("B".to_string(), vec![2, 2, 2]).unwrap_err();
+        assert!(err.to_string().contains("Array 'B' has 3 dimensions, the limit is 2"));
+    }
+
+    #[test]
+    fn test_variable_limit() {
+        let dialect = Dialect { max_variables: 2, ..Dialect::default() };
+        let mut table = SymbolTable::with_dialect(dialect);
+        table.put_symbol("A".to_string(), SymbolValue::Number(1.0)).unwrap();
+        table.create_array("B".to_string(), vec![3]).unwrap();
+        // Existing variables can still be updated
+        assert!(table.check_variable_limit("A").is_ok());
+        let err = table.check_variable_limit("C").unwrap_err();
+        assert!(err.to_string().contains("OUT OF MEMORY"));
+        assert!(table.create_array("D".to_string(), vec![3]).is_err());
+    }
+
     #[test]
     fn test_define_function() {
         let mut table = SymbolTable::new();
@@ -602,18 +831,33 @@ mod tests {
     #[test]
     fn test_nested_scope_lookup() {
         let mut root = SymbolTable::new();
-        root.put_symbol("X".to_string(), SymbolValue::Number(5.0));
+        root.put_symbol("X".to_string(), SymbolValue::Number(5.0)).unwrap();
         let nested = root.get_nested_scope();
         assert_eq!(nested.get_symbol("X"), Some(&SymbolValue::Number(5.0)));
     }
 
+    #[test]
+    fn test_moved_scope_parent() {
+        let mut root = SymbolTable::new();
+        root.put_symbol("X".to_string(), SymbolValue::Number(5.0)).unwrap();
+        let mut nested = root.into_nested_scope();
+        nested.parent_mut().unwrap().put_symbol("Y".to_string(), SymbolValue::Number(6.0)).unwrap();
+        assert_eq!(nested.get_symbol("Y"), Some(&SymbolValue::Number(6.0)));
+
+        let root = nested.take_parent().unwrap();
+        assert_eq!(root.get_symbol("X"), Some(&SymbolValue::Number(5.0)));
+        assert_eq!(nested.get_symbol("X"), None);
+        nested.set_parent(root);
+        assert_eq!(nested.get_symbol("X"), Some(&SymbolValue::Number(5.0)));
+    }
+
     #[test]
     fn test_dump_merges_with_parent() {
         let mut parent = SymbolTable::new();
-        parent.put_symbol("A".to_string(), SymbolValue::Number(1.0));
+        parent.put_symbol("A".to_string(), SymbolValue::Number(1.0)).unwrap();
 
         let mut child = parent.get_nested_scope();
-        child.put_symbol("B".to_string(), SymbolValue::Number(2.0));
+        child.put_symbol("B".to_string(), SymbolValue::Number(2.0)).unwrap();
 
         let dump = child.dump();
         assert_eq!(dump.len(), 2);
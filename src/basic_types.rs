@@ -1,5 +1,8 @@
 use std::fmt;
 use crate::basic_function_registry::FUNCTION_REGISTRY;
+use basic_runtime::files::FileErrorKind;
+use basic_runtime::matrix::{self, MatrixErrorKind};
+pub use basic_runtime::files::FileMode;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -26,12 +29,53 @@ pub enum Token {
     Dim,
     On,
     Def,
+    Error,
+    Resume,
+    While,
+    Wend,
+    Do,
+    Loop,
+    Until,
+    Exit,
+    ElseIf,
+    Select,
+    Case,
+    Is,
+    Sub,
+    Function,
+    Call,
+    Local,
+    Shared,
+    Open,
+    Close,
+    Output,
+    Append,
+    As,
+    Write,
+    Line,
+    Random,
+    Field,
+    Get,
+    Put,
+    Lset,
+    Rset,
+    Using,
+    DefType(VarType),   // DEFINT, DEFSNG, DEFDBL or DEFSTR
+    Mat,
+    Zer,
+    Con,
+    Idn,
+    Trn,
+    Inv,
+    Randomize,
     
     // Operators
     Plus,
     Minus,
     Star,
     Slash,
+    Backslash,
+    Mod,
     Power,
     Equal,
     NotEqual,
@@ -41,6 +85,9 @@ pub enum Token {
     GreaterEqual,
     And,
     Or,
+    Xor,
+    Eqv,
+    Imp,
     Not,
     
     // Punctuation
@@ -49,6 +96,7 @@ pub enum Token {
     Comma,
     Semicolon,
     Colon,
+    Hash,       // Marks a file number: PRINT #1
     
     // Values
     Number(String),
@@ -97,10 +145,51 @@ impl fmt::Display for Token {
             Token::Dim => write!(f, "DIM"),
             Token::On => write!(f, "ON"),
             Token::Def => write!(f, "DEF"),
+            Token::Error => write!(f, "ERROR"),
+            Token::Resume => write!(f, "RESUME"),
+            Token::While => write!(f, "WHILE"),
+            Token::Wend => write!(f, "WEND"),
+            Token::Do => write!(f, "DO"),
+            Token::Loop => write!(f, "LOOP"),
+            Token::Until => write!(f, "UNTIL"),
+            Token::Exit => write!(f, "EXIT"),
+            Token::ElseIf => write!(f, "ELSEIF"),
+            Token::Select => write!(f, "SELECT"),
+            Token::Case => write!(f, "CASE"),
+            Token::Is => write!(f, "IS"),
+            Token::Sub => write!(f, "SUB"),
+            Token::Function => write!(f, "FUNCTION"),
+            Token::Call => write!(f, "CALL"),
+            Token::Local => write!(f, "LOCAL"),
+            Token::Shared => write!(f, "SHARED"),
+            Token::Open => write!(f, "OPEN"),
+            Token::Close => write!(f, "CLOSE"),
+            Token::Output => write!(f, "OUTPUT"),
+            Token::Append => write!(f, "APPEND"),
+            Token::As => write!(f, "AS"),
+            Token::Write => write!(f, "WRITE"),
+            Token::Line => write!(f, "LINE"),
+            Token::Random => write!(f, "RANDOM"),
+            Token::Field => write!(f, "FIELD"),
+            Token::Get => write!(f, "GET"),
+            Token::Put => write!(f, "PUT"),
+            Token::Lset => write!(f, "LSET"),
+            Token::Rset => write!(f, "RSET"),
+            Token::Using => write!(f, "USING"),
+            Token::DefType(var_type) => write!(f, "{}", var_type.keyword()),
+            Token::Mat => write!(f, "MAT"),
+            Token::Zer => write!(f, "ZER"),
+            Token::Con => write!(f, "CON"),
+            Token::Idn => write!(f, "IDN"),
+            Token::Trn => write!(f, "TRN"),
+            Token::Inv => write!(f, "INV"),
+            Token::Randomize => write!(f, "RANDOMIZE"),
             Token::Plus => write!(f, "+"),
             Token::Minus => write!(f, "-"),
             Token::Star => write!(f, "*"),
             Token::Slash => write!(f, "/"),
+            Token::Backslash => write!(f, "\\"),
+            Token::Mod => write!(f, "MOD"),
             Token::Power => write!(f, "^"),
             Token::Equal => write!(f, "="),
             Token::NotEqual => write!(f, "<>"),
@@ -110,17 +199,21 @@ impl fmt::Display for Token {
             Token::GreaterEqual => write!(f, ">="),
             Token::And => write!(f, "AND"),
             Token::Or => write!(f, "OR"),
+            Token::Xor => write!(f, "XOR"),
+            Token::Eqv => write!(f, "EQV"),
+            Token::Imp => write!(f, "IMP"),
             Token::Not => write!(f, "NOT"),
             Token::LeftParen => write!(f, "("),
             Token::RightParen => write!(f, ")"),
             Token::Comma => write!(f, ","),
             Token::Semicolon => write!(f, ";"),
             Token::Colon => write!(f, ":"),
+            Token::Hash => write!(f, "#"),
             Token::Number(n) => write!(f, "{}", n),
             Token::String(s) => write!(f, "\"{}\"", s),
             Token::Identifier(i, j ) => write!(f, "{} {}", i, j),
             Token::LineNumber(l) => write!(f, "{}", l),
-            Token::Newline => write!(f, "\n"),
+            Token::Newline => writeln!(f),
         }
     }
 }
@@ -153,6 +246,7 @@ impl Token {
             Token::Identifier(id, id_type) => match id_type {
                 IdentifierType::BuiltInFunction => Err(BasicError::Runtime {
                     message: format!("Cannot pass function '{}' as an argument to a function", id),
+                    condition: ErrorCondition::Unprintable,
                     basic_line_number: None,
                     file_line_number: None,
                 }),
@@ -160,6 +254,7 @@ impl Token {
             },
             _ => Err(BasicError::Runtime {
                 message: format!("Unexpected token type: {:?}", self),
+                condition: ErrorCondition::Unprintable,
                 basic_line_number: None,
                 file_line_number: None,
             }),
@@ -167,6 +262,78 @@ impl Token {
     }
 }
 
+/// Errors a program can trap with ON ERROR GOTO. ERR reports the dialect's code for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCondition {
+    NextWithoutFor,
+    Syntax,
+    ReturnWithoutGosub,
+    OutOfData,
+    IllegalFunctionCall,
+    Overflow,
+    OutOfMemory,
+    UndefinedLine,
+    SubscriptOutOfRange,
+    DuplicateDefinition,
+    DivisionByZero,
+    TypeMismatch,
+    StringTooLong,
+    UndefinedUserFunction,
+    ResumeWithoutError,
+    /// WHILE or DO without its WEND or LOOP
+    WhileWithoutWend,
+    /// WEND or LOOP without its WHILE or DO, or closing the wrong kind of loop
+    WendWithoutWhile,
+    /// A file number that is out of range or not open
+    BadFileNumber,
+    FileNotFound,
+    /// Reading a file open for output, or writing one open for input
+    BadFileMode,
+    FileAlreadyOpen,
+    InputPastEnd,
+    /// A file that cannot be opened or written, or a path outside the sandbox
+    PathAccess,
+    /// FIELD widths longer than the record, or a bad LEN= for a random file
+    FieldOverflow,
+    /// A GET or PUT record number below 1 or too high
+    BadRecordNumber,
+    /// FOR without its NEXT
+    ForWithoutNext,
+    /// INPUT still given the wrong number of values, or a bad number, once its retries
+    /// run out
+    BadInput,
+    /// Any error without a code of its own
+    Unprintable,
+}
+
+impl From<FileErrorKind> for ErrorCondition {
+    fn from(kind: FileErrorKind) -> Self {
+        match kind {
+            FileErrorKind::BadFileNumber => ErrorCondition::BadFileNumber,
+            FileErrorKind::FileNotFound => ErrorCondition::FileNotFound,
+            FileErrorKind::BadFileMode => ErrorCondition::BadFileMode,
+            FileErrorKind::FileAlreadyOpen => ErrorCondition::FileAlreadyOpen,
+            FileErrorKind::InputPastEnd => ErrorCondition::InputPastEnd,
+            FileErrorKind::TypeMismatch => ErrorCondition::TypeMismatch,
+            FileErrorKind::PathAccess => ErrorCondition::PathAccess,
+            FileErrorKind::FieldOverflow => ErrorCondition::FieldOverflow,
+            FileErrorKind::BadRecordNumber => ErrorCondition::BadRecordNumber,
+        }
+    }
+}
+
+impl From<MatrixErrorKind> for ErrorCondition {
+    fn from(kind: MatrixErrorKind) -> Self {
+        match kind {
+            MatrixErrorKind::IllegalFunctionCall => ErrorCondition::IllegalFunctionCall,
+            MatrixErrorKind::SubscriptOutOfRange => ErrorCondition::SubscriptOutOfRange,
+            MatrixErrorKind::OutOfData => ErrorCondition::OutOfData,
+            MatrixErrorKind::TypeMismatch => ErrorCondition::TypeMismatch,
+            MatrixErrorKind::Overflow => ErrorCondition::Overflow,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BasicError {
     Syntax {
@@ -176,6 +343,7 @@ pub enum BasicError {
     },
     Runtime {
         message: String,
+        condition: ErrorCondition,
         basic_line_number: Option<usize>,
         file_line_number: Option<usize>,
     },
@@ -201,7 +369,7 @@ impl fmt::Display for BasicError {
                     (None, None) => write!(f, "Syntax error: {}", message),
                 }
             }
-            BasicError::Runtime { message, basic_line_number, file_line_number } => {
+            BasicError::Runtime { message, basic_line_number, file_line_number, .. } => {
                 match (basic_line_number, file_line_number) {
                     (Some(basic), _) => write!(f, "Runtime error at BASIC line {}: {}", basic, message),
                     (None, Some(file)) => write!(f, "Runtime error at file line {}: {}", file, message),
@@ -226,6 +394,19 @@ impl fmt::Display for BasicError {
     }
 }
 
+impl BasicError {
+    /// The condition ON ERROR GOTO sees for an error raised while running. Internal
+    /// errors cannot be trapped.
+    pub fn condition(&self) -> Option<ErrorCondition> {
+        match self {
+            BasicError::Syntax { .. } => Some(ErrorCondition::Syntax),
+            BasicError::Runtime { condition, .. } => Some(*condition),
+            BasicError::Type { .. } => Some(ErrorCondition::TypeMismatch),
+            BasicError::Internal { .. } => None,
+        }
+    }
+}
+
 impl std::error::Error for BasicError {}
 
 impl From<std::io::Error> for BasicError {
@@ -260,6 +441,52 @@ pub enum SymbolType {
     Array,
 }
 
+/// The type of a variable, given by its suffix: `A%`, `A!`, `A#` or `A$`. A variable
+/// without one takes the type DEFINT, DEFSNG, DEFDBL or DEFSTR gave its first letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarType {
+    Integer,
+    Single,
+    Double,
+    String,
+}
+
+impl VarType {
+    pub fn from_suffix(c: char) -> Option<VarType> {
+        match c {
+            '%' => Some(VarType::Integer),
+            '!' => Some(VarType::Single),
+            '#' => Some(VarType::Double),
+            '$' => Some(VarType::String),
+            _ => None,
+        }
+    }
+
+    /// The type of a variable name that ends in a suffix
+    pub fn of(name: &str) -> Option<VarType> {
+        name.chars().last().and_then(VarType::from_suffix)
+    }
+
+    pub fn suffix(self) -> char {
+        match self {
+            VarType::Integer => '%',
+            VarType::Single => '!',
+            VarType::Double => '#',
+            VarType::String => '$',
+        }
+    }
+
+    /// The statement that makes it the default type of letters
+    pub fn keyword(self) -> &'static str {
+        match self {
+            VarType::Integer => "DEFINT",
+            VarType::Single => "DEFSNG",
+            VarType::Double => "DEFDBL",
+            VarType::String => "DEFSTR",
+        }
+    }
+}
+
 // Constants for BASIC syntax
 pub const NUMBERS: &str = "0123456789";
 pub const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
@@ -317,6 +544,206 @@ pub enum Statement {
     OnGoto { expr: Expression, line_numbers: Vec<usize> },
     OnGosub { expr: Expression, line_numbers: Vec<usize> },
     Def { name: String, params: Vec<String>, expr: Expression },
+    OnErrorGoto { line: usize },     // line 0 turns error trapping off
+    Resume { target: ResumeTarget },
+    While { condition: Expression },
+    Wend,
+    Do { condition: Option<LoopCondition> },    // Tested before each pass
+    Loop { condition: Option<LoopCondition> },  // Tested after each pass
+    ExitDo,
+    IfBlock { condition: Expression },  // IF ... THEN ending its line, closed by END IF
+    ElseIf { condition: Expression },
+    ElseBlock,                          // ELSE starting a line, inside a block IF
+    EndIf,
+    SelectCase { selector: Expression },
+    Case { tests: Vec<CaseTest> },      // Matches if any of the tests does
+    CaseElse,
+    EndSelect,
+    Sub { name: String, params: Vec<String> },       // Runs to END SUB when called
+    Function { name: String, params: Vec<String> },  // Runs to END FUNCTION when called
+    EndSub,
+    EndFunction,
+    ExitSub,
+    ExitFunction,
+    Call { name: String, args: Vec<Expression> },
+    Local { vars: Vec<String> },    // Variables of the procedure, reset to 0 or ""
+    Shared { vars: Vec<String> },   // Variables of the program, used by the procedure
+    Open { path: Expression, mode: FileMode, number: Expression, record_len: Option<Expression> },
+    Close { numbers: Vec<Expression> },                     // No numbers closes every file
+    PrintFile { number: Expression, items: Vec<PrintItem> },
+    WriteFile { number: Expression, exprs: Vec<Expression> },  // Comma-separated, strings quoted
+    InputFile { number: Expression, vars: Vec<String> },
+    LineInputFile { number: Expression, var: String },
+    Field { number: Expression, fields: Vec<(Expression, String)> },  // Widths and string variables
+    Get { number: Expression, record: Option<Expression> },           // No record reads the next one
+    Put { number: Expression, record: Option<Expression> },
+    Lset { var: String, value: Expression },
+    Rset { var: String, value: Expression },
+    PrintUsing { number: Option<Expression>, format: Expression, exprs: Vec<Expression>, newline: bool },  // No number prints to the screen
+    DefType { var_type: VarType, ranges: Vec<(char, char)> },  // DEFINT A-Z: letter ranges, first to last
+    MatRead { arrays: Vec<(String, Vec<Expression>)> },  // Arrays with the dimensions to give them first, if any
+    MatPrint { arrays: Vec<(String, bool)> },            // Arrays, and whether a semicolon packs each
+    MatAssign { var: String, value: MatValue },
+    Randomize { seed: Option<Expression> },  // No seed reseeds from TIMER
+    MidAssign { var: Expression, start: Expression, length: Option<Expression>, value: Expression },  // MID$(var, start[, length]) = value
+}
+
+/// The right-hand side of a MAT assignment
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatValue {
+    Array(String),
+    Sum(String, String),
+    Difference(String, String),
+    Product(String, String),
+    Scaled(Expression, String),       // (K) * B
+    Transpose(String),
+    Inverse(String),
+    Zero(Vec<Expression>),            // With no dimensions, those of the array assigned to
+    Constant(Vec<Expression>),
+    Identity(Vec<Expression>),
+}
+
+impl MatValue {
+    pub fn operation(&self) -> matrix::Operation {
+        use matrix::Operation;
+        match self {
+            MatValue::Array(_) => Operation::Copy,
+            MatValue::Sum(..) => Operation::Sum,
+            MatValue::Difference(..) => Operation::Difference,
+            MatValue::Product(..) => Operation::Product,
+            MatValue::Scaled(..) => Operation::Scaled,
+            MatValue::Transpose(_) => Operation::Transpose,
+            MatValue::Inverse(_) => Operation::Inverse,
+            MatValue::Zero(_) => Operation::Zero,
+            MatValue::Constant(_) => Operation::Constant,
+            MatValue::Identity(_) => Operation::Identity,
+        }
+    }
+
+    /// The arrays the value is worked out from, in order
+    pub fn arrays(&self) -> Vec<&String> {
+        match self {
+            MatValue::Array(a) | MatValue::Scaled(_, a) | MatValue::Transpose(a) | MatValue::Inverse(a) => vec![a],
+            MatValue::Sum(a, b) | MatValue::Difference(a, b) | MatValue::Product(a, b) => vec![a, b],
+            MatValue::Zero(_) | MatValue::Constant(_) | MatValue::Identity(_) => Vec::new(),
+        }
+    }
+
+    /// The dimensions given to ZER, CON or IDN
+    pub fn bounds(&self) -> &[Expression] {
+        match self {
+            MatValue::Zero(bounds) | MatValue::Constant(bounds) | MatValue::Identity(bounds) => bounds,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for MatValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let with_bounds = |f: &mut fmt::Formatter, keyword: &str, bounds: &[Expression]| {
+            if bounds.is_empty() {
+                return write!(f, "{}", keyword);
+            }
+            let bounds: Vec<String> = bounds.iter().map(|b| b.to_string()).collect();
+            write!(f, "{}({})", keyword, bounds.join(", "))
+        };
+        match self {
+            MatValue::Array(a) => write!(f, "{}", a),
+            MatValue::Sum(a, b) => write!(f, "{} + {}", a, b),
+            MatValue::Difference(a, b) => write!(f, "{} - {}", a, b),
+            MatValue::Product(a, b) => write!(f, "{} * {}", a, b),
+            MatValue::Scaled(k, a) => write!(f, "({}) * {}", k, a),
+            MatValue::Transpose(a) => write!(f, "TRN({})", a),
+            MatValue::Inverse(a) => write!(f, "INV({})", a),
+            MatValue::Zero(bounds) => with_bounds(f, "ZER", bounds),
+            MatValue::Constant(bounds) => with_bounds(f, "CON", bounds),
+            MatValue::Identity(bounds) => with_bounds(f, "IDN", bounds),
+        }
+    }
+}
+
+/// One of the tests listed on a CASE statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaseTest {
+    Value(Expression),              // CASE 1: equal to the value
+    Range(Expression, Expression),  // CASE 3 TO 5: between the bounds, inclusive
+    Is(String, Expression),         // CASE IS > 10: the comparison operator holds
+}
+
+impl fmt::Display for CaseTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaseTest::Value(expr) => write!(f, "{}", expr),
+            CaseTest::Range(low, high) => write!(f, "{} TO {}", low, high),
+            CaseTest::Is(op, expr) => write!(f, "IS {} {}", op, expr),
+        }
+    }
+}
+
+/// The test on a DO or LOOP statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopCondition {
+    While(Expression),  // Keep looping while the expression is true
+    Until(Expression),  // Keep looping until the expression is true
+}
+
+impl LoopCondition {
+    pub fn expression(&self) -> &Expression {
+        match self {
+            LoopCondition::While(expr) | LoopCondition::Until(expr) => expr,
+        }
+    }
+}
+
+impl fmt::Display for LoopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoopCondition::While(expr) => write!(f, "WHILE {}", expr),
+            LoopCondition::Until(expr) => write!(f, "UNTIL {}", expr),
+        }
+    }
+}
+
+/// Kind of structured loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopKind {
+    While,  // WHILE ... WEND
+    Do,     // DO ... LOOP
+}
+
+impl LoopKind {
+    /// The kind of loop a statement opens or closes, and whether it opens it
+    pub fn of(stmt: &Statement) -> Option<(LoopKind, bool)> {
+        match stmt {
+            Statement::While { .. } => Some((LoopKind::While, true)),
+            Statement::Wend => Some((LoopKind::While, false)),
+            Statement::Do { .. } => Some((LoopKind::Do, true)),
+            Statement::Loop { .. } => Some((LoopKind::Do, false)),
+            _ => None,
+        }
+    }
+
+    pub fn opener(&self) -> &'static str {
+        match self {
+            LoopKind::While => "WHILE",
+            LoopKind::Do => "DO",
+        }
+    }
+
+    pub fn closer(&self) -> &'static str {
+        match self {
+            LoopKind::While => "WEND",
+            LoopKind::Do => "LOOP",
+        }
+    }
+}
+
+/// Where RESUME continues after an error handler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumeTarget {
+    Retry,          // RESUME or RESUME 0: run the failing statement again
+    Next,           // RESUME NEXT: the statement after the failing one
+    Line(usize),    // RESUME line
 }
 
 impl Statement {
@@ -404,6 +831,26 @@ impl Statement {
     pub fn new_def(name: String, params: Vec<String>, expr: Expression) -> Self {
         Statement::Def { name, params, expr }
     }
+
+    pub fn new_on_error_goto(line: usize) -> Self {
+        Statement::OnErrorGoto { line }
+    }
+
+    pub fn new_resume(target: ResumeTarget) -> Self {
+        Statement::Resume { target }
+    }
+
+    pub fn new_while(condition: Expression) -> Self {
+        Statement::While { condition }
+    }
+
+    pub fn new_do(condition: Option<LoopCondition>) -> Self {
+        Statement::Do { condition }
+    }
+
+    pub fn new_loop(condition: Option<LoopCondition>) -> Self {
+        Statement::Loop { condition }
+    }
 }
 
 impl fmt::Display for Statement {
@@ -517,6 +964,130 @@ impl fmt::Display for Statement {
                 }
                 write!(f, ") = {}", expr)
             }
+            OnErrorGoto { line } => write!(f, "ON ERROR GOTO {}", line),
+            Resume { target } => match target {
+                ResumeTarget::Retry => write!(f, "RESUME"),
+                ResumeTarget::Next => write!(f, "RESUME NEXT"),
+                ResumeTarget::Line(line) => write!(f, "RESUME {}", line),
+            },
+            While { condition } => write!(f, "WHILE {}", condition),
+            Wend => write!(f, "WEND"),
+            Do { condition: None } => write!(f, "DO"),
+            Do { condition: Some(condition) } => write!(f, "DO {}", condition),
+            Loop { condition: None } => write!(f, "LOOP"),
+            Loop { condition: Some(condition) } => write!(f, "LOOP {}", condition),
+            ExitDo => write!(f, "EXIT DO"),
+            IfBlock { condition } => write!(f, "IF {} THEN", condition),
+            ElseIf { condition } => write!(f, "ELSEIF {} THEN", condition),
+            ElseBlock => write!(f, "ELSE"),
+            EndIf => write!(f, "END IF"),
+            SelectCase { selector } => write!(f, "SELECT CASE {}", selector),
+            Case { tests } => {
+                let tests: Vec<String> = tests.iter().map(|test| test.to_string()).collect();
+                write!(f, "CASE {}", tests.join(", "))
+            }
+            CaseElse => write!(f, "CASE ELSE"),
+            EndSelect => write!(f, "END SELECT"),
+            Sub { name, params } if params.is_empty() => write!(f, "SUB {}", name),
+            Sub { name, params } => write!(f, "SUB {}({})", name, params.join(", ")),
+            Function { name, params } if params.is_empty() => write!(f, "FUNCTION {}", name),
+            Function { name, params } => write!(f, "FUNCTION {}({})", name, params.join(", ")),
+            EndSub => write!(f, "END SUB"),
+            EndFunction => write!(f, "END FUNCTION"),
+            ExitSub => write!(f, "EXIT SUB"),
+            ExitFunction => write!(f, "EXIT FUNCTION"),
+            Call { name, args } if args.is_empty() => write!(f, "CALL {}", name),
+            Call { name, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "CALL {}({})", name, args.join(", "))
+            }
+            Local { vars } => write!(f, "LOCAL {}", vars.join(", ")),
+            Shared { vars } => write!(f, "SHARED {}", vars.join(", ")),
+            Open { path, mode, number, record_len } => {
+                write!(f, "OPEN {} FOR {} AS #{}", path, mode, number)?;
+                if let Some(record_len) = record_len {
+                    write!(f, " LEN={}", record_len)?;
+                }
+                Ok(())
+            }
+            Close { numbers } => {
+                write!(f, "CLOSE")?;
+                let numbers: Vec<String> = numbers.iter().map(|n| format!("#{}", n)).collect();
+                if !numbers.is_empty() {
+                    write!(f, " {}", numbers.join(", "))?;
+                }
+                Ok(())
+            }
+            PrintFile { number, items } => {
+                write!(f, "PRINT #{},", number)?;
+                for item in items {
+                    match item {
+                        PrintItem::Expression(expr) => write!(f, " {}", expr)?,
+                        PrintItem::Tab(n) => write!(f, "\t{}", " ".repeat(*n))?,
+                        PrintItem::Comma => write!(f, ", ")?,
+                        PrintItem::Semicolon => write!(f, "; ")?,
+                    }
+                }
+                Ok(())
+            }
+            WriteFile { number, exprs } => {
+                let exprs: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+                write!(f, "WRITE #{}, {}", number, exprs.join(", "))
+            }
+            InputFile { number, vars } => write!(f, "INPUT #{}, {}", number, vars.join(", ")),
+            LineInputFile { number, var } => write!(f, "LINE INPUT #{}, {}", number, var),
+            Field { number, fields } => {
+                let fields: Vec<String> = fields.iter().map(|(width, var)| format!("{} AS {}", width, var)).collect();
+                write!(f, "FIELD #{}, {}", number, fields.join(", "))
+            }
+            Get { number, record: Some(record) } => write!(f, "GET #{}, {}", number, record),
+            Get { number, record: None } => write!(f, "GET #{}", number),
+            Put { number, record: Some(record) } => write!(f, "PUT #{}, {}", number, record),
+            Put { number, record: None } => write!(f, "PUT #{}", number),
+            Lset { var, value } => write!(f, "LSET {} = {}", var, value),
+            Rset { var, value } => write!(f, "RSET {} = {}", var, value),
+            PrintUsing { number, format, exprs, newline } => {
+                write!(f, "PRINT ")?;
+                if let Some(number) = number {
+                    write!(f, "#{}, ", number)?;
+                }
+                let exprs: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+                write!(f, "USING {}; {}{}", format, exprs.join("; "), if *newline { "" } else { ";" })
+            }
+            DefType { var_type, ranges } => {
+                let ranges: Vec<String> = ranges.iter()
+                    .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+                    .collect();
+                write!(f, "{} {}", var_type.keyword(), ranges.join(", "))
+            }
+            MatRead { arrays } => {
+                let arrays: Vec<String> = arrays.iter()
+                    .map(|(name, bounds)| if bounds.is_empty() {
+                        name.clone()
+                    } else {
+                        let bounds: Vec<String> = bounds.iter().map(|b| b.to_string()).collect();
+                        format!("{}({})", name, bounds.join(", "))
+                    })
+                    .collect();
+                write!(f, "MAT READ {}", arrays.join(", "))
+            }
+            MatPrint { arrays } => {
+                write!(f, "MAT PRINT")?;
+                for (i, (name, packed)) in arrays.iter().enumerate() {
+                    let last = i + 1 == arrays.len();
+                    match (packed, last) {
+                        (true, _) => write!(f, " {};", name)?,
+                        (false, false) => write!(f, " {},", name)?,
+                        (false, true) => write!(f, " {}", name)?,
+                    }
+                }
+                Ok(())
+            }
+            MatAssign { var, value } => write!(f, "MAT {} = {}", var, value),
+            Randomize { seed: Some(seed) } => write!(f, "RANDOMIZE {}", seed),
+            Randomize { seed: None } => write!(f, "RANDOMIZE"),
+            MidAssign { var, start, length: Some(length), value } => write!(f, "MID$({}, {}, {}) = {}", var, start, length, value),
+            MidAssign { var, start, length: None, value } => write!(f, "MID$({}, {}) = {}", var, start, value),
         }
     }
 }
@@ -580,6 +1151,10 @@ impl fmt::Display for ExpressionType {
                 write!(f, "({} {} {})", left, op, right)
             }
 
+            ExpressionType::UnaryOp { op, expr } if op == "NOT" => {
+                write!(f, "NOT {}", expr)
+            }
+
             ExpressionType::UnaryOp { op, expr } => {
                 write!(f, "{}{}", op, expr)
             }
@@ -657,6 +1232,50 @@ impl Expression {
             expr_type: ExpressionType::FunctionCall { name, args },
         }
     }
+
+    /// True if the expression uses a double precision variable, `A#`. As in GW-BASIC, it
+    /// is then computed in double precision rather than in the dialect's own.
+    pub fn is_double_precision(&self) -> bool {
+        match &self.expr_type {
+            ExpressionType::Number(_) | ExpressionType::String(_) => false,
+            ExpressionType::Variable(name) => VarType::of(name) == Some(VarType::Double),
+            ExpressionType::Array { name, indices } => {
+                VarType::of(name) == Some(VarType::Double) || indices.iter().any(Expression::is_double_precision)
+            }
+            ExpressionType::BinaryOp { left, right, .. } => left.is_double_precision() || right.is_double_precision(),
+            ExpressionType::UnaryOp { expr, .. } => expr.is_double_precision(),
+            ExpressionType::FunctionCall { args, .. } => args.iter().any(Expression::is_double_precision),
+        }
+    }
+
+    /// True if the expression uses a single precision variable, `A!`, and no double
+    /// precision one or one of the dialect's own type. It is then computed, and printed,
+    /// in single precision, as `A! * 2` is in GW-BASIC.
+    pub fn is_single_precision(&self) -> bool {
+        !self.is_double_precision()
+            && self.uses_variable(&|var_type| var_type == Some(VarType::Single))
+            && !self.uses_variable(&|var_type| var_type.is_none())
+    }
+
+    /// True if a variable or array whose type passes `test` is part of the value; None is
+    /// the type of a name without a suffix
+    fn uses_variable(&self, test: &dyn Fn(Option<VarType>) -> bool) -> bool {
+        match &self.expr_type {
+            ExpressionType::Number(_) | ExpressionType::String(_) => false,
+            ExpressionType::Variable(name) | ExpressionType::Array { name, .. } => test(VarType::of(name)),
+            ExpressionType::BinaryOp { left, right, .. } => left.uses_variable(test) || right.uses_variable(test),
+            ExpressionType::UnaryOp { expr, .. } => expr.uses_variable(test),
+            ExpressionType::FunctionCall { args, .. } => args.iter().any(|arg| arg.uses_variable(test)),
+        }
+    }
+
+    /// True for a string variable or string array element, which a value can be stored in
+    pub fn is_string_variable(&self) -> bool {
+        match &self.expr_type {
+            ExpressionType::Variable(name) | ExpressionType::Array { name, .. } => VarType::of(name) == Some(VarType::String),
+            _ => false,
+        }
+    }
 }
 
 // Program line structure
@@ -683,6 +1302,12 @@ pub struct Program {
     pub lines: Vec<ProgramLine>,
 }
 
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Program {
     pub fn new() -> Self {
         Program { lines: Vec::new() }
@@ -706,6 +1331,18 @@ impl Program {
             self.lines.remove(pos);
         }
     }
+
+    /// Names of the FUNCTIONs the program defines. The lexer needs them to read calls,
+    /// as names longer than two characters are otherwise split up.
+    pub fn function_names(&self) -> Vec<String> {
+        self.lines.iter()
+            .flat_map(|line| line.statements.iter())
+            .filter_map(|stmt| match stmt {
+                Statement::Function { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 impl fmt::Display for Program {
@@ -753,33 +1390,18 @@ pub fn is_valid_identifier(name: &str) -> bool {
         }
     }
     
-    // Standard BASIC variable rules: letter + optional digit + optional $
-    if chars.len() > 1 {
-        let last_char = chars[chars.len() - 1];
-        if !last_char.is_ascii_digit() && last_char != '$' {
-            return false;
-        }
-        
-        // Allow up to 3 characters: letter + digit + $
-        if chars.len() > 3 {
-            return false;
-        }
-        
-        // Check for valid patterns: A, A1, A$, A1$
-        if chars.len() == 2 {
-            // Two characters: must be A1 or A$
-            if !chars[1].is_ascii_digit() && chars[1] != '$' {
-                return false;
-            }
-        } else if chars.len() == 3 {
-            // Three characters: must be A1$ (letter + digit + $)
-            if !chars[1].is_ascii_digit() || chars[2] != '$' {
-                return false;
-            }
-        }
+    // Standard BASIC variable rules: letter + optional digit + optional type suffix
+    let name_chars = match chars.last().and_then(|&c| VarType::from_suffix(c)) {
+        Some(_) => &chars[..chars.len() - 1],
+        None => &chars[..],
+    };
+    match name_chars.len() {
+        // A, A%, A$
+        1 => true,
+        // A1, A1%, A1$
+        2 => name_chars[1].is_ascii_digit(),
+        _ => false,
     }
-    
-    true
 }
 
 // Symbol table entry types
@@ -840,6 +1462,9 @@ mod tests {
         assert!(is_valid_identifier("B2"));
         assert!(is_valid_identifier("Z9"));
         assert!(is_valid_identifier("X$"));
+        assert!(is_valid_identifier("I%"));
+        assert!(is_valid_identifier("A1!"));
+        assert!(is_valid_identifier("D#"));
     }
 
     #[test]
@@ -847,6 +1472,7 @@ mod tests {
         assert!(!is_valid_identifier(""));
         assert!(!is_valid_identifier("1A"));
         assert!(!is_valid_identifier("A1B"));
+        assert!(!is_valid_identifier("A%$"));
         assert!(!is_valid_identifier("AB"));
         assert!(!is_valid_identifier("A$B"));
         assert!(!is_valid_identifier("A12"));
@@ -954,20 +1580,17 @@ impl SymbolValue {
             _ => 0,
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl fmt::Display for SymbolValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SymbolValue::Number(n) => {
-                // In classic BASIC, positive numbers get both leading and trailing spaces
-                // Negative numbers get only trailing space (minus sign serves as leading char)
-                if *n >= 0.0 {
-                    write!(f, " {} ", n)
-                } else {
-                    write!(f, "{} ", n)
-                }
-            },
+            // As PRINT shows it in the default dialect; PRINT itself uses the program's dialect
+            SymbolValue::Number(n) => write!(f, "{}", basic_runtime::format::print_number(*n, &Default::default())),
             SymbolValue::String(s) => write!(f, "{}", s),
 
             SymbolValue::Array { element_type, dimensions, data } => {
@@ -4,7 +4,10 @@ use std::process;
 use basic_rs::basic_parser::Parser;
 use basic_rs::basic_lexer::Lexer;
 use basic_rs::llvm_codegen::LLVMCodeGenerator;
+use basic_rs::llvm_toolchain::{EmitKind, Toolchain};
 use basic_rs::basic_types::BasicError;
+use basic_rs::basic_dialect::Dialect;
+use basic_runtime::rng::RndAlgorithm;
 use clap::Parser as ClapParser;
 
 #[derive(ClapParser)]
@@ -13,9 +16,14 @@ struct Args {
     /// BASIC program file to compile
     input: String,
     
-    /// Output LLVM-IR file (defaults to input with .ll extension)
+    /// Output file (defaults to the input name with the extension for --emit)
     #[arg(short, long)]
     output: Option<String>,
+
+    /// What to produce: exe, obj, ll or asm. Defaults to the kind implied by the
+    /// --output extension, or ll when no output is given
+    #[arg(long, value_enum)]
+    emit: Option<EmitKind>,
     
     /// Enable debug output during code generation
     #[arg(long)]
@@ -24,6 +32,22 @@ struct Args {
     /// Enable trace statements in generated code
     #[arg(long)]
     trace: bool,
+
+    /// BASIC dialect: basicrs, dartmouth, msbasic2, gwbasic or ansi-minimal
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// Restrict OPEN to files inside this directory
+    #[arg(long)]
+    sandbox: Option<String>,
+
+    /// Generator RND draws from, instead of the dialect's: basicrs, microsoft or trekbasic
+    #[arg(long)]
+    rnd: Option<RndAlgorithm>,
+
+    /// Seed RND as RANDOMIZE would before the program starts, so runs repeat
+    #[arg(long, allow_negative_numbers = true)]
+    seed: Option<f64>,
 }
 
 fn print_basic_error(kind: &str, message: &str, basic_line_number: &Option<usize>, file_line_number: &Option<usize>) {
@@ -39,9 +63,17 @@ fn print_basic_error(kind: &str, message: &str, basic_line_number: &Option<usize
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(rnd) = args.rnd {
+        args.dialect.rnd = rnd;
+    }
 
-    // Determine output file name
+    // Determine what to produce and the output file name
+    let emit = match (args.emit, &args.output) {
+        (Some(emit), _) => emit,
+        (None, Some(path)) => EmitKind::from_output_path(Path::new(path)),
+        (None, None) => EmitKind::Ll,
+    };
     let output_path = match args.output {
         Some(path) => path,
         None => {
@@ -49,14 +81,14 @@ fn main() {
             let stem = input_path.file_stem()
                 .and_then(|s| s.to_str())
                 .unwrap_or("output");
-            format!("{}.ll", stem)
+            emit.default_output(stem)
         }
     };
 
     // Read and parse the BASIC program
     let program = match fs::read_to_string(&args.input) {
         Ok(source) => {
-            let mut lexer = Lexer::new(&source);
+            let mut lexer = Lexer::with_dialect(&source, args.dialect);
             
             let tokens = match lexer.tokenize() {
                 Ok(tokens) => tokens,
@@ -65,8 +97,11 @@ fn main() {
                     process::exit(10);
                 }
             };
+            for warning in lexer.name_collisions() {
+                eprintln!("Warning: {}", warning);
+            }
             
-            let mut parser = Parser::new(tokens);
+            let mut parser = Parser::with_dialect(tokens, args.dialect);
             match parser.parse() {
                 Ok(program) => {
                     if args.debug {
@@ -81,7 +116,7 @@ fn main() {
                             print_basic_error("Parse", message, basic_line_number, file_line_number);
                             process::exit(11);
                         }
-                        BasicError::Runtime { message, basic_line_number, file_line_number } => {
+                        BasicError::Runtime { message, basic_line_number, file_line_number, .. } => {
                             print_basic_error("Parse", message, basic_line_number, file_line_number);
                             process::exit(12);
                         }
@@ -104,7 +139,13 @@ fn main() {
     };
 
     // Generate LLVM-IR
-    let mut codegen = LLVMCodeGenerator::new(program, args.debug, args.trace);
+    let mut codegen = LLVMCodeGenerator::with_dialect(program, args.debug, args.trace, args.dialect);
+    if let Some(ref dir) = args.sandbox {
+        codegen.set_sandbox(dir);
+    }
+    if let Some(seed) = args.seed {
+        codegen.set_seed(seed);
+    }
     
     let llvm_ir = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
         codegen.generate_ir()
@@ -117,16 +158,36 @@ fn main() {
     };
 
     // Write LLVM-IR to output file
-    match fs::write(&output_path, llvm_ir) {
-        Ok(_) => {
+    if emit == EmitKind::Ll {
+        match fs::write(&output_path, llvm_ir) {
+            Ok(_) => {
+                if args.debug {
+                    println!("Successfully generated LLVM-IR: {}", output_path);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error writing LLVM-IR file {}: {}", output_path, e);
+                process::exit(17);
+            }
+        }
+    }
+
+    // Hand the IR to clang, or llc plus the C compiler, for native output
+    let toolchain = Toolchain::discover();
+    if args.debug {
+        println!("Using toolchain: {:?}", toolchain);
+    }
+    match toolchain.build(&llvm_ir, emit, Path::new(&output_path)) {
+        Ok(()) => {
             if args.debug {
-                println!("Successfully generated LLVM-IR: {}", output_path);
+                println!("Successfully generated {:?} output: {}", emit, output_path);
             }
             process::exit(0);
         }
         Err(e) => {
-            eprintln!("Error writing LLVM-IR file {}: {}", output_path, e);
-            process::exit(17);
+            eprintln!("{}", e);
+            process::exit(e.exit_code());
         }
     }
-} 
\ No newline at end of file
+}
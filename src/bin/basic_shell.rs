@@ -8,6 +8,8 @@ use std::time::Instant;
 use basic_rs::basic_lexer::Lexer;
 use basic_rs::basic_parser::Parser;
 use basic_rs::basic_interpreter::Interpreter;
+use basic_rs::basic_console::StdioConsole;
+use basic_rs::basic_dialect::Dialect;
 use basic_rs::basic_types::{BasicError, RunStatus, SymbolType, Program};
 use basic_rs::basic_reports::{print_coverage_report, generate_html_coverage_report};
 
@@ -19,10 +21,11 @@ pub struct BasicShell {
     breakpoints: Vec<(usize, usize)>, // (line_number, offset)
     data_breakpoints: Vec<String>,
     coverage_enabled: bool,
+    dialect: Dialect,
 }
 
 impl BasicShell {
-    pub fn new(program_file: Option<String>) -> Self {
+    pub fn new(program_file: Option<String>, dialect: Dialect) -> Self {
         let mut shell = BasicShell {
             program_file: program_file.clone(),
             interpreter: None,
@@ -30,6 +33,7 @@ impl BasicShell {
             breakpoints: Vec::new(),
             data_breakpoints: Vec::new(),
             coverage_enabled: false,
+            dialect,
         };
         
         if let Some(ref file) = program_file {
@@ -54,21 +58,21 @@ impl BasicShell {
     
     /// Load a program from a string (used by tests)
     pub fn load_from_string(&mut self, source: &str) -> Result<(), BasicError> {
-        let mut lexer = Lexer::new(source);
+        let mut lexer = Lexer::with_dialect(source, self.dialect);
         let tokens = lexer.tokenize().map_err(|e| BasicError::Syntax {
             message: e.to_string(),
             basic_line_number: None,
             file_line_number: None,
         })?;
         
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::with_dialect(tokens, self.dialect);
         let program = parser.parse().map_err(|e| BasicError::Syntax {
             message: format!("{:?}", e),
             basic_line_number: None,
             file_line_number: None,
         })?;
         
-        let mut interpreter = Interpreter::new(program);
+        let mut interpreter = Interpreter::with_dialect(program, self.dialect, Box::new(StdioConsole::new()));
         self.transfer_breakpoints_to_interpreter(&mut interpreter);
         self.interpreter = Some(interpreter);
         self.load_status = true;
@@ -569,7 +573,7 @@ impl BasicShell {
             
             // Create fresh interpreter with same program
             let program = interpreter.get_program().clone();
-            let mut new_interpreter = Interpreter::new(program);
+            let mut new_interpreter = Interpreter::with_dialect(program, self.dialect, Box::new(StdioConsole::new()));
             
             if enable_coverage {
                 new_interpreter.enable_coverage();
@@ -592,7 +596,7 @@ impl BasicShell {
     fn cmd_benchmark(&mut self, _args: Option<&str>) {
         if let Some(ref file) = self.program_file.clone() {
             let load_start = Instant::now();
-            self.load_from_file(&file, false);
+            self.load_from_file(file, false);
             let load_time = load_start.elapsed();
             
             let run_start = Instant::now();
@@ -788,7 +792,7 @@ impl BasicShell {
         // Parse line number and content
         let parts: Vec<&str> = line_input.splitn(2, ' ').collect();
         if let Ok(line_number) = parts[0].parse::<usize>() {
-            if line_number < 1 || line_number > 65536 {
+            if !(1..=65536).contains(&line_number) {
                 println!("Line number {} out of range (1-65536)", line_number);
                 return;
             }
@@ -798,7 +802,7 @@ impl BasicShell {
                 if let Some(ref mut interpreter) = self.interpreter {
                     let mut program = interpreter.get_program().clone();
                     program.remove_line(line_number);
-                    let mut new_interpreter = Interpreter::new(program);
+                    let mut new_interpreter = Interpreter::with_dialect(program, self.dialect, Box::new(StdioConsole::new()));
                     self.transfer_breakpoints_to_interpreter(&mut new_interpreter);
                     self.interpreter = Some(new_interpreter);
                     println!("Line {} deleted", line_number);
@@ -811,17 +815,18 @@ impl BasicShell {
                     let line_content = parts[1].trim();
                     let full_line = format!("{} {}", line_number, line_content);
                     
-                    // Parse the new line
-                    let mut lexer = Lexer::new(&full_line);
+                    // Parse the new line, reading calls of the program's FUNCTIONs whole
+                    let mut lexer = Lexer::with_dialect(&full_line, self.dialect);
+                    lexer.set_function_names(interpreter.get_program().function_names());
                     match lexer.tokenize() {
                         Ok(tokens) => {
-                            let mut parser = Parser::new(tokens);
-                            match parser.parse() {
+                            let mut parser = Parser::with_dialect(tokens, self.dialect);
+                            match parser.parse_lines() {
                                                                  Ok(temp_program) => {
                                      if let Some(new_line) = temp_program.lines.first() {
                                          let mut program = interpreter.get_program().clone();
                                          program.add_line(line_number, line_content.to_string(), new_line.statements.clone());
-                                         let mut new_interpreter = Interpreter::new(program);
+                                         let mut new_interpreter = Interpreter::with_dialect(program, self.dialect, Box::new(StdioConsole::new()));
                                          self.transfer_breakpoints_to_interpreter(&mut new_interpreter);
                                          self.interpreter = Some(new_interpreter);
                                          println!("Line {} updated", line_number);
@@ -842,16 +847,16 @@ impl BasicShell {
                      // No program loaded yet - create a new one
                      let line_content = parts[1].trim();
                      let full_line = format!("{} {}", line_number, line_content);
-                     let mut lexer = Lexer::new(&full_line);
+                     let mut lexer = Lexer::with_dialect(&full_line, self.dialect);
                      match lexer.tokenize() {
                          Ok(tokens) => {
-                             let mut parser = Parser::new(tokens);
-                             match parser.parse() {
+                             let mut parser = Parser::with_dialect(tokens, self.dialect);
+                             match parser.parse_lines() {
                                  Ok(temp_program) => {
                                      if let Some(new_line) = temp_program.lines.first() {
                                          let mut program = Program::new();
                                          program.add_line(line_number, line_content.to_string(), new_line.statements.clone());
-                                         let mut new_interpreter = Interpreter::new(program);
+                                         let mut new_interpreter = Interpreter::with_dialect(program, self.dialect, Box::new(StdioConsole::new()));
                                          self.transfer_breakpoints_to_interpreter(&mut new_interpreter);
                                          self.interpreter = Some(new_interpreter);
                                          println!("Line {} added to new program", line_number);
@@ -983,13 +988,27 @@ impl BasicShell {
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let program_file = if args.len() > 1 {
-        Some(args[1].clone())
-    } else {
-        None
-    };
+    let mut program_file = None;
+    let mut dialect = Dialect::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--dialect" || arg.starts_with("--dialect=") {
+            let name = match arg.strip_prefix("--dialect=") {
+                Some(name) => name.to_string(),
+                None => args.next().unwrap_or_default(),
+            };
+            dialect = match name.parse() {
+                Ok(dialect) => dialect,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            };
+        } else if program_file.is_none() {
+            program_file = Some(arg);
+        }
+    }
     
-    let mut shell = BasicShell::new(program_file);
+    let mut shell = BasicShell::new(program_file, dialect);
     shell.run();
 } 
\ No newline at end of file
@@ -1,4 +1,6 @@
 pub mod basic_types;
+pub mod basic_blocks;
+pub mod basic_procedures;
 pub mod basic_lexer;
 pub mod basic_parser;
 pub mod basic_operators;
@@ -7,6 +9,8 @@ pub mod basic_function_registry;
 pub mod basic_keyword_registry;
 pub mod basic_reports;
 pub mod basic_symbols;
+pub mod basic_console;
+pub mod basic_input_log;
 pub mod basic_interpreter;
 pub mod llvm_codegen;
-pub mod llvm_ir_builder;
\ No newline at end of file
+pub mod llvm_ir_builder;pub mod llvm_toolchain;
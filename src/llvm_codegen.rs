@@ -1,6 +1,40 @@
-use std::collections::HashMap;
-use crate::basic_types::{Program, Statement, Expression, ExpressionType, PrintItem};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::basic_types::{Program, Statement, Expression, ExpressionType, FileMode, PrintItem, SymbolValue, ResumeTarget, LoopCondition, CaseTest, VarType, MatValue, ErrorCondition};
+use crate::basic_function_registry::{ArgType, FUNCTION_REGISTRY};
+use crate::basic_dialect::Dialect;
+use crate::basic_blocks::{BlockTargets, LoopTarget};
+use crate::basic_procedures::{Procedure, ProcedureKind, Procedures};
 use crate::llvm_ir_builder::LLVMIRBuilder;
+use basic_runtime::ffi::{ERROR_RUNTIME, ERROR_TYPE};
+use basic_runtime::files::{self, FileErrorKind};
+use basic_runtime::matrix::{self, MatrixErrorKind};
+use basic_runtime::precision::{self, Precision};
+use basic_runtime::rng::RndAlgorithm;
+use basic_runtime::using::UsingError;
+use basic_runtime::operators::IntegerOp;
+use basic_runtime::{limits, mbf, strings};
+
+/// `i8*` constant expression for the shared empty string
+const EMPTY_STRING: &str = "getelementptr inbounds ([1 x i8], [1 x i8]* @basic_empty_string, i64 0, i64 0)";
+
+/// Label of the block that returns from main when execution runs off the last line
+const PROGRAM_END: &str = "program_end";
+
+/// Label of the block that pops the GOSUB stack and dispatches to the return point
+const RETURN_DISPATCH: &str = "gosub_return_dispatch";
+
+/// Label of the block that sends a raised error to the ON ERROR GOTO handler
+const ERROR_DISPATCH: &str = "error_dispatch";
+
+/// Label of the block that reports the raised error and exits
+const ERROR_REPORT: &str = "error_report";
+
+/// Labels of the blocks that RESUME and RESUME NEXT dispatch through
+const RESUME_DISPATCH: &str = "resume_dispatch";
+const RESUME_NEXT_DISPATCH: &str = "resume_next_dispatch";
+
+/// Label of the block that returns from a SUB or FUNCTION
+const PROCEDURE_RETURN: &str = "procedure_return";
 
 pub struct LLVMCodeGenerator {
     builder: LLVMIRBuilder,
@@ -8,9 +42,31 @@ pub struct LLVMCodeGenerator {
     array_info: HashMap<String, ArrayInfo>,
     line_blocks: HashMap<usize, String>, // line number -> block name
     current_line_index: usize,
+    current_offset: usize,
     program: Program,
     debug: bool,
     trace: bool,
+    dialect: Dialect,
+    terminated: bool, // the current block already ends in a terminator
+    user_functions: BTreeMap<String, UserFunction>,
+    function_params: HashMap<String, String>, // parameter name -> LLVM argument, while emitting DEF FN bodies
+    for_loops: Vec<ForLoop>,
+    for_ids: HashMap<(usize, usize), usize>, // (line index, offset) of a FOR -> index into for_loops
+    blocks: BlockTargets, // block IF, SELECT CASE and loop jump targets
+    procedures: Procedures, // SUB and FUNCTION extents and variables
+    current_procedure: Option<Procedure>, // the procedure being emitted, after main
+    gosub_returns: Vec<String>, // return-point label for each GOSUB site, indexed by site id
+    uses_return: bool,
+    data_values: Vec<SymbolValue>,
+    data_line_map: HashMap<usize, usize>, // line number -> position of its DATA in data_values
+    missing_lines: BTreeSet<usize>, // jump targets that do not exist in the program
+    error_messages: HashMap<String, String>, // message -> i8* constant expression
+    error_handlers: BTreeSet<usize>, // lines named by ON ERROR GOTO
+    error_sites: Vec<(String, String)>, // (retry, next) labels of each statement that can raise a trappable error
+    error_site_ids: HashMap<(usize, usize), usize>, // (line index, offset) -> index into error_sites
+    trapping: bool, // errors raised now go through the ON ERROR dispatch
+    sandbox: Option<String>,  // Directory OPEN is restricted to
+    seed: Option<f64>,        // RANDOMIZE seed the program starts with
 }
 
 #[derive(Clone)]
@@ -18,341 +74,3031 @@ struct ArrayInfo {
     global_name: String,
     dimensions: Vec<usize>,
     element_type: String, // "double" or "i8*"
+    dims_global: Option<String>, // elements in each dimension, for arrays MAT can redimension
+}
+
+#[derive(Clone)]
+struct UserFunction {
+    params: Vec<String>,
+    body: Expression,
+    line_index: usize,
+}
+
+#[derive(Clone)]
+struct ForLoop {
+    var: String,
+    location: (usize, usize),        // the FOR statement
+    stop: Expression,
+    step: Expression,
+    body_label: String,              // first statement of the loop body
+    exit_label: Result<String, (ErrorCondition, String)>, // statement after the matching NEXT, or why there is none
 }
 
 impl LLVMCodeGenerator {
     pub fn new(program: Program, debug: bool, trace: bool) -> Self {
+        Self::with_dialect(program, debug, trace, Dialect::default())
+    }
+
+    pub fn with_dialect(program: Program, debug: bool, trace: bool, dialect: Dialect) -> Self {
         let mut builder = LLVMIRBuilder::new();
-        
+
         // Set up module header
         builder.add_module_header("basic_program");
-        
+
         // Declare external C functions
         Self::declare_external_functions(&mut builder);
-        
+
         Self {
             builder,
             symbol_table: HashMap::new(),
             array_info: HashMap::new(),
             line_blocks: HashMap::new(),
             current_line_index: 0,
+            current_offset: 0,
             program,
             debug,
             trace,
+            dialect,
+            terminated: false,
+            user_functions: BTreeMap::new(),
+            function_params: HashMap::new(),
+            for_loops: Vec::new(),
+            for_ids: HashMap::new(),
+            blocks: BlockTargets::default(),
+            procedures: Procedures::default(),
+            current_procedure: None,
+            gosub_returns: Vec::new(),
+            uses_return: false,
+            data_values: Vec::new(),
+            data_line_map: HashMap::new(),
+            missing_lines: BTreeSet::new(),
+            error_messages: HashMap::new(),
+            error_handlers: BTreeSet::new(),
+            error_sites: Vec::new(),
+            error_site_ids: HashMap::new(),
+            trapping: false,
+            sandbox: None,
+            seed: None,
         }
     }
-    
+
+    /// Restricts the compiled program's OPEN to files inside `dir`
+    pub fn set_sandbox(&mut self, dir: &str) {
+        self.sandbox = Some(dir.to_string());
+    }
+
+    /// Seeds the compiled program's RND as `RANDOMIZE seed` would, for runs that repeat
+    pub fn set_seed(&mut self, seed: f64) {
+        self.seed = Some(seed);
+    }
+
     pub fn generate_ir(&mut self) -> String {
+        // Collect DATA, DEF FN and FOR information the statements refer to
+        self.collect_program_info();
+
         // Allocate variables
         self.allocate_variables();
-        
+        self.allocate_runtime_state();
+
         // Create main function
         self.builder.add_main_function();
-        
+
         // Initialize runtime (seed random, etc.)
-        // self.init_runtime(); // TODO: Only call when RND() is used
-        
+        self.init_runtime();
+
         // Create basic blocks for each line
-        for line in &self.program.lines {
-            let block_name = format!("line_{}", line.line_number);
-            self.line_blocks.insert(line.line_number, block_name.clone());
+        for i in 0..self.program.lines.len() {
+            let block_name = self.statement_label(i, 0);
+            self.line_blocks.insert(self.program.lines[i].line_number, block_name);
         }
-        
+
         // Branch to first line if program exists
         if !self.program.lines.is_empty() {
-            let first_line = self.program.lines[0].line_number;
-            let first_block = self.line_blocks.get(&first_line).unwrap();
-            self.builder.add_branch(first_block);
+            let first_block = self.statement_label(0, 0);
+            self.branch(&first_block);
         } else {
-            self.builder.add_return(Some("0"));
+            self.emit_exit("0");
             self.builder.end_function();
             return self.builder.build();
         }
-        
+
         // Generate code for each line
         let line_info: Vec<_> = self.program.lines.iter().enumerate()
             .map(|(i, line)| (i, line.line_number, line.statements.clone()))
             .collect();
+        // Errors in DEF FN bodies and in the runtime library itself are not trapped
+        self.trapping = !self.error_handlers.is_empty();
         for (i, line_number, statements) in line_info {
             self.current_line_index = i;
-            let block_name = self.line_blocks.get(&line_number).unwrap();
-            self.builder.add_basic_block(block_name);
-            
+            let block_name = self.statement_label(i, 0);
+            if let Some(procedure) = self.procedures.containing((i, 0)) {
+                // Procedures are emitted as functions after main; the program flows past them
+                if procedure.start.0 == i {
+                    let after = self.label_after(procedure.end.0, procedure.end.1);
+                    self.start_block(&block_name);
+                    self.branch(&after);
+                }
+                continue;
+            }
+            self.start_block(&block_name);
+
             // Add trace output if enabled
             if self.trace {
                 self.emit_trace(line_number);
             }
-            
+
             // Generate statements for this line
-            self.generate_line_statements(&statements);
-            
-            // If not terminated, branch to next line
-            if i + 1 < self.program.lines.len() {
-                let next_line = self.program.lines[i + 1].line_number;
-                let next_block = self.line_blocks.get(&next_line).unwrap();
-                self.builder.add_branch(next_block);
-            } else {
-                self.builder.add_return(Some("0"));
+            for (offset, statement) in statements.iter().enumerate() {
+                self.current_offset = offset;
+                if offset > 0 {
+                    let label = self.statement_label(i, offset);
+                    self.start_block(&label);
+                }
+                self.generate_statement(statement, i, offset);
             }
         }
-        
+
+        // Running off the end of the program ends it normally
+        self.trapping = false;
+        self.start_block(PROGRAM_END);
+        self.emit_exit("0");
+
+        self.emit_return_dispatch();
+        self.emit_error_dispatch();
+        self.emit_missing_lines();
+
         self.builder.end_function();
+
+        self.emit_procedures();
+        self.emit_user_functions();
         self.builder.build()
     }
-    
+
     fn declare_external_functions(builder: &mut LLVMIRBuilder) {
-        // I/O functions
-        builder.declare_function("printf", "i32", &["i8*".to_string()], true);
-        builder.declare_function("scanf", "i32", &["i8*".to_string()], true);
-        builder.declare_function("sprintf", "i32", &["i8*".to_string(), "i8*".to_string()], true);
-        builder.declare_function("sscanf", "i32", &["i8*".to_string(), "i8*".to_string()], true);
-        
-        // Memory management
-        builder.declare_function("malloc", "i8*", &["i64".to_string()], false);
-        builder.declare_function("strlen", "i64", &["i8*".to_string()], false);
-        builder.declare_function("strcmp", "i32", &["i8*".to_string(), "i8*".to_string()], false);
-        builder.declare_function("strcat", "i8*", &["i8*".to_string(), "i8*".to_string()], false);
-        builder.declare_function("strcpy", "i8*", &["i8*".to_string(), "i8*".to_string()], false);
-        builder.declare_function("strncpy", "i8*", &["i8*".to_string(), "i8*".to_string(), "i64".to_string()], false);
-        
-        // Math functions
-        builder.declare_function("sin", "double", &["double".to_string()], false);
-        builder.declare_function("cos", "double", &["double".to_string()], false);
-        builder.declare_function("sqrt", "double", &["double".to_string()], false);
-        builder.declare_function("exp", "double", &["double".to_string()], false);
-        builder.declare_function("log", "double", &["double".to_string()], false);
-        builder.declare_function("fabs", "double", &["double".to_string()], false);
-        builder.declare_function("pow", "double", &["double".to_string(), "double".to_string()], false);
-        builder.declare_function("floor", "double", &["double".to_string()], false);
-        
-        // Random functions
-        builder.declare_function("rand", "i32", &[], false);
-        builder.declare_function("srand", "void", &["i32".to_string()], false);
-        builder.declare_function("time", "i64", &["i64*".to_string()], false);
-        
+        let string = || "i8*".to_string();
+        let number = || "double".to_string();
+        let int = || "i32".to_string();
+
+        // Runtime library (basic_runtime): program lifetime and errors
+        builder.declare_function("basic_runtime_init", "void", &[], false);
+        builder.declare_function("basic_runtime_shutdown", "void", &[], false);
+        builder.declare_function("basic_runtime_error", "void", &[int(), string(), int()], false);
+        builder.declare_function("exit", "void", &[int()], false);
+        builder.declare_function("basic_trace_line", "void", &[int()], false);
+
+        // PRINT
+        builder.declare_function("basic_print_string", "void", &[string()], false);
+        builder.declare_function("basic_print_number", "void", &[number()], false);
+        builder.declare_function("basic_print_double", "void", &[number()], false);
+        builder.declare_function("basic_print_single", "void", &[number()], false);
+        builder.declare_function("basic_print_newline", "void", &[], false);
+        builder.declare_function("basic_print_comma", "void", &[], false);
+        builder.declare_function("basic_print_tab", "void", &[number()], false);
+        builder.declare_function("basic_number_format", "void", &[int(), int(), int()], false);
+        builder.declare_function("basic_round_mbf40", "double", &[number()], false);
+        builder.declare_function("basic_integer_op", "double", &[int(), number(), number(), int()], false);
+        builder.declare_function("basic_divides_by_zero", "i32", &[int(), number(), int()], false);
+        builder.declare_function("basic_not", "double", &[number(), int()], false);
+
+        // INPUT
+        builder.declare_function("basic_input_begin", "void", &[], false);
+        builder.declare_function("basic_input_line", "i8*", &[string()], false);
+        builder.declare_function("basic_input_field_count", "i32", &[string()], false);
+        builder.declare_function("basic_input_field", "i8*", &[string(), int()], false);
+        builder.declare_function("basic_parse_number", "i32", &[string(), "double*".to_string()], false);
+        builder.declare_function("basic_to_upper", "i8*", &[string()], false);
+        builder.declare_function("basic_input_bad_count", "i8*", &[int(), int()], false);
+        builder.declare_function("basic_input_bad_number", "i8*", &[], false);
+
+        // Strings
+        builder.declare_function("basic_str_concat", "i8*", &[string(), string()], false);
+        builder.declare_function("basic_check_string", "i8*", &[string(), int()], false);
+        builder.declare_function("basic_str_compare", "double", &[string(), string()], false);
+        builder.declare_function("basic_left", "i8*", &[string(), number()], false);
+        builder.declare_function("basic_right", "i8*", &[string(), number()], false);
+        builder.declare_function("basic_mid", "i8*", &[string(), number(), number()], false);
+        builder.declare_function("basic_chr", "i8*", &[number()], false);
+        builder.declare_function("basic_space", "i8*", &[number()], false);
+        builder.declare_function("basic_str", "i8*", &[number()], false);
+        builder.declare_function("basic_len", "double", &[string()], false);
+        builder.declare_function("basic_asc", "double", &[string(), int()], false);
+        builder.declare_function("basic_mid_assign", "i8*", &[string(), number(), number(), int(), string()], false);
+        builder.declare_function("basic_instr", "double", &[number(), string(), string()], false);
+        builder.declare_function("basic_string", "i8*", &[number(), number()], false);
+        builder.declare_function("basic_string_of", "i8*", &[number(), string()], false);
+        builder.declare_function("basic_ucase", "i8*", &[string()], false);
+        builder.declare_function("basic_lcase", "i8*", &[string()], false);
+        builder.declare_function("basic_ltrim", "i8*", &[string()], false);
+        builder.declare_function("basic_rtrim", "i8*", &[string()], false);
+        builder.declare_function("basic_hex", "i8*", &[number(), int()], false);
+        builder.declare_function("basic_oct", "i8*", &[number(), int()], false);
+        builder.declare_function("basic_val", "double", &[string(), int()], false);
+
+        // Random numbers
+        builder.declare_function("basic_rnd", "double", &[number()], false);
+        builder.declare_function("basic_rnd_algorithm", "void", &[int()], false);
+        builder.declare_function("basic_randomize", "void", &[number()], false);
+        builder.declare_function("basic_timer", "double", &[], false);
+
+        // Files: each operation leaves its status for basic_file_status
+        builder.declare_function("basic_file_status", "i32", &[], false);
+        builder.declare_function("basic_file_error", "i8*", &[], false);
+        builder.declare_function("basic_file_sandbox", "void", &[string()], false);
+        builder.declare_function("basic_file_open", "void", &[string(), int(), number(), number()], false);
+        builder.declare_function("basic_file_close", "void", &[number()], false);
+        builder.declare_function("basic_file_close_all", "void", &[], false);
+        builder.declare_function("basic_file_print_string", "void", &[number(), string()], false);
+        builder.declare_function("basic_file_print_number", "void", &[number(), number()], false);
+        builder.declare_function("basic_file_print_double", "void", &[number(), number()], false);
+        builder.declare_function("basic_file_print_single", "void", &[number(), number()], false);
+        builder.declare_function("basic_file_print_comma", "void", &[number()], false);
+        builder.declare_function("basic_file_print_tab", "void", &[number(), number()], false);
+        builder.declare_function("basic_file_print_newline", "void", &[number()], false);
+        builder.declare_function("basic_file_write_string", "void", &[number(), string()], false);
+        builder.declare_function("basic_file_write_number", "void", &[number(), number()], false);
+        builder.declare_function("basic_file_input_string", "i8*", &[number()], false);
+        builder.declare_function("basic_file_input_number", "double", &[number()], false);
+        builder.declare_function("basic_file_line_input", "i8*", &[number()], false);
+        builder.declare_function("basic_file_eof", "double", &[number()], false);
+        builder.declare_function("basic_file_lof", "double", &[number()], false);
+        builder.declare_function("basic_file_field_add", "void", &[number(), string()], false);
+        builder.declare_function("basic_file_field", "void", &[number()], false);
+        builder.declare_function("basic_file_field_value", "i8*", &[number(), string(), string()], false);
+        builder.declare_function("basic_file_set_field", "i8*", &[string(), string(), string(), int()], false);
+        builder.declare_function("basic_file_get", "void", &[number(), number(), int()], false);
+        builder.declare_function("basic_file_put", "void", &[number(), number(), int()], false);
+
+        // MKI$ and its relatives: null or NaN when the conversion fails
+        builder.declare_function("basic_mki", "i8*", &[number()], false);
+        builder.declare_function("basic_mks", "i8*", &[number()], false);
+        builder.declare_function("basic_mkd", "i8*", &[number()], false);
+        builder.declare_function("basic_cvi", "double", &[string()], false);
+        builder.declare_function("basic_cvs", "double", &[string()], false);
+        builder.declare_function("basic_cvd", "double", &[string()], false);
+
+        // PRINT USING: values are added one by one, then laid out by the format
+        builder.declare_function("basic_using_number", "void", &[number()], false);
+        builder.declare_function("basic_using_string", "void", &[string()], false);
+        builder.declare_function("basic_print_using", "i8*", &[string()], false);
+        builder.declare_function("basic_using_status", "i32", &[], false);
+        builder.declare_function("basic_using_error", "i8*", &[], false);
+
+        // MAT statements: operands are pushed, worked out, then stored in the array assigned to
+        builder.declare_function("basic_mat_push", "void", &["double*".to_string(), "i64*".to_string(), int(), int(), string()], false);
+        builder.declare_function("basic_mat_compute", "void", &[int(), number(), number(), number(), int()], false);
+        builder.declare_function("basic_mat_read", "void", &["double*".to_string(), "i8*".to_string(), int(), "i32*".to_string()], false);
+        builder.declare_function("basic_mat_store", "void", &["double*".to_string(), "i64*".to_string(), int(), int(), "i64".to_string(), string(), int(), int()], false);
+        builder.declare_function("basic_mat_print", "void", &[int(), int()], false);
+        builder.declare_function("basic_mat_status", "i32", &[], false);
+        builder.declare_function("basic_mat_error", "i8*", &[], false);
+        builder.declare_function("basic_mat_det", "double", &[], false);
+
+        // Math functions (libm)
+        builder.declare_function("sin", "double", &[number()], false);
+        builder.declare_function("cos", "double", &[number()], false);
+        builder.declare_function("tan", "double", &[number()], false);
+        builder.declare_function("atan", "double", &[number()], false);
+        builder.declare_function("sqrt", "double", &[number()], false);
+        builder.declare_function("exp", "double", &[number()], false);
+        builder.declare_function("log", "double", &[number()], false);
+        builder.declare_function("fabs", "double", &[number()], false);
+        builder.declare_function("pow", "double", &[number(), number()], false);
+        builder.declare_function("floor", "double", &[number()], false);
+        builder.declare_function("round", "double", &[number()], false);
+
         builder.line(""); // Add blank line after declarations
     }
-    
+
+    /// Gathers the program-wide facts statements need at code generation time:
+    /// DATA values, DEF FN bodies, procedures and the FOR loops with their matching NEXTs.
+    fn collect_program_info(&mut self) {
+        // The parser has already rejected misplaced procedure statements
+        self.procedures = Procedures::resolve(&self.program).unwrap_or_default();
+        for (i, line) in self.program.lines.iter().enumerate() {
+            for (offset, statement) in line.statements.iter().enumerate() {
+                match statement {
+                    Statement::Data { values } => {
+                        self.data_line_map.insert(line.line_number, self.data_values.len());
+                        self.data_values.extend(values.iter().cloned());
+                    }
+                    Statement::Def { name, params, expr } => {
+                        self.user_functions.entry(name.clone()).or_insert(UserFunction {
+                            params: params.clone(),
+                            body: expr.clone(),
+                            line_index: i,
+                        });
+                    }
+                    Statement::For { var, stop, step, .. } => {
+                        let for_loop = ForLoop {
+                            var: var.clone(),
+                            location: (i, offset),
+                            stop: stop.clone(),
+                            step: step.clone().unwrap_or_else(|| Expression::new_number(1.0)),
+                            body_label: self.label_after(i, offset),
+                            exit_label: self.find_matching_next(i, offset, var),
+                        };
+                        self.for_ids.insert((i, offset), self.for_loops.len());
+                        self.for_loops.push(for_loop);
+                    }
+                    Statement::OnErrorGoto { line } if *line != 0 => {
+                        self.error_handlers.insert(*line);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // The parser has already rejected unbalanced blocks
+        self.blocks = BlockTargets::resolve(&self.program).unwrap_or_default();
+    }
+
+    /// Lexical search for the NEXT that closes the FOR at (index, offset), mirroring the
+    /// interpreter. Returns the label of the statement after that NEXT.
+    fn find_matching_next(&self, index: usize, offset: usize, var: &str) -> Result<String, (ErrorCondition, String)> {
+        let mut depth = 0;
+        for (i, line) in self.program.lines.iter().enumerate().skip(index) {
+            let start_offset = if i == index { offset + 1 } else { 0 };
+            for (j, stmt) in line.statements.iter().enumerate().skip(start_offset) {
+                if !self.procedures.same_owner((index, offset), (i, j)) {
+                    continue;
+                }
+                match stmt {
+                    Statement::For { .. } => depth += 1,
+                    Statement::Next { var: next_var } => {
+                        if depth == 0 {
+                            if next_var == var {
+                                return Ok(self.label_after(i, j));
+                            }
+                            return Err((ErrorCondition::NextWithoutFor,
+                                format!("Unexpected NEXT for '{}' while looking for NEXT for '{}'", next_var, var)));
+                        }
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Err((ErrorCondition::ForWithoutNext, format!("No matching NEXT found for FOR {}", var)))
+    }
+
     fn allocate_variables(&mut self) {
         // Scan program for variables and arrays
-        let mut variables = HashMap::new();
-        let mut arrays = HashMap::new();
-        
+        let mut variables = BTreeSet::new();
+        let mut arrays = BTreeMap::new();
+        let mut mat_arrays = BTreeSet::new();
+
         for line in &self.program.lines {
             for statement in &line.statements {
                 self.collect_variables_from_statement(statement, &mut variables, &mut arrays);
+                mat_arrays.extend(Self::mat_arrays(statement).into_iter().cloned());
             }
         }
-        
+
+        self.builder.add_string_constant("basic_empty_string", "");
+
         // Allocate global variables
-        for (var_name, _var_type) in variables {
-            let global_name = format!("@global_{}", var_name);
-            let llvm_type = if var_name.ends_with('$') { "i8*" } else { "double" };
-            let initializer = if var_name.ends_with('$') { 
-                Some("null") 
-            } else { 
-                Some("0.0") 
-            };
-            
-            self.builder.add_global_variable(&global_name, llvm_type, initializer, false);
-            self.symbol_table.insert(var_name, global_name);
+        for var_name in variables {
+            let global_name = format!("global_{}", Self::ir_name(&var_name));
+            let (llvm_type, initializer) = self.variable_type(&var_name);
+
+            self.builder.add_global_variable(&global_name, &llvm_type, Some(initializer), false);
+            self.symbol_table.insert(var_name, format!("@{}", global_name));
         }
-        
+
         // Allocate arrays
         for (array_name, dimensions) in arrays {
-            let global_name = format!("@array_{}", array_name);
+            let global_name = format!("array_{}", Self::ir_name(&array_name));
             let element_type = if array_name.ends_with('$') { "i8*" } else { "double" };
-            
-            // For now, create a simple array - in practice this would be more complex
+
+            // DIM gives the highest subscript; arrays are stored flat, in row-major order
+            let offset = self.dialect.array_offset;
+            let dimensions: Vec<usize> = dimensions.iter().map(|&bound| (bound + 1).saturating_sub(offset)).collect();
             let array_size = dimensions.iter().product::<usize>();
             let array_type = format!("[{} x {}]", array_size, element_type);
-            
+
             self.builder.add_global_variable(&global_name, &array_type, None, false);
-            
+
+            // MAT can give an array new dimensions that fit in its elements, so the
+            // arrays it works on keep theirs in a global
+            let dims_global = mat_arrays.contains(&array_name).then(|| {
+                let dims_name = format!("dims_{}", Self::ir_name(&array_name));
+                let sizes: Vec<String> = dimensions.iter().map(|size| format!("i64 {}", size)).collect();
+                let dims_type = format!("[{} x i64]", dimensions.len());
+                self.builder.add_global_variable(&dims_name, &dims_type, Some(&format!("[{}]", sizes.join(", "))), false);
+                format!("@{}", dims_name)
+            });
+
             self.array_info.insert(array_name, ArrayInfo {
-                global_name,
+                global_name: format!("@{}", global_name),
                 dimensions,
                 element_type: element_type.to_string(),
+                dims_global,
             });
         }
     }
-    
-    fn collect_variables_from_statement(&self, statement: &Statement, variables: &mut HashMap<String, String>, arrays: &mut HashMap<String, Vec<usize>>) {
+
+    /// Globals backing the GOSUB and FOR stacks and the DATA tables
+    fn allocate_runtime_state(&mut self) {
+        self.builder.line(&format!("@basic_gosub_stack = internal global [{} x i32] zeroinitializer", self.dialect.max_gosub_depth));
+        self.builder.line("@basic_gosub_sp = internal global i32 0");
+        self.builder.line(&format!("@basic_for_stack = internal global [{} x i32] zeroinitializer", self.dialect.max_for_depth));
+        self.builder.line("@basic_for_sp = internal global i32 0");
+        self.builder.line("@basic_for_scan = internal global i32 0");
+        self.builder.line("@basic_fn_depth = internal global i32 0");
+        if !self.procedures.is_empty() {
+            // Depths of the FOR and GOSUB stacks when the running procedure was called
+            self.builder.line("@basic_for_base = internal global i32 0");
+            self.builder.line("@basic_gosub_base = internal global i32 0");
+        }
+        self.builder.line("@basic_data_ptr = internal global i32 0");
+        self.builder.line("@basic_input_scratch = internal global double 0.0");
+        if !self.error_handlers.is_empty() {
+            // ON ERROR state: the handler line (0 when off), whether the handler is running,
+            // ERR and ERL, and the raised error kept for RESUME or for reporting
+            self.builder.line("@basic_err_handler = internal global i32 0");
+            self.builder.line("@basic_err_active = internal global i32 0");
+            self.builder.line("@basic_err_code = internal global i32 0");
+            self.builder.line("@basic_err_line = internal global i32 0");
+            self.builder.line("@basic_err_site = internal global i32 0");
+            self.builder.line("@basic_err_kind = internal global i32 0");
+            self.builder.line(&format!("@basic_err_message = internal global i8* {}", EMPTY_STRING));
+            // ERR for each status a file operation or MAT statement can leave, indexed by the status
+            let codes = self.error_codes(FileErrorKind::ALL.into_iter().map(ErrorCondition::from));
+            self.builder.line(&format!("@basic_file_err_codes = internal constant [{} x i32] [{}]", codes.len(), codes.join(", ")));
+            if self.array_info.values().any(|info| info.dims_global.is_some()) {
+                let codes = self.error_codes(MatrixErrorKind::ALL.into_iter().map(ErrorCondition::from));
+                self.builder.line(&format!("@basic_mat_err_codes = internal constant [{} x i32] [{}]", codes.len(), codes.join(", ")));
+            }
+        }
+
+        if !self.data_values.is_empty() {
+            let count = self.data_values.len();
+            let mut kinds = Vec::new();
+            let mut numbers = Vec::new();
+            let mut strings = Vec::new();
+            for value in self.data_values.clone() {
+                match value {
+                    SymbolValue::String(s) => {
+                        kinds.push("i8 1".to_string());
+                        numbers.push("double 0.0".to_string());
+                        strings.push(format!("i8* {}", self.builder.add_string_constant_ptr(&s)));
+                    }
+                    SymbolValue::Number(n) => {
+                        kinds.push("i8 0".to_string());
+                        numbers.push(format!("double {}", Self::float_literal(self.dialect.precision.round(n))));
+                        strings.push("i8* null".to_string());
+                    }
+                    _ => {
+                        kinds.push("i8 0".to_string());
+                        numbers.push("double 0.0".to_string());
+                        strings.push("i8* null".to_string());
+                    }
+                }
+            }
+            self.builder.line(&format!("@basic_data_kinds = internal constant [{} x i8] [{}]", count, kinds.join(", ")));
+            self.builder.line(&format!("@basic_data_numbers = internal constant [{} x double] [{}]", count, numbers.join(", ")));
+            self.builder.line(&format!("@basic_data_strings = internal constant [{} x i8*] [{}]", count, strings.join(", ")));
+        }
+        self.builder.line("");
+    }
+
+    /// The dialect's ERR codes for the runtime statuses with these conditions, after 0 for success
+    fn error_codes(&self, conditions: impl Iterator<Item = ErrorCondition>) -> Vec<String> {
+        std::iter::once("i32 0".to_string())
+            .chain(conditions.map(|condition| format!("i32 {}", self.dialect.error_code(condition))))
+            .collect()
+    }
+
+    fn collect_variables_from_statement(&self, statement: &Statement, variables: &mut BTreeSet<String>, arrays: &mut BTreeMap<String, Vec<usize>>) {
+        let mut expressions: Vec<&Expression> = Vec::new();
         match statement {
-            Statement::Let { var, value: _ } => {
-                if let ExpressionType::Variable(name) = &var.expr_type {
-                    let var_type = if name.ends_with('$') { "string" } else { "number" };
-                    variables.insert(name.clone(), var_type.to_string());
+            Statement::Let { var, value } => {
+                expressions.push(var);
+                expressions.push(value);
+            },
+            Statement::Print { items } => {
+                for item in items {
+                    if let PrintItem::Expression(expr) = item {
+                        expressions.push(expr);
+                    }
+                }
+            },
+            Statement::Input { vars, .. } => {
+                variables.extend(vars.iter().cloned());
+            },
+            Statement::Open { path, number, record_len, .. } => {
+                expressions.extend([path, number]);
+                expressions.extend(record_len.iter());
+            },
+            Statement::Close { numbers } => expressions.extend(numbers.iter()),
+            Statement::PrintFile { number, items } => {
+                expressions.push(number);
+                for item in items {
+                    if let PrintItem::Expression(expr) = item {
+                        expressions.push(expr);
+                    }
+                }
+            },
+            Statement::PrintUsing { number, format, exprs, .. } => {
+                expressions.extend(number.iter());
+                expressions.push(format);
+                expressions.extend(exprs.iter());
+            },
+            Statement::WriteFile { number, exprs } => {
+                expressions.push(number);
+                expressions.extend(exprs.iter());
+            },
+            Statement::InputFile { number, vars } => {
+                expressions.push(number);
+                variables.extend(vars.iter().cloned());
+            },
+            Statement::LineInputFile { number, var } => {
+                expressions.push(number);
+                variables.insert(var.clone());
+            },
+            Statement::Field { number, fields } => {
+                expressions.push(number);
+                for (width, var) in fields {
+                    expressions.push(width);
+                    variables.insert(var.clone());
+                }
+            },
+            Statement::Get { number, record } | Statement::Put { number, record } => {
+                expressions.push(number);
+                expressions.extend(record.iter());
+            },
+            Statement::Lset { var, value } | Statement::Rset { var, value } => {
+                variables.insert(var.clone());
+                expressions.push(value);
+            },
+            Statement::MidAssign { var, start, length, value } => {
+                expressions.extend([var, start, value]);
+                expressions.extend(length.iter());
+            },
+            Statement::If { condition } => expressions.push(condition),
+            Statement::For { var, start, stop, step } => {
+                variables.insert(var.clone());
+                expressions.push(start);
+                expressions.push(stop);
+                if let Some(step) = step {
+                    expressions.push(step);
+                }
+            },
+            Statement::Next { var } => {
+                variables.insert(var.clone());
+            },
+            Statement::Read { vars } => expressions.extend(vars.iter()),
+            Statement::MatRead { arrays: mat_arrays } => {
+                for (_, bounds) in mat_arrays {
+                    expressions.extend(bounds.iter());
+                }
+            },
+            Statement::MatAssign { value, .. } => {
+                if let MatValue::Scaled(scalar, _) = value {
+                    expressions.push(scalar);
                 }
+                expressions.extend(value.bounds().iter());
             },
             Statement::Dim { arrays: dim_arrays } => {
                 for array_decl in dim_arrays {
-                    let _array_type = if array_decl.name.ends_with('$') { "string" } else { "number" };
-                    arrays.insert(array_decl.name.clone(), array_decl.dimensions.clone());
+                    arrays.entry(array_decl.name.clone()).or_insert_with(|| array_decl.dimensions.clone());
+                }
+            },
+            Statement::OnGoto { expr, .. } | Statement::OnGosub { expr, .. } => expressions.push(expr),
+            Statement::Def { expr, .. } => expressions.push(expr),
+            Statement::While { condition } => expressions.push(condition),
+            Statement::IfBlock { condition } | Statement::ElseIf { condition } => expressions.push(condition),
+            Statement::SelectCase { selector } => expressions.push(selector),
+            Statement::Case { tests } => {
+                for test in tests {
+                    match test {
+                        CaseTest::Value(expr) | CaseTest::Is(_, expr) => expressions.push(expr),
+                        CaseTest::Range(low, high) => expressions.extend([low, high]),
+                    }
                 }
             },
+            Statement::Do { condition: Some(condition) } | Statement::Loop { condition: Some(condition) } => {
+                expressions.push(condition.expression());
+            },
             _ => {}
         }
+        for expr in expressions {
+            Self::collect_variables_from_expression(expr, variables);
+        }
+    }
+
+    /// The arrays a MAT statement works on
+    fn mat_arrays(statement: &Statement) -> Vec<&String> {
+        match statement {
+            Statement::MatRead { arrays } => arrays.iter().map(|(name, _)| name).collect(),
+            Statement::MatPrint { arrays } => arrays.iter().map(|(name, _)| name).collect(),
+            Statement::MatAssign { var, value } => std::iter::once(var).chain(value.arrays()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn collect_variables_from_expression(expr: &Expression, variables: &mut BTreeSet<String>) {
+        match &expr.expr_type {
+            ExpressionType::Variable(name) => {
+                variables.insert(name.clone());
+            }
+            ExpressionType::Array { indices, .. } => {
+                for index in indices {
+                    Self::collect_variables_from_expression(index, variables);
+                }
+            }
+            ExpressionType::FunctionCall { args, .. } => {
+                for arg in args {
+                    Self::collect_variables_from_expression(arg, variables);
+                }
+            }
+            ExpressionType::BinaryOp { left, right, .. } => {
+                Self::collect_variables_from_expression(left, variables);
+                Self::collect_variables_from_expression(right, variables);
+            }
+            ExpressionType::UnaryOp { expr, .. } => {
+                Self::collect_variables_from_expression(expr, variables);
+            }
+            ExpressionType::Number(_) | ExpressionType::String(_) => {}
+        }
+    }
+
+    fn init_runtime(&mut self) {
+        // Seeds the random number generator, among other things
+        self.builder.add_call_void("basic_runtime_init", &[]);
+        let format = self.dialect.number_format;
+        self.builder.add_call_void("basic_number_format", &[
+            format!("i32 {}", format.digits), format!("i32 {}", format.min_fixed_exponent), format!("i32 {}", format.str_sign_space as i32),
+        ]);
+        if let Some(dir) = self.sandbox.clone() {
+            let dir = self.builder.add_string_constant_ptr(&dir);
+            self.builder.add_call_void("basic_file_sandbox", &[format!("i8* {}", dir)]);
+        }
+        if self.dialect.rnd != RndAlgorithm::BasicRs {
+            self.builder.add_call_void("basic_rnd_algorithm", &[format!("i32 {}", self.dialect.rnd.code())]);
+        }
+        if let Some(seed) = self.seed {
+            self.builder.add_call_void("basic_randomize", &[format!("double {}", Self::float_literal(seed))]);
+        }
+    }
+
+    fn emit_trace(&mut self, line_number: usize) {
+        self.builder.add_call_void("basic_trace_line", &[format!("i32 {}", line_number)]);
+    }
+
+    /// Returns from main with the given exit code, flushing the runtime's output first.
+    /// Inside a procedure, exits the process instead.
+    fn emit_exit(&mut self, code: &str) {
+        self.builder.add_call_void("basic_runtime_shutdown", &[]);
+        if self.current_procedure.is_some() {
+            self.builder.add_call_void("exit", &[format!("i32 {}", code)]);
+            self.builder.add_unreachable();
+        } else {
+            self.builder.add_return(Some(code));
+        }
+        self.terminated = true;
+    }
+
+    // ------------------------------------------------------------------
+    // Labels and block management
+    // ------------------------------------------------------------------
+
+    fn statement_label(&self, index: usize, offset: usize) -> String {
+        let line_number = self.program.lines[index].line_number;
+        if offset == 0 {
+            format!("line_{}", line_number)
+        } else {
+            format!("line_{}_{}", line_number, offset)
+        }
+    }
+
+    /// Label of the statement that follows (index, offset) in execution order
+    fn label_after(&self, index: usize, offset: usize) -> String {
+        if offset + 1 < self.program.lines[index].statements.len() {
+            self.statement_label(index, offset + 1)
+        } else {
+            self.next_line_label(index)
+        }
+    }
+
+    fn next_line_label(&self, index: usize) -> String {
+        if index + 1 < self.program.lines.len() {
+            self.statement_label(index + 1, 0)
+        } else {
+            PROGRAM_END.to_string()
+        }
+    }
+
+    /// Label of the statement after the next ELSE on this line, or of the next line
+    fn label_after_else(&self, index: usize, offset: usize) -> String {
+        let statements = &self.program.lines[index].statements;
+        match (offset + 1..statements.len()).find(|&j| matches!(statements[j], Statement::Else)) {
+            Some(else_offset) => self.label_after(index, else_offset),
+            None => self.next_line_label(index),
+        }
+    }
+
+    fn line_target(&mut self, line: usize) -> String {
+        match self.line_blocks.get(&line) {
+            Some(block) => block.clone(),
+            None => {
+                self.missing_lines.insert(line);
+                format!("missing_line_{}", line)
+            }
+        }
+    }
+
+    /// Opens a new block, falling through into it from the current one if needed
+    fn start_block(&mut self, label: &str) {
+        if !self.terminated {
+            self.builder.add_branch(label);
+        }
+        self.builder.add_basic_block(label);
+        self.terminated = false;
+    }
+
+    fn branch(&mut self, label: &str) {
+        self.builder.add_branch(label);
+        self.terminated = true;
+    }
+
+    fn conditional_branch(&mut self, condition: &str, true_label: &str, false_label: &str) {
+        self.builder.add_conditional_branch(condition, true_label, false_label);
+        self.terminated = true;
+    }
+
+    fn current_line_number(&self) -> usize {
+        self.program.lines[self.current_line_index].line_number
+    }
+
+    // ------------------------------------------------------------------
+    // Runtime errors
+    // ------------------------------------------------------------------
+
+    fn error_message(&mut self, message: &str) -> String {
+        if let Some(ptr) = self.error_messages.get(message) {
+            return ptr.clone();
+        }
+        let ptr = self.builder.add_string_constant_ptr(message);
+        self.error_messages.insert(message.to_string(), ptr.clone());
+        ptr
+    }
+
+    fn emit_error_call(&mut self, condition: ErrorCondition, message: &str, line_number: usize) {
+        self.emit_error_call_with_kind(ERROR_RUNTIME, condition, message, line_number);
+    }
+
+    /// Calls the runtime's error reporter; `kind` selects the label and exit code
+    fn emit_error_call_with_kind(&mut self, kind: i32, condition: ErrorCondition, message: &str, line_number: usize) {
+        let msg = self.error_message(message);
+        self.emit_raise(kind, &msg, condition, line_number);
+    }
+
+    /// Raises an error whose text is the i8* value `msg`; ERR reports the code for
+    /// `condition`. While the program may have ON ERROR GOTO active, the error is recorded
+    /// and sent to the error dispatch instead of straight to the runtime's reporter.
+    fn emit_raise(&mut self, kind: i32, msg: &str, condition: ErrorCondition, line_number: usize) {
+        if !self.trapping {
+            self.builder.add_call_void("basic_runtime_error", &[format!("i32 {}", kind), format!("i8* {}", msg), format!("i32 {}", line_number)]);
+            self.builder.add_unreachable();
+            self.terminated = true;
+            return;
+        }
+        let code = self.dialect.error_code(condition).to_string();
+        self.emit_trapped_raise(kind, msg, &code, line_number);
+    }
+
+    /// Records a raised error for the error dispatch; `code` is the i32 value ERR reports
+    fn emit_trapped_raise(&mut self, kind: i32, msg: &str, code: &str, line_number: usize) {
+        let site = self.error_site();
+        self.builder.add_typed_store("i32", code, "@basic_err_code");
+        self.builder.add_typed_store("i32", &line_number.to_string(), "@basic_err_line");
+        self.builder.add_typed_store("i32", &site.to_string(), "@basic_err_site");
+        self.builder.add_typed_store("i32", &kind.to_string(), "@basic_err_kind");
+        self.builder.add_typed_store("i8*", msg, "@basic_err_message");
+        self.branch(ERROR_DISPATCH);
+    }
+
+    /// Site id of the statement being generated, for RESUME and RESUME NEXT
+    fn error_site(&mut self) -> usize {
+        let key = (self.current_line_index, self.current_offset);
+        if let Some(&site) = self.error_site_ids.get(&key) {
+            return site;
+        }
+        let site = self.error_sites.len();
+        self.error_sites.push((self.statement_label(key.0, key.1), self.label_after(key.0, key.1)));
+        self.error_site_ids.insert(key, site);
+        site
+    }
+
+    /// Jumps to the handler if trapping is on and the handler is not already running;
+    /// otherwise reports the raised error. Also emits the RESUME dispatch blocks.
+    fn emit_error_dispatch(&mut self) {
+        if self.error_handlers.is_empty() {
+            return;
+        }
+        self.builder.add_basic_block(ERROR_DISPATCH);
+        let handler = self.load("i32", "@basic_err_handler");
+        let active = self.load("i32", "@basic_err_active");
+        let has_handler = self.builder.next_temp();
+        self.builder.add_icmp("ne", &handler, "0", &has_handler[1..]);
+        let idle = self.builder.next_temp();
+        self.builder.add_icmp("eq", &active, "0", &idle[1..]);
+        let trap = self.builder.next_temp();
+        self.builder.add_binary_op("and", &has_handler, &idle, "i1", &trap[1..]);
+        let enter = self.builder.next_block();
+        self.conditional_branch(&trap, &enter, ERROR_REPORT);
+        self.builder.add_basic_block(&enter);
+        self.builder.add_typed_store("i32", "1", "@basic_err_active");
+        // ON ERROR GOTO only stores lines that exist
+        let cases: Vec<(i64, String)> = self.error_handlers.iter()
+            .filter_map(|line| self.line_blocks.get(line).map(|label| (*line as i64, label.clone())))
+            .collect();
+        self.builder.add_switch(&handler, ERROR_REPORT, &cases);
+
+        self.builder.add_basic_block(ERROR_REPORT);
+        self.emit_error_report();
+
+        for (label, next) in [(RESUME_DISPATCH, false), (RESUME_NEXT_DISPATCH, true)] {
+            self.builder.add_basic_block(label);
+            let site = self.load("i32", "@basic_err_site");
+            let cases: Vec<(i64, String)> = self.error_sites.iter().enumerate()
+                .map(|(id, (retry, after))| (id as i64, if next { after.clone() } else { retry.clone() }))
+                .collect();
+            let invalid = self.builder.next_block();
+            self.builder.add_switch(&site, &invalid, &cases);
+            self.builder.add_basic_block(&invalid);
+            self.builder.add_unreachable();
+        }
+        self.terminated = true;
+    }
+
+    /// Reports the error kept for the handler, and exits
+    fn emit_error_report(&mut self) {
+        let kind = self.load("i32", "@basic_err_kind");
+        let msg = self.load("i8*", "@basic_err_message");
+        let line = self.load("i32", "@basic_err_line");
+        self.builder.add_call_void("basic_runtime_error", &[format!("i32 {}", kind), format!("i8* {}", msg), format!("i32 {}", line)]);
+        self.builder.add_unreachable();
+        self.terminated = true;
+    }
+
+    /// Unconditionally raises a runtime error. Code generation continues in a fresh
+    /// (unreachable) block so callers can keep emitting instructions.
+    fn emit_runtime_error(&mut self, condition: ErrorCondition, message: &str) {
+        let line_number = self.current_line_number();
+        self.emit_error_call(condition, message, line_number);
+        let dead = self.builder.next_block();
+        self.builder.add_basic_block(&dead);
+        self.terminated = false;
+    }
+
+    /// Raises the error the last file operation left in the runtime, if there is one
+    fn emit_file_check(&mut self) {
+        self.emit_status_check("basic_file_status", "basic_file_error", "@basic_file_err_codes", FileErrorKind::ALL.len() + 1);
+    }
+
+    /// Raises the error the last MAT statement left in the runtime, if there is one
+    fn emit_mat_check(&mut self) {
+        self.emit_status_check("basic_mat_status", "basic_mat_error", "@basic_mat_err_codes", MatrixErrorKind::ALL.len() + 1);
+    }
+
+    /// Calls the runtime's `status` function and, unless it gives 0, raises the message
+    /// `error` gives. Trapped, ERR comes from the `count` codes of `codes`, by status.
+    fn emit_status_check(&mut self, status: &str, error: &str, codes: &str, count: usize) {
+        let status = self.call("i32", status, &[]);
+        let ok = self.builder.next_temp();
+        self.builder.add_icmp("eq", &status, "0", &ok[1..]);
+        let ok_block = self.builder.next_block();
+        let err_block = self.builder.next_block();
+        self.conditional_branch(&ok, &ok_block, &err_block);
+
+        self.builder.add_basic_block(&err_block);
+        let msg = self.call("i8*", error, &[]);
+        let line_number = self.current_line_number();
+        if self.trapping {
+            let slot = self.builder.next_temp();
+            self.builder.line(&format!("  {} = getelementptr inbounds [{} x i32], [{} x i32]* {}, i64 0, i32 {}",
+                slot, count, count, codes, status));
+            let code = self.load("i32", &slot);
+            self.emit_trapped_raise(ERROR_RUNTIME, &msg, &code, line_number);
+        } else {
+            self.emit_raise(ERROR_RUNTIME, &msg, ErrorCondition::Unprintable, line_number);
+        }
+        self.builder.add_basic_block(&ok_block);
+        self.terminated = false;
+    }
+
+    /// Raises a runtime error unless the i1 value `ok` is true
+    fn emit_error_unless(&mut self, ok: &str, condition: ErrorCondition, message: &str) {
+        let ok_block = self.builder.next_block();
+        let err_block = self.builder.next_block();
+        self.conditional_branch(ok, &ok_block, &err_block);
+        self.builder.add_basic_block(&err_block);
+        let line_number = self.current_line_number();
+        self.emit_error_call(condition, message, line_number);
+        self.builder.add_basic_block(&ok_block);
+        self.terminated = false;
+    }
+
+    fn emit_missing_lines(&mut self) {
+        for line in self.missing_lines.clone() {
+            self.builder.add_basic_block(&format!("missing_line_{}", line));
+            self.emit_error_call(ErrorCondition::UndefinedLine, &format!("Line number {} not found", line), line);
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // Statements
+    // ------------------------------------------------------------------
+
+    fn generate_statement(&mut self, statement: &Statement, index: usize, offset: usize) {
+        match statement {
+            Statement::Let { var, value } => self.codegen_let(var, value),
+            Statement::MidAssign { var, start, length, value } => self.codegen_mid_assign(var, start, length.as_ref(), value),
+            Statement::Print { items } => self.codegen_print(items),
+            Statement::Input { vars, prompt } => self.codegen_input(vars, prompt.as_deref(), index, offset),
+            Statement::Open { path, mode, number, record_len } => self.codegen_open(path, *mode, number, record_len.as_ref()),
+            Statement::Close { numbers } => self.codegen_close(numbers),
+            Statement::PrintFile { number, items } => self.codegen_print_file(number, items),
+            Statement::PrintUsing { number, format, exprs, newline } => self.codegen_print_using(number.as_ref(), format, exprs, *newline),
+            Statement::WriteFile { number, exprs } => self.codegen_write_file(number, exprs),
+            Statement::InputFile { number, vars } => self.codegen_input_file(number, vars),
+            Statement::LineInputFile { number, var } => {
+                let Some(number) = self.codegen_file_number(number) else {
+                    return;
+                };
+                let line = self.call("i8*", "basic_file_line_input", &[format!("double {}", number)]);
+                self.emit_file_check();
+                self.store_variable(var, &line);
+            },
+            Statement::Field { number, fields } => self.codegen_field(number, fields),
+            Statement::Get { number, record } => self.codegen_get_put("basic_file_get", number, record.as_ref()),
+            Statement::Put { number, record } => self.codegen_get_put("basic_file_put", number, record.as_ref()),
+            Statement::Lset { var, value } => self.codegen_set_field(var, value, false),
+            Statement::Rset { var, value } => self.codegen_set_field(var, value, true),
+            Statement::If { condition } => {
+                let Some(truth) = self.codegen_condition(condition, "IF") else {
+                    return;
+                };
+                let true_label = self.label_after(index, offset);
+                let false_label = self.label_after_else(index, offset);
+                self.conditional_branch(&truth, &true_label, &false_label);
+            },
+            Statement::Then => {},
+            Statement::Else => {
+                // Reaching an ELSE means the THEN branch finished: skip the ELSE branch
+                let target = self.label_after_else(index, offset);
+                self.branch(&target);
+            },
+            Statement::For { var, start, .. } => self.codegen_for(var, start, index, offset),
+            Statement::Next { var } => self.codegen_next(var, index, offset),
+            Statement::Goto { line } => {
+                let target = self.line_target(*line);
+                self.branch(&target);
+            },
+            Statement::Gosub { line } => {
+                let return_label = self.label_after(index, offset);
+                self.emit_gosub_push(return_label);
+                let target = self.line_target(*line);
+                self.branch(&target);
+            },
+            Statement::Return => {
+                self.uses_return = true;
+                let sp = self.load("i32", "@basic_gosub_sp");
+                let base = self.stack_base("gosub");
+                let ok = self.builder.next_temp();
+                self.builder.add_icmp("sgt", &sp, &base, &ok[1..]);
+                self.emit_error_unless(&ok, ErrorCondition::ReturnWithoutGosub, "RETURN without GOSUB");
+                self.branch(RETURN_DISPATCH);
+            },
+            Statement::End => self.emit_exit("0"),
+            Statement::Stop => self.emit_exit("1"),
+            Statement::Rem { comment: _ } => {
+                // Comments are no-ops in generated code
+            },
+            Statement::Data { .. } => {
+                // DATA values are collected into constant tables up front
+            },
+            Statement::DefType { .. } => {
+                // The parser gave the variables their types
+            },
+            Statement::Read { vars } => self.codegen_read(vars),
+            Statement::MatRead { arrays } => self.codegen_mat_read(arrays),
+            Statement::MatPrint { arrays } => self.codegen_mat_print(arrays),
+            Statement::MatAssign { var, value } => self.codegen_mat_assign(var, value),
+            Statement::Randomize { seed } => {
+                let seed = match seed {
+                    Some(expr) => self.codegen_number(expr, "RANDOMIZE seed must be a number"),
+                    None => self.call("double", "basic_timer", &[]),
+                };
+                self.builder.add_call_void("basic_randomize", &[format!("double {}", seed)]);
+            },
+            Statement::Restore { line } => {
+                let position = match line {
+                    None => Some(0),
+                    Some(line_number) => self.data_line_map.get(line_number).copied(),
+                };
+                match position {
+                    Some(position) => self.builder.add_typed_store("i32", &position.to_string(), "@basic_data_ptr"),
+                    None => self.emit_runtime_error(ErrorCondition::OutOfData, &format!("Line {} has no DATA statements", line.unwrap_or(0))),
+                }
+            },
+            Statement::Dim { arrays } => {
+                // Arrays are allocated statically from their DIM declarations
+                let max_dims = self.dialect.max_array_dims;
+                if let Some(array) = arrays.iter().find(|array| array.dimensions.len() > max_dims) {
+                    self.emit_runtime_error(ErrorCondition::IllegalFunctionCall, &limits::array_dims_message(&array.name, array.dimensions.len(), max_dims));
+                }
+            },
+            Statement::OnGoto { expr, line_numbers } => self.codegen_on(expr, line_numbers, false, index, offset),
+            Statement::OnGosub { expr, line_numbers } => self.codegen_on(expr, line_numbers, true, index, offset),
+            Statement::Def { name, .. } => {
+                // DEF FN bodies are emitted as separate functions after main
+                if self.debug {
+                    self.builder.comment(&format!("DEF {} compiled as @fn_{}", name, name));
+                }
+            },
+            Statement::While { condition } => {
+                // WEND branches back here, so the test runs before every pass
+                let Some(truth) = self.codegen_condition(condition, "WHILE") else {
+                    return;
+                };
+                self.emit_loop_test(&truth, index, offset);
+            },
+            Statement::Do { condition } => {
+                if let Some(condition) = condition {
+                    let Some(truth) = self.codegen_loop_condition(condition, "DO") else {
+                        return;
+                    };
+                    self.emit_loop_test(&truth, index, offset);
+                }
+            },
+            Statement::Wend => {
+                if let Some(start) = self.loop_start(index, offset) {
+                    let target = self.statement_label(start.0, start.1);
+                    self.branch(&target);
+                }
+            },
+            Statement::Loop { condition } => {
+                let Some(start) = self.loop_start(index, offset) else {
+                    return;
+                };
+                let target = self.statement_label(start.0, start.1);
+                match condition {
+                    Some(condition) => {
+                        let Some(truth) = self.codegen_loop_condition(condition, "LOOP") else {
+                            return;
+                        };
+                        let after = self.label_after(index, offset);
+                        self.conditional_branch(&truth, &target, &after);
+                    }
+                    None => self.branch(&target),
+                }
+            },
+            Statement::ExitDo => {
+                let Some(start) = self.loop_start(index, offset) else {
+                    return;
+                };
+                if let Some(end) = self.loop_end(start.0, start.1) {
+                    let target = self.label_after(end.0, end.1);
+                    self.branch(&target);
+                }
+            },
+            Statement::IfBlock { condition } => {
+                let Some(truth) = self.codegen_condition(condition, "IF") else {
+                    return;
+                };
+                let body = self.label_after(index, offset);
+                let next = self.clause_label(self.blocks.next_clause((index, offset)));
+                self.conditional_branch(&truth, &body, &next);
+            },
+            Statement::ElseIf { condition } => {
+                // Falling in from the branch above ends the block; a false IF or ELSEIF
+                // comes to the test instead
+                let end = self.clause_label(self.blocks.block_end((index, offset)));
+                self.branch(&end);
+                self.builder.add_basic_block(&Self::else_if_test_label(&self.statement_label(index, offset)));
+                self.terminated = false;
+                let Some(truth) = self.codegen_condition(condition, "ELSEIF") else {
+                    return;
+                };
+                let body = self.label_after(index, offset);
+                let next = self.clause_label(self.blocks.next_clause((index, offset)));
+                self.conditional_branch(&truth, &body, &next);
+            },
+            Statement::ElseBlock | Statement::Case { .. } | Statement::CaseElse => {
+                // Reached from the end of the branch before it, which is done
+                let end = self.clause_label(self.blocks.block_end((index, offset)));
+                self.branch(&end);
+            },
+            Statement::EndIf | Statement::EndSelect => {},
+            Statement::SelectCase { selector } => self.codegen_select_case(selector, index, offset),
+            Statement::OnErrorGoto { line } => self.codegen_on_error(*line),
+            Statement::Resume { target } => self.codegen_resume(*target),
+            Statement::Sub { .. } | Statement::Function { .. } => {
+                // Procedures are emitted as separate functions after main
+            },
+            Statement::EndSub | Statement::ExitSub | Statement::EndFunction | Statement::ExitFunction => {
+                self.branch(PROCEDURE_RETURN);
+            },
+            Statement::Call { name, args } => self.codegen_call(name, args),
+            Statement::Local { vars } => {
+                for var in vars {
+                    let (llvm_type, initial) = self.variable_type(var);
+                    let ptr = self.variable_ptr(var);
+                    self.builder.add_typed_store(&llvm_type, initial, &ptr);
+                }
+            },
+            Statement::Shared { .. } => {},
+        }
+    }
+
+    /// Where control goes on reaching a block clause: the test of an ELSEIF, or the
+    /// statement after an ELSE, CASE ELSE or END
+    fn clause_label(&self, clause: Option<(usize, usize)>) -> String {
+        let (index, offset) = clause.expect("block IF targets are resolved before code generation");
+        match self.program.lines[index].statements[offset] {
+            Statement::ElseIf { .. } => Self::else_if_test_label(&self.statement_label(index, offset)),
+            _ => self.label_after(index, offset),
+        }
+    }
+
+    fn else_if_test_label(statement_label: &str) -> String {
+        format!("{}_test", statement_label)
+    }
+
+    /// Evaluates the selector once and tests each CASE in turn, as the interpreter does when
+    /// it runs the SELECT CASE. The tests are generated as if at their CASE statement, so
+    /// errors in them report the CASE's line.
+    fn codegen_select_case(&mut self, selector: &Expression, index: usize, offset: usize) {
+        let is_string = self.is_string_expression(selector);
+        let value = self.codegen_expression(selector);
+        let mut clause = self.blocks.next_clause((index, offset));
+        while let Some((case_index, case_offset)) = clause {
+            let Statement::Case { tests } = self.program.lines[case_index].statements[case_offset].clone() else {
+                break;
+            };
+            let body = self.label_after(case_index, case_offset);
+            (self.current_line_index, self.current_offset) = (case_index, case_offset);
+            for test in &tests {
+                let Some(matched) = self.codegen_case_test(&value, is_string, test) else {
+                    break;
+                };
+                let next = self.builder.next_block();
+                self.conditional_branch(&matched, &body, &next);
+                self.builder.add_basic_block(&next);
+                self.terminated = false;
+            }
+            (self.current_line_index, self.current_offset) = (index, offset);
+            clause = self.blocks.next_clause((case_index, case_offset));
+        }
+        // No CASE matched: on to the CASE ELSE branch, or past END SELECT
+        let target = self.clause_label(clause);
+        self.branch(&target);
+    }
+
+    /// An i1 that is true if the selector `value` passes one CASE test. A test of the other
+    /// type raises the type error and gives None.
+    fn codegen_case_test(&mut self, value: &str, is_string: bool, test: &CaseTest) -> Option<String> {
+        let comparisons: Vec<(&str, &Expression)> = match test {
+            CaseTest::Value(expr) => vec![("oeq", expr)],
+            CaseTest::Range(low, high) => vec![("oge", low), ("ole", high)],
+            CaseTest::Is(op, expr) => vec![(Self::comparison_predicate(op)?, expr)],
+        };
+        let mut result: Option<String> = None;
+        for (predicate, expr) in comparisons {
+            if self.is_string_expression(expr) != is_string {
+                let line_number = self.current_line_number();
+                self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, "CASE value must have the same type as SELECT CASE", line_number);
+                let dead = self.builder.next_block();
+                self.builder.add_basic_block(&dead);
+                self.terminated = false;
+                return None;
+            }
+            let operand = self.codegen_expression(expr);
+            let (left, right) = if is_string {
+                let ordering = self.call("double", "basic_str_compare", &[format!("i8* {}", value), format!("i8* {}", operand)]);
+                (ordering, "0.0".to_string())
+            } else {
+                (value.to_string(), operand)
+            };
+            let flag = self.builder.next_temp();
+            self.builder.add_fcmp(predicate, &left, &right, &flag[1..]);
+            result = Some(match result {
+                Some(previous) => {
+                    let both = self.builder.next_temp();
+                    self.builder.add_binary_op("and", &previous, &flag, "i1", &both[1..]);
+                    both
+                }
+                None => flag,
+            });
+        }
+        result
+    }
+
+    /// Compares a condition with zero, giving an i1 that is true when it holds. A string
+    /// condition raises the type error `keyword` would, and gives None.
+    fn codegen_condition(&mut self, condition: &Expression, keyword: &str) -> Option<String> {
+        if self.is_string_expression(condition) {
+            let line_number = self.current_line_number();
+            self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, &format!("{} condition must evaluate to a number", keyword), line_number);
+            return None;
+        }
+        let value = self.codegen_expression(condition);
+        let truth = self.builder.next_temp();
+        self.builder.add_fcmp("une", &value, "0.0", &truth[1..]);
+        Some(truth)
+    }
+
+    /// The WHILE or UNTIL test of a DO or LOOP, as an i1 that is true to keep looping
+    fn codegen_loop_condition(&mut self, condition: &LoopCondition, keyword: &str) -> Option<String> {
+        let truth = self.codegen_condition(condition.expression(), keyword)?;
+        match condition {
+            LoopCondition::While(_) => Some(truth),
+            LoopCondition::Until(_) => {
+                let negated = self.builder.next_temp();
+                self.builder.add_binary_op("xor", &truth, "true", "i1", &negated[1..]);
+                Some(negated)
+            }
+        }
+    }
+
+    /// Enters the body of the WHILE or DO at (index, offset) if `keep_going` is true, and
+    /// otherwise continues after its WEND or LOOP
+    fn emit_loop_test(&mut self, keep_going: &str, index: usize, offset: usize) {
+        let body = self.label_after(index, offset);
+        let skip = self.builder.next_block();
+        self.conditional_branch(keep_going, &body, &skip);
+        self.builder.add_basic_block(&skip);
+        self.terminated = false;
+        if let Some(end) = self.loop_end(index, offset) {
+            let target = self.label_after(end.0, end.1);
+            self.branch(&target);
+        }
+    }
+
+    /// The WHILE or DO a WEND, LOOP or EXIT DO belongs to; raises the mismatch error if none
+    fn loop_start(&mut self, index: usize, offset: usize) -> Option<(usize, usize)> {
+        let target = self.blocks.loop_start((index, offset)).cloned();
+        self.loop_target(target)
+    }
+
+    /// The WEND or LOOP that closes the WHILE or DO at (index, offset), or None after
+    /// raising the error for lacking one
+    fn loop_end(&mut self, index: usize, offset: usize) -> Option<(usize, usize)> {
+        let target = self.blocks.loop_end((index, offset)).cloned();
+        self.loop_target(target)
+    }
+
+    fn loop_target(&mut self, target: Option<LoopTarget>) -> Option<(usize, usize)> {
+        match target.expect("the parser pairs every loop statement") {
+            Ok(location) => Some(location),
+            Err(mismatch) => {
+                self.emit_error_call(mismatch.condition, &mismatch.message, mismatch.line_number);
+                None
+            }
+        }
+    }
+
+    fn codegen_on_error(&mut self, line: usize) {
+        if self.error_handlers.is_empty() {
+            // Only ON ERROR GOTO 0 statements: trapping is never turned on
+            return;
+        }
+        if line == 0 {
+            // Turning trapping off inside a handler stops with the error being handled
+            self.builder.add_typed_store("i32", "0", "@basic_err_handler");
+            let active = self.load("i32", "@basic_err_active");
+            let in_handler = self.builder.next_temp();
+            self.builder.add_icmp("ne", &active, "0", &in_handler[1..]);
+            let off = self.builder.next_block();
+            if self.current_procedure.is_none() {
+                self.conditional_branch(&in_handler, ERROR_REPORT, &off);
+            } else {
+                // The report block belongs to main
+                let report = self.builder.next_block();
+                self.conditional_branch(&in_handler, &report, &off);
+                self.builder.add_basic_block(&report);
+                self.emit_error_report();
+            }
+            self.builder.add_basic_block(&off);
+            self.terminated = false;
+        } else if self.line_blocks.contains_key(&line) {
+            self.builder.add_typed_store("i32", &line.to_string(), "@basic_err_handler");
+        } else {
+            self.emit_runtime_error(ErrorCondition::UndefinedLine, &format!("Line number {} not found", line));
+        }
+    }
+
+    fn codegen_resume(&mut self, target: ResumeTarget) {
+        if self.error_handlers.is_empty() {
+            self.emit_runtime_error(ErrorCondition::ResumeWithoutError, "RESUME without error");
+            return;
+        }
+        let active = self.load("i32", "@basic_err_active");
+        let in_handler = self.builder.next_temp();
+        self.builder.add_icmp("ne", &active, "0", &in_handler[1..]);
+        self.emit_error_unless(&in_handler, ErrorCondition::ResumeWithoutError, "RESUME without error");
+        self.builder.add_typed_store("i32", "0", "@basic_err_active");
+        match target {
+            ResumeTarget::Retry => self.branch(RESUME_DISPATCH),
+            ResumeTarget::Next => self.branch(RESUME_NEXT_DISPATCH),
+            ResumeTarget::Line(line) => {
+                let target = self.line_target(line);
+                self.branch(&target);
+            }
+        }
+    }
+
+    fn codegen_let(&mut self, var: &Expression, value: &Expression) {
+        if self.debug {
+            self.builder.comment(&format!("LET statement: {:?} = {:?}", var, value));
+        }
+        let is_string = self.is_string_expression(value);
+        let result = self.codegen_expression(value);
+        self.store_to(var, &result, is_string);
+    }
+
+    /// `MID$(var, start[, length]) = value`: the runtime builds the new string, which is
+    /// then stored as LET would
+    fn codegen_mid_assign(&mut self, var: &Expression, start: &Expression, length: Option<&Expression>, value: &Expression) {
+        if !self.is_string_expression(value) {
+            self.emit_runtime_error(ErrorCondition::TypeMismatch, "Type mismatch in MID$ assignment");
+            return;
+        }
+        let current = self.codegen_expression(var);
+        let start = self.codegen_number(start, "Type mismatch in MID$ assignment");
+        let length_given = length.is_some();
+        let length = match length {
+            Some(length) => self.codegen_number(length, "Type mismatch in MID$ assignment"),
+            None => "0.0".to_string(),
+        };
+        let text = self.codegen_expression(value);
+        let result = self.call("i8*", "basic_mid_assign", &[
+            format!("i8* {}", current), format!("double {}", start), format!("double {}", length),
+            format!("i32 {}", length_given as i32), format!("i8* {}", text),
+        ]);
+        let ok = self.builder.next_temp();
+        self.builder.line(&format!("  {} = icmp ne i8* {}, null", ok, result));
+        self.emit_error_unless(&ok, ErrorCondition::IllegalFunctionCall, &strings::illegal_call_message("MID$"));
+        self.store_to(var, &result, true);
+    }
+
+    /// Stores a value into a scalar variable or an array element
+    fn store_to(&mut self, target: &Expression, value: &str, value_is_string: bool) {
+        let target_is_string = match &target.expr_type {
+            ExpressionType::Variable(name) | ExpressionType::Array { name, .. } => name.ends_with('$'),
+            _ => {
+                self.emit_runtime_error(ErrorCondition::Unprintable, "Invalid left-hand side in assignment");
+                return;
+            }
+        };
+        if target_is_string != value_is_string {
+            self.emit_runtime_error(ErrorCondition::TypeMismatch, "Type mismatch in assignment");
+            return;
+        }
+        let llvm_type = if value_is_string { "i8*" } else { "double" };
+        match &target.expr_type {
+            ExpressionType::Variable(name) => {
+                self.store_variable(name, value);
+            }
+            ExpressionType::Array { name, indices } => {
+                if let Some(ptr) = self.array_element_ptr(name, indices) {
+                    let value = if value_is_string { value.to_string() } else { self.typed_number(name, value) };
+                    self.builder.add_typed_store(llvm_type, &value, &ptr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn codegen_print(&mut self, items: &[PrintItem]) {
+        if self.debug {
+            self.builder.comment(&format!("PRINT statement with {} items", items.len()));
+        }
+
+        for item in items {
+            match item {
+                PrintItem::Expression(expr) => {
+                    if let ExpressionType::FunctionCall { name, args } = &expr.expr_type {
+                        if name == "TAB" && args.len() == 1 {
+                            let column = self.codegen_number(&args[0], "Function 'TAB' expects a number argument");
+                            self.builder.add_call_void("basic_print_tab", &[format!("double {}", column)]);
+                            continue;
+                        }
+                    }
+                    if self.is_string_expression(expr) {
+                        let value = self.codegen_expression(expr);
+                        self.builder.add_call_void("basic_print_string", &[format!("i8* {}", value)]);
+                    } else {
+                        let value = self.codegen_expression(expr);
+                        let print = if expr.is_double_precision() {
+                            "basic_print_double"
+                        } else if expr.is_single_precision() {
+                            "basic_print_single"
+                        } else {
+                            "basic_print_number"
+                        };
+                        self.builder.add_call_void(print, &[format!("double {}", value)]);
+                    }
+                }
+                PrintItem::Tab(column) => {
+                    let column = Self::float_literal(*column as f64);
+                    self.builder.add_call_void("basic_print_tab", &[format!("double {}", column)]);
+                }
+                PrintItem::Comma => {
+                    self.builder.add_call_void("basic_print_comma", &[]);
+                }
+                PrintItem::Semicolon => {
+                    // Semicolons add no spacing at all
+                }
+            }
+        }
+
+        // Add newline unless last item was a semicolon
+        if !matches!(items.last(), Some(PrintItem::Semicolon)) {
+            self.builder.add_call_void("basic_print_newline", &[]);
+        }
+    }
+
+    fn codegen_input(&mut self, vars: &[String], prompt: Option<&str>, index: usize, offset: usize) {
+        let read_block = self.builder.next_block();
+        let fields_block = self.builder.next_block();
+        let bad_count_block = self.builder.next_block();
+        let bad_value_block = self.builder.next_block();
+
+        self.builder.add_call_void("basic_input_begin", &[]);
+        self.start_block(&read_block);
+        let prompt_arg = match prompt {
+            Some(p) => self.builder.add_string_constant_ptr(p),
+            None => "null".to_string(),
+        };
+        let line = self.call("i8*", "basic_input_line", &[format!("i8* {}", prompt_arg)]);
+        let count = self.call("i32", "basic_input_field_count", &[format!("i8* {}", line)]);
+        let count_ok = self.builder.next_temp();
+        self.builder.add_icmp("eq", &count, &vars.len().to_string(), &count_ok[1..]);
+        self.conditional_branch(&count_ok, &fields_block, &bad_count_block);
+
+        // Parse every field before assigning any of them
+        self.builder.add_basic_block(&fields_block);
+        self.terminated = false;
+        let mut values = Vec::new();
+        let mut has_numeric = false;
+        for (i, var) in vars.iter().enumerate() {
+            let field = self.call("i8*", "basic_input_field", &[format!("i8* {}", line), format!("i32 {}", i)]);
+            if var.ends_with('$') {
+                let value = if self.dialect.uppercase_input {
+                    self.call("i8*", "basic_to_upper", &[format!("i8* {}", field)])
+                } else {
+                    field
+                };
+                values.push((var.clone(), value));
+            } else {
+                has_numeric = true;
+                let parsed = self.call("i32", "basic_parse_number", &[format!("i8* {}", field), "double* @basic_input_scratch".to_string()]);
+                let parsed_ok = self.builder.next_temp();
+                self.builder.add_icmp("ne", &parsed, "0", &parsed_ok[1..]);
+                let next_block = self.builder.next_block();
+                self.conditional_branch(&parsed_ok, &next_block, &bad_value_block);
+                self.builder.add_basic_block(&next_block);
+                self.terminated = false;
+                let value = self.load("double", "@basic_input_scratch");
+                values.push((var.clone(), value));
+            }
+        }
+        for (var, value) in values {
+            self.store_variable(&var, &value);
+        }
+        let after = self.label_after(index, offset);
+        self.branch(&after);
+
+        // The runtime prints "?Redo from start", or hands back the error once retries run out
+        self.builder.add_basic_block(&bad_count_block);
+        let message = self.call("i8*", "basic_input_bad_count", &[format!("i32 {}", vars.len()), format!("i32 {}", count)]);
+        self.emit_input_retry(&message, &read_block, ErrorCondition::BadInput);
+        if has_numeric {
+            self.builder.add_basic_block(&bad_value_block);
+            let message = self.call("i8*", "basic_input_bad_number", &[]);
+            self.emit_input_retry(&message, &read_block, ErrorCondition::BadInput);
+        }
+    }
+
+    /// Evaluates a file number; a string raises the interpreter's type error and gives None
+    fn codegen_file_number(&mut self, number: &Expression) -> Option<String> {
+        self.codegen_file_argument(number, "File number")
+    }
+
+    /// A numeric argument of a file statement, such as a record length or number
+    fn codegen_file_argument(&mut self, expr: &Expression, what: &str) -> Option<String> {
+        if self.is_string_expression(expr) {
+            let line_number = self.current_line_number();
+            self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, &format!("{} must be a number", what), line_number);
+            return None;
+        }
+        Some(self.codegen_expression(expr))
+    }
+
+    fn codegen_open(&mut self, path: &Expression, mode: FileMode, number: &Expression, record_len: Option<&Expression>) {
+        if !self.is_string_expression(path) {
+            let line_number = self.current_line_number();
+            self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, "OPEN file name must be a string", line_number);
+            return;
+        }
+        let path = self.codegen_expression(path);
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        let record_len = match record_len {
+            Some(expr) => match self.codegen_file_argument(expr, "Record length") {
+                Some(value) => value,
+                None => return,
+            },
+            None => Self::float_literal(files::DEFAULT_RECORD_LEN as f64),
+        };
+        self.builder.add_call_void("basic_file_open", &[
+            format!("i8* {}", path), format!("i32 {}", mode.code()), format!("double {}", number), format!("double {}", record_len),
+        ]);
+        self.emit_file_check();
+    }
+
+    /// FIELD: the widths are all evaluated before the runtime hears of any, so an error
+    /// in one leaves no half-built FIELD behind
+    fn codegen_field(&mut self, number: &Expression, fields: &[(Expression, String)]) {
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        let mut widths = Vec::new();
+        for (width, _) in fields {
+            let Some(width) = self.codegen_file_argument(width, "Field width") else {
+                return;
+            };
+            widths.push(width);
+        }
+        for (width, (_, var)) in widths.iter().zip(fields) {
+            let name = self.builder.add_string_constant_ptr(var);
+            self.builder.add_call_void("basic_file_field_add", &[format!("double {}", width), format!("i8* {}", name)]);
+        }
+        self.builder.add_call_void("basic_file_field", &[format!("double {}", number)]);
+        self.emit_file_check();
+        self.load_fields(&number, fields.iter().map(|(_, var)| var.clone()).collect());
+    }
+
+    fn codegen_get_put(&mut self, func: &str, number: &Expression, record: Option<&Expression>) {
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        let (record, has_record) = match record {
+            Some(expr) => match self.codegen_file_argument(expr, "Record number") {
+                Some(value) => (value, 1),
+                None => return,
+            },
+            None => ("0.0".to_string(), 0),
+        };
+        self.builder.add_call_void(func, &[format!("double {}", number), format!("double {}", record), format!("i32 {}", has_record)]);
+        self.emit_file_check();
+        if func == "basic_file_get" {
+            // Which variables are bound to this file is only known at run time, so every
+            // FIELD variable in scope asks the runtime for its new value
+            let vars = self.program.lines.iter()
+                .flat_map(|line| line.statements.iter())
+                .filter_map(|statement| match statement {
+                    Statement::Field { fields, .. } => Some(fields.iter().map(|(_, var)| var.clone())),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|var| self.symbol_table.contains_key(var))
+                .collect();
+            self.load_fields(&number, vars);
+        }
+    }
+
+    /// Sets variables FIELD bound to file `number` from its record buffer
+    fn load_fields(&mut self, number: &str, vars: BTreeSet<String>) {
+        for var in vars {
+            let name = self.builder.add_string_constant_ptr(&var);
+            let ptr = self.variable_ptr(&var);
+            let current = self.load("i8*", &ptr);
+            let value = self.call("i8*", "basic_file_field_value", &[format!("double {}", number), format!("i8* {}", name), format!("i8* {}", current)]);
+            self.builder.add_typed_store("i8*", &value, &ptr);
+        }
+    }
+
+    fn codegen_set_field(&mut self, var: &str, value: &Expression, right: bool) {
+        if !self.is_string_expression(value) {
+            let line_number = self.current_line_number();
+            let message = format!("{} needs a string value", if right { "RSET" } else { "LSET" });
+            self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, &message, line_number);
+            return;
+        }
+        let value = self.codegen_expression(value);
+        let name = self.builder.add_string_constant_ptr(var);
+        let ptr = self.variable_ptr(var);
+        let current = self.load("i8*", &ptr);
+        let result = self.call("i8*", "basic_file_set_field", &[
+            format!("i8* {}", name), format!("i8* {}", current), format!("i8* {}", value), format!("i32 {}", right as i32),
+        ]);
+        self.builder.add_typed_store("i8*", &result, &ptr);
+    }
+
+    fn codegen_close(&mut self, numbers: &[Expression]) {
+        if numbers.is_empty() {
+            self.builder.add_call_void("basic_file_close_all", &[]);
+            self.emit_file_check();
+        }
+        for number in numbers {
+            let Some(number) = self.codegen_file_number(number) else {
+                return;
+            };
+            self.builder.add_call_void("basic_file_close", &[format!("double {}", number)]);
+            self.emit_file_check();
+        }
+    }
+
+    fn codegen_print_file(&mut self, number: &Expression, items: &[PrintItem]) {
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        let file = format!("double {}", number);
+        for item in items {
+            match item {
+                PrintItem::Expression(expr) => {
+                    let value = self.codegen_expression(expr);
+                    if self.is_string_expression(expr) {
+                        self.builder.add_call_void("basic_file_print_string", &[file.clone(), format!("i8* {}", value)]);
+                    } else {
+                        let print = if expr.is_double_precision() {
+                            "basic_file_print_double"
+                        } else if expr.is_single_precision() {
+                            "basic_file_print_single"
+                        } else {
+                            "basic_file_print_number"
+                        };
+                        self.builder.add_call_void(print, &[file.clone(), format!("double {}", value)]);
+                    }
+                }
+                PrintItem::Tab(column) => {
+                    let column = Self::float_literal(*column as f64);
+                    self.builder.add_call_void("basic_file_print_tab", &[file.clone(), format!("double {}", column)]);
+                }
+                PrintItem::Comma => self.builder.add_call_void("basic_file_print_comma", std::slice::from_ref(&file)),
+                PrintItem::Semicolon => continue,
+            }
+            self.emit_file_check();
+        }
+        if !matches!(items.last(), Some(PrintItem::Semicolon)) {
+            self.builder.add_call_void("basic_file_print_newline", &[file]);
+            self.emit_file_check();
+        }
+    }
+
+    fn codegen_print_using(&mut self, number: Option<&Expression>, format: &Expression, exprs: &[Expression], newline: bool) {
+        let number = match number {
+            Some(number) => match self.codegen_file_number(number) {
+                Some(number) => Some(format!("double {}", number)),
+                None => return,
+            },
+            None => None,
+        };
+        if !self.is_string_expression(format) {
+            let line_number = self.current_line_number();
+            self.emit_error_call_with_kind(ERROR_TYPE, ErrorCondition::TypeMismatch, "PRINT USING format must be a string", line_number);
+            return;
+        }
+        let format = self.codegen_expression(format);
+        for expr in exprs {
+            let value = self.codegen_expression(expr);
+            if self.is_string_expression(expr) {
+                self.builder.add_call_void("basic_using_string", &[format!("i8* {}", value)]);
+            } else {
+                self.builder.add_call_void("basic_using_number", &[format!("double {}", value)]);
+            }
+        }
+        let text = self.call("i8*", "basic_print_using", &[format!("i8* {}", format)]);
+        self.emit_using_check();
+        match number {
+            Some(file) => {
+                self.builder.add_call_void("basic_file_print_string", &[file.clone(), format!("i8* {}", text)]);
+                self.emit_file_check();
+                if newline {
+                    self.builder.add_call_void("basic_file_print_newline", &[file]);
+                    self.emit_file_check();
+                }
+            }
+            None => {
+                self.builder.add_call_void("basic_print_string", &[format!("i8* {}", text)]);
+                if newline {
+                    self.builder.add_call_void("basic_print_newline", &[]);
+                }
+            }
+        }
+    }
+
+    /// Raises the error the last basic_print_using left, if there is one: a type mismatch
+    /// for a value its field cannot show, an illegal function call for a format with no fields
+    fn emit_using_check(&mut self) {
+        let status = self.call("i32", "basic_using_status", &[]);
+        let ok = self.builder.next_temp();
+        self.builder.add_icmp("eq", &status, "0", &ok[1..]);
+        let ok_block = self.builder.next_block();
+        let err_block = self.builder.next_block();
+        self.conditional_branch(&ok, &ok_block, &err_block);
+
+        self.builder.add_basic_block(&err_block);
+        let msg = self.call("i8*", "basic_using_error", &[]);
+        let is_type = self.builder.next_temp();
+        self.builder.add_icmp("eq", &status, &UsingError::TYPE_MISMATCH.to_string(), &is_type[1..]);
+        let type_block = self.builder.next_block();
+        let call_block = self.builder.next_block();
+        self.conditional_branch(&is_type, &type_block, &call_block);
+        let line_number = self.current_line_number();
+        self.builder.add_basic_block(&type_block);
+        self.emit_raise(ERROR_TYPE, &msg, ErrorCondition::TypeMismatch, line_number);
+        self.builder.add_basic_block(&call_block);
+        self.emit_raise(ERROR_RUNTIME, &msg, ErrorCondition::IllegalFunctionCall, line_number);
+
+        self.builder.add_basic_block(&ok_block);
+        self.terminated = false;
+    }
+
+    fn codegen_write_file(&mut self, number: &Expression, exprs: &[Expression]) {
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        let file = format!("double {}", number);
+        // Every value is worked out before anything is written, as in the interpreter
+        let values: Vec<(bool, String)> = exprs.iter()
+            .map(|expr| (self.is_string_expression(expr), self.codegen_expression(expr)))
+            .collect();
+        let comma = self.builder.add_string_constant_ptr(",");
+        for (i, (is_string, value)) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.builder.add_call_void("basic_file_print_string", &[file.clone(), format!("i8* {}", comma)]);
+            }
+            if is_string {
+                self.builder.add_call_void("basic_file_write_string", &[file.clone(), format!("i8* {}", value)]);
+            } else {
+                self.builder.add_call_void("basic_file_write_number", &[file.clone(), format!("double {}", value)]);
+            }
+            self.emit_file_check();
+        }
+        self.builder.add_call_void("basic_file_print_newline", &[file]);
+        self.emit_file_check();
+    }
+
+    fn codegen_input_file(&mut self, number: &Expression, vars: &[String]) {
+        let Some(number) = self.codegen_file_number(number) else {
+            return;
+        };
+        for var in vars {
+            let value = if var.ends_with('$') {
+                self.call("i8*", "basic_file_input_string", &[format!("double {}", number)])
+            } else {
+                self.call("double", "basic_file_input_number", &[format!("double {}", number)])
+            };
+            self.emit_file_check();
+            self.store_variable(var, &value);
+        }
+    }
+
+    /// Reads INPUT again while the runtime's `message` is null, and raises it otherwise
+    fn emit_input_retry(&mut self, message: &str, read_block: &str, condition: ErrorCondition) {
+        let retry = self.builder.next_temp();
+        self.builder.line(&format!("  {} = icmp eq i8* {}, null", retry, message));
+        let give_up = self.builder.next_block();
+        self.conditional_branch(&retry, read_block, &give_up);
+        self.builder.add_basic_block(&give_up);
+        let line_number = self.current_line_number();
+        self.emit_raise(ERROR_RUNTIME, message, condition, line_number);
+    }
+
+    fn codegen_for(&mut self, var: &str, start: &Expression, index: usize, offset: usize) {
+        let id = self.for_ids[&(index, offset)];
+        let for_loop = self.for_loops[id].clone();
+
+        let start_value = self.codegen_number(start, "FOR loop start value must be a number");
+        let stop_value = self.codegen_number(&for_loop.stop, "FOR loop stop value must be a number");
+        let step_value = self.codegen_number(&for_loop.step, "FOR loop step must be a number");
+        let start_value = self.store_variable(var, &start_value);
+        self.emit_for_unwind(var);
+
+        // The loop is skipped entirely when the start value is already past the stop value
+        let runs = self.loop_continues(&start_value, &stop_value, &step_value);
+        let push_block = self.builder.next_block();
+        let skip_block = self.builder.next_block();
+        self.conditional_branch(&runs, &push_block, &skip_block);
+
+        self.builder.add_basic_block(&skip_block);
+        self.terminated = false;
+        match for_loop.exit_label {
+            Ok(label) => self.branch(&label),
+            Err((condition, message)) => {
+                let line_number = self.current_line_number();
+                self.emit_error_call(condition, &message, line_number);
+            }
+        }
+
+        self.builder.add_basic_block(&push_block);
+        self.terminated = false;
+        let overflow = limits::for_depth_message(self.dialect.max_for_depth);
+        self.emit_stack_push("basic_for_stack", "basic_for_sp", self.dialect.max_for_depth, id, &overflow);
+        self.branch(&for_loop.body_label);
+    }
+
+    /// Drops an active FOR on `var`, and every loop above it, before the loop starts again.
+    /// Scans the stack from the top, down to the running procedure's part of it, for any
+    /// loop id over the same variable.
+    fn emit_for_unwind(&mut self, var: &str) {
+        let here = (self.current_line_index, self.current_offset);
+        let cases: Vec<usize> = self.for_loops.iter().enumerate()
+            .filter(|(_, for_loop)| for_loop.var == var && self.procedures.same_owner(for_loop.location, here))
+            .map(|(id, _)| id)
+            .collect();
+        let base = self.stack_base("for");
+        let scan = self.builder.next_block();
+        let check = self.builder.next_block();
+        let found = self.builder.next_block();
+        let done = self.builder.next_block();
+
+        let sp = self.load("i32", "@basic_for_sp");
+        self.builder.add_typed_store("i32", &sp, "@basic_for_scan");
+        self.branch(&scan);
+
+        self.builder.add_basic_block(&scan);
+        let position = self.load("i32", "@basic_for_scan");
+        let more = self.builder.next_temp();
+        self.builder.add_icmp("sgt", &position, &base, &more[1..]);
+        self.conditional_branch(&more, &check, &done);
+
+        self.builder.add_basic_block(&check);
+        let below = self.builder.next_temp();
+        self.builder.add_binary_op("sub", &position, "1", "i32", &below[1..]);
+        self.builder.add_typed_store("i32", &below, "@basic_for_scan");
+        let slot = self.stack_slot("basic_for_stack", self.dialect.max_for_depth, &below);
+        let frame = self.load("i32", &slot);
+        let cases: Vec<(i64, String)> = cases.into_iter().map(|id| (id as i64, found.clone())).collect();
+        self.builder.add_switch(&frame, &scan, &cases);
+
+        self.builder.add_basic_block(&found);
+        let frame_position = self.load("i32", "@basic_for_scan");
+        self.builder.add_typed_store("i32", &frame_position, "@basic_for_sp");
+        self.branch(&done);
+
+        self.builder.add_basic_block(&done);
+        self.terminated = false;
+    }
+
+    fn codegen_next(&mut self, var: &str, index: usize, offset: usize) {
+        let sp = self.load("i32", "@basic_for_sp");
+        let base = self.stack_base("for");
+        let has_frame = self.builder.next_temp();
+        self.builder.add_icmp("sgt", &sp, &base, &has_frame[1..]);
+        self.emit_error_unless(&has_frame, ErrorCondition::NextWithoutFor, "NEXT without matching FOR");
+        let top_index = self.builder.next_temp();
+        self.builder.add_binary_op("sub", &sp, "1", "i32", &top_index[1..]);
+        let slot = self.stack_slot("basic_for_stack", self.dialect.max_for_depth, &top_index);
+        let top = self.load("i32", &slot);
+
+        // Dispatch on the innermost active FOR. Loops over another variable report a
+        // mismatch naming the variable that loop expected. Only loops of the same
+        // procedure, or of the main program, can be above the base.
+        let mut cases = Vec::new();
+        let mut candidates = Vec::new();
+        let mut mismatches: BTreeMap<String, String> = BTreeMap::new();
+        for (id, for_loop) in self.for_loops.clone().into_iter().enumerate() {
+            if !self.procedures.same_owner(for_loop.location, (index, offset)) {
+                continue;
+            }
+            let block = if for_loop.var == var {
+                let block = self.builder.next_block();
+                candidates.push((for_loop, block.clone()));
+                block
+            } else {
+                match mismatches.get(&for_loop.var) {
+                    Some(block) => block.clone(),
+                    None => {
+                        let block = self.builder.next_block();
+                        mismatches.insert(for_loop.var.clone(), block.clone());
+                        block
+                    }
+                }
+            };
+            cases.push((id as i64, block));
+        }
+        let invalid_block = self.builder.next_block();
+        self.builder.add_switch(&top, &invalid_block, &cases);
+        self.builder.add_basic_block(&invalid_block);
+        self.builder.add_unreachable();
+        self.terminated = true;
+
+        let line_number = self.current_line_number();
+        for (expected, block) in mismatches {
+            self.builder.add_basic_block(&block);
+            self.emit_error_call(ErrorCondition::NextWithoutFor, &format!("Mismatched NEXT: expected '{}', found '{}'", expected, var), line_number);
+        }
+
+        let after = self.label_after(index, offset);
+        for (for_loop, block) in candidates {
+            self.builder.add_basic_block(&block);
+            self.terminated = false;
+            let current = self.load_variable(var);
+            let step = self.codegen_number(&for_loop.step, "FOR loop step must be numeric");
+            let stop = self.codegen_number(&for_loop.stop, "FOR loop stop value must be numeric");
+            let next_value = self.builder.next_temp();
+            self.builder.add_binary_op("fadd", &current, &step, "double", &next_value[1..]);
+            // The variable holds the sum rounded to its type
+            let next_value = self.store_variable(var, &next_value);
+            let continues = self.loop_continues(&next_value, &stop, &step);
+            let exit_block = self.builder.next_block();
+            self.conditional_branch(&continues, &for_loop.body_label, &exit_block);
+
+            self.builder.add_basic_block(&exit_block);
+            self.terminated = false;
+            self.emit_stack_pop("basic_for_sp");
+            self.branch(&after);
+        }
+    }
+
+    /// i1 that is true while `value` has not passed `stop` in the direction of `step`
+    fn loop_continues(&mut self, value: &str, stop: &str, step: &str) -> String {
+        let ascending = self.builder.next_temp();
+        self.builder.add_fcmp("oge", step, "0.0", &ascending[1..]);
+        let below = self.builder.next_temp();
+        self.builder.add_fcmp("ole", value, stop, &below[1..]);
+        let above = self.builder.next_temp();
+        self.builder.add_fcmp("oge", value, stop, &above[1..]);
+        let result = self.builder.next_temp();
+        self.builder.line(&format!("  {} = select i1 {}, i1 {}, i1 {}", result, ascending, below, above));
+        result
+    }
+
+    /// The lowest depth of the FOR or GOSUB stack the running code may pop to: where it
+    /// stood when the running procedure was called, and 0 in the main program
+    fn stack_base(&mut self, stack: &str) -> String {
+        if self.procedures.is_empty() {
+            return "0".to_string();
+        }
+        self.load("i32", &format!("@basic_{}_base", stack))
+    }
+
+    fn stack_slot(&mut self, stack: &str, depth: usize, index: &str) -> String {
+        let slot = self.builder.next_temp();
+        self.builder.line(&format!("  {} = getelementptr inbounds [{} x i32], [{} x i32]* @{}, i64 0, i32 {}",
+            slot, depth, depth, stack, index));
+        slot
+    }
+
+    fn emit_stack_push(&mut self, stack: &str, sp_name: &str, depth: usize, value: usize, overflow_message: &str) {
+        let sp = self.load("i32", &format!("@{}", sp_name));
+        let has_room = self.builder.next_temp();
+        self.builder.add_icmp("slt", &sp, &depth.to_string(), &has_room[1..]);
+        self.emit_error_unless(&has_room, ErrorCondition::OutOfMemory, overflow_message);
+        let slot = self.stack_slot(stack, depth, &sp);
+        self.builder.add_typed_store("i32", &value.to_string(), &slot);
+        let new_sp = self.builder.next_temp();
+        self.builder.add_binary_op("add", &sp, "1", "i32", &new_sp[1..]);
+        self.builder.add_typed_store("i32", &new_sp, &format!("@{}", sp_name));
+    }
+
+    fn emit_stack_pop(&mut self, sp_name: &str) -> String {
+        let sp = self.load("i32", &format!("@{}", sp_name));
+        let new_sp = self.builder.next_temp();
+        self.builder.add_binary_op("sub", &sp, "1", "i32", &new_sp[1..]);
+        self.builder.add_typed_store("i32", &new_sp, &format!("@{}", sp_name));
+        new_sp
+    }
+
+    fn emit_gosub_push(&mut self, return_label: String) {
+        let site = self.gosub_returns.len();
+        self.gosub_returns.push(return_label);
+        let overflow = limits::gosub_depth_message(self.dialect.max_gosub_depth);
+        self.emit_stack_push("basic_gosub_stack", "basic_gosub_sp", self.dialect.max_gosub_depth, site, &overflow);
+    }
+
+    /// RETURN pops the GOSUB stack and switches on the site id to the statement after that GOSUB
+    fn emit_return_dispatch(&mut self) {
+        if !self.uses_return {
+            return;
+        }
+        self.builder.add_basic_block(RETURN_DISPATCH);
+        let top = self.emit_stack_pop("basic_gosub_sp");
+        let slot = self.stack_slot("basic_gosub_stack", self.dialect.max_gosub_depth, &top);
+        let site = self.load("i32", &slot);
+        let cases: Vec<(i64, String)> = self.gosub_returns.iter().enumerate()
+            .map(|(id, label)| (id as i64, label.clone()))
+            .collect();
+        let invalid = self.builder.next_block();
+        self.builder.add_switch(&site, &invalid, &cases);
+        self.builder.add_basic_block(&invalid);
+        self.builder.add_unreachable();
+        self.terminated = true;
+    }
+
+    fn codegen_on(&mut self, expr: &Expression, line_numbers: &[usize], is_gosub: bool, index: usize, offset: usize) {
+        let value = self.codegen_number(expr, "ON index must be a positive integer");
+        let at_least_one = self.builder.next_temp();
+        self.builder.add_fcmp("oge", &value, "1.0", &at_least_one[1..]);
+        let floored = self.call("double", "floor", &[format!("double {}", value)]);
+        let is_integer = self.builder.next_temp();
+        self.builder.add_fcmp("oeq", &floored, &value, &is_integer[1..]);
+        let valid = self.builder.next_temp();
+        self.builder.add_binary_op("and", &at_least_one, &is_integer, "i1", &valid[1..]);
+        self.emit_error_unless(&valid, ErrorCondition::IllegalFunctionCall, "ON index must be a positive integer");
+
+        // An index past the end of the list falls through to the next statement
+        let after = self.label_after(index, offset);
+        let in_range = self.builder.next_temp();
+        self.builder.add_fcmp("ole", &value, &Self::float_literal(line_numbers.len() as f64), &in_range[1..]);
+        let dispatch = self.builder.next_block();
+        self.conditional_branch(&in_range, &dispatch, &after);
+        self.builder.add_basic_block(&dispatch);
+        self.terminated = false;
+        if is_gosub {
+            self.emit_gosub_push(after.clone());
+        }
+        let selector = self.fptosi(&value, "i32");
+        let cases: Vec<(i64, String)> = line_numbers.iter().enumerate()
+            .map(|(i, line)| (i as i64 + 1, self.line_target(*line)))
+            .collect();
+        self.builder.add_switch(&selector, &after, &cases);
+        self.terminated = true;
+    }
+
+    fn codegen_read(&mut self, vars: &[Expression]) {
+        let count = self.data_values.len();
+        for var in vars {
+            if count == 0 {
+                self.emit_runtime_error(ErrorCondition::OutOfData, "Out of DATA values");
+                continue;
+            }
+            let position = self.load("i32", "@basic_data_ptr");
+            let available = self.builder.next_temp();
+            self.builder.add_icmp("slt", &position, &count.to_string(), &available[1..]);
+            self.emit_error_unless(&available, ErrorCondition::OutOfData, "Out of DATA values");
+            let next = self.builder.next_temp();
+            self.builder.add_binary_op("add", &position, "1", "i32", &next[1..]);
+            self.builder.add_typed_store("i32", &next, "@basic_data_ptr");
+
+            let kind_ptr = self.builder.next_temp();
+            self.builder.line(&format!("  {} = getelementptr inbounds [{} x i8], [{} x i8]* @basic_data_kinds, i64 0, i32 {}",
+                kind_ptr, count, count, position));
+            let kind = self.load("i8", &kind_ptr);
+            let is_string = match &var.expr_type {
+                ExpressionType::Variable(name) | ExpressionType::Array { name, .. } => name.ends_with('$'),
+                _ => {
+                    self.emit_runtime_error(ErrorCondition::Unprintable, "Invalid variable in READ statement");
+                    continue;
+                }
+            };
+            let kind_ok = self.builder.next_temp();
+            self.builder.line(&format!("  {} = icmp eq i8 {}, {}", kind_ok, kind, if is_string { 1 } else { 0 }));
+            self.emit_error_unless(&kind_ok, ErrorCondition::TypeMismatch, "Type mismatch in READ");
+
+            let (table, llvm_type) = if is_string {
+                ("basic_data_strings", "i8*")
+            } else {
+                ("basic_data_numbers", "double")
+            };
+            let value_ptr = self.builder.next_temp();
+            self.builder.line(&format!("  {} = getelementptr inbounds [{} x {}], [{} x {}]* @{}, i64 0, i32 {}",
+                value_ptr, count, llvm_type, count, llvm_type, table, position));
+            let value = self.load(llvm_type, &value_ptr);
+            self.store_to(var, &value, is_string);
+        }
+    }
+
+    fn codegen_mat_read(&mut self, arrays: &[(String, Vec<Expression>)]) {
+        let count = self.data_values.len();
+        let (numbers, kinds) = if count == 0 {
+            ("double* null".to_string(), "i8* null".to_string())
+        } else {
+            (format!("double* getelementptr inbounds ([{} x double], [{} x double]* @basic_data_numbers, i64 0, i64 0)", count, count),
+             format!("i8* getelementptr inbounds ([{} x i8], [{} x i8]* @basic_data_kinds, i64 0, i64 0)", count, count))
+        };
+        for (name, bounds) in arrays {
+            // Read into an array of the dimensions given, or of the array's own
+            if bounds.is_empty() {
+                if !self.codegen_mat_push(name) {
+                    return;
+                }
+                self.codegen_mat_compute(matrix::Operation::Copy, "0.0", &[]);
+            } else {
+                let bounds = self.codegen_mat_bounds(bounds);
+                self.codegen_mat_compute(matrix::Operation::Zero, "0.0", &bounds);
+            }
+            self.builder.add_call_void("basic_mat_read", &[numbers.clone(), kinds.clone(),
+                format!("i32 {}", count), "i32* @basic_data_ptr".to_string()]);
+            if !self.codegen_mat_store(name) {
+                return;
+            }
+            self.emit_mat_check();
+        }
+    }
+
+    fn codegen_mat_print(&mut self, arrays: &[(String, bool)]) {
+        for (name, packed) in arrays {
+            if !self.codegen_mat_push(name) {
+                return;
+            }
+            let double = VarType::of(name) == Some(VarType::Double);
+            self.builder.add_call_void("basic_mat_print", &[format!("i32 {}", *packed as i32), format!("i32 {}", double as i32)]);
+            self.emit_mat_check();
+        }
+    }
+
+    fn codegen_mat_assign(&mut self, var: &str, value: &MatValue) {
+        let bounds = self.codegen_mat_bounds(value.bounds());
+        let scalar = match value {
+            MatValue::Scaled(scalar, _) => self.codegen_number(scalar, "MAT scalar must be a number"),
+            _ => "0.0".to_string(),
+        };
+        let mut names: Vec<&str> = value.arrays().into_iter().map(String::as_str).collect();
+        if names.is_empty() && bounds.is_empty() {
+            // ZER, CON and IDN keep the size of the array assigned to
+            names.push(var);
+        }
+        for name in names {
+            if !self.codegen_mat_push(name) {
+                return;
+            }
+        }
+        self.codegen_mat_compute(value.operation(), &scalar, &bounds);
+        if self.codegen_mat_store(var) {
+            self.emit_mat_check();
+        }
+    }
+
+    /// The dimensions given to MAT READ's arrays and to ZER, CON and IDN, as doubles
+    fn codegen_mat_bounds(&mut self, bounds: &[Expression]) -> Vec<String> {
+        bounds.iter().map(|bound| self.codegen_number(bound, "MAT dimension must be a number")).collect()
+    }
+
+    /// Works out a MAT assignment from the operands pushed. Only two dimensions are passed:
+    /// the runtime rejects more, whatever they are.
+    fn codegen_mat_compute(&mut self, op: matrix::Operation, scalar: &str, bounds: &[String]) {
+        let bound = |i: usize| format!("double {}", bounds.get(i).map_or("0.0", String::as_str));
+        self.builder.add_call_void("basic_mat_compute", &[format!("i32 {}", op.code()), format!("double {}", scalar),
+            bound(0), bound(1), format!("i32 {}", bounds.len())]);
+    }
+
+    /// Pointers to array `name`'s elements and dimensions, with its rank and subscript
+    /// offset, as the runtime's MAT functions take them; None, after raising the error,
+    /// if there is no such array
+    fn mat_array_args(&mut self, name: &str) -> Option<(ArrayInfo, Vec<String>)> {
+        let Some(info) = self.array_info.get(name).cloned() else {
+            self.emit_runtime_error(ErrorCondition::SubscriptOutOfRange, &format!("Array '{}' not found", name));
+            return None;
+        };
+        let size: usize = info.dimensions.iter().product();
+        let rank = info.dimensions.len();
+        let dims = info.dims_global.clone().unwrap_or_default();
+        let args = vec![
+            format!("double* getelementptr inbounds ([{} x double], [{} x double]* {}, i64 0, i64 0)", size, size, info.global_name),
+            format!("i64* getelementptr inbounds ([{} x i64], [{} x i64]* {}, i64 0, i64 0)", rank, rank, dims),
+            format!("i32 {}", rank),
+            format!("i32 {}", self.dialect.array_offset),
+        ];
+        Some((info, args))
+    }
+
+    /// Adds array `name` to the operands of the MAT statement; false if there is no such array
+    fn codegen_mat_push(&mut self, name: &str) -> bool {
+        let Some((_, mut args)) = self.mat_array_args(name) else {
+            return false;
+        };
+        args.push(format!("i8* {}", self.builder.add_string_constant_ptr(name)));
+        self.builder.add_call_void("basic_mat_push", &args);
+        true
+    }
+
+    /// Stores the MAT result in array `name`, rounded as its type is; false if there is no
+    /// such array
+    fn codegen_mat_store(&mut self, name: &str) -> bool {
+        let Some((info, mut args)) = self.mat_array_args(name) else {
+            return false;
+        };
+        let precision = match VarType::of(name) {
+            Some(VarType::Single) => Precision::Single,
+            Some(VarType::Double) => Precision::Double,
+            _ => self.dialect.precision,
+        };
+        let integer_bits = if VarType::of(name) == Some(VarType::Integer) { self.dialect.integer_bits } else { 0 };
+        args.push(format!("i64 {}", info.dimensions.iter().product::<usize>()));
+        args.push(format!("i8* {}", self.builder.add_string_constant_ptr(name)));
+        args.push(format!("i32 {}", precision.code()));
+        args.push(format!("i32 {}", integer_bits));
+        self.builder.add_call_void("basic_mat_store", &args);
+        true
+    }
+
+    // ------------------------------------------------------------------
+    // Expressions
+    // ------------------------------------------------------------------
+
+    /// Static type of an expression: true for strings, false for numbers
+    fn is_string_expression(&self, expr: &Expression) -> bool {
+        self.is_string_expression_within(expr, &mut BTreeSet::new())
+    }
+
+    /// `calling` holds the user functions already being looked into, so a function that
+    /// calls itself does not recurse forever (it can only be numeric)
+    fn is_string_expression_within(&self, expr: &Expression, calling: &mut BTreeSet<String>) -> bool {
+        match &expr.expr_type {
+            ExpressionType::String(_) => true,
+            ExpressionType::Number(_) | ExpressionType::UnaryOp { .. } => false,
+            ExpressionType::Variable(name) => !self.function_params.contains_key(name) && name.ends_with('$'),
+            ExpressionType::Array { name, .. } => name.ends_with('$'),
+            ExpressionType::BinaryOp { op, left, .. } => op == "+" && self.is_string_expression_within(left, calling),
+            ExpressionType::FunctionCall { name, .. } => {
+                if FUNCTION_REGISTRY.is_function(name) {
+                    FUNCTION_REGISTRY.is_string_function(name)
+                } else if let Some(procedure) = self.procedures.get(name) {
+                    procedure.returns_string()
+                } else if !calling.insert(name.clone()) {
+                    false
+                } else {
+                    let is_string = self.user_functions.get(name).is_some_and(|f| self.is_string_expression_within(&f.body, calling));
+                    calling.remove(name);
+                    is_string
+                }
+            }
+        }
+    }
+
+    /// LLVM double constants must be exact, so they are written as hex bit patterns
+    fn float_literal(n: f64) -> String {
+        format!("0x{:016X}", n.to_bits())
+    }
+
+    fn dummy_value(is_string: bool) -> String {
+        if is_string { EMPTY_STRING.to_string() } else { "0.0".to_string() }
+    }
+
+    fn call(&mut self, return_type: &str, func: &str, args: &[String]) -> String {
+        let result = self.builder.next_temp();
+        self.builder.add_call(func, args, return_type, &result[1..]);
+        result
+    }
+
+    fn load(&mut self, llvm_type: &str, ptr: &str) -> String {
+        let result = self.builder.next_temp();
+        self.builder.add_load(llvm_type, ptr, &result[1..])
+    }
+
+    fn fptosi(&mut self, value: &str, target_type: &str) -> String {
+        let result = self.builder.next_temp();
+        self.builder.add_fptosi(value, target_type, &result[1..])
+    }
+
+    /// Evaluates an expression that must be numeric, raising `message` if it is a string
+    fn codegen_number(&mut self, expr: &Expression, message: &str) -> String {
+        if self.is_string_expression(expr) {
+            self.emit_runtime_error(ErrorCondition::TypeMismatch, message);
+            return Self::dummy_value(false);
+        }
+        self.codegen_expression(expr)
+    }
+
+    fn variable_ptr(&self, name: &str) -> String {
+        self.symbol_table.get(name)
+            .unwrap_or_else(|| panic!("variable {} was not allocated", name))
+            .clone()
+    }
+
+    /// Computes the address of an array element, with the interpreter's bounds checks
+    fn array_element_ptr(&mut self, name: &str, indices: &[Expression]) -> Option<String> {
+        let info = match self.array_info.get(name) {
+            Some(info) => info.clone(),
+            None => {
+                self.emit_runtime_error(ErrorCondition::SubscriptOutOfRange, &format!("Array {} not found", name));
+                return None;
+            }
+        };
+        if indices.len() != info.dimensions.len() {
+            self.emit_runtime_error(ErrorCondition::SubscriptOutOfRange, &format!("Array {} has {} dimensions, got {} indices",
+                name, info.dimensions.len(), indices.len()));
+            return None;
+        }
+
+        let rank = info.dimensions.len();
+        let dimensions: Vec<String> = match &info.dims_global {
+            Some(dims) => (0..rank).map(|i| {
+                let ptr = self.builder.next_temp();
+                self.builder.line(&format!("  {} = getelementptr inbounds [{} x i64], [{} x i64]* {}, i64 0, i64 {}",
+                    ptr, rank, rank, dims, i));
+                self.load("i64", &ptr)
+            }).collect(),
+            None => info.dimensions.iter().map(|size| size.to_string()).collect(),
+        };
+
+        let mut flat: Option<String> = None;
+        for (index, dimension) in indices.iter().zip(dimensions.iter()) {
+            let value = self.codegen_number(index, "Array index must be a number");
+            let non_negative = self.builder.next_temp();
+            self.builder.add_fcmp("oge", &value, "0.0", &non_negative[1..]);
+            self.emit_error_unless(&non_negative, ErrorCondition::SubscriptOutOfRange, "Array index must be non-negative");
+            let whole = self.fptosi(&value, "i64");
+            let adjusted = self.builder.next_temp();
+            self.builder.add_binary_op("sub", &whole, &self.dialect.array_offset.to_string(), "i64", &adjusted[1..]);
+            let in_bounds = self.builder.next_temp();
+            self.builder.line(&format!("  {} = icmp ult i64 {}, {}", in_bounds, adjusted, dimension));
+            self.emit_error_unless(&in_bounds, ErrorCondition::SubscriptOutOfRange, "Array index out of bounds");
+            flat = Some(match flat {
+                None => adjusted,
+                Some(previous) => {
+                    let scaled = self.builder.next_temp();
+                    self.builder.add_binary_op("mul", &previous, dimension, "i64", &scaled[1..]);
+                    let sum = self.builder.next_temp();
+                    self.builder.add_binary_op("add", &scaled, &adjusted, "i64", &sum[1..]);
+                    sum
+                }
+            });
+        }
+
+        let size: usize = info.dimensions.iter().product();
+        let ptr = self.builder.next_temp();
+        self.builder.line(&format!("  {} = getelementptr inbounds [{} x {}], [{} x {}]* {}, i64 0, i64 {}",
+            ptr, size, info.element_type, size, info.element_type, info.global_name, flat.unwrap_or_else(|| "0".to_string())));
+        Some(ptr)
+    }
+
+    fn codegen_expression(&mut self, expr: &Expression) -> String {
+        match &expr.expr_type {
+            ExpressionType::Number(n) => Self::float_literal(self.dialect.precision.round(*n)),
+            ExpressionType::String(s) => self.builder.add_string_constant_ptr(s),
+            ExpressionType::Variable(name) => {
+                if let Some(param) = self.function_params.get(name) {
+                    return param.clone();
+                }
+                self.load_variable(name)
+            }
+            ExpressionType::Array { name, indices } => {
+                let is_string = name.ends_with('$');
+                let ptr = match self.array_element_ptr(name, indices) {
+                    Some(ptr) => ptr,
+                    None => return Self::dummy_value(is_string),
+                };
+                if is_string {
+                    // Unassigned string elements are null and read as ""
+                    let value = self.load("i8*", &ptr);
+                    let is_null = self.builder.next_temp();
+                    self.builder.line(&format!("  {} = icmp eq i8* {}, null", is_null, value));
+                    let result = self.builder.next_temp();
+                    self.builder.line(&format!("  {} = select i1 {}, i8* {}, i8* {}", result, is_null, EMPTY_STRING, value));
+                    result
+                } else {
+                    self.load("double", &ptr)
+                }
+            }
+            ExpressionType::BinaryOp { left, op, right } => self.codegen_binary_op(expr, op, left, right),
+            ExpressionType::UnaryOp { op, expr } => {
+                if self.is_string_expression(expr) {
+                    self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Invalid operand type for unary operator '{}'", op));
+                    return Self::dummy_value(false);
+                }
+                let value = self.codegen_expression(expr);
+                let result = self.builder.next_temp();
+                match op.as_str() {
+                    "-" => self.builder.line(&format!("  {} = fneg double {}", result, value)),
+                    "NOT" if self.dialect.logical_not => return self.codegen_comparison("oeq", &value, "0.0"),
+                    "NOT" => {
+                        let bits = self.dialect.integer_bits;
+                        let flipped = self.call("double", "basic_not", &[format!("double {}", value), format!("i32 {}", bits)]);
+                        return self.check_integer_result(&flipped, "NOT");
+                    }
+                    _ => {
+                        self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Unknown unary operator: {}", op));
+                        return Self::dummy_value(false);
+                    }
+                }
+                result
+            }
+            ExpressionType::FunctionCall { name, args } => {
+                if FUNCTION_REGISTRY.is_function(name) {
+                    let value = self.codegen_builtin_call(name, args);
+                    if FUNCTION_REGISTRY.is_numeric_function(name) { self.round_expression(&value, expr) } else { value }
+                } else if let Some(procedure) = self.procedures.get(name).filter(|p| p.kind == ProcedureKind::Function).cloned() {
+                    self.codegen_procedure_call(&procedure, args).unwrap_or_else(|| Self::dummy_value(procedure.returns_string()))
+                } else {
+                    self.codegen_user_call(name, args)
+                }
+            }
+        }
+    }
+
+    fn codegen_binary_op(&mut self, expr: &Expression, op: &str, left: &Expression, right: &Expression) -> String {
+        let left_is_string = self.is_string_expression(left);
+        let right_is_string = self.is_string_expression(right);
+        let left_val = self.codegen_expression(left);
+        let right_val = self.codegen_expression(right);
+        if left_is_string != right_is_string {
+            self.emit_runtime_error(ErrorCondition::TypeMismatch, &format!("Type mismatch for operator '{}'", op));
+            return Self::dummy_value(left_is_string && op == "+");
+        }
+
+        let predicate = Self::comparison_predicate(op);
+
+        if left_is_string {
+            if op == "+" {
+                let joined = self.call("i8*", "basic_str_concat", &[format!("i8* {}", left_val), format!("i8* {}", right_val)]);
+                return self.check_string_length(&joined);
+            }
+            // Like the interpreter, strings only support equality tests
+            let Some(predicate) = predicate.filter(|_| op == "=" || op == "<>") else {
+                self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Invalid operator '{}' for strings", op));
+                return Self::dummy_value(false);
+            };
+            // basic_str_compare returns -1, 0 or 1: compare that against zero
+            let ordering = self.call("double", "basic_str_compare", &[format!("i8* {}", left_val), format!("i8* {}", right_val)]);
+            return self.codegen_comparison(predicate, &ordering, "0.0");
+        }
+
+        if let Some(predicate) = predicate {
+            return self.codegen_comparison(predicate, &left_val, &right_val);
+        }
+
+        if let Some(integer_op) = IntegerOp::from_name(op) {
+            return self.codegen_integer_op(integer_op, &left_val, &right_val);
+        }
+
+        let result = self.builder.next_temp();
+        match op {
+            "+" => { self.builder.add_binary_op("fadd", &left_val, &right_val, "double", &result[1..]); }
+            "-" => { self.builder.add_binary_op("fsub", &left_val, &right_val, "double", &result[1..]); }
+            "*" => { self.builder.add_binary_op("fmul", &left_val, &right_val, "double", &result[1..]); }
+            "/" => {
+                let non_zero = self.builder.next_temp();
+                self.builder.add_fcmp("une", &right_val, "0.0", &non_zero[1..]);
+                self.emit_error_unless(&non_zero, ErrorCondition::DivisionByZero, "Division by zero");
+                self.builder.add_binary_op("fdiv", &left_val, &right_val, "double", &result[1..]);
+            }
+            "^" => {
+                let power = self.call("double", "pow", &[format!("double {}", left_val), format!("double {}", right_val)]);
+                return self.round_expression(&power, expr);
+            }
+            _ => {
+                self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Unknown binary operator: {}", op));
+                return Self::dummy_value(false);
+            }
+        }
+        self.round_expression(&result, expr)
+    }
+
+    /// `\\`, MOD or a bitwise operator, on integers of the dialect's size. The divisor of
+    /// `\\` and MOD is checked here, as it is rounded, so the runtime only fails on Overflow.
+    fn codegen_integer_op(&mut self, op: IntegerOp, left: &str, right: &str) -> String {
+        if op.divides() {
+            // The runtime rounds the divisor as the interpreter does
+            let zero = self.call("i32", "basic_divides_by_zero", &[
+                format!("i32 {}", op.code()),
+                format!("double {}", right),
+                format!("i32 {}", self.dialect.integer_bits),
+            ]);
+            let non_zero = self.builder.next_temp();
+            self.builder.add_icmp("eq", &zero, "0", &non_zero[1..]);
+            self.emit_error_unless(&non_zero, ErrorCondition::DivisionByZero, "Division by zero");
+        }
+        let result = self.call("double", "basic_integer_op", &[
+            format!("i32 {}", op.code()),
+            format!("double {}", left),
+            format!("double {}", right),
+            format!("i32 {}", self.dialect.integer_bits),
+        ]);
+        self.check_integer_result(&result, op.name())
+    }
+
+    /// Raises Overflow in `op` if an integer operator's result is NaN
+    fn check_integer_result(&mut self, value: &str, op: &str) -> String {
+        let ok = self.builder.next_temp();
+        self.builder.add_fcmp("ord", value, "0.0", &ok[1..]);
+        self.emit_error_unless(&ok, ErrorCondition::Overflow, &mbf::overflow_message(op));
+        value.to_string()
+    }
+
+    /// A number computed from `expr` rounded to the dialect's precision, unless it is
+    /// double or single precision, as the interpreter does
+    fn round_expression(&mut self, value: &str, expr: &Expression) -> String {
+        if expr.is_double_precision() {
+            value.to_string()
+        } else if expr.is_single_precision() {
+            self.round_to(Precision::Single, value)
+        } else {
+            self.round_number(value)
+        }
+    }
+
+    /// A computed number rounded to the dialect's precision
+    fn round_number(&mut self, value: &str) -> String {
+        self.round_to(self.dialect.precision, value)
+    }
+
+    fn round_to(&mut self, precision: Precision, value: &str) -> String {
+        match precision {
+            Precision::Double => value.to_string(),
+            Precision::Single => {
+                let single = self.builder.next_temp();
+                self.builder.line(&format!("  {} = fptrunc double {} to float", single, value));
+                let result = self.builder.next_temp();
+                self.builder.line(&format!("  {} = fpext float {} to double", result, single));
+                result
+            }
+            Precision::Mbf40 => self.call("double", "basic_round_mbf40", &[format!("double {}", value)]),
+        }
+    }
+
+    /// Raises STRING TOO LONG if a string result is longer than the dialect allows;
+    /// hands the value back for the caller to use
+    fn check_string_length(&mut self, value: &str) -> String {
+        let message = self.call("i8*", "basic_check_string", &[
+            format!("i8* {}", value),
+            format!("i32 {}", self.dialect.max_string_length),
+        ]);
+        let ok = self.builder.next_temp();
+        self.builder.line(&format!("  {} = icmp eq i8* {}, null", ok, message));
+        let ok_block = self.builder.next_block();
+        let err_block = self.builder.next_block();
+        self.conditional_branch(&ok, &ok_block, &err_block);
+        self.builder.add_basic_block(&err_block);
+        let line_number = self.current_line_number();
+        self.emit_raise(ERROR_RUNTIME, &message, ErrorCondition::StringTooLong, line_number);
+        self.builder.add_basic_block(&ok_block);
+        self.terminated = false;
+        value.to_string()
+    }
+
+    /// The fcmp predicate of a comparison operator, or None for any other operator
+    fn comparison_predicate(op: &str) -> Option<&'static str> {
+        match op {
+            "=" => Some("oeq"),
+            "<>" => Some("une"),
+            "<" => Some("olt"),
+            "<=" => Some("ole"),
+            ">" => Some("ogt"),
+            ">=" => Some("oge"),
+            _ => None,
+        }
+    }
+
+    fn codegen_comparison(&mut self, predicate: &str, left: &str, right: &str) -> String {
+        let flag = self.builder.next_temp();
+        self.builder.add_fcmp(predicate, left, right, &flag[1..]);
+        let result = self.builder.next_temp();
+        self.builder.line(&format!("  {} = select i1 {}, double {}, double 0.0", result, flag, Self::float_literal(self.dialect.true_value as f64)));
+        result
+    }
+
+    fn codegen_builtin_call(&mut self, name: &str, args: &[Expression]) -> String {
+        let returns_string = FUNCTION_REGISTRY.is_string_function(name);
+        let is_string: Vec<bool> = args.iter().map(|arg| self.is_string_expression(arg)).collect();
+        let expected_types = match FUNCTION_REGISTRY.select_arg_form(name, &is_string) {
+            Ok(form) => form.to_vec(),
+            Err(message) => {
+                self.emit_runtime_error(ErrorCondition::Unprintable, &message);
+                return Self::dummy_value(returns_string);
+            }
+        };
+
+        let mut values = Vec::new();
+        for (arg, expected_type) in args.iter().zip(expected_types.iter()) {
+            let is_string = self.is_string_expression(arg);
+            match (expected_type, is_string) {
+                (ArgType::Number, false) => values.push(format!("double {}", self.codegen_expression(arg))),
+                (ArgType::String, true) => values.push(format!("i8* {}", self.codegen_expression(arg))),
+                (ArgType::Number, true) => {
+                    self.emit_runtime_error(ErrorCondition::TypeMismatch, &format!("Function '{}' expects a number argument", name));
+                    return Self::dummy_value(returns_string);
+                }
+                (ArgType::String, false) => {
+                    self.emit_runtime_error(ErrorCondition::TypeMismatch, &format!("Function '{}' expects a string argument", name));
+                    return Self::dummy_value(returns_string);
+                }
+            }
+        }
+
+        let (return_type, func) = match name {
+            "ABS" => ("double", "fabs"),
+            "ATN" => ("double", "atan"),
+            "COS" => ("double", "cos"),
+            "EXP" => ("double", "exp"),
+            "INT" => ("double", "floor"),
+            "LOG" => ("double", "log"),
+            "RND" => ("double", "basic_rnd"),
+            "SIN" => ("double", "sin"),
+            "SQR" => ("double", "sqrt"),
+            "TAN" => ("double", "tan"),
+            "LEN" => ("double", "basic_len"),
+            "CHR$" => ("i8*", "basic_chr"),
+            "LEFT$" => ("i8*", "basic_left"),
+            "MID$" => {
+                if values.len() == 2 {
+                    // Without a length, MID$ takes the rest of the string
+                    let length = self.call("double", "basic_len", &values[..1]);
+                    values.push(format!("double {}", length));
+                }
+                let result = self.call("i8*", "basic_mid", &values);
+                let ok = self.builder.next_temp();
+                self.builder.line(&format!("  {} = icmp ne i8* {}, null", ok, result));
+                self.emit_error_unless(&ok, ErrorCondition::IllegalFunctionCall, &strings::illegal_call_message(name));
+                return result;
+            }
+            "UCASE$" => ("i8*", "basic_ucase"),
+            "LCASE$" => ("i8*", "basic_lcase"),
+            "LTRIM$" => ("i8*", "basic_ltrim"),
+            "RTRIM$" => ("i8*", "basic_rtrim"),
+            "INSTR" => {
+                if values.len() == 2 {
+                    values.insert(0, "double 1.0".to_string());
+                }
+                let result = self.call("double", "basic_instr", &values);
+                let ok = self.builder.next_temp();
+                self.builder.add_fcmp("ord", &result, "0.0", &ok[1..]);
+                self.emit_error_unless(&ok, ErrorCondition::IllegalFunctionCall, &strings::illegal_call_message(name));
+                return result;
+            }
+            "VAL" => {
+                let syntax = self.dialect.val;
+                values.push(format!("i32 {}", syntax.code()));
+                let result = self.call("double", "basic_val", &values);
+                if let Some(message) = syntax.error_message() {
+                    let ok = self.builder.next_temp();
+                    self.builder.add_fcmp("ord", &result, "0.0", &ok[1..]);
+                    self.emit_error_unless(&ok, self.dialect.val_error(), &message);
+                }
+                return result;
+            }
+            "STRING$" | "HEX$" | "OCT$" => {
+                let (func, condition, message) = match name {
+                    "STRING$" if is_string[1] => ("basic_string_of", ErrorCondition::IllegalFunctionCall, strings::illegal_call_message(name)),
+                    "STRING$" => ("basic_string", ErrorCondition::IllegalFunctionCall, strings::illegal_call_message(name)),
+                    "HEX$" => ("basic_hex", ErrorCondition::Overflow, mbf::overflow_message(name)),
+                    _ => ("basic_oct", ErrorCondition::Overflow, mbf::overflow_message(name)),
+                };
+                if name != "STRING$" {
+                    values.push(format!("i32 {}", self.dialect.integer_bits));
+                }
+                let result = self.call("i8*", func, &values);
+                let ok = self.builder.next_temp();
+                self.builder.line(&format!("  {} = icmp ne i8* {}, null", ok, result));
+                self.emit_error_unless(&ok, condition, &message);
+                return self.check_string_length(&result);
+            }
+            "RIGHT$" => ("i8*", "basic_right"),
+            "SPACE$" => ("i8*", "basic_space"),
+            "STR$" => ("i8*", "basic_str"),
+            "ASC" => {
+                values.push(format!("i32 {}", self.current_line_number()));
+                ("double", "basic_asc")
+            }
+            "MKI$" | "MKS$" | "MKD$" => {
+                let func = format!("basic_{}", name[..3].to_ascii_lowercase());
+                let result = self.call("i8*", &func, &values);
+                let ok = self.builder.next_temp();
+                self.builder.line(&format!("  {} = icmp ne i8* {}, null", ok, result));
+                self.emit_error_unless(&ok, ErrorCondition::Overflow, &mbf::overflow_message(name));
+                return result;
+            }
+            "CVI" | "CVS" | "CVD" => {
+                let func = format!("basic_{}", name.to_ascii_lowercase());
+                let result = self.call("double", &func, &values);
+                let ok = self.builder.next_temp();
+                self.builder.add_fcmp("ord", &result, "0.0", &ok[1..]);
+                let bytes = match name { "CVI" => 2, "CVS" => 4, _ => 8 };
+                self.emit_error_unless(&ok, ErrorCondition::IllegalFunctionCall, &mbf::short_string_message(name, bytes));
+                return result;
+            }
+            "EOF" | "LOF" => {
+                let func = if name == "EOF" { "basic_file_eof" } else { "basic_file_lof" };
+                let result = self.call("double", func, &values);
+                self.emit_file_check();
+                if name == "EOF" {
+                    // The runtime gives 1 at the end, which becomes the dialect's true
+                    return self.codegen_comparison("une", &result, "0.0");
+                }
+                return result;
+            }
+            "ERR" | "ERL" => {
+                // Both stay 0 until an error is trapped
+                if self.error_handlers.is_empty() {
+                    return "0.0".to_string();
+                }
+                let global = if name == "ERR" { "@basic_err_code" } else { "@basic_err_line" };
+                let value = self.load("i32", global);
+                let result = self.builder.next_temp();
+                self.builder.line(&format!("  {} = sitofp i32 {} to double", result, value));
+                return result;
+            }
+            "DET" => return self.call("double", "basic_mat_det", &[]),
+            "TIMER" => return self.call("double", "basic_timer", &[]),
+            "SGN" => {
+                let value = &values[0]["double ".len()..];
+                let positive = self.builder.next_temp();
+                self.builder.add_fcmp("ogt", value, "0.0", &positive[1..]);
+                let negative = self.builder.next_temp();
+                self.builder.add_fcmp("olt", value, "0.0", &negative[1..]);
+                let p = self.builder.next_temp();
+                self.builder.line(&format!("  {} = uitofp i1 {} to double", p, positive));
+                let n = self.builder.next_temp();
+                self.builder.line(&format!("  {} = uitofp i1 {} to double", n, negative));
+                let result = self.builder.next_temp();
+                self.builder.add_binary_op("fsub", &p, &n, "double", &result[1..]);
+                return result;
+            }
+            _ => {
+                // TAB only has meaning as a PRINT item
+                self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Function '{}' is not supported here", name));
+                return Self::dummy_value(returns_string);
+            }
+        };
+        let result = self.call(return_type, func, &values);
+        if returns_string {
+            return self.check_string_length(&result);
+        }
+        result
+    }
+
+    fn codegen_user_call(&mut self, name: &str, args: &[Expression]) -> String {
+        let Some(function) = self.user_functions.get(name).cloned() else {
+            self.emit_runtime_error(ErrorCondition::UndefinedUserFunction, &format!("Undefined user function '{}'", name));
+            return Self::dummy_value(false);
+        };
+        let returns_string = self.is_string_expression(&function.body);
+        if args.len() != function.params.len() {
+            self.emit_runtime_error(ErrorCondition::Unprintable, &format!("User-defined function '{}' expects {} arguments", name, function.params.len()));
+            return Self::dummy_value(returns_string);
+        }
+        let mut values = Vec::new();
+        for arg in args {
+            let message = format!("User-defined function '{}' expects number arguments", name);
+            let value = self.codegen_number(arg, &message);
+            values.push(format!("double {}", value));
+        }
+        let return_type = if returns_string { "i8*" } else { "double" };
+        self.call(return_type, &format!("fn_{}", name), &values)
     }
-    
-    fn init_runtime(&mut self) {
-        // Seed random number generator
-        let null_ptr = "null";
-        let time_call = self.builder.add_call("time", &[null_ptr.to_string()], "i64", "time_val");
-        let time_int = self.builder.add_trunc(&time_call, "i32", "time_int");
-        self.builder.add_call_void("srand", &[time_int]);
+
+    /// Counts the nesting depth of DEF FN and procedure calls, so runaway recursion fails
+    /// like the interpreter
+    fn emit_depth_enter(&mut self) {
+        let depth = self.load("i32", "@basic_fn_depth");
+        let has_room = self.builder.next_temp();
+        self.builder.add_icmp("slt", &depth, &self.dialect.max_recursion_depth.to_string(), &has_room[1..]);
+        self.emit_error_unless(&has_room, ErrorCondition::OutOfMemory, &limits::recursion_depth_message(self.dialect.max_recursion_depth));
+        let deeper = self.builder.next_temp();
+        self.builder.add_binary_op("add", &depth, "1", "i32", &deeper[1..]);
+        self.builder.add_typed_store("i32", &deeper, "@basic_fn_depth");
     }
-    
-    fn emit_trace(&mut self, line_number: usize) {
-        let debug_str = format!("Executing line {}\\n", line_number);
-        let debug_name = format!("debug_str_{}", line_number);
-        self.builder.add_string_constant(&debug_name, &debug_str);
-        
-        let debug_ptr = self.builder.add_bitcast(&format!("@{}", debug_name), "i8*", "debug_ptr");
-        self.builder.add_call_void("printf", &[debug_ptr]);
+
+    fn emit_depth_leave(&mut self) {
+        let depth = self.load("i32", "@basic_fn_depth");
+        let shallower = self.builder.next_temp();
+        self.builder.add_binary_op("sub", &depth, "1", "i32", &shallower[1..]);
+        self.builder.add_typed_store("i32", &shallower, "@basic_fn_depth");
     }
-    
-    fn generate_line_statements(&mut self, statements: &[Statement]) {
-        for statement in statements {
-            self.generate_statement(statement);
+
+    /// The LLVM type a variable is stored as, and its initial value. Integer variables,
+    /// `A%`, are integers of the dialect's size.
+    fn variable_type(&self, name: &str) -> (String, &'static str) {
+        match VarType::of(name) {
+            Some(VarType::String) => ("i8*".to_string(), EMPTY_STRING),
+            Some(VarType::Integer) => (self.integer_type(), "0"),
+            _ => ("double".to_string(), "0.0"),
         }
     }
-    
-    fn generate_statement(&mut self, statement: &Statement) {
-        match statement {
-            Statement::Let { var, value } => self.codegen_let(var, value),
-            Statement::Print { items } => self.codegen_print(items),
-            Statement::End => {
-                self.builder.add_return(Some("0"));
-            },
-            Statement::Stop => {
-                self.builder.add_return(Some("1"));
-            },
-            Statement::Rem { comment: _ } => {
-                // Comments are no-ops in generated code
-            },
-            _ => {
-                if self.debug {
-                    self.builder.comment(&format!("TODO: Statement {:?} not yet implemented", statement));
-                }
+
+    /// The LLVM type of a variable's value in expressions, arguments and results
+    fn value_type(name: &str) -> &'static str {
+        if name.ends_with('$') { "i8*" } else { "double" }
+    }
+
+    fn integer_type(&self) -> String {
+        format!("i{}", self.dialect.integer_bits)
+    }
+
+    /// A variable's name inside an LLVM identifier, which cannot hold `%`, `!` or `#`
+    fn ir_name(name: &str) -> String {
+        name.replace('%', ".int").replace('!', ".sng").replace('#', ".dbl")
+    }
+
+    /// Reads a variable; an integer one is converted to a double
+    fn load_variable(&mut self, name: &str) -> String {
+        let ptr = self.variable_ptr(name);
+        if VarType::of(name) != Some(VarType::Integer) {
+            return self.load(Self::value_type(name), &ptr);
+        }
+        let integer_type = self.integer_type();
+        let value = self.load(&integer_type, &ptr);
+        let result = self.builder.next_temp();
+        self.builder.line(&format!("  {} = sitofp {} {} to double", result, integer_type, value));
+        result
+    }
+
+    /// Stores a value in a variable, converted to the variable's type. Returns the value
+    /// as stored, as a double for a number.
+    fn store_variable(&mut self, name: &str, value: &str) -> String {
+        let ptr = self.variable_ptr(name);
+        if name.ends_with('$') {
+            self.builder.add_typed_store("i8*", value, &ptr);
+            return value.to_string();
+        }
+        let value = self.typed_number(name, value);
+        if VarType::of(name) == Some(VarType::Integer) {
+            let integer_type = self.integer_type();
+            let integer = self.builder.next_temp();
+            self.builder.line(&format!("  {} = fptosi double {} to {}", integer, value, integer_type));
+            self.builder.add_typed_store(&integer_type, &integer, &ptr);
+        } else {
+            self.builder.add_typed_store("double", &value, &ptr);
+        }
+        value
+    }
+
+    /// A number as variable `name` holds it, as SymbolTable::typed_value gives it: a whole
+    /// number for `A%`, raising Overflow outside the dialect's range, single precision for
+    /// `A!`, double for `A#`, and the dialect's precision without a suffix
+    fn typed_number(&mut self, name: &str, value: &str) -> String {
+        match VarType::of(name) {
+            Some(VarType::Integer) => {
+                let bits = self.dialect.integer_bits;
+                let limit = 2f64.powi(bits as i32 - 1);
+                let rounded = self.call("double", "round", &[format!("double {}", value)]);
+                let above = self.builder.next_temp();
+                self.builder.add_fcmp("oge", &rounded, &Self::float_literal(-limit), &above[1..]);
+                let below = self.builder.next_temp();
+                self.builder.add_fcmp("olt", &rounded, &Self::float_literal(limit), &below[1..]);
+                let fits = self.builder.next_temp();
+                self.builder.add_binary_op("and", &above, &below, "i1", &fits[1..]);
+                self.emit_error_unless(&fits, ErrorCondition::Overflow, &precision::integer_overflow_message(name, bits));
+                rounded
             }
+            Some(VarType::Single) => self.round_to(Precision::Single, value),
+            Some(VarType::Double) => value.to_string(),
+            _ => self.round_number(value),
         }
     }
-    
-    fn codegen_let(&mut self, var: &Expression, value: &Expression) {
-        // TODO: Implement LET statement
-        if self.debug {
-            self.builder.comment(&format!("LET statement: {:?} = {:?}", var, value));
+
+    /// CALL of a SUB
+    fn codegen_call(&mut self, name: &str, args: &[Expression]) {
+        match self.procedures.get(name).filter(|p| p.kind == ProcedureKind::Sub).cloned() {
+            Some(procedure) => {
+                self.codegen_procedure_call(&procedure, args);
+            }
+            None => self.emit_runtime_error(ErrorCondition::Unprintable, &format!("Undefined SUB {}", name)),
         }
     }
-    
-    fn codegen_print(&mut self, items: &[PrintItem]) {
-        if self.debug {
-            self.builder.comment(&format!("PRINT statement with {} items", items.len()));
+
+    /// Calls a procedure's function with its arguments, checked as the interpreter does.
+    /// Returns the FUNCTION's value, or None if the arguments do not fit.
+    fn codegen_procedure_call(&mut self, procedure: &Procedure, args: &[Expression]) -> Option<String> {
+        let keyword = procedure.kind.keyword();
+        if args.len() != procedure.params.len() {
+            self.emit_runtime_error(ErrorCondition::Unprintable, &format!("{} {} expects {} arguments, got {}", keyword, procedure.name, procedure.params.len(), args.len()));
+            return None;
         }
-        
-        for item in items {
-            match item {
-                PrintItem::Expression(expr) => {
-                    match &expr.expr_type {
-                        ExpressionType::String(s) => {
-                            // Generate string constant at module level
-                            let str_name = format!("str_{}", self.builder.next_global().replace("@", ""));
-                            self.builder.add_string_constant(&str_name, s);
-                            
-                            let str_ptr = self.builder.add_bitcast(&format!("@{}", str_name), "i8*", "str_ptr");
-                            self.builder.add_call_void("printf", &[str_ptr]);
-                        }
-                        ExpressionType::Number(n) => {
-                            // Handle number literals
-                            let format_str = self.builder.next_global();
-                            self.builder.add_string_constant(&format_str, "%.2f\n");
-                            
-                            let format_ptr = self.builder.add_bitcast(&format_str, "i8*", "format_ptr");
-                            self.builder.add_call_void("printf", &[format_ptr, format!("{:.2}", n)]);
-                        }
-                        _ => {
-                            // For other expression types, use the expression codegen
-                            let result = self.codegen_expression(expr);
-                            let format_str = self.builder.next_global();
-                            self.builder.add_string_constant(&format_str, "%f\n");
-                            
-                            let format_ptr = self.builder.add_bitcast(&format_str, "i8*", "format_ptr");
-                            self.builder.add_call_void("printf", &[format_ptr, result]);
-                        }
-                    }
-                }
-                PrintItem::Tab(_) => {
-                    // TODO: Implement tab functionality
-                    if self.debug {
-                        self.builder.comment("TODO: Implement TAB");
-                    }
-                }
-                PrintItem::Comma => {
-                    // TODO: Implement comma spacing
-                    if self.debug {
-                        self.builder.comment("TODO: Implement comma spacing");
-                    }
-                }
-                PrintItem::Semicolon => {
-                    // TODO: Implement semicolon (no spacing)
-                    if self.debug {
-                        self.builder.comment("TODO: Implement semicolon");
-                    }
-                }
+        let mut values = Vec::new();
+        for (i, (arg, param)) in args.iter().zip(&procedure.params).enumerate() {
+            let llvm_type = Self::value_type(param);
+            if self.is_string_expression(arg) != param.ends_with('$') {
+                self.emit_runtime_error(ErrorCondition::TypeMismatch, &format!("Type mismatch in argument {} of {} {}", i + 1, keyword, procedure.name));
+                return None;
             }
+            let value = self.codegen_expression(arg);
+            values.push(format!("{} {}", llvm_type, value));
         }
+        // Counted here rather than in the procedure, so that an overflow reports the call
+        self.emit_depth_enter();
+        let func = Self::procedure_function(procedure);
+        let result = match procedure.kind {
+            ProcedureKind::Sub => {
+                self.builder.add_call_void(&func, &values);
+                String::new()
+            }
+            ProcedureKind::Function => {
+                self.call(Self::value_type(&procedure.name), &func, &values)
+            }
+        };
+        self.emit_depth_leave();
+        Some(result)
     }
-    
-    fn codegen_expression(&mut self, expr: &Expression) -> String {
-        match &expr.expr_type {
-            ExpressionType::Number(n) => {
-                // Return the number as a string for printf
-                format!("{:.2}", n)
-            }
-            ExpressionType::String(s) => {
-                let str_name = self.builder.next_global();
-                self.builder.add_string_constant(&str_name, s);
-                
-                let str_ptr = self.builder.add_bitcast(&str_name, "i8*", "str_ptr");
-                self.builder.add_call("printf", &[str_ptr], "i32", "print_result");
-                
-                "0".to_string() // Return dummy value for now
-            }
-            ExpressionType::Variable(_name) => {
-                // For now, just return a dummy value
-                // TODO: Implement variable lookup
-                "0".to_string()
-            }
-            ExpressionType::BinaryOp { left, op, right } => {
-                let left_val = self.codegen_expression(left);
-                let right_val = self.codegen_expression(right);
-                
-                let temp = self.builder.next_temp();
-                match op.as_str() {
-                    "+" => {
-                        self.builder.add_binary_op("fadd", &left_val, &right_val, "double", &temp[1..]);
-                    }
-                    "-" => {
-                        self.builder.add_binary_op("fsub", &left_val, &right_val, "double", &temp[1..]);
-                    }
-                    "*" => {
-                        self.builder.add_binary_op("fmul", &left_val, &right_val, "double", &temp[1..]);
-                    }
-                    "/" => {
-                        self.builder.add_binary_op("fdiv", &left_val, &right_val, "double", &temp[1..]);
+
+    fn procedure_function(procedure: &Procedure) -> String {
+        match procedure.kind {
+            ProcedureKind::Sub => format!("sub_{}", procedure.name),
+            ProcedureKind::Function => format!("func_{}", procedure.name),
+        }
+    }
+
+    /// Each SUB and FUNCTION becomes an internal function. Its locals are stack slots that
+    /// shadow the globals of the same name; every other variable is the global. The FOR
+    /// and GOSUB stacks are shared with the caller, so the procedure marks where its own
+    /// part of them starts, and drops what it left there on return.
+    fn emit_procedures(&mut self) {
+        for procedure in self.procedures.iter().cloned().collect::<Vec<_>>() {
+            let (start_index, start_offset) = procedure.start;
+            (self.current_line_index, self.current_offset) = procedure.start;
+            let return_type = match procedure.kind {
+                ProcedureKind::Sub => "void",
+                ProcedureKind::Function => Self::value_type(&procedure.name),
+            };
+            let params: Vec<String> = procedure.params.iter()
+                .map(|param| format!("{} %arg_{}", Self::value_type(param), Self::ir_name(param)))
+                .collect();
+            self.builder.line(&format!("define internal {} @{}({}) {{", return_type, Self::procedure_function(&procedure), params.join(", ")));
+            self.builder.add_basic_block("entry");
+            self.terminated = false;
+
+            let globals = self.symbol_table.clone();
+            for local in &procedure.locals {
+                let (llvm_type, initial) = self.variable_type(local);
+                let ptr = format!("%local_{}", Self::ir_name(local));
+                self.builder.line(&format!("  {} = alloca {}", ptr, llvm_type));
+                self.builder.add_typed_store(&llvm_type, initial, &ptr);
+                self.symbol_table.insert(local.clone(), ptr);
+            }
+            for param in &procedure.params {
+                self.store_variable(param, &format!("%arg_{}", Self::ir_name(param)));
+            }
+            let mut saved_bases = Vec::new();
+            for stack in ["for", "gosub"] {
+                let saved = self.load("i32", &format!("@basic_{}_base", stack));
+                let sp = self.load("i32", &format!("@basic_{}_sp", stack));
+                self.builder.add_typed_store("i32", &sp, &format!("@basic_{}_base", stack));
+                saved_bases.push((stack, saved, sp));
+            }
+            if self.trace {
+                self.emit_trace(self.program.lines[start_index].line_number);
+            }
+            let body = self.label_after(start_index, start_offset);
+            self.branch(&body);
+
+            let saved_returns = std::mem::take(&mut self.gosub_returns);
+            let saved_uses_return = std::mem::replace(&mut self.uses_return, false);
+            let saved_missing_lines = std::mem::take(&mut self.missing_lines);
+            self.current_procedure = Some(procedure.clone());
+            for i in start_index..=procedure.end.0 {
+                self.current_line_index = i;
+                let statements = self.program.lines[i].statements.clone();
+                for (offset, statement) in statements.iter().enumerate() {
+                    if (i, offset) == procedure.start {
+                        continue;
                     }
-                    _ => {
-                        // Default to addition for unknown operators
-                        self.builder.add_binary_op("fadd", &left_val, &right_val, "double", &temp[1..]);
+                    self.current_offset = offset;
+                    let label = self.statement_label(i, offset);
+                    self.start_block(&label);
+                    if offset == 0 && self.trace {
+                        self.emit_trace(self.program.lines[i].line_number);
                     }
+                    self.generate_statement(statement, i, offset);
                 }
-                temp
             }
-            _ => {
-                // For other expression types, return a dummy value
-                "0".to_string()
+
+            self.start_block(PROCEDURE_RETURN);
+            for (stack, saved, sp) in saved_bases {
+                self.builder.add_typed_store("i32", &sp, &format!("@basic_{}_sp", stack));
+                self.builder.add_typed_store("i32", &saved, &format!("@basic_{}_base", stack));
             }
+            match procedure.kind {
+                ProcedureKind::Sub => self.builder.add_return(None),
+                ProcedureKind::Function => {
+                    let result = self.load_variable(&procedure.name);
+                    self.builder.line(&format!("  ret {} {}", return_type, result));
+                }
+            }
+            self.terminated = true;
+            self.emit_return_dispatch();
+            self.emit_missing_lines();
+            self.builder.end_function();
+
+            self.current_procedure = None;
+            self.symbol_table = globals;
+            self.gosub_returns = saved_returns;
+            self.uses_return = saved_uses_return;
+            self.missing_lines = saved_missing_lines;
+        }
+    }
+
+    /// Each DEF FN becomes an internal function; its parameters shadow globals of the same name
+    fn emit_user_functions(&mut self) {
+        for (name, function) in self.user_functions.clone() {
+            self.current_line_index = function.line_index;
+            let returns_string = self.is_string_expression(&function.body);
+            let return_type = if returns_string { "i8*" } else { "double" };
+            let params: Vec<String> = function.params.iter()
+                .map(|param| format!("double %param_{}", Self::ir_name(param)))
+                .collect();
+            self.builder.line(&format!("define internal {} @fn_{}({}) {{", return_type, name, params.join(", ")));
+            self.builder.add_basic_block("entry");
+            self.terminated = false;
+            // Parameters take the arguments as their types hold them
+            for param in &function.params {
+                let value = self.typed_number(param, &format!("%param_{}", Self::ir_name(param)));
+                self.function_params.insert(param.clone(), value);
+            }
+
+            self.emit_depth_enter();
+            let value = self.codegen_expression(&function.body);
+            self.emit_depth_leave();
+            self.builder.line(&format!("  ret {} {}", return_type, value));
+            self.builder.end_function();
+            self.function_params.clear();
         }
     }
 }
@@ -364,7 +3110,7 @@ mod tests {
 
     fn create_test_program() -> Program {
         let mut program = Program::new();
-        
+
         // Add a simple program: 10 PRINT "HELLO" : 20 END
         let line1 = ProgramLine {
             line_number: 10,
@@ -375,22 +3121,22 @@ mod tests {
                 }
             ],
         };
-        
+
         let line2 = ProgramLine {
             line_number: 20,
             source: "20 END".to_string(),
             statements: vec![Statement::End],
         };
-        
+
         program.add_line(10, line1.source.clone(), line1.statements.clone());
         program.add_line(20, line2.source.clone(), line2.statements.clone());
-        
+
         program
     }
 
     fn create_variable_test_program() -> Program {
         let mut program = Program::new();
-        
+
         // Add a program with variables: 10 LET A = 42 : 20 PRINT A : 30 END
         let line1 = ProgramLine {
             line_number: 10,
@@ -402,7 +3148,7 @@ mod tests {
                 }
             ],
         };
-        
+
         let line2 = ProgramLine {
             line_number: 20,
             source: "20 PRINT A".to_string(),
@@ -412,23 +3158,23 @@ mod tests {
                 }
             ],
         };
-        
+
         let line3 = ProgramLine {
             line_number: 30,
             source: "30 END".to_string(),
             statements: vec![Statement::End],
         };
-        
+
         program.add_line(10, line1.source.clone(), line1.statements.clone());
         program.add_line(20, line2.source.clone(), line2.statements.clone());
         program.add_line(30, line3.source.clone(), line3.statements.clone());
-        
+
         program
     }
 
     fn create_array_test_program() -> Program {
         let mut program = Program::new();
-        
+
         // Add a program with arrays: 10 DIM A(10) : 20 LET A(5) = 42 : 30 END
         let line1 = ProgramLine {
             line_number: 10,
@@ -442,7 +3188,7 @@ mod tests {
                 }
             ],
         };
-        
+
         let line2 = ProgramLine {
             line_number: 20,
             source: "20 LET A(5) = 42".to_string(),
@@ -453,50 +3199,60 @@ mod tests {
                 }
             ],
         };
-        
+
         let line3 = ProgramLine {
             line_number: 30,
             source: "30 END".to_string(),
             statements: vec![Statement::End],
         };
-        
+
         program.add_line(10, line1.source.clone(), line1.statements.clone());
         program.add_line(20, line2.source.clone(), line2.statements.clone());
         program.add_line(30, line3.source.clone(), line3.statements.clone());
-        
+
         program
     }
 
+    fn compile_source(source: &str) -> String {
+        let mut lexer = crate::basic_lexer::Lexer::new(source);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = crate::basic_parser::Parser::new(tokens);
+        let program = parser.parse().unwrap();
+        LLVMCodeGenerator::new(program, false, false).generate_ir()
+    }
+
     #[test]
     fn test_basic_program_generation() {
         let program = create_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
         let ir = codegen.generate_ir();
-        
+
         // Verify basic structure
         assert!(ir.contains("define i32 @main()"));
         assert!(ir.contains("entry:"));
         assert!(ir.contains("line_10:"));
         assert!(ir.contains("line_20:"));
         assert!(ir.contains("ret i32 0"));
-        
-        // Verify external function declarations
-        assert!(ir.contains("declare i32 @printf"));
-        assert!(ir.contains("declare i32 @scanf"));
-        assert!(ir.contains("declare i8* @malloc"));
+
+        // Verify runtime library declarations, and that libc I/O is no longer used
+        assert!(ir.contains("declare void @basic_print_number(double)"));
+        assert!(ir.contains("declare void @basic_print_newline()"));
+        assert!(!ir.contains("@printf"));
     }
 
     #[test]
     fn test_program_with_variables() {
         let program = create_variable_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
         let ir = codegen.generate_ir();
-        
+
         // Verify variable allocation
-        assert!(ir.contains("@global_A = global double double = 0.0"));
-        
+        assert!(ir.contains("@global_A = global double 0.0"));
+        assert!(ir.contains("store double 0x4045000000000000, double* @global_A"));
+        assert!(ir.contains("load double, double* @global_A"));
+
         // Verify program structure
         assert!(ir.contains("line_10:"));
         assert!(ir.contains("line_20:"));
@@ -507,12 +3263,14 @@ mod tests {
     fn test_program_with_arrays() {
         let program = create_array_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
         let ir = codegen.generate_ir();
-        
+
         // Verify array allocation
-        assert!(ir.contains("@array_A = global"));
-        
+        assert!(ir.contains("@array_A = global [10 x double] zeroinitializer"));
+        assert!(ir.contains("getelementptr inbounds [10 x double], [10 x double]* @array_A"));
+        assert!(ir.contains("Array index out of bounds"));
+
         // Verify program structure
         assert!(ir.contains("line_10:"));
         assert!(ir.contains("line_20:"));
@@ -523,10 +3281,10 @@ mod tests {
     fn test_debug_mode() {
         let program = create_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, true, false);
-        
+
         let ir = codegen.generate_ir();
         println!("Debug mode IR:\n{}", ir);
-        
+
         // Verify debug comments are added
         assert!(ir.contains("; PRINT statement with"));
     }
@@ -535,22 +3293,21 @@ mod tests {
     fn test_trace_mode() {
         let program = create_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, true);
-        
+
         let ir = codegen.generate_ir();
-        
+
         // Verify trace statements are added
-        assert!(ir.contains("Executing line 10"));
-        assert!(ir.contains("Executing line 20"));
-        assert!(ir.contains("call void @printf"));
+        assert!(ir.contains("call void @basic_trace_line(i32 10)"));
+        assert!(ir.contains("call void @basic_trace_line(i32 20)"));
     }
 
     #[test]
     fn test_empty_program() {
         let program = Program::new();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
         let ir = codegen.generate_ir();
-        
+
         // Verify empty program still generates valid IR
         assert!(ir.contains("define i32 @main()"));
         assert!(ir.contains("entry:"));
@@ -561,10 +3318,10 @@ mod tests {
     fn test_stop_statement() {
         let mut program = Program::new();
         program.add_line(10, "10 STOP".to_string(), vec![Statement::Stop]);
-        
+
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
         let ir = codegen.generate_ir();
-        
+
         // Verify STOP generates return 1
         assert!(ir.contains("ret i32 1"));
     }
@@ -576,10 +3333,10 @@ mod tests {
             Statement::Rem { comment: "This is a comment".to_string() }
         ]);
         program.add_line(20, "20 END".to_string(), vec![Statement::End]);
-        
+
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
         let ir = codegen.generate_ir();
-        
+
         // Verify REM statements are ignored (no-op)
         assert!(ir.contains("line_10:"));
         assert!(ir.contains("line_20:"));
@@ -589,7 +3346,7 @@ mod tests {
     #[test]
     fn test_variable_collection() {
         let mut program = Program::new();
-        
+
         // Add variables of different types
         program.add_line(10, "10 LET A = 42".to_string(), vec![
             Statement::Let {
@@ -597,28 +3354,28 @@ mod tests {
                 value: Expression::new_number(42.0),
             }
         ]);
-        
+
         program.add_line(20, "20 LET B$ = \"HELLO\"".to_string(), vec![
             Statement::Let {
                 var: Expression::new_variable("B$".to_string()),
                 value: Expression::new_string("HELLO".to_string()),
             }
         ]);
-        
+
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
         let ir = codegen.generate_ir();
-        
+
         // Verify numeric variable allocation
-        assert!(ir.contains("@global_A = global double double = 0.0"));
-        
+        assert!(ir.contains("@global_A = global double 0.0"));
+
         // Verify string variable allocation
-        assert!(ir.contains("@global_B$ = global i8* i8* = null"));
+        assert!(ir.contains("@global_B$ = global i8* getelementptr inbounds ([1 x i8], [1 x i8]* @basic_empty_string"));
     }
 
     #[test]
     fn test_array_collection() {
         let mut program = Program::new();
-        
+
         // Add arrays of different types
         program.add_line(10, "10 DIM A(10, 5)".to_string(), vec![
             Statement::Dim {
@@ -628,7 +3385,7 @@ mod tests {
                 }]
             }
         ]);
-        
+
         program.add_line(20, "20 DIM B$(5)".to_string(), vec![
             Statement::Dim {
                 arrays: vec![crate::basic_types::ArrayDecl {
@@ -637,10 +3394,10 @@ mod tests {
                 }]
             }
         ]);
-        
+
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
         let ir = codegen.generate_ir();
-        
+
         // Verify array allocations
         assert!(ir.contains("@array_A = global"));
         assert!(ir.contains("@array_B$ = global"));
@@ -650,21 +3407,19 @@ mod tests {
     fn test_external_function_declarations() {
         let program = create_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
         let ir = codegen.generate_ir();
-        
-        // Verify all external function declarations
-        assert!(ir.contains("declare i32 @printf"));
-        assert!(ir.contains("declare i32 @scanf"));
-        assert!(ir.contains("declare i32 @sprintf"));
-        assert!(ir.contains("declare i32 @sscanf"));
-        assert!(ir.contains("declare i8* @malloc"));
-        assert!(ir.contains("declare i64 @strlen"));
-        assert!(ir.contains("declare i32 @strcmp"));
-        assert!(ir.contains("declare i8* @strcat"));
-        assert!(ir.contains("declare i8* @strcpy"));
-        assert!(ir.contains("declare i8* @strncpy"));
-        
+
+        // Runtime library
+        assert!(ir.contains("declare void @basic_runtime_init()"));
+        assert!(ir.contains("declare void @basic_runtime_error(i32, i8*, i32)"));
+        assert!(ir.contains("declare void @basic_print_string(i8*)"));
+        assert!(ir.contains("declare i8* @basic_input_line(i8*)"));
+        assert!(ir.contains("declare i8* @basic_str_concat(i8*, i8*)"));
+        assert!(ir.contains("declare double @basic_str_compare(i8*, i8*)"));
+        assert!(ir.contains("declare i8* @basic_mid(i8*, double, double)"));
+        assert!(ir.contains("declare double @basic_rnd(double)"));
+
         // Math functions
         assert!(ir.contains("declare double @sin"));
         assert!(ir.contains("declare double @cos"));
@@ -674,22 +3429,429 @@ mod tests {
         assert!(ir.contains("declare double @fabs"));
         assert!(ir.contains("declare double @pow"));
         assert!(ir.contains("declare double @floor"));
-        
-        // Random functions
-        assert!(ir.contains("declare i32 @rand"));
-        assert!(ir.contains("declare void @srand"));
-        assert!(ir.contains("declare i64 @time"));
+
+        // libc is only used for math
+        assert!(!ir.contains("@malloc"));
+        assert!(!ir.contains("@scanf"));
     }
 
     #[test]
     fn test_runtime_initialization() {
         let program = create_test_program();
         let mut codegen = LLVMCodeGenerator::new(program, false, false);
-        
+
+        let ir = codegen.generate_ir();
+
+        // Verify runtime initialization and shutdown
+        assert!(ir.contains("call void @basic_runtime_init()"));
+        assert!(ir.contains("call void @basic_runtime_shutdown()"));
+    }
+
+    #[test]
+    fn test_goto_and_gosub() {
+        let ir = compile_source("10 GOSUB 100\n20 GOTO 200\n100 RETURN\n200 END");
+
+        assert!(ir.contains("br label %line_200"));
+        assert!(ir.contains("store i32 0, i32* %t"));
+        assert!(ir.contains("gosub_return_dispatch:"));
+        assert!(ir.contains("i32 0, label %line_20"));
+        assert!(ir.contains("RETURN without GOSUB"));
+    }
+
+    #[test]
+    fn test_missing_goto_target() {
+        let ir = compile_source("10 GOTO 50");
+
+        assert!(ir.contains("br label %missing_line_50"));
+        assert!(ir.contains("missing_line_50:"));
+        assert!(ir.contains("Line number 50 not found"));
+    }
+
+    #[test]
+    fn test_for_next() {
+        let ir = compile_source("10 FOR I = 1 TO 3\n20 PRINT I\n30 NEXT I\n40 END");
+
+        // The loop body starts at line 20 and the loop exits to line 40
+        assert!(ir.contains("@basic_for_stack"));
+        assert!(ir.contains("label %line_20, label %"));
+        assert!(ir.contains("br label %line_40"));
+        assert!(ir.contains("NEXT without matching FOR"));
+    }
+
+    #[test]
+    fn test_if_then_else() {
+        let ir = compile_source("10 IF A = 1 THEN 30 ELSE 40\n30 PRINT \"YES\"\n40 END");
+
+        // A false condition jumps past the ELSE; the THEN branch skips the ELSE branch
+        assert!(ir.contains("label %line_10_1, label %line_10_4"));
+        assert!(ir.contains("line_10_3:\n  br label %line_30"));
+    }
+
+    #[test]
+    fn test_on_goto() {
+        let ir = compile_source("10 ON X GOTO 20, 30\n20 END\n30 END");
+
+        assert!(ir.contains("ON index must be a positive integer"));
+        assert!(ir.contains("i32 1, label %line_20 i32 2, label %line_30"));
+    }
+
+    #[test]
+    fn test_dialect_arrays_and_stacks() {
+        let mut lexer = crate::basic_lexer::Lexer::new("10 DIM A(10)\n20 GOSUB 40\n30 END\n40 A(0) = 1\n50 RETURN");
+        let program = crate::basic_parser::Parser::new(lexer.tokenize().unwrap()).parse().unwrap();
+        let ir = LLVMCodeGenerator::with_dialect(program, false, false, Dialect::ANSI_MINIMAL).generate_ir();
+
+        // Zero-based: DIM A(10) holds eleven elements, and no offset is subtracted
+        assert!(ir.contains("@array_A = global [11 x double] zeroinitializer"));
+        assert!(ir.lines().any(|line| line.contains("= sub i64 %") && line.ends_with(", 0")));
+        assert!(ir.contains("@basic_gosub_stack = internal global [26 x i32]"));
+    }
+
+    #[test]
+    fn test_dialect_limits() {
+        let source = "10 DEF FNA(X) = FNA(X + 1)\n20 FOR I = 1 TO 2\n30 A$ = \"X\" + LEFT$(\"YZ\", 1)\n40 NEXT I\n50 DIM B(1,1,1)";
+        let mut lexer = crate::basic_lexer::Lexer::new(source);
+        let program = crate::basic_parser::Parser::new(lexer.tokenize().unwrap()).parse().unwrap();
+        let ir = LLVMCodeGenerator::with_dialect(program, false, false, Dialect::ANSI_MINIMAL).generate_ir();
+
+        assert!(ir.contains("OUT OF MEMORY: FOR loops nested more than 26 deep"));
+        assert!(ir.contains("store i32 %t"));
+        assert!(ir.contains("i32* @basic_for_scan"));
+        assert_eq!(ir.matches("call i8* @basic_check_string(").count(), 2);
+        assert!(ir.contains("i32 18)"));
+        assert!(ir.contains("OUT OF MEMORY: function calls nested more than 100 deep"));
+        assert!(ir.contains("load i32, i32* @basic_fn_depth"));
+        assert!(ir.contains("Array 'B' has 3 dimensions, the limit is 2"));
+    }
+
+    #[test]
+    fn test_dialect_precision() {
+        let source = "10 READ A: B = A / 3 + .1\n20 FOR I = 1 TO 2: NEXT I\n30 DATA 0.1";
+        let compile = |dialect: Dialect| {
+            let mut lexer = crate::basic_lexer::Lexer::new(source);
+            let program = crate::basic_parser::Parser::new(lexer.tokenize().unwrap()).parse().unwrap();
+            LLVMCodeGenerator::with_dialect(program, false, false, dialect).generate_ir()
+        };
+
+        let ir = compile(Dialect::GWBASIC);
+        // Constants are rounded when compiled, results as they are computed and stored:
+        // A / 3, + .1, and the stores to A, B and I, twice
+        assert!(ir.contains(&LLVMCodeGenerator::float_literal(0.1f32 as f64)));
+        assert!(!ir.contains(&LLVMCodeGenerator::float_literal(0.1)));
+        assert_eq!(ir.matches("fptrunc double").count(), 6);
+        assert_eq!(ir.matches("fpext float").count(), 6);
+
+        let ir = compile(Dialect::MSBASIC2);
+        assert_eq!(ir.matches("call double @basic_round_mbf40(").count(), 6);
+        assert!(!compile(Dialect::default()).contains("fptrunc"));
+    }
+
+    #[test]
+    fn test_typed_variables() {
+        let source = "10 DEFINT I\n20 FOR I = 1 TO 10: A# = A# + I / 3: NEXT I\n30 PRINT A#";
+        let compile = |dialect: Dialect| {
+            let program = crate::basic_parser::Parser::with_dialect(crate::basic_lexer::Lexer::new(source).tokenize().unwrap(), dialect).parse().unwrap();
+            LLVMCodeGenerator::with_dialect(program, false, false, dialect).generate_ir()
+        };
+
+        let ir = compile(Dialect::GWBASIC);
+        assert!(ir.contains("@global_I.int = global i16 0"));
+        assert!(ir.contains("@global_A.dbl = global double 0.0"));
+        assert!(ir.contains("Overflow: I% holds whole numbers from -32768 to 32767"));
+        assert!(ir.contains("call void @basic_print_double("));
+        // I / 3 is single precision; adding it to A# is not
+        assert_eq!(ir.matches("fptrunc double").count(), 1);
+
+        let ir = compile(Dialect::default());
+        assert!(ir.contains("@global_I.int = global i32 0"));
+        assert!(ir.contains("@global_A = global double 0.0"));
+        assert!(!ir.contains("fptrunc double"));
+
+        // A single precision variable is computed and printed as one
+        let ir = compile_source("10 B! = 1 / 3\n20 PRINT B! * 2");
+        assert_eq!(ir.matches("fptrunc double").count(), 2);
+        assert!(ir.contains("call void @basic_print_single("));
+    }
+
+    #[test]
+    fn test_dialect_number_format() {
+        let ir = compile_source("10 PRINT 1/3");
+        assert!(ir.contains("call void @basic_number_format(i32 16, i32 -3, i32 0)"));
+
+        let mut lexer = crate::basic_lexer::Lexer::new("10 PRINT STR$(1/3)");
+        let program = crate::basic_parser::Parser::new(lexer.tokenize().unwrap()).parse().unwrap();
+        let ir = LLVMCodeGenerator::with_dialect(program, false, false, Dialect::MSBASIC2).generate_ir();
+        assert!(ir.contains("call void @basic_number_format(i32 9, i32 -2, i32 1)"));
+    }
+
+    #[test]
+    fn test_on_error() {
+        let ir = compile_source("10 ON ERROR GOTO 100\n20 X = 1 / 0\n30 END\n100 PRINT ERR; ERL\n110 RESUME NEXT");
+
+        assert!(ir.contains("store i32 100, i32* @basic_err_handler"));
+        // Division by zero is code 11; the error at line 20 is site 0
+        assert!(ir.contains("store i32 11, i32* @basic_err_code"));
+        assert!(ir.contains("store i32 20, i32* @basic_err_line"));
+        assert!(ir.contains("br label %error_dispatch"));
+        assert!(ir.contains("i32 100, label %line_100"));
+        assert!(ir.contains("br label %resume_next_dispatch"));
+        assert!(ir.contains("i32 0, label %line_30"));
+        assert!(ir.contains("load i32, i32* @basic_err_code"));
+
+        // Without ON ERROR, errors go straight to the runtime
+        let ir = compile_source("10 X = 1 / 0\n20 PRINT ERR");
+        assert!(!ir.contains("error_dispatch"));
+        assert!(ir.contains("call void @basic_print_number(double 0.0)"));
+    }
+
+    #[test]
+    fn test_while_and_do_loops() {
+        let ir = compile_source("10 WHILE I < 3\n20 I = I + 1\n30 WEND\n40 DO\n50 EXIT DO\n60 LOOP UNTIL I > 5\n70 END");
+
+        // WEND goes back to the WHILE, which leaves the loop after line 30
+        assert!(ir.contains("label %line_20, label %"));
+        assert!(ir.contains("br label %line_10"));
+        assert!(ir.contains("br label %line_40"));
+        // EXIT DO and a false UNTIL both continue at line 70
+        assert!(ir.contains("br label %line_70"));
+        assert!(ir.contains("xor i1 %t"));
+        assert!(ir.contains("label %line_40, label %line_70"));
+
+        let ir = compile_source("10 DO\n20 WEND\n30 LOOP");
+        assert!(ir.contains("Mismatched WEND: expected LOOP for the DO at line 10"));
+    }
+
+    #[test]
+    fn test_block_if() {
+        let ir = compile_source("10 IF A = 1 THEN\n20 PRINT 1\n30 ELSEIF A = 2 THEN\n40 PRINT 2\n50 ELSE\n60 PRINT 3\n70 END IF\n80 END");
+
+        // A false IF tries the ELSEIF test, which falls back to the ELSE branch
+        assert!(ir.contains("label %line_20, label %line_30_test"));
+        assert!(ir.contains("line_30_test:"));
+        assert!(ir.contains("label %line_40, label %line_60"));
+        // The branches above ELSEIF and ELSE end at the statement after END IF
+        assert_eq!(ir.matches("br label %line_80").count(), 3);
+    }
+
+    #[test]
+    fn test_select_case() {
+        let ir = compile_source("10 SELECT CASE A\n20 CASE 1, 2\n30 PRINT 1\n40 CASE 3 TO 5\n50 CASE ELSE\n60 END SELECT\n70 A$ = \"X\"\n\
+            80 SELECT CASE A$\n90 CASE IS < \"M\"\n100 END SELECT");
+
+        // Each test branches to its CASE's body; with none matching, on to the CASE ELSE branch
+        assert_eq!(ir.matches("label %line_30, label %").count(), 2);
+        assert!(ir.contains("fcmp oge double"));
+        assert!(ir.contains("and i1 %t"));
+        assert!(ir.contains("label %line_50, label %"));
+        assert!(ir.contains("br label %line_60"));
+        // Each CASE and CASE ELSE ends the branch above it after END SELECT, which falls through
+        assert_eq!(ir.matches("br label %line_70").count(), 4);
+        // String selectors are ordered with basic_str_compare
+        assert!(ir.contains("call double @basic_str_compare"));
+        assert!(ir.contains("fcmp olt double %t"));
+    }
+
+    #[test]
+    fn test_procedures() {
+        let ir = compile_source("10 CALL SHOW(1, \"A\")\n20 PRINT TWICE$(\"B\")\n30 END\n\
+            40 SUB SHOW(N, A$)\n50 SHARED T\n60 FOR I = 1 TO N: T = T + I: NEXT I\n70 END SUB\n\
+            80 FUNCTION TWICE$(S$): TWICE$ = S$ + S$: END FUNCTION");
+
+        // The program flows past each procedure, which is a function of its own
+        assert!(ir.contains("line_40:\n  br label %line_80"));
+        assert!(ir.contains("line_80:\n  br label %program_end"));
+        assert!(ir.contains("call void @sub_SHOW(double 0x3FF0000000000000, i8* "));
+        assert!(ir.contains("call i8* @func_TWICE$(i8* "));
+        assert!(ir.contains("define internal void @sub_SHOW(double %arg_N, i8* %arg_A$) {"));
+        assert!(ir.contains("define internal i8* @func_TWICE$(i8* %arg_S$) {"));
+        // Locals live on the stack; SHARED and other names are the globals
+        assert!(ir.contains("%local_I = alloca double"));
+        assert!(ir.contains("store double %arg_N, double* %local_N"));
+        assert!(!ir.contains("%local_T = "));
+        assert!(ir.contains("@global_T"));
+        // FOR and NEXT stay within the procedure's part of the stack
+        assert!(ir.contains("@basic_for_base = internal global i32 0"));
+        assert!(ir.contains("load i32, i32* @basic_for_base"));
+        assert_eq!(ir.matches("br label %procedure_return").count(), 2);
+        assert!(ir.contains("ret i8* %t"));
+    }
+
+    #[test]
+    fn test_sequential_files() {
+        let ir = compile_source("10 OPEN \"S.TXT\" FOR APPEND AS #1\n20 WRITE #1, A$, 2\n30 PRINT #1, A$;\n\
+            40 INPUT #1, X, B$\n50 LINE INPUT #1, L$\n60 PRINT EOF(1); LOF(1)\n70 CLOSE");
+
+        assert!(ir.contains("call void @basic_file_open(i8* "));
+        assert!(ir.contains(", i32 2, double 0x3FF0000000000000, double 0x4060000000000000)"));
+        assert!(ir.contains("call void @basic_file_write_string(double 0x3FF0000000000000, i8* "));
+        assert!(ir.contains("call void @basic_file_write_number(double 0x3FF0000000000000, double 0x4000000000000000)"));
+        // PRINT # ending in a semicolon leaves the line open
+        assert_eq!(ir.matches("call void @basic_file_print_newline").count(), 1);
+        assert!(ir.contains("call double @basic_file_input_number(double 0x3FF0000000000000)"));
+        assert!(ir.contains("call i8* @basic_file_line_input(double 0x3FF0000000000000)"));
+        assert!(ir.contains("call double @basic_file_eof(double 0x3FF0000000000000)"));
+        assert!(ir.contains("call void @basic_file_close_all()"));
+        // Every file operation is followed by a check of its status
+        assert_eq!(ir.matches("call i32 @basic_file_status()").count(), 11);
+        assert!(!ir.contains("@basic_file_err_codes"));
+
+        // With ON ERROR GOTO, ERR comes from a table indexed by the status
+        let ir = compile_source("10 ON ERROR GOTO 100\n20 CLOSE #3\n30 END\n100 RESUME NEXT");
+        assert!(ir.contains("@basic_file_err_codes = internal constant [10 x i32] [i32 0, i32 52, i32 53, i32 54, i32 55, i32 62, i32 13, i32 75, i32 50, i32 63]"));
+        assert!(ir.contains("[10 x i32]* @basic_file_err_codes, i64 0, i32 %t"));
+    }
+
+    #[test]
+    fn test_random_files() {
+        let ir = compile_source("10 OPEN \"R.DAT\" AS #1 LEN=16\n20 FIELD #1, 8 AS N$, 8 AS V$\n\
+            30 LSET N$ = \"BOB\": RSET V$ = MKD$(2)\n40 PUT #1, 2\n50 GET #1\n60 PRINT CVD(V$); CVI(N$)");
+
+        assert!(ir.contains(", i32 3, double 0x3FF0000000000000, double 0x4030000000000000)"));
+        assert!(ir.contains("call void @basic_file_field_add(double 0x4020000000000000, i8* "));
+        assert!(ir.contains("call void @basic_file_field(double 0x3FF0000000000000)"));
+        assert!(ir.contains("call void @basic_file_put(double 0x3FF0000000000000, double 0x4000000000000000, i32 1)"));
+        assert!(ir.contains("call void @basic_file_get(double 0x3FF0000000000000, double 0.0, i32 0)"));
+        // FIELD and GET both reload N$ and V$ from the record buffer
+        assert_eq!(ir.matches("call i8* @basic_file_field_value(").count(), 4);
+        assert!(ir.contains(", i32 0)") && ir.contains("call i8* @basic_file_set_field("));
+        // MKD$ and CVD report a failed conversion as null or NaN
+        assert!(ir.contains("icmp ne i8* %t"));
+        assert!(ir.contains("Overflow in MKD$"));
+        assert!(ir.contains("CVI requires a string of at least 2 bytes"));
+        assert!(ir.contains("fcmp ord double"));
+    }
+
+    #[test]
+    fn test_print_using() {
+        let ir = compile_source("10 ON ERROR GOTO 100\n20 PRINT USING \"##.## &\"; X; \"KG\";\n30 PRINT #1, USING F$; 1\n40 PRINT USING 5; 1\n100 END");
+
+        assert!(ir.contains("call void @basic_using_number(double "));
+        assert!(ir.contains("call void @basic_using_string(i8* "));
+        assert_eq!(ir.matches("call i8* @basic_print_using(i8* ").count(), 2);
+        assert_eq!(ir.matches("call i32 @basic_using_status()").count(), 2);
+        // A type mismatch traps as ERR 13, a format without fields as ERR 5
+        assert!(ir.contains("store i32 13, i32* @basic_err_code"));
+        assert!(ir.contains("store i32 5, i32* @basic_err_code"));
+        assert!(ir.contains("call void @basic_file_print_string(double 0x3FF0000000000000, i8* "));
+        // Line 20 ends with a semicolon, so only line 30 ends its line
+        assert_eq!(ir.matches("call void @basic_print_newline()").count(), 0);
+        assert_eq!(ir.matches("call void @basic_file_print_newline(").count(), 1);
+        assert!(ir.contains("PRINT USING format must be a string"));
+    }
+
+    #[test]
+    fn test_mat() {
+        let ir = compile_source("10 DIM A(2,2), B(2,2), V(3)\n20 MAT READ A\n30 MAT B = INV(A)\n40 MAT PRINT B;\n\
+            50 MAT V = CON(2)\n60 PRINT DET, V(2)\n70 DATA 1, 2, 3, 4");
+
+        // Arrays MAT works on keep their dimensions in a global, which subscripts then use
+        assert!(ir.contains("@dims_A = global [2 x i64] [i64 2, i64 2]"));
+        assert!(ir.contains("@dims_V = global [1 x i64] [i64 3]"));
+        assert!(ir.contains("load i64, i64* %t"));
+        assert!(ir.contains("call void @basic_mat_read(double* getelementptr inbounds ([4 x double], [4 x double]* @basic_data_numbers, i64 0, i64 0), "));
+        assert!(ir.contains("i32 4, i32* @basic_data_ptr)"));
+        assert!(ir.contains(&format!("call void @basic_mat_compute(i32 {}, double 0.0, double 0.0, double 0.0, i32 0)", matrix::Operation::Inverse.code())));
+        assert!(ir.contains(&format!("call void @basic_mat_compute(i32 {}, double 0.0, double 0x4000000000000000, double 0.0, i32 1)", matrix::Operation::Constant.code())));
+        assert!(ir.contains("call void @basic_mat_print(i32 1, i32 0)"));
+        assert!(ir.contains("call double @basic_mat_det()"));
+        assert_eq!(ir.matches("call void @basic_mat_store(").count(), 3);
+        assert_eq!(ir.matches("call i32 @basic_mat_status()").count(), 4);
+        assert!(!ir.contains("@basic_mat_err_codes"));
+
+        let ir = compile_source("10 ON ERROR GOTO 100\n20 DIM A(2)\n30 MAT A = ZER(3)\n40 MAT PRINT Q\n100 RESUME NEXT");
+        assert!(ir.contains("@basic_mat_err_codes = internal constant [6 x i32] [i32 0, i32 5, i32 9, i32 4, i32 13, i32 6]"));
+        assert!(ir.contains("Array 'Q' not found"));
+    }
+
+    #[test]
+    fn test_randomize() {
+        let ir = compile_source("10 RANDOMIZE 42: PRINT RND(1)\n20 RANDOMIZE\n30 PRINT TIMER");
+        assert!(ir.contains("call void @basic_randomize(double 0x4045000000000000)"));
+        assert_eq!(ir.matches("call double @basic_timer()").count(), 2);
+        assert!(!ir.contains("call void @basic_rnd_algorithm("));
+
+        let tokens = crate::basic_lexer::Lexer::new("10 PRINT RND(1)").tokenize().unwrap();
+        let program = crate::basic_parser::Parser::new(tokens).parse().unwrap();
+        let dialect = Dialect { rnd: RndAlgorithm::TrekBasic, ..Dialect::default() };
+        let mut codegen = LLVMCodeGenerator::with_dialect(program, false, false, dialect);
+        codegen.set_seed(-1.0);
         let ir = codegen.generate_ir();
-        
-        // Verify runtime initialization (random seed)
-        assert!(ir.contains("call i64 @time"));
-        assert!(ir.contains("call void @srand"));
+        assert!(ir.contains(&format!("call void @basic_rnd_algorithm(i32 {})", RndAlgorithm::TrekBasic.code())));
+        assert!(ir.contains("call void @basic_randomize(double 0xBFF0000000000000)"));
+    }
+
+    #[test]
+    fn test_integer_operators() {
+        let ir = compile_source("10 PRINT 7 \\ 2, 7 MOD 3, 5 XOR 3, NOT 0, 1 < 2");
+        assert!(ir.contains(&format!("call double @basic_integer_op(i32 {}, ", IntegerOp::IntDivide.code())));
+        assert!(ir.contains(&format!("call double @basic_integer_op(i32 {}, ", IntegerOp::Xor.code())));
+        assert!(ir.contains(&format!("call i32 @basic_divides_by_zero(i32 {}, ", IntegerOp::Mod.code())));
+        assert!(ir.contains("Overflow in MOD"));
+        assert!(!ir.contains("call double @basic_not("));
+        assert!(ir.contains(&format!("double {}, double 0.0", LLVMCodeGenerator::float_literal(1.0))));
+
+        // GW-BASIC's NOT flips bits, and its true is -1
+        let tokens = crate::basic_lexer::Lexer::new("10 PRINT NOT 0, 1 < 2").tokenize().unwrap();
+        let program = crate::basic_parser::Parser::new(tokens).parse().unwrap();
+        let ir = LLVMCodeGenerator::with_dialect(program, false, false, Dialect::GWBASIC).generate_ir();
+        assert!(ir.contains("call double @basic_not("));
+        assert!(ir.contains(&format!("double {}, double 0.0", LLVMCodeGenerator::float_literal(-1.0))));
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let ir = compile_source("10 A$ = \"HELLO\": MID$(A$, 2) = \"EY\"\n20 PRINT INSTR(A$, \"E\"), VAL(A$), MID$(A$, 3)\n30 PRINT STRING$(3, \"*\"), HEX$(10), UCASE$(A$)");
+        assert!(ir.contains("@basic_mid_assign("));
+        assert!(ir.contains("call double @basic_instr(double 1.0, "));
+        assert!(ir.contains(&format!("i32 {})", strings::ValSyntax::PrefixRadix.code())));
+        assert!(ir.contains("@basic_string_of("));
+        assert!(ir.contains("@basic_hex("));
+        assert!(ir.contains("@basic_ucase("));
+        assert!(ir.contains("Illegal function call in MID$"));
+
+        // MID$ itself checks its start too
+        let ir = compile_source("10 PRINT MID$(\"AB\", 0)");
+        assert!(ir.contains("Illegal function call in MID$"));
+    }
+
+    #[test]
+    fn test_read_data_restore() {
+        let ir = compile_source("10 READ A, B$\n20 RESTORE\n30 DATA 1, \"X\"");
+
+        assert!(ir.contains("@basic_data_kinds = internal constant [2 x i8] [i8 0, i8 1]"));
+        assert!(ir.contains("@basic_data_numbers = internal constant [2 x double]"));
+        assert!(ir.contains("Out of DATA values"));
+        assert!(ir.contains("store i32 0, i32* @basic_data_ptr"));
+    }
+
+    #[test]
+    fn test_input() {
+        let ir = compile_source("10 INPUT \"NAME\"; N$, A");
+
+        assert!(ir.contains("call i8* @basic_input_line"));
+        assert!(ir.contains("call i8* @basic_to_upper"));
+        assert!(ir.contains("call i32 @basic_parse_number"));
+        assert!(ir.contains("call void @basic_input_begin()"));
+        assert!(ir.contains("call i8* @basic_input_bad_count(i32 2"));
+        assert!(ir.contains("call i8* @basic_input_bad_number()"));
+    }
+
+    #[test]
+    fn test_def_fn() {
+        let ir = compile_source("10 DEF FNA(X) = X * X + Y\n20 PRINT FNA(3)");
+
+        assert!(ir.contains("define internal double @fn_FNA(double %param_X)"));
+        assert!(ir.contains("fmul double %param_X, %param_X"));
+        assert!(ir.contains("load double, double* @global_Y"));
+        assert!(ir.contains("call double @fn_FNA(double 0x4008000000000000)"));
+    }
+
+    #[test]
+    fn test_string_operations() {
+        let ir = compile_source("10 A$ = \"AB\" + LEFT$(\"CD\", 1)\n20 IF A$ = \"ABC\" THEN 10");
+
+        assert!(ir.contains("call i8* @basic_str_concat"));
+        assert!(ir.contains("call i8* @basic_left"));
+        assert!(ir.contains("call double @basic_str_compare"));
     }
-} 
\ No newline at end of file
+}
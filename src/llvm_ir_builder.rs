@@ -4,9 +4,9 @@ use std::collections::HashMap;
 /// Similar to the Java LLVMBuilder approach
 pub struct LLVMIRBuilder {
     buffer: String,
+    constants: String, // module-level constants, emitted after the function bodies
     temp_counter: u32,
     global_counter: u32,
-    function_counter: u32,
     block_counter: u32,
     declared_functions: HashMap<String, FunctionSignature>,
     global_variables: HashMap<String, GlobalVariable>,
@@ -30,9 +30,9 @@ impl LLVMIRBuilder {
     pub fn new() -> Self {
         Self {
             buffer: String::new(),
+            constants: String::new(),
             temp_counter: 0,
             global_counter: 0,
-            function_counter: 0,
             block_counter: 0,
             declared_functions: HashMap::new(),
             global_variables: HashMap::new(),
@@ -40,7 +40,7 @@ impl LLVMIRBuilder {
     }
 
     pub fn build(&self) -> String {
-        self.buffer.clone()
+        format!("{}{}", self.buffer, self.constants)
     }
 
     // Module and function declarations
@@ -87,6 +87,10 @@ impl LLVMIRBuilder {
             self.get_value_type(value), value, self.get_value_type(value), ptr));
     }
 
+    pub fn add_typed_store(&mut self, var_type: &str, value: &str, ptr: &str) {
+        self.line(&format!("  store {} {}, {}* {}", var_type, value, var_type, ptr));
+    }
+
     pub fn add_load(&mut self, var_type: &str, ptr: &str, name: &str) -> String {
         let load_name = format!("%{}", name);
         self.line(&format!("  {} = load {}, {}* {}", load_name, var_type, var_type, ptr));
@@ -126,6 +130,17 @@ impl LLVMIRBuilder {
         self.line(&format!("  br i1 {}, label %{}, label %{}", condition, true_target, false_target));
     }
 
+    pub fn add_switch(&mut self, value: &str, default_target: &str, cases: &[(i64, String)]) {
+        let case_str: Vec<String> = cases.iter()
+            .map(|(val, label)| format!("i32 {}, label %{}", val, label))
+            .collect();
+        self.line(&format!("  switch i32 {}, label %{} [ {} ]", value, default_target, case_str.join(" ")));
+    }
+
+    pub fn add_unreachable(&mut self) {
+        self.line("  unreachable");
+    }
+
     pub fn add_icmp(&mut self, pred: &str, left: &str, right: &str, name: &str) -> String {
         let result_name = format!("%{}", name);
         self.line(&format!("  {} = icmp {} i32 {}, {}", result_name, pred, left, right));
@@ -194,11 +209,8 @@ impl LLVMIRBuilder {
     // Global variables and constants
     pub fn add_global_variable(&mut self, name: &str, var_type: &str, initializer: Option<&str>, is_constant: bool) {
         let constant_str = if is_constant { "constant" } else { "global" };
-        let init_str = match initializer {
-            Some(init) => format!(" = {}", init),
-            None => String::new(),
-        };
-        self.line(&format!("@{} = {} {} {}{}", name, constant_str, var_type, var_type, init_str));
+        let init_str = initializer.unwrap_or("zeroinitializer");
+        self.line(&format!("@{} = {} {} {}", name, constant_str, var_type, init_str));
         
         self.global_variables.insert(name.to_string(), GlobalVariable {
             var_type: var_type.to_string(),
@@ -210,11 +222,19 @@ impl LLVMIRBuilder {
     pub fn add_string_constant(&mut self, name: &str, content: &str) {
         // Escape the string content for LLVM-IR
         let escaped = self.escape_string(content);
-        let length = escaped.len() + 1; // +1 for null terminator
-        self.line(&format!("@{} = private unnamed_addr constant [{} x i8] c\"{}\00\"", 
+        let length = content.len() + 1; // +1 for null terminator
+        self.constants.push_str(&format!("@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n",
             name, length, escaped));
     }
 
+    /// Adds an anonymous string constant and returns an `i8*` constant expression pointing at it
+    pub fn add_string_constant_ptr(&mut self, content: &str) -> String {
+        let name = self.next_global();
+        self.add_string_constant(&name[1..], content);
+        format!("getelementptr inbounds ([{len} x i8], [{len} x i8]* {name}, i64 0, i64 0)",
+            len = content.len() + 1, name = name)
+    }
+
     // Utility methods
     pub fn next_temp(&mut self) -> String {
         self.temp_counter += 1;
@@ -263,14 +283,11 @@ impl LLVMIRBuilder {
     }
 
     fn escape_string(&self, s: &str) -> String {
-        s.chars()
-            .map(|c| match c {
-                '\n' => "\\0A".to_string(),
-                '\r' => "\\0D".to_string(),
-                '\t' => "\\09".to_string(),
-                '"' => "\\22".to_string(),
-                '\\' => "\\5C".to_string(),
-                _ => c.to_string(),
+        s.bytes()
+            .map(|b| match b {
+                b'"' | b'\\' => format!("\\{:02X}", b),
+                0x20..=0x7E => (b as char).to_string(),
+                _ => format!("\\{:02X}", b),
             })
             .collect()
     }
@@ -402,9 +419,9 @@ mod tests {
         builder.add_global_variable("uninit_var", "i8*", None, false);
         
         let result = builder.build();
-        assert!(result.contains("@global_var = global i32 i32 = 42"));
-        assert!(result.contains("@const_var = constant double double = 3.14"));
-        assert!(result.contains("@uninit_var = global i8* i8*"));
+        assert!(result.contains("@global_var = global i32 42"));
+        assert!(result.contains("@const_var = constant double 3.14"));
+        assert!(result.contains("@uninit_var = global i8* zeroinitializer"));
     }
 
     #[test]
@@ -466,7 +483,7 @@ mod tests {
 
     #[test]
     fn test_string_escaping() {
-        let mut builder = LLVMIRBuilder::new();
+        let builder = LLVMIRBuilder::new();
         
         // Test various escape sequences
         let test_cases = vec![
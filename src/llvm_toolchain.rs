@@ -0,0 +1,444 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What `basic-compiler` should produce from the generated LLVM-IR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmitKind {
+    /// Native executable
+    Exe,
+    /// Native object file
+    Obj,
+    /// LLVM-IR text
+    Ll,
+    /// Native assembly
+    Asm,
+}
+
+impl EmitKind {
+    /// Picks the output kind implied by an output file name: `.ll`, `.s`, `.o`, anything else is an executable
+    pub fn from_output_path(path: &Path) -> EmitKind {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("ll") => EmitKind::Ll,
+            Some("s") => EmitKind::Asm,
+            Some("o") | Some("obj") => EmitKind::Obj,
+            _ => EmitKind::Exe,
+        }
+    }
+
+    /// Default output file name for a program with the given stem
+    pub fn default_output(&self, stem: &str) -> String {
+        match self {
+            EmitKind::Exe => stem.to_string(),
+            EmitKind::Obj => format!("{}.o", stem),
+            EmitKind::Ll => format!("{}.ll", stem),
+            EmitKind::Asm => format!("{}.s", stem),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ToolchainError {
+    /// No usable compiler/assembler was found on PATH
+    NotFound {
+        message: String,
+    },
+    /// clang or llc ran but failed to compile the IR
+    CompileFailed {
+        tool: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// The linker step failed
+    LinkFailed {
+        tool: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// A tool could not be started, or a temporary file could not be written
+    Io {
+        message: String,
+    },
+}
+
+impl ToolchainError {
+    /// Process exit code used by `basic-compiler`, following the existing 10-17 codes
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ToolchainError::NotFound { .. } => 18,
+            ToolchainError::CompileFailed { .. } => 19,
+            ToolchainError::LinkFailed { .. } => 20,
+            ToolchainError::Io { .. } => 21,
+        }
+    }
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn status_text(status: &Option<i32>) -> String {
+            match status {
+                Some(code) => format!("exit code {}", code),
+                None => "terminated by signal".to_string(),
+            }
+        }
+        match self {
+            ToolchainError::NotFound { message } => write!(f, "Toolchain error: {}", message),
+            ToolchainError::CompileFailed { tool, status, stderr } => {
+                write!(f, "Compile error: {} failed ({})", tool, status_text(status))?;
+                if !stderr.trim().is_empty() {
+                    write!(f, "\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+            ToolchainError::LinkFailed { tool, status, stderr } => {
+                write!(f, "Link error: {} failed ({})", tool, status_text(status))?;
+                if !stderr.trim().is_empty() {
+                    write!(f, "\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+            ToolchainError::Io { message } => write!(f, "Toolchain I/O error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ToolchainError {}
+
+/// Whether a step compiles IR or links objects, which decides the error it reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    Compile,
+    Link,
+}
+
+/// One external command in a build plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolInvocation {
+    pub kind: StepKind,
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+}
+
+impl ToolInvocation {
+    fn new(kind: StepKind, program: &Path, args: &[&dyn AsRef<std::ffi::OsStr>]) -> Self {
+        ToolInvocation {
+            kind,
+            program: program.to_path_buf(),
+            args: args.iter().map(|a| a.as_ref().to_os_string()).collect(),
+        }
+    }
+
+    fn tool_name(&self) -> String {
+        self.program.file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.program.display().to_string())
+    }
+
+    fn run(&self) -> Result<(), ToolchainError> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .output()
+            .map_err(|e| ToolchainError::Io {
+                message: format!("could not run {}: {}", self.program.display(), e),
+            })?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let tool = self.tool_name();
+        let status = output.status.code();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        Err(match self.kind {
+            StepKind::Compile => ToolchainError::CompileFailed { tool, status, stderr },
+            StepKind::Link => ToolchainError::LinkFailed { tool, status, stderr },
+        })
+    }
+}
+
+/// Native tools used to turn generated LLVM-IR into assembly, objects and executables.
+///
+/// clang handles every step by itself. Without clang we fall back to `llc` for code
+/// generation and the system C compiler (`cc`/`gcc`) for linking. Executables are linked
+/// against the `basic_runtime` static library.
+#[derive(Debug, Clone, Default)]
+pub struct Toolchain {
+    pub clang: Option<PathBuf>,
+    pub llc: Option<PathBuf>,
+    pub cc: Option<PathBuf>,
+    pub runtime_lib: Option<PathBuf>,
+}
+
+/// File name of the runtime static library built from the `runtime` crate
+pub const RUNTIME_LIB_NAME: &str = "libbasic_runtime.a";
+
+/// System libraries the Rust standard library inside the runtime needs
+#[cfg(target_os = "linux")]
+const RUNTIME_SYSTEM_LIBS: &[&str] = &["-lm", "-lpthread", "-ldl"];
+#[cfg(not(target_os = "linux"))]
+const RUNTIME_SYSTEM_LIBS: &[&str] = &["-lm"];
+
+/// Versioned binary names tried after the plain name, newest first
+const LLVM_VERSIONS: [u32; 8] = [20, 19, 18, 17, 16, 15, 14, 13];
+
+impl Toolchain {
+    /// Looks for clang, llc and a C compiler on PATH. `BASIC_CLANG`, `BASIC_LLC` and `BASIC_CC`
+    /// override the search with an explicit path.
+    pub fn discover() -> Self {
+        let path = env::var_os("PATH").unwrap_or_default();
+        Toolchain {
+            clang: Self::from_env("BASIC_CLANG").or_else(|| find_versioned("clang", &path)),
+            llc: Self::from_env("BASIC_LLC").or_else(|| find_versioned("llc", &path)),
+            cc: Self::from_env("BASIC_CC")
+                .or_else(|| find_on_path("cc", &path))
+                .or_else(|| find_on_path("gcc", &path)),
+            runtime_lib: Self::from_env("BASIC_RUNTIME_LIB").or_else(find_runtime_lib),
+        }
+    }
+
+    fn from_env(var: &str) -> Option<PathBuf> {
+        env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from)
+    }
+
+    /// True when executables can be produced
+    pub fn can_build_executables(&self) -> bool {
+        self.runtime_lib.is_some() && (self.clang.is_some() || (self.llc.is_some() && self.cc.is_some()))
+    }
+
+    fn runtime_lib(&self) -> Result<&Path, ToolchainError> {
+        self.runtime_lib.as_deref().ok_or_else(|| ToolchainError::NotFound {
+            message: format!("runtime library {} not found next to basic-compiler (build it with `cargo build`, or set BASIC_RUNTIME_LIB)", RUNTIME_LIB_NAME),
+        })
+    }
+
+    /// Arguments that link the runtime library and the system libraries it needs
+    fn link_args(&self) -> Result<Vec<OsString>, ToolchainError> {
+        let mut args = vec![self.runtime_lib()?.as_os_str().to_os_string()];
+        args.extend(RUNTIME_SYSTEM_LIBS.iter().map(OsString::from));
+        Ok(args)
+    }
+
+    /// Lists the commands that turn `ir_path` into `output`. `scratch_object` is only used
+    /// when linking has to be done as a separate step.
+    pub fn plan(&self, emit: EmitKind, ir_path: &Path, output: &Path, scratch_object: &Path) -> Result<Vec<ToolInvocation>, ToolchainError> {
+        use StepKind::{Compile, Link};
+
+        if emit == EmitKind::Ll {
+            return Ok(Vec::new());
+        }
+
+        if let Some(clang) = &self.clang {
+            let step = match emit {
+                EmitKind::Asm => ToolInvocation::new(Compile, clang, &[&"-Wno-override-module", &"-S", &"-x", &"ir", &ir_path, &"-o", &output]),
+                EmitKind::Obj => ToolInvocation::new(Compile, clang, &[&"-Wno-override-module", &"-c", &"-x", &"ir", &ir_path, &"-o", &output]),
+                _ => {
+                    // "-x none" so the runtime archive is not read as IR
+                    let mut step = ToolInvocation::new(Link, clang, &[&"-Wno-override-module", &"-x", &"ir", &ir_path, &"-x", &"none", &"-o", &output]);
+                    step.args.extend(self.link_args()?);
+                    step
+                }
+            };
+            return Ok(vec![step]);
+        }
+
+        let Some(llc) = &self.llc else {
+            return Err(ToolchainError::NotFound {
+                message: "neither clang nor llc was found on PATH (set BASIC_CLANG or BASIC_LLC to point at one)".to_string(),
+            });
+        };
+        // Position independent code so the object links into the default PIE executables
+        match emit {
+            EmitKind::Asm => Ok(vec![ToolInvocation::new(Compile, llc, &[&"-relocation-model=pic", &ir_path, &"-o", &output])]),
+            EmitKind::Obj => Ok(vec![ToolInvocation::new(Compile, llc, &[&"-relocation-model=pic", &"-filetype=obj", &ir_path, &"-o", &output])]),
+            _ => {
+                let Some(cc) = &self.cc else {
+                    return Err(ToolchainError::NotFound {
+                        message: "no C compiler (cc or gcc) was found on PATH to link with (set BASIC_CC)".to_string(),
+                    });
+                };
+                let mut link = ToolInvocation::new(Link, cc, &[&scratch_object, &"-o", &output]);
+                link.args.extend(self.link_args()?);
+                Ok(vec![
+                    ToolInvocation::new(Compile, llc, &[&"-relocation-model=pic", &"-filetype=obj", &ir_path, &"-o", &scratch_object]),
+                    link,
+                ])
+            }
+        }
+    }
+
+    /// Builds `output` from the LLVM-IR text in `ir`. Intermediate files go in the system
+    /// temporary directory and are removed afterwards.
+    pub fn build(&self, ir: &str, emit: EmitKind, output: &Path) -> Result<(), ToolchainError> {
+        if emit == EmitKind::Ll {
+            return std::fs::write(output, ir).map_err(|e| ToolchainError::Io {
+                message: format!("could not write {}: {}", output.display(), e),
+            });
+        }
+
+        let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("program");
+        let scratch = env::temp_dir().join(format!("basic_compiler_{}_{}", std::process::id(), stem));
+        let ir_path = scratch.with_extension("ll");
+        let object_path = scratch.with_extension("o");
+        std::fs::write(&ir_path, ir).map_err(|e| ToolchainError::Io {
+            message: format!("could not write {}: {}", ir_path.display(), e),
+        })?;
+
+        let result = self.plan(emit, &ir_path, output, &object_path)
+            .and_then(|steps| steps.iter().try_for_each(|step| step.run()));
+
+        let _ = std::fs::remove_file(&ir_path);
+        let _ = std::fs::remove_file(&object_path);
+        result
+    }
+}
+
+/// Finds an executable called `name` in the directories of a PATH-style list
+pub fn find_on_path(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Looks for the runtime library beside the running executable, then one directory up
+/// (test binaries live in `target/<profile>/deps`, the library in `target/<profile>`)
+fn find_runtime_lib() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(RUNTIME_LIB_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Tries `name`, then `name-20` down to `name-13`, as Debian-style installs only ship the versioned names
+fn find_versioned(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    find_on_path(name, path).or_else(|| {
+        LLVM_VERSIONS.iter().find_map(|version| find_on_path(&format!("{}-{}", name, version), path))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(step: &ToolInvocation) -> Vec<String> {
+        step.args.iter().map(|a| a.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn test_emit_kind_from_output_path() {
+        assert_eq!(EmitKind::from_output_path(Path::new("game")), EmitKind::Exe);
+        assert_eq!(EmitKind::from_output_path(Path::new("game.exe")), EmitKind::Exe);
+        assert_eq!(EmitKind::from_output_path(Path::new("game.ll")), EmitKind::Ll);
+        assert_eq!(EmitKind::from_output_path(Path::new("game.s")), EmitKind::Asm);
+        assert_eq!(EmitKind::from_output_path(Path::new("game.o")), EmitKind::Obj);
+    }
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(EmitKind::Exe.default_output("game"), "game");
+        assert_eq!(EmitKind::Obj.default_output("game"), "game.o");
+        assert_eq!(EmitKind::Ll.default_output("game"), "game.ll");
+        assert_eq!(EmitKind::Asm.default_output("game"), "game.s");
+    }
+
+    #[test]
+    fn test_plan_with_clang() {
+        let toolchain = Toolchain {
+            clang: Some(PathBuf::from("/usr/bin/clang")),
+            runtime_lib: Some(PathBuf::from("/lib/libbasic_runtime.a")),
+            ..Toolchain::default()
+        };
+        let steps = toolchain.plan(EmitKind::Exe, Path::new("a.ll"), Path::new("a"), Path::new("a.o")).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].kind, StepKind::Link);
+        assert_eq!(args(&steps[0])[..9], ["-Wno-override-module", "-x", "ir", "a.ll", "-x", "none", "-o", "a", "/lib/libbasic_runtime.a"]);
+        assert!(args(&steps[0]).contains(&"-lm".to_string()));
+
+        let steps = toolchain.plan(EmitKind::Asm, Path::new("a.ll"), Path::new("a.s"), Path::new("a.o")).unwrap();
+        assert!(args(&steps[0]).contains(&"-S".to_string()));
+    }
+
+    #[test]
+    fn test_plan_with_llc_and_cc() {
+        let toolchain = Toolchain {
+            clang: None,
+            llc: Some(PathBuf::from("/usr/bin/llc")),
+            cc: Some(PathBuf::from("/usr/bin/cc")),
+            runtime_lib: Some(PathBuf::from("/lib/libbasic_runtime.a")),
+        };
+        let steps = toolchain.plan(EmitKind::Exe, Path::new("a.ll"), Path::new("a"), Path::new("tmp.o")).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].program, PathBuf::from("/usr/bin/llc"));
+        assert_eq!(args(&steps[0]), vec!["-relocation-model=pic", "-filetype=obj", "a.ll", "-o", "tmp.o"]);
+        assert_eq!(steps[1].kind, StepKind::Link);
+        assert_eq!(args(&steps[1])[..4], ["tmp.o", "-o", "a", "/lib/libbasic_runtime.a"]);
+        assert!(args(&steps[1]).contains(&"-lm".to_string()));
+
+        let steps = toolchain.plan(EmitKind::Obj, Path::new("a.ll"), Path::new("a.o"), Path::new("tmp.o")).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(args(&steps[0]), vec!["-relocation-model=pic", "-filetype=obj", "a.ll", "-o", "a.o"]);
+    }
+
+    #[test]
+    fn test_plan_without_tools() {
+        let toolchain = Toolchain::default();
+        let err = toolchain.plan(EmitKind::Obj, Path::new("a.ll"), Path::new("a.o"), Path::new("tmp.o")).unwrap_err();
+        assert_eq!(err.exit_code(), 18);
+        assert!(toolchain.plan(EmitKind::Ll, Path::new("a.ll"), Path::new("a.ll"), Path::new("tmp.o")).unwrap().is_empty());
+
+        // llc alone can emit objects but cannot link
+        let toolchain = Toolchain { llc: Some(PathBuf::from("/usr/bin/llc")), ..Toolchain::default() };
+        assert!(toolchain.plan(EmitKind::Obj, Path::new("a.ll"), Path::new("a.o"), Path::new("tmp.o")).is_ok());
+        let err = toolchain.plan(EmitKind::Exe, Path::new("a.ll"), Path::new("a"), Path::new("tmp.o")).unwrap_err();
+        assert!(matches!(err, ToolchainError::NotFound { .. }));
+        assert!(!toolchain.can_build_executables());
+
+        // Linking needs the runtime library
+        let toolchain = Toolchain { clang: Some(PathBuf::from("/usr/bin/clang")), ..Toolchain::default() };
+        let err = toolchain.plan(EmitKind::Exe, Path::new("a.ll"), Path::new("a"), Path::new("tmp.o")).unwrap_err();
+        assert!(err.to_string().contains(RUNTIME_LIB_NAME));
+    }
+
+    #[test]
+    fn test_find_on_path() {
+        let dir = env::temp_dir().join(format!("basic_toolchain_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("llc-15");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path = env::join_paths([dir.clone()]).unwrap();
+
+        assert_eq!(find_versioned("llc", &path), Some(tool.clone()));
+        assert_eq!(find_on_path("clang", &path), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_exit_codes() {
+        let compile = ToolchainError::CompileFailed { tool: "llc".to_string(), status: Some(1), stderr: "bad".to_string() };
+        let link = ToolchainError::LinkFailed { tool: "cc".to_string(), status: Some(1), stderr: String::new() };
+        assert_eq!(compile.exit_code(), 19);
+        assert_eq!(link.exit_code(), 20);
+        assert_eq!(compile.to_string(), "Compile error: llc failed (exit code 1)\nbad");
+        assert_eq!(link.to_string(), "Link error: cc failed (exit code 1)");
+    }
+}
@@ -3,6 +3,8 @@ use std::process;
 use basic_rs::basic_parser::Parser;
 use basic_rs::basic_lexer::Lexer;
 use basic_rs::basic_types::RunStatus;
+use basic_rs::basic_dialect::Dialect;
+use basic_runtime::rng::RndAlgorithm;
 use basic_rs::basic_reports::{CoverageData, save_coverage_to_file, load_coverage_from_file, merge_coverage};
 use clap::Parser as ClapParser;
 
@@ -19,6 +21,30 @@ struct Args {
     /// Reset coverage data (delete existing file before starting)
     #[arg(long)]
     reset_coverage: bool,
+
+    /// BASIC dialect: basicrs, dartmouth, msbasic2, gwbasic or ansi-minimal
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// Restrict OPEN to files inside this directory
+    #[arg(long)]
+    sandbox: Option<String>,
+
+    /// Generator RND draws from, instead of the dialect's: basicrs, microsoft or trekbasic
+    #[arg(long)]
+    rnd: Option<RndAlgorithm>,
+
+    /// Seed RND as RANDOMIZE would before the program starts, so runs repeat
+    #[arg(long, allow_negative_numbers = true)]
+    seed: Option<f64>,
+
+    /// Log every line typed for INPUT, with its BASIC line number and the time, to this file
+    #[arg(long)]
+    record_input: Option<String>,
+
+    /// Answer INPUT from a file written by --record-input, failing if the program asks at a different line
+    #[arg(long)]
+    replay_input: Option<String>,
 }
 
 fn print_basic_error(kind: &str, message: &str, basic_line_number: &Option<usize>, file_line_number: &Option<usize>) {
@@ -34,7 +60,10 @@ fn print_basic_error(kind: &str, message: &str, basic_line_number: &Option<usize
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(rnd) = args.rnd {
+        args.dialect.rnd = rnd;
+    }
 
     // Handle reset coverage flag
     if args.reset_coverage {
@@ -53,16 +82,45 @@ fn main() {
     let program_path = &args.program;
     match fs::read_to_string(program_path) {
         Ok(source) => {
-            let mut lexer = Lexer::new(&source);
+            let mut lexer = Lexer::with_dialect(&source, args.dialect);
 
             let tokens = lexer.tokenize().expect("Lexing failed");
-            let mut parser = Parser::new(tokens);
+            for warning in lexer.name_collisions() {
+                eprintln!("Warning: {}", warning);
+            }
+            let mut parser = Parser::with_dialect(tokens, args.dialect);
             match parser.parse() {
                 Ok(program) => {
                     // println!("Program parsed successfully!");
                     // println!("Program has {} lines.", program.lines.len());
                     use basic_rs::basic_interpreter::Interpreter;
-                    let mut interpreter = Interpreter::new(program);
+                    use basic_rs::basic_console::StdioConsole;
+                    use basic_rs::basic_input_log::{InputRecorder, InputReplay};
+                    let mut interpreter = Interpreter::with_dialect(program, args.dialect, Box::new(StdioConsole::new()));
+                    if let Some(ref dir) = args.sandbox {
+                        interpreter.set_sandbox(dir.into());
+                    }
+                    if let Some(seed) = args.seed {
+                        interpreter.randomize(seed);
+                    }
+                    if let Some(ref path) = args.record_input {
+                        match InputRecorder::create(path) {
+                            Ok(recorder) => interpreter.record_input(recorder),
+                            Err(e) => {
+                                eprintln!("Error creating input recording {}: {}", path, e);
+                                process::exit(1);
+                            }
+                        }
+                    }
+                    if let Some(ref path) = args.replay_input {
+                        match InputReplay::load(path) {
+                            Ok(replay) => interpreter.replay_input(replay),
+                            Err(e) => {
+                                eprintln!("Error reading input recording {}: {}", path, e);
+                                process::exit(1);
+                            }
+                        }
+                    }
                     if let Err(e) = interpreter.enable_trace() {
                         eprintln!("Failed to enable trace: {}", e);
                         process::exit(97);
@@ -119,7 +177,7 @@ fn main() {
                                     print_basic_error("Syntax", message, basic_line_number, file_line_number);
                                     process::exit(5);
                                 }
-                                BasicError::Runtime { message, basic_line_number, file_line_number } => {
+                                BasicError::Runtime { message, basic_line_number, file_line_number, .. } => {
                                     print_basic_error("Runtime", message, basic_line_number, file_line_number);
                                     process::exit(6);
                                 }
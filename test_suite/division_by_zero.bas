@@ -0,0 +1,4 @@
+10 REM @EXPECT_EXIT_CODE=6
+20 PRINT "BEFORE"
+30 PRINT 1 / 0
+40 PRINT "NOT REACHED"
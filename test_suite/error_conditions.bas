@@ -0,0 +1,22 @@
+10 REM Errors that ON ERROR traps with their own codes
+20 ON ERROR GOTO 100
+30 INPUT A
+40 PRINT "A ="; A
+50 DIM B(1, 1, 1)
+60 FOR I = 1 TO 0
+70 PRINT "END"
+80 END
+100 PRINT "ERROR"; ERR; "IN"; ERL
+110 RESUME NEXT
+200 REM @INPUT X
+210 REM @INPUT Y
+220 REM @INPUT Z
+230 REM @EXPECT_OUTPUT
+240 REM ? ?Redo from start
+250 REM ? ?Redo from start
+260 REM ? ERROR 13 IN 30 
+270 REM A = 0 
+280 REM ERROR 5 IN 50 
+290 REM ERROR 26 IN 60 
+300 REM END
+310 REM @END_EXPECT_OUTPUT
@@ -0,0 +1,16 @@
+10 REM CHR$(200) counts as one character towards the 255 character limit
+20 ON ERROR GOTO 100
+30 A$ = STRING$(200, 200)
+40 PRINT LEN(A$); ASC(A$)
+50 B$ = A$ + LEFT$(A$, 55)
+60 PRINT LEN(B$)
+70 C$ = B$ + CHR$(200)
+80 PRINT "NOT REACHED"
+90 END
+100 PRINT "ERROR"; ERR
+110 END
+200 REM @EXPECT_OUTPUT
+210 REM  200  200 
+220 REM  255 
+230 REM ERROR 15 
+240 REM @END_EXPECT_OUTPUT
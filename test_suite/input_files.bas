@@ -0,0 +1,8 @@
+10 REM Stdin from input_files.in, golden output in input_files.out
+20 T = 0
+30 FOR I = 1 TO 3
+40 INPUT X
+50 T = T + X
+60 NEXT I
+70 PRINT
+80 PRINT "TOTAL"; T
@@ -0,0 +1,10 @@
+10 REM Stdin and golden output given inline
+20 INPUT "NAME"; N$
+30 INPUT A, B
+40 PRINT "HELLO " + N$ + ", THE SUM IS " + STR$(A + B)
+50 END
+100 REM @INPUT ANN
+110 REM @INPUT 3, 4
+120 REM @EXPECT_OUTPUT
+130 REM NAME? ? HELLO ANN, THE SUM IS 7
+140 REM @END_EXPECT_OUTPUT
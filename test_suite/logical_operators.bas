@@ -0,0 +1,8 @@
+10 REM The logical operators round their operands; NOT is logical and true is 1
+20 PRINT 2.7 AND 3; 2.4 AND 3; -2.5 OR 0
+30 PRINT NOT 0; NOT 5; 1 < 2; 5 XOR 3
+40 END
+100 REM @EXPECT_OUTPUT
+110 REM  3  2 -3 
+120 REM  1  0  1  6 
+130 REM @END_EXPECT_OUTPUT
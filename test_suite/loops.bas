@@ -0,0 +1,14 @@
+10 REM Golden output given inline
+20 FOR I = 1 TO 3
+30 GOSUB 100
+40 NEXT I
+50 PRINT "DONE"
+60 END
+100 PRINT "LINE" + STR$(I)
+110 RETURN
+200 REM @EXPECT_OUTPUT
+210 REM LINE1
+220 REM LINE2
+230 REM LINE3
+240 REM DONE
+250 REM @END_EXPECT_OUTPUT
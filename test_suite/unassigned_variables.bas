@@ -0,0 +1,13 @@
+10 REM A variable that was never assigned is 0, or an empty string
+20 PRINT X; "[" + A$ + "]"
+30 ON ERROR GOTO 100
+40 Y = 1 / 0
+50 PRINT Y; Y + 1
+60 END
+100 PRINT "ERROR"; ERR
+110 RESUME NEXT
+200 REM @EXPECT_OUTPUT
+210 REM  0 []
+220 REM ERROR 11 
+230 REM  0  1 
+240 REM @END_EXPECT_OUTPUT